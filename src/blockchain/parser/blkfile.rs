@@ -4,13 +4,26 @@ use std::fs::{self, DirEntry, File};
 use std::io::{self, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
 
+use bitcoin::hashes::{sha256d, Hash};
 use byteorder::{LittleEndian, ReadBytesExt};
 use seek_bufread::BufReader;
 
 use crate::blockchain::parser::reader::BlockchainRead;
 use crate::blockchain::parser::types::CoinType;
+use crate::blockchain::parser::ProgressLog;
 use crate::blockchain::proto::block::Block;
-use crate::errors::{OpError, OpErrorKind, OpResult};
+use crate::blockchain::proto::header::BlockHeader;
+use crate::blockchain::proto::Hashed;
+use crate::errors::{OpError, OpErrorKind, OpResult, ParseErrorKind, ParseResult};
+
+/// Number of blocks, transactions and bytes stored in a blk file
+#[derive(Debug, Default, PartialEq)]
+pub struct BlkFileStats {
+    pub blocks: u64,
+    pub txs: u64,
+    // Serialized size of all blocks without magic bytes and size prefix
+    pub bytes: u64,
+}
 
 /// Holds all necessary data about a raw blk file
 #[derive(Debug)]
@@ -48,9 +61,114 @@ impl BlkFile {
 
     pub fn read_block(&mut self, offset: u64, coin: &CoinType) -> OpResult<Block> {
         let reader = self.open()?;
-        reader.seek(SeekFrom::Start(offset - 4))?;
-        let block_size = reader.read_u32::<LittleEndian>()?;
-        reader.read_block(block_size, coin)
+        let block = BlkFile::seek_block(reader, offset, coin.magic)
+            .and_then(|block_size| reader.read_block(block_size, coin))
+            .map_err(|e| e.at(&self.path, offset))?;
+        Ok(block)
+    }
+
+    /// Reads the serialized block at the given offset without parsing it
+    pub fn read_raw_block(&mut self, offset: u64, magic: u32) -> OpResult<Vec<u8>> {
+        let reader = self.open()?;
+        let raw_block = BlkFile::seek_block(reader, offset, magic)
+            .and_then(|block_size| reader.read_u8_vec(block_size))
+            .map_err(|e| e.at(&self.path, offset))?;
+        Ok(raw_block)
+    }
+
+    /// Reads the size and header of the block at the given offset
+    pub fn read_header(&mut self, offset: u64, magic: u32) -> OpResult<(u32, BlockHeader)> {
+        let reader = self.open()?;
+        let header = BlkFile::seek_block(reader, offset, magic)
+            .and_then(|block_size| Ok((block_size, reader.read_block_header()?)))
+            .map_err(|e| e.at(&self.path, offset))?;
+        Ok(header)
+    }
+
+    /// Seeks to the block at the given data offset, checks the preceding magic value and returns the block size
+    fn seek_block(reader: &mut BufReader<File>, offset: u64, magic: u32) -> ParseResult<u32> {
+        reader.seek(SeekFrom::Start(offset.saturating_sub(8)))?;
+        let found = reader.read_u32::<LittleEndian>()?;
+        if found != magic {
+            return Err(ParseErrorKind::InvalidMagic {
+                expected: magic,
+                found,
+            }
+            .into());
+        }
+        Ok(reader.read_u32::<LittleEndian>()?)
+    }
+
+    /// Walks the file by magic bytes and returns the data offset and header of each block.
+    /// Stops at zero padding or at the first incomplete block, e.g. the tail of a file
+    /// which is still being written by a running node.
+    pub fn scan_headers(&mut self, coin: &CoinType) -> OpResult<Vec<(u64, Hashed<BlockHeader>)>> {
+        let size = self.size;
+        let path = self.path.clone();
+        let reader = self.open()?;
+        let mut headers = Vec::new();
+        let mut progress = ProgressLog::new("blkfile", format!("Scanning {} ...", path.display()));
+        let mut pos = 0;
+        while pos + 8 <= size {
+            progress.update(pos, size);
+            reader.seek(SeekFrom::Start(pos))?;
+            let magic = reader.read_u32::<LittleEndian>()?;
+            if magic == 0 {
+                break;
+            } else if magic != coin.magic {
+                // Skip garbage until the next magic value
+                pos += 1;
+                continue;
+            }
+            let block_size = reader.read_u32::<LittleEndian>()? as u64;
+            if block_size < 80 || pos + 8 + block_size > size {
+                warn!(target: "blkfile", "Ignoring truncated block at offset {} in {}", pos, path.display());
+                break;
+            }
+            let header = reader.read_block_header()?;
+            // Space reserved by the node, but the block data hasn't been flushed yet
+            if header.timestamp == 0 && header.merkle_root == sha256d::Hash::all_zeros() {
+                warn!(target: "blkfile", "Ignoring incomplete block at offset {} in {}", pos, path.display());
+                break;
+            }
+            headers.push((pos + 8, Hashed::double_sha256(header)));
+            pos += 8 + block_size;
+        }
+        debug!(target: "blkfile", "Found {} blocks in {}", headers.len(), path.display());
+        Ok(headers)
+    }
+
+    /// Counts the blocks, transactions and bytes in the file in disk order.
+    /// Only the header and the transaction count of each block are read, stale blocks are included.
+    pub fn scan_stats(&mut self, coin: &CoinType) -> OpResult<BlkFileStats> {
+        let offsets: Vec<u64> = self
+            .scan_headers(coin)?
+            .into_iter()
+            .map(|(offset, _)| offset)
+            .collect();
+        let path = self.path.clone();
+        let mut stats = BlkFileStats::default();
+        let reader = self.open()?;
+        for offset in offsets {
+            let block_size = BlkFile::seek_block(reader, offset, coin.magic)
+                .and_then(|block_size| {
+                    reader.seek(SeekFrom::Current(80))?;
+                    stats.txs += reader.read_compact_size(coin.canonical_compact_size)?.value;
+                    Ok(block_size)
+                })
+                .map_err(|e| e.at(&path, offset))?;
+            stats.blocks += 1;
+            stats.bytes += block_size as u64;
+        }
+        self.close();
+        Ok(stats)
+    }
+
+    /// Opens a single file containing concatenated blocks
+    pub fn from_file(path: &Path) -> OpResult<BlkFile> {
+        info!(target: "blkfile", "Reading blocks from {} ...", path.display());
+        let size = fs::metadata(path)?.len();
+        Ok(BlkFile::new(PathBuf::from(path), size))
     }
 
     /// Collects all blk*.dat paths in the given directory
@@ -117,6 +235,97 @@ impl BlkFile {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::blockchain::parser::types::Bitcoin;
+    use std::io::Write;
+
+    /// Serializes a block with a header distinguished by `nonce` and `size - 80` bytes of padding
+    fn raw_block(nonce: u32, size: u32) -> Vec<u8> {
+        let mut raw = Vec::new();
+        raw.extend_from_slice(&CoinType::from(Bitcoin).magic.to_le_bytes());
+        raw.extend_from_slice(&size.to_le_bytes());
+        raw.extend_from_slice(&1u32.to_le_bytes());
+        raw.extend_from_slice(&[0u8; 32]);
+        raw.extend_from_slice(&[nonce as u8; 32]);
+        raw.extend_from_slice(&1231006505u32.to_le_bytes());
+        raw.extend_from_slice(&0x1d00ffffu32.to_le_bytes());
+        raw.extend_from_slice(&nonce.to_le_bytes());
+        raw.resize(raw.len() + size as usize - 80, 0xAB);
+        raw
+    }
+
+    fn scan(data: &[u8]) -> Vec<u64> {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(data).unwrap();
+        file.flush().unwrap();
+        let mut blk_file = BlkFile::from_file(file.path()).unwrap();
+        blk_file
+            .scan_headers(&CoinType::from(Bitcoin))
+            .unwrap()
+            .iter()
+            .map(|(offset, _)| *offset)
+            .collect()
+    }
+
+    #[test]
+    fn test_scan_headers_truncated_tail() {
+        let complete = [raw_block(1, 100), raw_block(2, 120)].concat();
+        assert_eq!(scan(&complete), [8, 116]);
+
+        // Declared length exceeds the end of the file
+        let truncated = raw_block(3, 200);
+        assert_eq!(scan(&[&complete[..], &truncated[..150]].concat()), [8, 116]);
+        // Magic and size, but the header is incomplete
+        assert_eq!(scan(&[&complete[..], &truncated[..40]].concat()), [8, 116]);
+        // Only part of the magic
+        assert_eq!(scan(&[&complete[..], &truncated[..3]].concat()), [8, 116]);
+        // Magic and size were written into preallocated space, but the block data is missing
+        let unflushed = [&truncated[..8], &[0u8; 300][..]].concat();
+        assert_eq!(scan(&[&complete[..], &unflushed[..]].concat()), [8, 116]);
+    }
+
+    #[test]
+    fn test_scan_stats() {
+        let mut first = raw_block(1, 100);
+        first[88] = 2;
+        let mut second = raw_block(2, 120);
+        second[88] = 5;
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&[first, second, vec![0u8; 64]].concat())
+            .unwrap();
+        file.flush().unwrap();
+
+        let mut blk_file = BlkFile::from_file(file.path()).unwrap();
+        assert_eq!(
+            blk_file.scan_stats(&CoinType::from(Bitcoin)).unwrap(),
+            BlkFileStats {
+                blocks: 2,
+                txs: 7,
+                bytes: 220
+            }
+        );
+    }
+
+    #[test]
+    fn test_read_invalid_magic() {
+        let mut raw = raw_block(1, 100);
+        raw[0] = 0x00;
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&raw).unwrap();
+        file.flush().unwrap();
+
+        let mut blk_file = BlkFile::from_file(file.path()).unwrap();
+        let err = blk_file
+            .read_header(8, CoinType::from(Bitcoin).magic)
+            .unwrap_err();
+        match err.kind {
+            OpErrorKind::ParseError(err) => {
+                assert!(matches!(err.kind, ParseErrorKind::InvalidMagic { .. }));
+                assert_eq!(err.file.as_deref(), Some(file.path()));
+                assert_eq!(err.offset, Some(8));
+            }
+            kind => panic!("unexpected error: {}", kind),
+        }
+    }
 
     #[test]
     fn test_parse_blk_index() {
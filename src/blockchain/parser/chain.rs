@@ -1,56 +1,277 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Cursor, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use bitcoin::hashes::{sha256, sha256d, Hash, HashEngine};
+use rayon::prelude::*;
 
 use crate::blockchain::parser::blkfile::BlkFile;
-use crate::blockchain::parser::index::ChainIndex;
+use crate::blockchain::parser::index::{ChainIndex, EXTRA_BLK_INDEX};
+use crate::blockchain::parser::reader::BlockchainRead;
 use crate::blockchain::parser::types::CoinType;
 use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::header::BlockHeader;
+use crate::blockchain::proto::Hashed;
+use crate::common::utils;
 use crate::errors::{OpError, OpErrorKind, OpResult};
 use crate::ParserOptions;
 
+/// Parses trusted checkpoints with one `height;hash` pair per line,
+/// empty lines and lines starting with `#` are ignored
+pub fn parse_checkpoints(content: &str) -> OpResult<BTreeMap<u64, sha256d::Hash>> {
+    let mut checkpoints = BTreeMap::new();
+    for line in content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+    {
+        let (height, hash) = line
+            .split_once(';')
+            .and_then(|(height, hash)| {
+                Some((
+                    height.trim().parse::<u64>().ok()?,
+                    sha256d::Hash::from_str(hash.trim()).ok()?,
+                ))
+            })
+            .ok_or_else(|| {
+                OpError::new(OpErrorKind::InvalidArgsError)
+                    .join_msg(&format!("Invalid checkpoint: {}", line))
+            })?;
+        checkpoints.insert(height, hash);
+    }
+    Ok(checkpoints)
+}
+
+/// Matches the checkpoints against the chain index and returns the height of the highest one.
+/// Checkpoints above the tip are ignored, since the blocks below them can't be linked to the checkpoint yet.
+fn trusted_height(
+    chain_index: &ChainIndex,
+    checkpoints: &BTreeMap<u64, sha256d::Hash>,
+) -> OpResult<Option<u64>> {
+    let mut trusted_height = None;
+    for (height, hash) in checkpoints {
+        match chain_index.get(*height) {
+            Some(record) if record.block_hash == *hash => trusted_height = Some(*height),
+            Some(record) => {
+                let msg = format!(
+                    "Checkpoint at height {} doesn't match!\n  -> expected: {}\n  -> got: {}\n",
+                    height, hash, &record.block_hash
+                );
+                return Err(OpError::new(OpErrorKind::ValidationError).join_msg(&msg));
+            }
+            None => {
+                warn!(target: "chain", "Ignoring checkpoint at height {} above the tip", height);
+            }
+        }
+    }
+    if let Some(height) = trusted_height {
+        info!(target: "chain", "Trusting blocks up to checkpoint at height {}", height);
+    }
+    Ok(trusted_height)
+}
+
 /// Manages the index and data of longest valid chain
 pub struct ChainStorage {
     chain_index: ChainIndex,
     blk_files: HashMap<u64, BlkFile>, // maps blk_index to BlkFile
     coin: CoinType,
     verify: bool,
+    verify_pow: bool,
+    verify_merkle: bool,
+    max_money: bool,
+    strict: bool,
+    // Blocks up to this height are covered by a checkpoint and not verified
+    trusted_height: Option<u64>,
+    // SHA256 over the serialized bytes of all blocks returned by get_block()
+    fingerprint: Option<sha256::HashEngine>,
+    // Byte order of the hashes in the orphans and reorgs files
+    hash_byte_order: utils::HashByteOrder,
 }
 
 impl ChainStorage {
     pub fn new(options: &ParserOptions) -> OpResult<Self> {
+        let mut extra = match &options.extra_blocks {
+            Some(path) => Some(BlkFile::from_file(path)?),
+            None => None,
+        };
+        let (chain_index, mut blk_files) = match &options.block_file {
+            Some(path) => {
+                let mut blk_file = BlkFile::from_file(path)?;
+                let chain_index =
+                    ChainIndex::from_blk_file(&mut blk_file, extra.as_mut(), options)?;
+                (chain_index, HashMap::from([(0, blk_file)]))
+            }
+            None => (
+                ChainIndex::new(options, extra.as_mut())?,
+                BlkFile::from_path(options.blockchain_dir.as_path())?,
+            ),
+        };
+        if let Some(blk_file) = extra {
+            blk_files.insert(EXTRA_BLK_INDEX, blk_file);
+        }
+        let trusted_height = trusted_height(&chain_index, &options.checkpoints)?;
         Ok(Self {
-            chain_index: ChainIndex::new(options)?,
-            blk_files: BlkFile::from_path(options.blockchain_dir.as_path())?,
+            chain_index,
+            blk_files,
             coin: options.coin.clone(),
             verify: options.verify,
+            verify_pow: options.verify_pow,
+            verify_merkle: options.verify_merkle,
+            max_money: options.max_money,
+            strict: options.strict,
+            trusted_height,
+            fingerprint: options.chain_fingerprint.then(sha256::Hash::engine),
+            hash_byte_order: options.hash_byte_order,
         })
     }
 
-    /// Returns the next block and its height
-    pub fn get_block(&mut self, height: u64) -> Option<Block> {
+    /// Returns the block at the given height or None if there is no such block
+    pub fn get_block(&mut self, height: u64) -> OpResult<Option<Block>> {
         // Read block
-        let block_meta = self.chain_index.get(height)?;
-        let blk_file = self.blk_files.get_mut(&block_meta.blk_index)?;
-        let block = blk_file
-            .read_block(block_meta.data_offset, &self.coin)
-            .ok()?;
+        let block_meta = match self.chain_index.get(height) {
+            Some(block_meta) => block_meta,
+            None => return Ok(None),
+        };
+        let blk_file = match self.blk_files.get_mut(&block_meta.blk_index) {
+            Some(blk_file) => blk_file,
+            None => return Ok(None),
+        };
+        let block = match self.fingerprint.as_mut() {
+            Some(engine) => blk_file
+                .read_raw_block(block_meta.data_offset, self.coin.magic)
+                .and_then(|raw_block| {
+                    let block =
+                        Cursor::new(&raw_block).read_block(raw_block.len() as u32, &self.coin)?;
+                    engine.input(&raw_block);
+                    Ok(block)
+                }),
+            None => blk_file.read_block(block_meta.data_offset, &self.coin),
+        };
+        let block = match block {
+            Ok(block) => block,
+            Err(e) => return self.unreadable_block(e, height).map(|_| None),
+        };
 
         // Check if blk file can be closed
         if height == self.chain_index.max_height_by_blk(block_meta.blk_index) {
             blk_file.close()
         }
 
-        if self.verify {
-            self.verify(&block, height).unwrap();
+        self.check_block(&block, height)?;
+        Ok(Some(block))
+    }
+
+    /// Returns up to `count` consecutive blocks starting at the given height.
+    /// The blocks are read one after another, but deserialized in parallel on the rayon thread pool.
+    /// Like `get_block()`, stops at the first missing or unreadable block.
+    pub fn get_blocks(&mut self, height: u64, count: u64) -> OpResult<Vec<Block>> {
+        let mut raw_blocks = Vec::with_capacity(count as usize);
+        for height in height..height.saturating_add(count) {
+            match self.get_raw_block(height)? {
+                Some(raw_block) => raw_blocks.push(raw_block),
+                None => break,
+            }
+        }
+        let coin = &self.coin;
+        let parsed: Vec<OpResult<Block>> = raw_blocks
+            .par_iter()
+            .map(|raw_block| {
+                Cursor::new(raw_block)
+                    .read_block(raw_block.len() as u32, coin)
+                    .map_err(OpError::from)
+            })
+            .collect();
+
+        let mut blocks = Vec::with_capacity(parsed.len());
+        for (height, (raw_block, block)) in (height..).zip(raw_blocks.iter().zip(parsed)) {
+            let block = match block {
+                Ok(block) => block,
+                Err(e) => {
+                    self.unreadable_block(e, height)?;
+                    break;
+                }
+            };
+            if let Some(engine) = self.fingerprint.as_mut() {
+                engine.input(raw_block);
+            }
+            self.check_block(&block, height)?;
+            blocks.push(block);
+        }
+        Ok(blocks)
+    }
+
+    /// Reads the serialized block at the given height or None if it's missing or unreadable
+    fn get_raw_block(&mut self, height: u64) -> OpResult<Option<Vec<u8>>> {
+        let block_meta = match self.chain_index.get(height) {
+            Some(block_meta) => block_meta,
+            None => return Ok(None),
+        };
+        let blk_file = match self.blk_files.get_mut(&block_meta.blk_index) {
+            Some(blk_file) => blk_file,
+            None => return Ok(None),
+        };
+        let raw_block = match blk_file.read_raw_block(block_meta.data_offset, self.coin.magic) {
+            Ok(raw_block) => raw_block,
+            Err(e) => return self.unreadable_block(e, height).map(|_| None),
+        };
+        if height == self.chain_index.max_height_by_blk(block_meta.blk_index) {
+            blk_file.close()
+        }
+        Ok(Some(raw_block))
+    }
+
+    /// Returns the error of an indexed block which can't be read in strict mode,
+    /// otherwise the chain is logged to end before it.
+    fn unreadable_block(&self, err: OpError, height: u64) -> OpResult<()> {
+        if self.strict {
+            return Err(err.join_msg(&format!("Unable to read block at height {}:", height)));
         }
+        warn!(target: "chain", "Stopping at unreadable block at height {}: {}", height, err);
+        Ok(())
+    }
 
-        Some(block)
+    /// Runs the configured checks on the block at the given height
+    fn check_block(&self, block: &Block, height: u64) -> OpResult<()> {
+        let trusted = self.trusted_height.is_some_and(|trusted| height <= trusted);
+        if self.verify && !trusted {
+            self.verify(block, height)?;
+        }
+        if self.verify_pow && !trusted {
+            // Checked in parse_args
+            block.verify_pow(self.coin.pow_algorithm.unwrap())?;
+        }
+        if self.verify_merkle && !trusted {
+            self.sanity_check(block.verify_merkle(), height)?;
+        }
+        if self.max_money {
+            self.sanity_check(block.check_money_range(self.coin.max_money), height)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the result of a failed sanity check in strict mode, otherwise the block is only logged.
+    fn sanity_check(&self, result: OpResult<()>, height: u64) -> OpResult<()> {
+        match result {
+            Err(e) if self.strict => Err(e),
+            Err(e) => {
+                warn!(target: "chain", "Invalid block at height {}, processing anyway: {}", height, e);
+                Ok(())
+            }
+            Ok(_) => Ok(()),
+        }
     }
 
     /// Verifies the given block in a chain.
-    /// Panics if not valid
     fn verify(&self, block: &Block, height: u64) -> OpResult<()> {
         block.verify_merkle_root()?;
         if height == 0 {
+            // A --block-file range may start in the middle of the chain, its root isn't linked to anything
+            if block.header.value.prev_hash != sha256d::Hash::all_zeros() {
+                return Ok(());
+            }
             if block.header.hash != self.coin.genesis_hash {
                 let msg = format!(
                     "Genesis block hash doesn't match!\n  -> expected: {}\n  -> got: {}\n",
@@ -75,7 +296,198 @@ impl ChainStorage {
         Ok(())
     }
 
+    /// Checks that all blocks from `start_height` to the tip are indexed, readable
+    /// and linked by prev_hash without parsing any transactions.
+    pub fn dry_run(&mut self, start_height: u64) -> ChainReport {
+        let mut report = ChainReport {
+            tip_height: self.max_height(),
+            ..Default::default()
+        };
+        let mut prev_hash = start_height
+            .checked_sub(1)
+            .and_then(|height| self.chain_index.get(height))
+            .map(|record| record.block_hash);
+
+        for height in start_height..=self.max_height() {
+            let header = match self.read_header(height) {
+                Some((size, header)) => {
+                    report.blocks += 1;
+                    report.size += size as u64;
+                    header
+                }
+                None => {
+                    report.missing += 1;
+                    report.first_missing.get_or_insert(height);
+                    prev_hash = None;
+                    continue;
+                }
+            };
+            if prev_hash.is_some_and(|hash| hash != header.value.prev_hash) {
+                report.first_broken_link.get_or_insert(height);
+            }
+            prev_hash = Some(header.hash);
+        }
+        report
+    }
+
+    /// Returns the size and header of the block at the given height or None if it's not readable
+    fn read_header(&mut self, height: u64) -> Option<(u32, Hashed<BlockHeader>)> {
+        let block_meta = self.chain_index.get(height)?;
+        let blk_file = self.blk_files.get_mut(&block_meta.blk_index)?;
+        let header = blk_file.read_header(block_meta.data_offset, self.coin.magic);
+        if height == self.chain_index.max_height_by_blk(block_meta.blk_index) {
+            blk_file.close()
+        }
+        match header {
+            Ok((size, header)) => Some((size, Hashed::double_sha256(header))),
+            Err(e) => {
+                debug!(target: "chain", "Unable to read block at height {}: {}", height, e);
+                None
+            }
+        }
+    }
+
+    /// Writes the stale blocks discarded during main chain selection to a CSV file.
+    /// Returns the number of written blocks.
+    pub fn write_orphans(&self, path: &Path) -> OpResult<usize> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(b"orphan_hash;height;main_chain_hash\n")?;
+        let orphans = self.chain_index.orphans();
+        for orphan in orphans {
+            writer.write_all(
+                format!(
+                    "{};{};{}\n",
+                    self.hash_byte_order.hex(&orphan.block_hash),
+                    orphan.height,
+                    orphan
+                        .main_chain_hash
+                        .as_ref()
+                        .map_or(String::new(), |hash| self.hash_byte_order.hex(hash))
+                )
+                .as_bytes(),
+            )?;
+        }
+        writer.flush()?;
+        Ok(orphans.len())
+    }
+
+    /// Writes the forks off the main chain with the length of their longest stale branch to a CSV file.
+    /// Returns the number of written forks.
+    pub fn write_reorgs(&self, path: &Path) -> OpResult<usize> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(b"fork_height;orphan_branch_length;main_branch_hash\n")?;
+        let reorgs = self.chain_index.reorgs();
+        for reorg in &reorgs {
+            writer.write_all(
+                format!(
+                    "{};{};{}\n",
+                    reorg.fork_height,
+                    reorg.branch_length,
+                    reorg
+                        .main_branch_hash
+                        .as_ref()
+                        .map_or(String::new(), |hash| self.hash_byte_order.hex(hash))
+                )
+                .as_bytes(),
+            )?;
+        }
+        writer.flush()?;
+        Ok(reorgs.len())
+    }
+
+    /// Byte order of hashes in the output, see `--hash-byte-order`
+    pub fn hash_byte_order(&self) -> utils::HashByteOrder {
+        self.hash_byte_order
+    }
+
+    /// Returns the SHA256 over all blocks read so far, if enabled.
+    /// Blocks are hashed in the order they were read, without magic bytes and size prefix.
+    pub fn fingerprint(&self) -> Option<sha256::Hash> {
+        self.fingerprint
+            .as_ref()
+            .map(|engine| sha256::Hash::from_engine(engine.clone()))
+    }
+
+    /// Returns the heights of the main chain blocks from `start_height` to the tip
+    /// in the order they are stored in the blk files
+    pub fn heights_in_disk_order(&self, start_height: u64) -> Vec<u64> {
+        self.chain_index.heights_in_disk_order(start_height)
+    }
+
+    /// Returns the height of the given block if it is part of the main chain
+    pub fn height_of(&self, block_hash: &sha256d::Hash) -> Option<u64> {
+        self.chain_index.height_of(block_hash)
+    }
+
+    /// Returns the hash of the main chain block at the given height
+    pub fn hash_at(&self, height: u64) -> Option<sha256d::Hash> {
+        self.chain_index.get(height).map(|record| record.block_hash)
+    }
+
     pub(crate) fn max_height(&self) -> u64 {
         self.chain_index.max_height()
     }
 }
+
+/// Maps txids to the height of their block, used to serve transactions by txid
+#[cfg(any(feature = "grpc", feature = "rest"))]
+pub struct TxidIndex(HashMap<sha256d::Hash, u64>);
+
+#[cfg(any(feature = "grpc", feature = "rest"))]
+impl TxidIndex {
+    /// Reads all blocks from the given height up to the tip
+    pub fn build(chain_storage: &mut ChainStorage, start_height: u64) -> OpResult<Self> {
+        info!(target: "chain", "Building txid index from height {} ...", start_height);
+        let mut txids = HashMap::new();
+        let mut height = start_height;
+        while let Some(block) = chain_storage.get_block(height)? {
+            txids.extend(block.txs.iter().map(|tx| (tx.hash, height)));
+            height += 1;
+        }
+        info!(target: "chain", "Indexed {} transactions up to height {}", txids.len(), height.saturating_sub(1));
+        Ok(Self(txids))
+    }
+
+    /// Returns the height of the block containing the transaction
+    pub fn height(&self, txid: &sha256d::Hash) -> Option<u64> {
+        self.0.get(txid).copied()
+    }
+}
+
+/// Result of a dry run over the chain
+#[derive(Debug, Default)]
+pub struct ChainReport {
+    pub tip_height: u64,
+    // Number and total size of readable blocks
+    pub blocks: u64,
+    pub size: u64,
+    // Heights which are not indexed or not readable
+    pub missing: u64,
+    pub first_missing: Option<u64>,
+    // First height whose prev_hash doesn't match the previous block
+    pub first_broken_link: Option<u64>,
+}
+
+impl ChainReport {
+    /// Returns true if the chain is complete and continuous
+    pub fn is_ok(&self) -> bool {
+        self.first_missing.is_none() && self.first_broken_link.is_none()
+    }
+}
+
+impl fmt::Display for ChainReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "\n\t-> tip height:    {}\n\t-> blocks:        {}\n\t-> size:          {} bytes\n\t-> missing:       {}",
+            self.tip_height, self.blocks, self.size, self.missing
+        )?;
+        if let Some(height) = self.first_missing {
+            write!(f, "\n\t-> first missing: {}", height)?;
+        }
+        if let Some(height) = self.first_broken_link {
+            write!(f, "\n\t-> broken link:   {}", height)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,302 @@
+use std::convert::TryInto;
+use std::io::{Cursor, Read};
+use std::path::Path;
+
+use bitcoin::hashes::{sha256d, Hash};
+use bitcoin::secp256k1::PublicKey;
+use rusty_leveldb::{LdbIterator, Options, DB};
+
+use crate::blockchain::parser::index::read_varint;
+use crate::blockchain::proto::script::AddressFormat;
+use crate::blockchain::proto::tx::{EvaluatedTxOut, TxOutpoint, TxOutput};
+use crate::blockchain::proto::varuint::VarUint;
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+const DB_COIN: u8 = b'C';
+const DB_BEST_BLOCK: u8 = b'B';
+const OBFUSCATE_KEY: &[u8] = b"\x0e\x00obfuscate_key";
+
+/// Scripts above this size are replaced by OP_RETURN, as in Bitcoin Core
+const MAX_SCRIPT_SIZE: u64 = 10000;
+/// Number of special script types in the script compression
+const SPECIAL_SCRIPTS: u64 = 6;
+
+/// Unspent output read from a UTXO snapshot
+pub struct SnapshotUtxo {
+    pub outpoint: TxOutpoint,
+    pub height: u64,
+    pub is_coinbase: bool,
+    pub output: EvaluatedTxOut,
+}
+
+/// Reads the UTXO set from the `chainstate` LevelDB of Bitcoin Core (v0.15 and later).
+/// The node must not be running while the database is read.
+pub struct Chainstate {
+    db: DB,
+    obfuscation_key: Vec<u8>,
+}
+
+impl Chainstate {
+    pub fn open(path: &Path) -> OpResult<Self> {
+        info!(target: "chainstate", "Reading chainstate from {} ...", path.display());
+        let options = Options {
+            create_if_missing: false,
+            ..Default::default()
+        };
+        let mut db = DB::open(path, options)?;
+        // Stored as a vector with a length prefix, databases without key are not obfuscated
+        let obfuscation_key = match db.get(OBFUSCATE_KEY) {
+            Some(value) if !value.is_empty() => value[1..].to_vec(),
+            _ => Vec::new(),
+        };
+        Ok(Self {
+            db,
+            obfuscation_key,
+        })
+    }
+
+    /// Returns the hash of the block up to which the UTXO set is valid
+    pub fn best_block(&mut self) -> OpResult<sha256d::Hash> {
+        let value = match self.db.get(&[DB_BEST_BLOCK]) {
+            Some(value) => self.deobfuscate(&value),
+            None => {
+                return Err(OpError::new(OpErrorKind::RuntimeError)
+                    .join_msg("chainstate: best block is missing"))
+            }
+        };
+        let hash: [u8; 32] = value.as_slice().try_into().map_err(|_| {
+            OpError::new(OpErrorKind::RuntimeError).join_msg("chainstate: malformed best block")
+        })?;
+        Ok(sha256d::Hash::from_byte_array(hash))
+    }
+
+    /// Calls `f` for each unspent output in the database.
+    /// Returns the number of unspent outputs.
+    pub fn for_each_utxo<F>(&mut self, format: AddressFormat, mut f: F) -> OpResult<u64>
+    where
+        F: FnMut(SnapshotUtxo) -> OpResult<()>,
+    {
+        let mut iter = self.db.new_iter()?;
+        let (mut key, mut value) = (vec![], vec![]);
+        let mut count = 0;
+        iter.seek(&[DB_COIN]);
+        while iter.valid() {
+            iter.current(&mut key, &mut value);
+            if key.first() != Some(&DB_COIN) {
+                break;
+            }
+            let utxo = decode_coin(&key[1..], &self.deobfuscate(&value), format)?;
+            f(utxo)?;
+            count += 1;
+            iter.advance();
+        }
+        Ok(count)
+    }
+
+    /// XORs the value with the repeated obfuscation key
+    fn deobfuscate(&self, value: &[u8]) -> Vec<u8> {
+        if self.obfuscation_key.is_empty() {
+            return value.to_vec();
+        }
+        value
+            .iter()
+            .zip(self.obfuscation_key.iter().cycle())
+            .map(|(byte, key)| byte ^ key)
+            .collect()
+    }
+}
+
+/// Decodes a coin from its key (txid + VARINT(vout)) and deobfuscated value:
+/// VARINT(height * 2 + coinbase), VARINT(compressed amount), compressed script
+fn decode_coin(key: &[u8], value: &[u8], format: AddressFormat) -> OpResult<SnapshotUtxo> {
+    if key.len() < 33 {
+        return Err(
+            OpError::new(OpErrorKind::RuntimeError).join_msg("chainstate: malformed coin key")
+        );
+    }
+    let txid: [u8; 32] = key[..32].try_into().unwrap();
+    let index = read_varint(&mut Cursor::new(&key[32..]))?;
+
+    let mut reader = Cursor::new(value);
+    let code = read_varint(&mut reader)?;
+    let amount = decompress_amount(read_varint(&mut reader)?);
+    let script_pubkey = decompress_script(&mut reader)?;
+    let output = TxOutput {
+        value: amount,
+        script_len: VarUint::from(script_pubkey.len() as u64),
+        script_pubkey,
+    };
+    Ok(SnapshotUtxo {
+        outpoint: TxOutpoint::new(sha256d::Hash::from_byte_array(txid), index as u32),
+        height: code >> 1,
+        is_coinbase: code & 1 == 1,
+        output: EvaluatedTxOut::eval_script(output, format),
+    })
+}
+
+/// Reverses the amount compression of Bitcoin Core (see `CompressAmount()`)
+fn decompress_amount(x: u64) -> u64 {
+    if x == 0 {
+        return 0;
+    }
+    let mut x = x - 1;
+    let mut e = x % 10;
+    x /= 10;
+    let mut n = if e < 9 {
+        let d = (x % 9) + 1;
+        x /= 9;
+        x * 10 + d
+    } else {
+        x + 1
+    };
+    while e > 0 {
+        n *= 10;
+        e -= 1;
+    }
+    n
+}
+
+/// Reverses the script compression of Bitcoin Core (see `ScriptCompression`).
+/// The first VARINT is either a special script type (P2PKH, P2SH, P2PK)
+/// or the length of the raw script plus the number of special types.
+fn decompress_script(reader: &mut Cursor<&[u8]>) -> OpResult<Vec<u8>> {
+    let size = read_varint(reader)?;
+    let mut read = |len: usize| -> OpResult<Vec<u8>> {
+        let mut data = vec![0u8; len];
+        reader.read_exact(&mut data)?;
+        Ok(data)
+    };
+    let script = match size {
+        0 => [&[0x76, 0xa9, 0x14], read(20)?.as_slice(), &[0x88, 0xac]].concat(),
+        1 => [&[0xa9, 0x14], read(20)?.as_slice(), &[0x87]].concat(),
+        2 | 3 => [&[0x21, size as u8], read(32)?.as_slice(), &[0xac]].concat(),
+        4 | 5 => {
+            let compressed = [&[size as u8 - 2], read(32)?.as_slice()].concat();
+            let pubkey = PublicKey::from_slice(&compressed).map_err(|e| {
+                OpError::new(OpErrorKind::RuntimeError)
+                    .join_msg(&format!("chainstate: invalid public key: {}", e))
+            })?;
+            [&[0x41], &pubkey.serialize_uncompressed()[..], &[0xac]].concat()
+        }
+        size if size - SPECIAL_SCRIPTS > MAX_SCRIPT_SIZE => {
+            read((size - SPECIAL_SCRIPTS) as usize)?;
+            vec![0x6a]
+        }
+        size => read((size - SPECIAL_SCRIPTS) as usize)?,
+    };
+    Ok(script)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::script::ScriptPattern;
+    use crate::common::utils;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_decompress_amount() {
+        // Test vectors from Bitcoin Core's compress_tests.cpp
+        for (compressed, amount) in [
+            (0x0, 0),
+            (0x1, 1),
+            (0x7, 1000000),
+            (0x9, 100000000),
+            (0x32, 5000000000),
+            (0x1406f40, 2100000000000000),
+        ] {
+            assert_eq!(decompress_amount(compressed), amount);
+        }
+    }
+
+    #[test]
+    fn test_decompress_script() {
+        let hash = "62e907b15cbf27d5425399ebf6f0fb50ebb88f18";
+        let decompress = |hex: &str| {
+            let data = utils::hex_to_vec(hex);
+            utils::arr_to_hex(&decompress_script(&mut Cursor::new(&data)).unwrap())
+        };
+        assert_eq!(
+            decompress(&format!("00{}", hash)),
+            format!("76a914{}88ac", hash)
+        );
+        assert_eq!(
+            decompress(&format!("01{}", hash)),
+            format!("a914{}87", hash)
+        );
+        // Raw script with 2 bytes
+        assert_eq!(decompress("08516a"), "516a");
+
+        // Uncompressed public key of the genesis coinbase, its y coordinate is odd
+        let x = "678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb6";
+        assert_eq!(
+            decompress(&format!("05{}", x)),
+            concat!(
+                "4104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb6",
+                "49f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac"
+            )
+        );
+        assert_eq!(decompress(&format!("03{}", x)), format!("2103{}ac", x));
+    }
+
+    #[test]
+    fn test_read_chainstate() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = [0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88];
+        let obfuscate = |value: &[u8]| -> Vec<u8> {
+            value
+                .iter()
+                .zip(key.iter().cycle())
+                .map(|(byte, key)| byte ^ key)
+                .collect()
+        };
+        let best_block = sha256d::Hash::from_str(
+            "00000000839a8e6886ab5951d76f411475428afc90947ee320161bbf18eb6048",
+        )
+        .unwrap();
+        let txid = sha256d::Hash::from_str(
+            "0e3e2357e806b6cdb1f70b54c3a3a17b6714ee1f0e68bebb44a74b1efd512098",
+        )
+        .unwrap();
+        {
+            let mut db = DB::open(dir.path(), Options::default()).unwrap();
+            db.put(OBFUSCATE_KEY, &[&[0x08][..], &key].concat())
+                .unwrap();
+            db.put(&[DB_BEST_BLOCK], &obfuscate(best_block.as_byte_array()))
+                .unwrap();
+            // Coinbase output of block 1 (50 BTC to a compressed P2PK script) at vout 0
+            let coin_key = [&[DB_COIN][..], txid.as_byte_array(), &[0x00]].concat();
+            let coin = utils::hex_to_vec(concat!(
+                "03",
+                "32",
+                "04",
+                "96b538e853519c726a2c91e61ec11600ae1390813a627c66fb8be7947be63c52"
+            ));
+            db.put(&coin_key, &obfuscate(&coin)).unwrap();
+            // Unrelated record after the coins
+            db.put(b"Z", b"").unwrap();
+            db.close().unwrap();
+        }
+
+        let mut chainstate = Chainstate::open(dir.path()).unwrap();
+        assert_eq!(chainstate.best_block().unwrap(), best_block);
+        let mut utxos = Vec::new();
+        let count = chainstate
+            .for_each_utxo(AddressFormat::default(), |utxo| {
+                utxos.push(utxo);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(count, 1);
+        let utxo = &utxos[0];
+        assert_eq!(utxo.outpoint, TxOutpoint::new(txid, 0));
+        assert_eq!(utxo.height, 1);
+        assert!(utxo.is_coinbase);
+        assert_eq!(utxo.output.out.value, 5000000000);
+        assert_eq!(utxo.output.script.pattern, ScriptPattern::Pay2PublicKey);
+        assert_eq!(
+            utxo.output.script.address.as_deref(),
+            Some("12c6DSiU4Rq3P4ZxziKxzrL5LmMBrzjrJX")
+        );
+    }
+}
@@ -0,0 +1,150 @@
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use bitcoin::hashes::{sha256d, Hash};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::callbacks::Callback;
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+/// Identifies checkpoint files and their format version
+const MAGIC: &[u8; 8] = b"rbpchk01";
+
+/// Last fully processed block of a checkpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CheckpointHeader {
+    pub height: u64,
+    pub block_hash: sha256d::Hash,
+}
+
+/// File holding the last fully processed block and the serialized callback state,
+/// so an interrupted run can continue after that block.
+/// The file is replaced atomically, an interruption while saving keeps the previous checkpoint.
+pub struct Checkpoint {
+    path: PathBuf,
+    // Save every N blocks
+    pub interval: u64,
+}
+
+impl Checkpoint {
+    pub fn new(path: PathBuf, interval: u64) -> Self {
+        Self { path, interval }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Writes the given block and the state of the callback
+    pub fn save(&self, header: CheckpointHeader, callback: &dyn Callback) -> OpResult<()> {
+        let tmp_path = PathBuf::from(format!("{}.tmp", self.path.display()));
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        writer.write_all(MAGIC)?;
+        writer.write_u64::<LittleEndian>(header.height)?;
+        writer.write_all(header.block_hash.as_byte_array())?;
+        callback.save_state(&mut writer)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+
+    /// Opens the checkpoint and reads its header. The returned reader is positioned at the callback state.
+    /// Returns None if there is no checkpoint yet.
+    pub fn load(&self) -> OpResult<Option<(CheckpointHeader, impl Read)>> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(OpError::from(err)),
+        };
+        let mut reader = BufReader::new(file);
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(OpError::new(OpErrorKind::RuntimeError)
+                .join_msg(&format!("{} is not a checkpoint file", self.path.display())));
+        }
+        let height = reader.read_u64::<LittleEndian>()?;
+        let mut hash = [0u8; 32];
+        reader.read_exact(&mut hash)?;
+        let header = CheckpointHeader {
+            height,
+            block_hash: sha256d::Hash::from_byte_array(hash),
+        };
+        Ok(Some((header, reader)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::block::Block;
+    use crate::callbacks::BlockFlow;
+    use clap::{ArgMatches, Command};
+
+    /// Saves and restores a counter
+    struct Counter {
+        count: u64,
+    }
+
+    impl Callback for Counter {
+        fn build_subcommand() -> Command {
+            Command::new("counter")
+        }
+
+        fn new(_: &ArgMatches) -> OpResult<Self> {
+            Ok(Self { count: 0 })
+        }
+
+        fn on_start(&mut self, _: u64) -> OpResult<()> {
+            Ok(())
+        }
+
+        fn on_block(&mut self, _: &Block, _: u64) -> OpResult<BlockFlow> {
+            Ok(BlockFlow::Continue)
+        }
+
+        fn on_complete(&mut self, _: u64) -> OpResult<()> {
+            Ok(())
+        }
+
+        fn supports_checkpoint(&self) -> bool {
+            true
+        }
+
+        fn save_state(&self, writer: &mut dyn Write) -> OpResult<()> {
+            writer.write_u64::<LittleEndian>(self.count)?;
+            Ok(())
+        }
+
+        fn load_state(&mut self, reader: &mut dyn Read) -> OpResult<()> {
+            self.count = reader.read_u64::<LittleEndian>()?;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_checkpoint_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let checkpoint = Checkpoint::new(dir.path().join("checkpoint"), 1);
+        assert!(checkpoint.load().unwrap().is_none());
+
+        let header = CheckpointHeader {
+            height: 42,
+            block_hash: sha256d::Hash::hash(b"block"),
+        };
+        checkpoint.save(header, &Counter { count: 7 }).unwrap();
+        assert!(!dir.path().join("checkpoint.tmp").exists());
+
+        let mut restored = Counter { count: 0 };
+        let (loaded, mut reader) = checkpoint.load().unwrap().unwrap();
+        restored.load_state(&mut reader).unwrap();
+        assert_eq!(loaded, header);
+        assert_eq!(restored.count, 7);
+
+        // Files without the magic bytes are rejected
+        fs::write(dir.path().join("checkpoint"), b"not a checkpoint file").unwrap();
+        assert!(checkpoint.load().is_err());
+    }
+}
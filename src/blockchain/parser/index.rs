@@ -1,5 +1,6 @@
 use bitcoin::hashes::{sha256d, Hash};
-use std::collections::HashMap;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
 use std::convert::TryInto;
 use std::fmt;
 use std::io::Cursor;
@@ -8,23 +9,85 @@ use std::path::Path;
 use byteorder::ReadBytesExt;
 use rusty_leveldb::{LdbIterator, Options, DB};
 
-use crate::errors::OpResult;
+use crate::blockchain::parser::blkfile::BlkFile;
+use crate::blockchain::parser::types::CoinType;
+use crate::blockchain::parser::ProgressLog;
+use crate::blockchain::proto::header::BlockHeader;
+use crate::blockchain::proto::Hashed;
+use crate::errors::{OpError, OpErrorKind, OpResult};
 use crate::ParserOptions;
 
 const BLOCK_VALID_CHAIN: u64 = 4;
 const BLOCK_HAVE_DATA: u64 = 8;
+const BLOCK_FAILED_MASK: u64 = 32 | 64;
+
+/// blk_index assigned to the file passed with `--extra-blocks`
+pub const EXTRA_BLK_INDEX: u64 = u64::MAX;
 
 /// Holds the index of longest valid chain
 pub struct ChainIndex {
     max_height: u64,
     block_index: HashMap<u64, BlockIndexRecord>,
     max_height_blk_index: HashMap<u64, u64>, // Maps blk_index to max_height found in the file
+    orphans: Vec<OrphanRecord>,
+}
+
+/// A stale block which forks off the main chain
+#[derive(Debug, PartialEq)]
+pub struct OrphanRecord {
+    pub block_hash: sha256d::Hash,
+    pub prev_hash: sha256d::Hash,
+    pub height: u64,
+    // Main chain block at the same height, None if the stale branch is longer than the main chain
+    pub main_chain_hash: Option<sha256d::Hash>,
+}
+
+/// A fork off the main chain, reconstructed from the orphans
+#[derive(Debug, PartialEq)]
+pub struct ReorgRecord {
+    // Height of the last block shared by the main chain and the stale branch
+    pub fork_height: u64,
+    // Number of blocks in the longest stale branch forking off at this height
+    pub branch_length: u64,
+    // Main chain block which displaced the first block of the stale branch
+    pub main_branch_hash: Option<sha256d::Hash>,
 }
 
 impl ChainIndex {
-    pub fn new(options: &ParserOptions) -> OpResult<Self> {
+    /// Reads the index from leveldb. Blocks in `extra` are appended to the tip.
+    pub fn new(options: &ParserOptions, extra: Option<&mut BlkFile>) -> OpResult<Self> {
         let path = options.blockchain_dir.join("index");
-        let mut block_index = get_block_index(&path)?;
+        let (block_index, orphans) = select_main_chain(get_block_index(&path)?);
+        info!(target: "index", "Got longest chain with {} blocks ...", block_index.len());
+        Self::from_block_index(block_index, orphans, extra, options)
+    }
+
+    /// Builds the index by scanning a single file with concatenated blocks.
+    /// Heights are relative to the first block of the chain found in the file.
+    /// Blocks in `extra` are appended to the tip.
+    pub fn from_blk_file(
+        blk_file: &mut BlkFile,
+        extra: Option<&mut BlkFile>,
+        options: &ParserOptions,
+    ) -> OpResult<Self> {
+        let headers = blk_file.scan_headers(&options.coin)?;
+        if headers.is_empty() {
+            let msg = format!("No blocks found in {}!", blk_file.path.display());
+            return Err(OpError::new(OpErrorKind::RuntimeError).join_msg(&msg));
+        }
+        let (block_index, orphans) = chain_from_headers(&headers, 0);
+        Self::from_block_index(block_index, orphans, extra, options)
+    }
+
+    fn from_block_index(
+        mut block_index: HashMap<u64, BlockIndexRecord>,
+        orphans: Vec<OrphanRecord>,
+        extra: Option<&mut BlkFile>,
+        options: &ParserOptions,
+    ) -> OpResult<Self> {
+        if let Some(blk_file) = extra {
+            append_blk_file(&mut block_index, blk_file, &options.coin)?;
+        }
         let mut max_height_blk_index = HashMap::new();
 
         for (height, index_record) in &block_index {
@@ -41,13 +104,23 @@ impl ChainIndex {
 
         let min_height = options.range.start;
         let max_known_height = *block_index.keys().max().unwrap();
+        for (arg, height) in [("--start", Some(min_height)), ("--end", options.range.end)] {
+            if let Some(height) = height.filter(|height| *height > max_known_height) {
+                let msg = format!(
+                    "{} height {} is past the chain tip at height {}",
+                    arg, height, max_known_height
+                );
+                return Err(OpError::new(OpErrorKind::InvalidArgsError).join_msg(&msg));
+            }
+        }
+        let confirmed_height = max_known_height.saturating_sub(options.keep_confirmations);
         let max_height = match options.range.end {
-            Some(height) if height < max_known_height => height,
-            Some(_) | None => max_known_height,
+            Some(height) if height < confirmed_height => height,
+            Some(_) | None => confirmed_height,
         };
 
         // Filter to only keep relevant block index
-        if !options.range.is_default() {
+        if !options.range.is_default() || options.keep_confirmations > 0 {
             info!(target: "index", "Trimming block index from height {} to {} ...", min_height, max_height);
             block_index.retain(|height, _| {
                 *height >= min_height.saturating_sub(1) && *height <= max_height
@@ -58,6 +131,7 @@ impl ChainIndex {
             max_height,
             block_index,
             max_height_blk_index,
+            orphans,
         })
     }
 
@@ -71,16 +145,68 @@ impl ChainIndex {
         self.max_height
     }
 
+    /// Returns the heights of all blocks from `start_height` to `max_height` ordered by their position in the blk files
+    pub fn heights_in_disk_order(&self, start_height: u64) -> Vec<u64> {
+        let mut records: Vec<(&u64, &BlockIndexRecord)> = self
+            .block_index
+            .iter()
+            .filter(|(height, _)| **height >= start_height && **height <= self.max_height)
+            .collect();
+        records.sort_unstable_by_key(|(_, record)| (record.blk_index, record.data_offset));
+        records.into_iter().map(|(height, _)| *height).collect()
+    }
+
     /// Returns the maximum height that can be found in the given blk_index
     pub fn max_height_by_blk(&self, blk_index: u64) -> u64 {
         *self.max_height_blk_index.get(&blk_index).unwrap()
     }
+
+    /// Returns the height of the given block if it is part of the main chain
+    pub fn height_of(&self, block_hash: &sha256d::Hash) -> Option<u64> {
+        self.block_index
+            .values()
+            .find(|record| record.block_hash == *block_hash)
+            .map(|record| record.height)
+    }
+
+    /// Returns the stale blocks which were discarded during main chain selection, ordered by height
+    pub fn orphans(&self) -> &[OrphanRecord] {
+        &self.orphans
+    }
+
+    /// Returns the forks off the main chain ordered by fork height.
+    /// Stale branches are followed by their prev_hash links, branches forking off
+    /// at the same height are merged and the longest one determines the length.
+    pub fn reorgs(&self) -> Vec<ReorgRecord> {
+        let by_hash: HashMap<sha256d::Hash, &OrphanRecord> = self
+            .orphans
+            .iter()
+            .map(|orphan| (orphan.block_hash, orphan))
+            .collect();
+        let mut reorgs: BTreeMap<u64, ReorgRecord> = BTreeMap::new();
+        for orphan in &self.orphans {
+            // Orphans always connect to the main chain, so the walk ends at the first stale block
+            let mut root = orphan;
+            while let Some(parent) = by_hash.get(&root.prev_hash) {
+                root = parent;
+            }
+            let fork_height = root.height.saturating_sub(1);
+            let reorg = reorgs.entry(fork_height).or_insert(ReorgRecord {
+                fork_height,
+                branch_length: 0,
+                main_branch_hash: root.main_chain_hash,
+            });
+            reorg.branch_length = reorg.branch_length.max(orphan.height - fork_height);
+        }
+        reorgs.into_values().collect()
+    }
 }
 
 /// Holds the metadata where the block data is stored,
 /// See https://bitcoin.stackexchange.com/questions/28168/what-are-the-keys-used-in-the-blockchain-leveldb-ie-what-are-the-keyvalue-pair
 pub struct BlockIndexRecord {
     pub block_hash: sha256d::Hash,
+    pub prev_hash: sha256d::Hash,
     pub blk_index: u64,
     pub data_offset: u64, // offset within the blk file
     version: u64,
@@ -90,6 +216,25 @@ pub struct BlockIndexRecord {
 }
 
 impl BlockIndexRecord {
+    fn new(
+        block_hash: sha256d::Hash,
+        prev_hash: sha256d::Hash,
+        height: u64,
+        blk_index: u64,
+        data_offset: u64,
+    ) -> Self {
+        BlockIndexRecord {
+            block_hash,
+            prev_hash,
+            blk_index,
+            data_offset,
+            version: 0,
+            height,
+            status: BLOCK_HAVE_DATA,
+            tx_count: 0,
+        }
+    }
+
     fn from(key: &[u8], values: &[u8]) -> OpResult<Self> {
         let mut reader = Cursor::new(values);
 
@@ -101,8 +246,19 @@ impl BlockIndexRecord {
         let blk_index = read_varint(&mut reader)?;
         let data_offset = read_varint(&mut reader)?;
 
+        // The serialized block header is stored at the end of the record
+        let header = match values.len().checked_sub(80) {
+            Some(start) => &values[start..],
+            None => {
+                return Err(OpError::new(OpErrorKind::RuntimeError)
+                    .join_msg("leveldb: block index record without header"))
+            }
+        };
+        let prev_hash: [u8; 32] = header[4..36].try_into().unwrap();
+
         Ok(BlockIndexRecord {
             block_hash: sha256d::Hash::from_byte_array(block_hash),
+            prev_hash: sha256d::Hash::from_byte_array(prev_hash),
             version,
             height,
             status,
@@ -117,6 +273,7 @@ impl fmt::Debug for BlockIndexRecord {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("BlockIndexRecord")
             .field("block_hash", &self.block_hash)
+            .field("prev_hash", &self.prev_hash)
             .field("version", &self.version)
             .field("height", &self.height)
             .field("status", &self.status)
@@ -127,26 +284,196 @@ impl fmt::Debug for BlockIndexRecord {
     }
 }
 
-pub fn get_block_index(path: &Path) -> OpResult<HashMap<u64, BlockIndexRecord>> {
+/// Reads all block index records with data, including stale blocks
+pub fn get_block_index(path: &Path) -> OpResult<Vec<BlockIndexRecord>> {
     info!(target: "index", "Reading index from {} ...", path.display());
 
-    let mut block_index = HashMap::with_capacity(900000);
+    let mut block_index = Vec::with_capacity(900000);
     let mut db_iter = DB::open(path, Options::default())?.new_iter()?;
     let (mut key, mut value) = (vec![], vec![]);
+    // Keys are sorted and block hashes are uniformly distributed,
+    // so the first hash byte tells how much of the index has been read
+    let mut progress = ProgressLog::new("index", String::from("Reading index ..."));
 
     while db_iter.advance() {
         db_iter.current(&mut key, &mut value);
         if is_block_index_record(&key) {
+            progress.update(key.get(1).copied().unwrap_or(0).into(), 256);
             let record = BlockIndexRecord::from(&key[1..], &value)?;
             if record.status & (BLOCK_VALID_CHAIN | BLOCK_HAVE_DATA) > 0 {
-                block_index.insert(record.height, record);
+                block_index.push(record);
             }
         }
     }
-    info!(target: "index", "Got longest chain with {} blocks ...", block_index.len());
     Ok(block_index)
 }
 
+/// Selects the main chain by walking back the prev_hash links from the highest block.
+/// Blocks marked as failed can't be the tip, on equal height the first record wins.
+/// All other blocks which connect to the main chain are returned as orphans,
+/// blocks without a known ancestor in the main chain are dropped.
+fn select_main_chain(
+    records: Vec<BlockIndexRecord>,
+) -> (HashMap<u64, BlockIndexRecord>, Vec<OrphanRecord>) {
+    let by_hash: HashMap<sha256d::Hash, usize> = records
+        .iter()
+        .enumerate()
+        .map(|(i, record)| (record.block_hash, i))
+        .collect();
+
+    // None = unknown, Some(true) = main chain or connected to it
+    let mut connected: Vec<Option<bool>> = vec![None; records.len()];
+    let mut in_main = vec![false; records.len()];
+    let mut cur = records
+        .iter()
+        .enumerate()
+        .filter(|(_, record)| record.status & BLOCK_FAILED_MASK == 0)
+        .max_by_key(|(i, record)| (record.height, Reverse(*i)))
+        .map(|(i, _)| i);
+    while let Some(i) = cur {
+        in_main[i] = true;
+        connected[i] = Some(true);
+        cur = by_hash.get(&records[i].prev_hash).copied();
+    }
+
+    for start in 0..records.len() {
+        let mut path = Vec::new();
+        let mut cur = Some(start);
+        while let Some(i) = cur {
+            if connected[i].is_some() {
+                break;
+            }
+            path.push(i);
+            cur = by_hash.get(&records[i].prev_hash).copied();
+        }
+        let is_connected = cur.is_some_and(|i| connected[i].unwrap());
+        for i in path {
+            connected[i] = Some(is_connected);
+        }
+    }
+
+    let mut block_index = HashMap::with_capacity(records.len());
+    let mut stale = Vec::new();
+    for (i, record) in records.into_iter().enumerate() {
+        if in_main[i] {
+            block_index.insert(record.height, record);
+        } else if connected[i] == Some(true) {
+            stale.push(record);
+        }
+    }
+    let mut orphans: Vec<OrphanRecord> = stale
+        .into_iter()
+        .map(|record| OrphanRecord {
+            block_hash: record.block_hash,
+            prev_hash: record.prev_hash,
+            height: record.height,
+            main_chain_hash: block_index.get(&record.height).map(|r| r.block_hash),
+        })
+        .collect();
+    orphans.sort_by_key(|orphan| (orphan.height, orphan.block_hash));
+    (block_index, orphans)
+}
+
+/// Orders scanned headers by following the prev_hash links of the longest chain.
+/// The first block of the chain gets assigned to height 0.
+fn chain_from_headers(
+    headers: &[(u64, Hashed<BlockHeader>)],
+    blk_index: u64,
+) -> (HashMap<u64, BlockIndexRecord>, Vec<OrphanRecord>) {
+    let by_hash: HashMap<sha256d::Hash, usize> = headers
+        .iter()
+        .enumerate()
+        .map(|(i, (_, header))| (header.hash, i))
+        .collect();
+
+    // Compute the height of each block relative to its chain root
+    let mut heights: Vec<Option<u64>> = vec![None; headers.len()];
+    for start in 0..headers.len() {
+        let mut path = Vec::new();
+        let mut cur = Some(start);
+        while let Some(i) = cur {
+            if heights[i].is_some() {
+                break;
+            }
+            path.push(i);
+            cur = by_hash.get(&headers[i].1.value.prev_hash).copied();
+        }
+        let base = cur.map_or(0, |i| heights[i].unwrap() + 1);
+        for (height, i) in (base..).zip(path.into_iter().rev()) {
+            heights[i] = Some(height);
+        }
+    }
+
+    let records = headers
+        .iter()
+        .zip(heights)
+        .map(|((offset, header), height)| {
+            BlockIndexRecord::new(
+                header.hash,
+                header.value.prev_hash,
+                height.unwrap(),
+                blk_index,
+                *offset,
+            )
+        })
+        .collect();
+    select_main_chain(records)
+}
+
+/// Appends the longest chain found in the given file onto the block index.
+/// The first block of that chain has to be known or connect to a block in the index,
+/// blocks which are already known are skipped.
+fn append_blk_file(
+    block_index: &mut HashMap<u64, BlockIndexRecord>,
+    blk_file: &mut BlkFile,
+    coin: &CoinType,
+) -> OpResult<()> {
+    let headers = blk_file.scan_headers(coin)?;
+    if headers.is_empty() {
+        warn!(target: "index", "No extra blocks found in {}", blk_file.path.display());
+        return Ok(());
+    }
+    let (extra_index, _) = chain_from_headers(&headers, EXTRA_BLK_INDEX);
+    let first = extra_index.get(&0).unwrap();
+    let (first_hash, prev_hash) = (first.block_hash, first.prev_hash);
+    // The file may overlap with the known chain, so the first block itself may be known already
+    let known = |hash| block_index.values().find(|r| r.block_hash == hash);
+    let base = match (known(first_hash), known(prev_hash)) {
+        (Some(record), _) => record.height,
+        (None, Some(record)) => record.height + 1,
+        (None, None) => {
+            let msg = format!(
+                "Extra blocks in {} don't connect to the chain (unknown prev_hash {})",
+                blk_file.path.display(),
+                prev_hash
+            );
+            return Err(OpError::new(OpErrorKind::ValidationError).join_msg(&msg));
+        }
+    };
+
+    let mut appended = 0;
+    for (offset, mut record) in extra_index {
+        let height = base + offset;
+        match block_index.get(&height) {
+            Some(known) if known.block_hash == record.block_hash => continue,
+            Some(known) => {
+                let msg = format!(
+                    "Extra block {} forks off the chain at height {} (known: {})",
+                    record.block_hash, height, known.block_hash
+                );
+                return Err(OpError::new(OpErrorKind::ValidationError).join_msg(&msg));
+            }
+            None => {
+                record.height = height;
+                block_index.insert(height, record);
+                appended += 1;
+            }
+        }
+    }
+    info!(target: "index", "Appended {} extra blocks from {}", appended, blk_file.path.display());
+    Ok(())
+}
+
 #[inline]
 fn is_block_index_record(data: &[u8]) -> bool {
     *data.first().unwrap() == b'b'
@@ -154,7 +481,7 @@ fn is_block_index_record(data: &[u8]) -> bool {
 
 /// TODO: this is a wonky 1:1 translation from https://github.com/bitcoin/bitcoin
 /// It is NOT the same as CompactSize.
-fn read_varint(reader: &mut Cursor<&[u8]>) -> OpResult<u64> {
+pub(super) fn read_varint(reader: &mut Cursor<&[u8]>) -> OpResult<u64> {
     let mut n = 0;
     loop {
         let ch_data = reader.read_u8()?;
@@ -173,3 +500,666 @@ fn read_varint(reader: &mut Cursor<&[u8]>) -> OpResult<u64> {
     }
     Ok(n)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::parser::chain::ChainStorage;
+    use crate::blockchain::parser::tests::{block1, child_of, genesis, StopAfter};
+    use crate::blockchain::parser::BlockchainParser;
+    use crate::common::utils;
+    use bitcoin::hashes::sha256;
+    use std::cell::RefCell;
+    use std::io::Write;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_chain_from_concatenated_file() {
+        let genesis = genesis();
+        let block1 = block1();
+
+        // Store blocks out of order followed by zero padding
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&block1).unwrap();
+        file.write_all(&genesis).unwrap();
+        file.write_all(&[0u8; 128]).unwrap();
+        file.flush().unwrap();
+
+        let coin = CoinType::default();
+        let mut blk_file = BlkFile::from_file(file.path()).unwrap();
+        let headers = blk_file.scan_headers(&coin).unwrap();
+        assert_eq!(headers.len(), 2);
+
+        let (block_index, orphans) = chain_from_headers(&headers, 0);
+        assert_eq!(block_index.len(), 2);
+        assert!(orphans.is_empty());
+        let record = block_index.get(&0).unwrap();
+        assert_eq!(record.block_hash, coin.genesis_hash);
+        assert_eq!(record.data_offset, block1.len() as u64 + 8);
+        let record = block_index.get(&1).unwrap();
+        assert_eq!(
+            format!("{}", record.block_hash),
+            "00000000839a8e6886ab5951d76f411475428afc90947ee320161bbf18eb6048"
+        );
+        assert_eq!(record.data_offset, 8);
+
+        let block = blk_file.read_block(record.data_offset, &coin).unwrap();
+        assert_eq!(block.header.hash, record.block_hash);
+        assert_eq!(block.header.value.prev_hash, coin.genesis_hash);
+    }
+
+    #[test]
+    fn test_extra_blocks_continue_chain() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&genesis()).unwrap();
+        file.flush().unwrap();
+        let mut extra = tempfile::NamedTempFile::new().unwrap();
+        extra.write_all(&block1()).unwrap();
+        extra.flush().unwrap();
+
+        let options = crate::parse_args(crate::command().get_matches_from([
+            "rusty-blockparser",
+            "--verify",
+            "--block-file",
+            file.path().to_str().unwrap(),
+            "--extra-blocks",
+            extra.path().to_str().unwrap(),
+            "simplestats",
+        ]))
+        .unwrap();
+        let mut chain_storage = ChainStorage::new(&options).unwrap();
+        assert_eq!(chain_storage.max_height(), 1);
+
+        // Block 1 is read from the extra file and verified against the genesis block
+        let genesis = chain_storage.get_block(0).unwrap().unwrap();
+        let block1 = chain_storage.get_block(1).unwrap().unwrap();
+        assert_eq!(block1.header.value.prev_hash, genesis.header.hash);
+        assert!(chain_storage.get_block(2).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_verify_range_without_genesis() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&block1()).unwrap();
+        file.flush().unwrap();
+
+        let options = crate::parse_args(crate::command().get_matches_from([
+            "rusty-blockparser",
+            "--verify",
+            "--block-file",
+            file.path().to_str().unwrap(),
+            "simplestats",
+        ]))
+        .unwrap();
+        let mut chain_storage = ChainStorage::new(&options).unwrap();
+        assert_eq!(chain_storage.max_height(), 0);
+
+        // Block 1 is the root of the range and isn't checked against the genesis hash
+        let root = chain_storage.get_block(0).unwrap().unwrap();
+        assert_eq!(root.header.value.prev_hash, options.coin.genesis_hash);
+    }
+
+    #[test]
+    fn test_corrupt_block() {
+        // Script length of the coinbase input, read as 8 byte varint
+        let mut corrupt = block1();
+        corrupt[130] = 0xff;
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&genesis()).unwrap();
+        file.write_all(&corrupt).unwrap();
+        file.flush().unwrap();
+
+        let chain_storage = |strict: bool| {
+            let mut args = vec!["rusty-blockparser", "--block-file"];
+            args.push(file.path().to_str().unwrap());
+            if strict {
+                args.push("--strict");
+            }
+            args.push("simplestats");
+            let options = crate::parse_args(crate::command().get_matches_from(args)).unwrap();
+            ChainStorage::new(&options).unwrap()
+        };
+
+        // The chain ends before the corrupt block
+        let mut lenient = chain_storage(false);
+        assert_eq!(lenient.max_height(), 1);
+        assert!(lenient.get_block(0).unwrap().is_some());
+        assert!(lenient.get_block(1).unwrap().is_none());
+        assert_eq!(chain_storage(false).get_blocks(0, 2).unwrap().len(), 1);
+
+        let mut strict = chain_storage(true);
+        assert!(strict.get_block(0).unwrap().is_some());
+        for err in [
+            strict.get_block(1).unwrap_err(),
+            chain_storage(true).get_blocks(0, 2).unwrap_err(),
+        ] {
+            assert!(matches!(err.kind, OpErrorKind::ParseError(_)), "{}", err);
+            assert!(err.to_string().contains("height 1"));
+        }
+    }
+
+    #[test]
+    fn test_strict_compact_size() {
+        // Transaction count of 1 encoded as uint16 instead of a single byte
+        let mut block = block1();
+        assert_eq!(block[88], 0x01);
+        block.splice(88..89, [0xfd, 0x01, 0x00]);
+        let size = u32::from_le_bytes(block[4..8].try_into().unwrap()) + 2;
+        block[4..8].copy_from_slice(&size.to_le_bytes());
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&genesis()).unwrap();
+        file.write_all(&block).unwrap();
+        file.flush().unwrap();
+
+        let chain_storage = |strict: bool| {
+            let mut args = vec!["rusty-blockparser", "--block-file"];
+            args.push(file.path().to_str().unwrap());
+            if strict {
+                args.push("--strict");
+            }
+            args.push("simplestats");
+            let options = crate::parse_args(crate::command().get_matches_from(args)).unwrap();
+            ChainStorage::new(&options).unwrap()
+        };
+
+        let block = chain_storage(false).get_block(1).unwrap().unwrap();
+        assert_eq!(block.tx_count.value, 1);
+        let err = chain_storage(true).get_block(1).unwrap_err();
+        assert!(err.to_string().contains("Bad VarInt: 1"), "{}", err);
+    }
+
+    #[test]
+    fn test_max_script_size() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&genesis()).unwrap();
+        file.flush().unwrap();
+
+        let chain_storage = |max_script_size: &str| {
+            let mut args = vec!["rusty-blockparser", "--block-file"];
+            args.push(file.path().to_str().unwrap());
+            args.extend([
+                "--strict",
+                "--max-script-size",
+                max_script_size,
+                "simplestats",
+            ]);
+            let options = crate::parse_args(crate::command().get_matches_from(args)).unwrap();
+            ChainStorage::new(&options).unwrap()
+        };
+
+        // The scriptSig of the genesis coinbase is the largest script with 77 bytes
+        assert!(chain_storage("77").get_block(0).unwrap().is_some());
+        let err = chain_storage("76").get_block(0).unwrap_err();
+        assert!(
+            err.to_string().contains("Script too large: 77 bytes"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_keep_confirmations() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&genesis()).unwrap();
+        file.write_all(&block1()).unwrap();
+        file.flush().unwrap();
+
+        let options = crate::parse_args(crate::command().get_matches_from([
+            "rusty-blockparser",
+            "--block-file",
+            file.path().to_str().unwrap(),
+            "--keep-confirmations",
+            "1",
+            "simplestats",
+        ]))
+        .unwrap();
+        let mut chain_storage = ChainStorage::new(&options).unwrap();
+        assert_eq!(chain_storage.max_height(), 0);
+        assert!(chain_storage.get_block(0).unwrap().is_some());
+        assert!(chain_storage.get_block(1).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_max_blocks() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        for block in [genesis(), block1(), child_of(&block1())] {
+            file.write_all(&block).unwrap();
+        }
+        file.flush().unwrap();
+        let dump_folder = tempfile::tempdir().unwrap();
+
+        let options = crate::parse_args(crate::command().get_matches_from([
+            "rusty-blockparser",
+            "--block-file",
+            file.path().to_str().unwrap(),
+            "--max-blocks",
+            "2",
+            "headers",
+            dump_folder.path().to_str().unwrap(),
+        ]))
+        .unwrap();
+        let chain_storage = ChainStorage::new(&options).unwrap();
+        assert_eq!(chain_storage.max_height(), 2);
+        BlockchainParser::new(options, chain_storage)
+            .start()
+            .unwrap();
+
+        // The callback is completed after the second block
+        let csv = std::fs::read_to_string(dump_folder.path().join("headers-0-1.csv")).unwrap();
+        assert_eq!(csv.lines().count(), 3);
+    }
+
+    #[test]
+    fn test_disk_order() {
+        let block1 = block1();
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        for block in [block1.clone(), genesis(), child_of(&block1)] {
+            file.write_all(&block).unwrap();
+        }
+        file.flush().unwrap();
+
+        let heights = |disk_order: bool| {
+            let dump_folder = tempfile::tempdir().unwrap();
+            let mut args = vec!["rusty-blockparser", "--block-file"];
+            args.push(file.path().to_str().unwrap());
+            if disk_order {
+                args.push("--disk-order");
+            }
+            args.extend(["headers", dump_folder.path().to_str().unwrap()]);
+            let options = crate::parse_args(crate::command().get_matches_from(args)).unwrap();
+            let chain_storage = ChainStorage::new(&options).unwrap();
+            BlockchainParser::new(options, chain_storage)
+                .start()
+                .unwrap();
+            let csv = std::fs::read_to_string(dump_folder.path().join("headers-0-2.csv")).unwrap();
+            csv.lines()
+                .skip(1)
+                .map(|line| line.split(';').next().unwrap().to_string())
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(heights(false), ["0", "1", "2"]);
+        assert_eq!(heights(true), ["1", "0", "2"]);
+    }
+
+    #[test]
+    fn test_get_blocks() {
+        let block1 = block1();
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        for block in [block1.clone(), genesis(), child_of(&block1)] {
+            file.write_all(&block).unwrap();
+        }
+        file.flush().unwrap();
+
+        let options = crate::parse_args(crate::command().get_matches_from([
+            "rusty-blockparser",
+            "--verify",
+            "--chain-fingerprint",
+            "--block-file",
+            file.path().to_str().unwrap(),
+            "simplestats",
+        ]))
+        .unwrap();
+        let mut sequential = ChainStorage::new(&options).unwrap();
+        let mut parallel = ChainStorage::new(&options).unwrap();
+        let blocks = parallel.get_blocks(0, 5).unwrap();
+        assert_eq!(blocks.len(), 3);
+        for (height, block) in blocks.iter().enumerate() {
+            let expected = sequential.get_block(height as u64).unwrap().unwrap();
+            assert_eq!(block.header.hash, expected.header.hash);
+        }
+        assert_eq!(parallel.fingerprint(), sequential.fingerprint());
+        assert!(parallel.get_blocks(3, 5).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_height_range() {
+        let block1 = block1();
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        for block in [genesis(), block1.clone(), child_of(&block1)] {
+            file.write_all(&block).unwrap();
+        }
+        file.flush().unwrap();
+
+        let run = |range: &[&str]| -> OpResult<(Vec<u64>, u64)> {
+            let mut args = vec!["rusty-blockparser", "--block-file"];
+            args.push(file.path().to_str().unwrap());
+            args.extend_from_slice(range);
+            args.push("simplestats");
+            let mut options = crate::parse_args(crate::command().get_matches_from(args))?;
+            let heights = Rc::new(RefCell::new(Vec::new()));
+            let completed = Rc::new(RefCell::new(None));
+            options.callback = Some(Box::new(StopAfter {
+                n: usize::MAX,
+                heights: Rc::clone(&heights),
+                completed: Rc::clone(&completed),
+            }));
+            let chain_storage = ChainStorage::new(&options)?;
+            BlockchainParser::new(options, chain_storage).start()?;
+            let heights = heights.borrow().clone();
+            let completed = completed.borrow().unwrap();
+            Ok((heights, completed))
+        };
+        assert_eq!(run(&[]).unwrap(), (vec![0, 1, 2], 2));
+        assert_eq!(
+            run(&["--start-height", "1", "--end-height", "1"]).unwrap(),
+            (vec![1], 1)
+        );
+        assert_eq!(run(&["--start-height", "1"]).unwrap(), (vec![1, 2], 2));
+        assert_eq!(run(&["--end-height", "1"]).unwrap(), (vec![0, 1], 1));
+
+        // Both heights have to be within the known chain
+        let err = run(&["--start-height", "3"]).unwrap_err();
+        assert_eq!(
+            err.message,
+            "--start height 3 is past the chain tip at height 2"
+        );
+        let err = run(&["--end-height", "3"]).unwrap_err();
+        assert_eq!(
+            err.message,
+            "--end height 3 is past the chain tip at height 2"
+        );
+    }
+
+    #[test]
+    fn test_blk_stats() {
+        let blocks_dir = tempfile::tempdir().unwrap();
+        std::fs::write(blocks_dir.path().join("blk00000.dat"), genesis()).unwrap();
+        std::fs::write(
+            blocks_dir.path().join("blk00001.dat"),
+            [block1(), child_of(&block1())].concat(),
+        )
+        .unwrap();
+        let stats_file = tempfile::NamedTempFile::new().unwrap();
+
+        let options = crate::parse_args(crate::command().get_matches_from([
+            "rusty-blockparser",
+            "--blockchain-dir",
+            blocks_dir.path().to_str().unwrap(),
+            "--blk-stats",
+            stats_file.path().to_str().unwrap(),
+        ]))
+        .unwrap();
+        assert!(options.callback.is_none());
+        let count =
+            crate::blockchain::parser::write_blk_stats(&options, stats_file.path()).unwrap();
+        assert_eq!(count, 2);
+
+        let csv = std::fs::read_to_string(stats_file.path()).unwrap();
+        assert_eq!(
+            csv,
+            format!(
+                "file_number;block_count;tx_count;byte_count\n0;1;1;{}\n1;2;2;{}\n",
+                genesis().len() - 8,
+                2 * (block1().len() - 8)
+            )
+        );
+    }
+
+    #[test]
+    fn test_chainstate_bootstrap() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&genesis()).unwrap();
+        file.write_all(&block1()).unwrap();
+        file.flush().unwrap();
+        let dump_folder = tempfile::tempdir().unwrap();
+
+        // Unobfuscated chainstate at the genesis block with a single P2PKH coin
+        let chainstate = tempfile::tempdir().unwrap();
+        {
+            let mut db = rusty_leveldb::DB::open(chainstate.path(), Default::default()).unwrap();
+            let genesis_hash =
+                CoinType::from(crate::blockchain::parser::types::Bitcoin).genesis_hash;
+            db.put(b"B", genesis_hash.as_byte_array()).unwrap();
+            let coin = utils::hex_to_vec("00320062e907b15cbf27d5425399ebf6f0fb50ebb88f18");
+            db.put(&[&[b'C'][..], &[0x11; 32], &[0x00]].concat(), &coin)
+                .unwrap();
+            db.close().unwrap();
+        }
+
+        let options = crate::parse_args(crate::command().get_matches_from([
+            "rusty-blockparser",
+            "--block-file",
+            file.path().to_str().unwrap(),
+            "--chainstate",
+            chainstate.path().to_str().unwrap(),
+            "balances",
+            dump_folder.path().to_str().unwrap(),
+        ]))
+        .unwrap();
+        let chain_storage = ChainStorage::new(&options).unwrap();
+        BlockchainParser::new(options, chain_storage)
+            .start()
+            .unwrap();
+
+        // Parsing continues after the genesis block with the coin from the snapshot
+        let csv = std::fs::read_to_string(dump_folder.path().join("balances-1-1.csv")).unwrap();
+        let mut lines: Vec<&str> = csv.lines().skip(1).collect();
+        lines.sort();
+        assert_eq!(
+            lines,
+            [
+                "12c6DSiU4Rq3P4ZxziKxzrL5LmMBrzjrJX;5000000000",
+                "1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa;5000000000"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dry_run() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&genesis()).unwrap();
+        file.write_all(&block1()).unwrap();
+        file.flush().unwrap();
+
+        let options = crate::parse_args(crate::command().get_matches_from([
+            "rusty-blockparser",
+            "--dry-run",
+            "--block-file",
+            file.path().to_str().unwrap(),
+        ]))
+        .unwrap();
+        let mut chain_storage = ChainStorage::new(&options).unwrap();
+        let report = chain_storage.dry_run(0);
+        assert!(report.is_ok());
+        assert_eq!(report.tip_height, 1);
+        assert_eq!(report.blocks, 2);
+        assert_eq!(report.size, (genesis().len() + block1().len() - 16) as u64);
+
+        // Truncate the file after indexing, so block 1 is no longer readable
+        file.as_file().set_len(genesis().len() as u64 + 8).unwrap();
+        let report = chain_storage.dry_run(0);
+        assert!(!report.is_ok());
+        assert_eq!(report.blocks, 1);
+        assert_eq!(report.first_missing, Some(1));
+    }
+
+    #[test]
+    fn test_orphans() {
+        let block1 = block1();
+        // Competing block at height 1 and a block at height 2 extending the real block 1
+        let mut stale1 = block1.clone();
+        stale1[84] ^= 0xff;
+        let block2 = child_of(&block1);
+
+        // The stale block comes first, so it would win if both branches had the same height
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        for block in [genesis(), stale1.clone(), block1.clone(), block2] {
+            file.write_all(&block).unwrap();
+        }
+        file.flush().unwrap();
+        let orphans_file = tempfile::NamedTempFile::new().unwrap();
+
+        let options = crate::parse_args(crate::command().get_matches_from([
+            "rusty-blockparser",
+            "--block-file",
+            file.path().to_str().unwrap(),
+            "--orphans",
+            orphans_file.path().to_str().unwrap(),
+            "simplestats",
+        ]))
+        .unwrap();
+        let mut chain_storage = ChainStorage::new(&options).unwrap();
+        assert_eq!(chain_storage.max_height(), 2);
+        let main1 = chain_storage.get_block(1).unwrap().unwrap().header.hash;
+        assert_eq!(main1, sha256d::Hash::hash(&block1[8..88]));
+
+        assert_eq!(
+            chain_storage
+                .write_orphans(options.orphans.as_ref().unwrap())
+                .unwrap(),
+            1
+        );
+        let csv = std::fs::read_to_string(orphans_file.path()).unwrap();
+        assert_eq!(
+            csv,
+            format!(
+                "orphan_hash;height;main_chain_hash\n{};1;{}\n",
+                utils::HashByteOrder::Rpc.hex(&sha256d::Hash::hash(&stale1[8..88])),
+                utils::HashByteOrder::Rpc.hex(&main1)
+            )
+        );
+    }
+
+    #[test]
+    fn test_reorgs() {
+        // Stale branch of 2 blocks forking off after the genesis block, the main chain is one block longer
+        let block1 = block1();
+        let mut stale1 = block1.clone();
+        stale1[84] ^= 0xff;
+        let stale2 = child_of(&stale1);
+        let block2 = child_of(&block1);
+        let block3 = child_of(&block2);
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        for block in [genesis(), stale1, block1.clone(), stale2, block2, block3] {
+            file.write_all(&block).unwrap();
+        }
+        file.flush().unwrap();
+        let reorgs_file = tempfile::NamedTempFile::new().unwrap();
+
+        let options = crate::parse_args(crate::command().get_matches_from([
+            "rusty-blockparser",
+            "--block-file",
+            file.path().to_str().unwrap(),
+            "--reorgs",
+            reorgs_file.path().to_str().unwrap(),
+            "simplestats",
+        ]))
+        .unwrap();
+        let chain_storage = ChainStorage::new(&options).unwrap();
+        assert_eq!(chain_storage.max_height(), 3);
+        assert_eq!(
+            chain_storage
+                .write_reorgs(options.reorgs.as_ref().unwrap())
+                .unwrap(),
+            1
+        );
+        let csv = std::fs::read_to_string(reorgs_file.path()).unwrap();
+        assert_eq!(
+            csv,
+            format!(
+                "fork_height;orphan_branch_length;main_branch_hash\n0;2;{}\n",
+                utils::HashByteOrder::Rpc.hex(&sha256d::Hash::hash(&block1[8..88]))
+            )
+        );
+    }
+
+    #[test]
+    fn test_checkpoints() {
+        // Blocks 1 and 2 have neither a valid merkle root nor a valid proof of work
+        let genesis = genesis();
+        let mut block1 = child_of(&genesis);
+        block1[44] ^= 0xff;
+        let mut block2 = child_of(&block1);
+        block2[44] ^= 0xff;
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&[genesis, block1.clone(), block2].concat())
+            .unwrap();
+        file.flush().unwrap();
+
+        let block_file = file.path().to_str().unwrap();
+        let storage = |checkpoints: &str| {
+            let mut checkpoints_file = tempfile::NamedTempFile::new().unwrap();
+            checkpoints_file.write_all(checkpoints.as_bytes()).unwrap();
+            let options = crate::parse_args(crate::command().get_matches_from([
+                "rusty-blockparser",
+                "--verify",
+                "--verify-pow",
+                "--block-file",
+                block_file,
+                "--checkpoints",
+                checkpoints_file.path().to_str().unwrap(),
+                "simplestats",
+            ]))
+            .unwrap();
+            ChainStorage::new(&options)
+        };
+
+        let mut chain_storage = storage("").unwrap();
+        assert!(chain_storage.get_block(0).unwrap().is_some());
+        assert!(chain_storage.get_block(1).is_err());
+
+        // Blocks up to the checkpoint are trusted, the block above it is still verified
+        let block1_hash = sha256d::Hash::hash(&block1[8..88]);
+        let mut chain_storage =
+            storage(&format!("1;{}\n5;{}\n", block1_hash, block1_hash)).unwrap();
+        assert!(chain_storage.get_block(0).unwrap().is_some());
+        assert!(chain_storage.get_block(1).unwrap().is_some());
+        assert!(chain_storage.get_block(2).is_err());
+
+        // The chain doesn't contain the checkpoint
+        let checkpoints = format!("1;{}\n", sha256d::Hash::all_zeros());
+        assert!(storage(&checkpoints).is_err());
+    }
+
+    #[test]
+    fn test_chain_fingerprint() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&genesis()).unwrap();
+        file.write_all(&block1()).unwrap();
+        file.flush().unwrap();
+
+        let options = crate::parse_args(crate::command().get_matches_from([
+            "rusty-blockparser",
+            "--block-file",
+            file.path().to_str().unwrap(),
+            "--chain-fingerprint",
+            "simplestats",
+        ]))
+        .unwrap();
+        let mut chain_storage = ChainStorage::new(&options).unwrap();
+        assert_eq!(chain_storage.fingerprint(), Some(sha256::Hash::hash(&[])));
+        chain_storage.get_block(0).unwrap().unwrap();
+        chain_storage.get_block(1).unwrap().unwrap();
+        assert!(chain_storage.get_block(2).unwrap().is_none());
+
+        // Same as hashing the concatenated blocks without magic bytes and size prefix
+        let mut raw_chain = genesis()[8..].to_vec();
+        raw_chain.extend_from_slice(&block1()[8..]);
+        assert_eq!(
+            chain_storage.fingerprint(),
+            Some(sha256::Hash::hash(&raw_chain))
+        );
+    }
+
+    #[test]
+    fn test_extra_blocks_not_connected() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&block1()).unwrap();
+        file.flush().unwrap();
+
+        let coin = CoinType::default();
+        let mut block_index = HashMap::new();
+        block_index.insert(
+            0,
+            BlockIndexRecord::new(
+                sha256d::Hash::all_zeros(),
+                sha256d::Hash::all_zeros(),
+                0,
+                0,
+                8,
+            ),
+        );
+        let mut blk_file = BlkFile::from_file(file.path()).unwrap();
+        assert!(append_blk_file(&mut block_index, &mut blk_file, &coin).is_err());
+    }
+}
@@ -1,15 +1,30 @@
+use bitcoin::hashes::{sha256d, Hash};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
 use std::time::{Duration, Instant};
 
+use crate::blockchain::parser::blkfile::BlkFile;
 use crate::blockchain::parser::chain::ChainStorage;
+use crate::blockchain::parser::chainstate::Chainstate;
+use crate::blockchain::parser::checkpoint::{Checkpoint, CheckpointHeader};
 use crate::blockchain::proto::block::Block;
-use crate::callbacks::Callback;
-use crate::errors::OpResult;
+use crate::blockchain::proto::script::AddressFormat;
+use crate::blockchain::proto::tx::EvaluatedTx;
+use crate::blockchain::proto::Hashed;
+use crate::callbacks::{BlockFlow, Callback};
+use crate::common::profile::{self, Phase};
+use crate::errors::{OpError, OpErrorKind, OpResult};
 use crate::ParserOptions;
 
 mod blkfile;
 pub mod chain;
+pub mod chainstate;
+mod checkpoint;
 mod index;
 pub mod reader;
+#[cfg(feature = "async")]
+pub mod stream;
 pub mod types;
 
 /// Small struct to hold statistics together
@@ -17,6 +32,7 @@ struct WorkerStats {
     pub started_at: Instant,
     pub last_log: Instant,
     pub last_height: u64,
+    pub start_height: u64,
     pub measure_frame: Duration,
 }
 
@@ -26,16 +42,133 @@ impl WorkerStats {
             started_at: Instant::now(),
             last_log: Instant::now(),
             last_height: start_range,
+            start_height: start_range,
             measure_frame: Duration::from_secs(10),
         }
     }
 }
 
+/// Logs the progress of a long running phase at most every 10 seconds.
+/// Used while building the index, before the number of blocks is known.
+struct ProgressLog {
+    target: &'static str,
+    label: String,
+    last_log: Instant,
+    measure_frame: Duration,
+}
+
+impl ProgressLog {
+    fn new(target: &'static str, label: String) -> Self {
+        Self {
+            target,
+            label,
+            last_log: Instant::now(),
+            measure_frame: Duration::from_secs(10),
+        }
+    }
+
+    /// Logs the percentage of `done` in `total` if the last log is older than the measure frame
+    fn update(&mut self, done: u64, total: u64) {
+        let now = Instant::now();
+        if now - self.last_log > self.measure_frame {
+            info!(target: self.target, "{} ({:.1}%)", self.label, percentage(done, total));
+            self.last_log = now;
+        }
+    }
+}
+
+/// Returns `done` relative to `total` in percent, capped at 100
+fn percentage(done: u64, total: u64) -> f64 {
+    match total {
+        0 => 100.0,
+        total => (done.min(total) as f64 * 100.0) / total as f64,
+    }
+}
+
+/// Rolling commitment over all processed blocks.
+/// Each block folds its txids into the previous digest, so two runs over the same data
+/// yield the same digest and a divergence can be bisected by comparing intermediate digests.
+struct StateDigest {
+    digest: sha256d::Hash,
+    interval: u64,
+}
+
+impl StateDigest {
+    fn new(interval: u64) -> Self {
+        Self {
+            digest: sha256d::Hash::all_zeros(),
+            interval,
+        }
+    }
+
+    fn update(&mut self, block: &Block) {
+        let mut data = Vec::with_capacity(32 * (block.txs.len() + 1));
+        data.extend_from_slice(self.digest.as_byte_array());
+        for tx in &block.txs {
+            data.extend_from_slice(tx.hash.as_byte_array());
+        }
+        self.digest = sha256d::Hash::hash(&data);
+    }
+}
+
+/// Writes the number of blocks, transactions and bytes of each blk file to a CSV file.
+/// The files are scanned in disk order without building the chain index.
+/// Returns the number of scanned files.
+pub fn write_blk_stats(options: &ParserOptions, path: &Path) -> OpResult<usize> {
+    let mut blk_files: Vec<(u64, BlkFile)> = match &options.block_file {
+        Some(block_file) => vec![(0, BlkFile::from_file(block_file)?)],
+        None => BlkFile::from_path(options.blockchain_dir.as_path())?
+            .into_iter()
+            .collect(),
+    };
+    blk_files.sort_unstable_by_key(|(index, _)| *index);
+    let mut progress = ProgressLog::new("parser", String::from("Scanning blk files ..."));
+
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(b"file_number;block_count;tx_count;byte_count\n")?;
+    let count = blk_files.len() as u64;
+    for (i, (index, blk_file)) in blk_files.iter_mut().enumerate() {
+        progress.update(i as u64, count);
+        let stats = blk_file.scan_stats(&options.coin)?;
+        writer.write_all(
+            format!("{};{};{};{}\n", index, stats.blocks, stats.txs, stats.bytes).as_bytes(),
+        )?;
+    }
+    writer.flush()?;
+    Ok(blk_files.len())
+}
+
+/// Returns the transactions of a block which should be passed to `on_txs()`
+fn select_txs<'a>(callback: &dyn Callback, block: &'a Block) -> &'a [Hashed<EvaluatedTx>] {
+    if callback.wants_only_coinbase() {
+        &block.txs[..block.txs.len().min(1)]
+    } else {
+        &block.txs
+    }
+}
+
 pub struct BlockchainParser {
     chain_storage: ChainStorage, // Hash storage with the longest chain
     stats: WorkerStats,          // struct for thread management & statistics
     callback: Box<dyn Callback>,
     cur_height: u64,
+    // Height after the last block to process, if limited by `--max-blocks`
+    stop_height: Option<u64>,
+    state_digest: Option<StateDigest>,
+    // UTXO snapshot which is loaded before the first block
+    chainstate: Option<PathBuf>,
+    // Saves the callback state periodically and resumes from it
+    checkpoint: Option<Checkpoint>,
+    // Last processed block, saved with the next checkpoint
+    last_block: Option<CheckpointHeader>,
+    saved_height: Option<u64>,
+    // Process the blocks in the order they are stored in the blk files
+    disk_order: bool,
+    // Print the time spent per phase every N blocks
+    profile: Option<u64>,
+    // Number of blocks which are deserialized in parallel
+    batch_size: u64,
+    address_format: AddressFormat,
 }
 
 impl BlockchainParser {
@@ -45,27 +178,192 @@ impl BlockchainParser {
         Self {
             chain_storage,
             stats: WorkerStats::new(options.range.start),
-            callback: options.callback,
+            callback: options
+                .callback
+                .expect("callback is only missing for dry runs"),
             cur_height: options.range.start,
+            stop_height: options
+                .max_blocks
+                .map(|max_blocks| options.range.start.saturating_add(max_blocks)),
+            state_digest: options.state_digest.map(StateDigest::new),
+            chainstate: options.chainstate,
+            checkpoint: options
+                .checkpoint_file
+                .map(|path| Checkpoint::new(path, options.checkpoint_every)),
+            last_block: None,
+            saved_height: None,
+            disk_order: options.disk_order,
+            profile: options.profile,
+            batch_size: options.threads.map_or(1, |threads| threads as u64),
+            address_format: options.coin.address_format(),
         }
     }
 
     pub fn start(&mut self) -> OpResult<()> {
         debug!(target: "parser", "Starting worker ...");
 
+        if let Some(path) = self.chainstate.take() {
+            self.load_snapshot(&path)?;
+        }
+        let loaded = match &self.checkpoint {
+            Some(checkpoint) => checkpoint.load()?,
+            None => None,
+        };
+        let mut state = match loaded {
+            Some((header, reader)) => {
+                self.resume_from(header)?;
+                Some(reader)
+            }
+            None => None,
+        };
         self.on_start(self.cur_height)?;
-        while let Some(block) = self.chain_storage.get_block(self.cur_height) {
-            self.on_block(&block, self.cur_height)?;
-            self.cur_height += 1;
+        if let Some(reader) = state.as_mut() {
+            self.callback.load_state(reader)?;
+            trace!(target: "parser", "load_state() called");
         }
+        if self.disk_order {
+            let height = self.process_disk_order()?;
+            return self.on_complete(height);
+        }
+        'outer: while self
+            .stop_height
+            .map_or(true, |height| self.cur_height < height)
+        {
+            let count = match self.stop_height {
+                Some(height) => self.batch_size.min(height - self.cur_height),
+                None => self.batch_size,
+            };
+            let blocks = self.read_blocks(self.cur_height, count)?;
+            if blocks.is_empty() {
+                break;
+            }
+            for block in blocks {
+                let flow = self.on_block(&block, self.cur_height)?;
+                self.cur_height += 1;
+                if flow == BlockFlow::Stop {
+                    info!(target: "parser", "Callback stopped at height {}", self.cur_height - 1);
+                    break 'outer;
+                }
+            }
+        }
+        self.save_checkpoint()?;
         self.on_complete(self.cur_height.saturating_sub(1))
     }
 
+    /// Processes the blocks in the order they are stored in the blk files instead of chain order,
+    /// so the heights passed to the callback aren't ascending. Returns the highest processed height,
+    /// or the height of the last block if the callback stopped early.
+    fn process_disk_order(&mut self) -> OpResult<u64> {
+        let mut max_height = self.cur_height;
+        for height in self.chain_storage.heights_in_disk_order(self.cur_height) {
+            if self.stop_height.is_some_and(|stop| height >= stop) {
+                continue;
+            }
+            let block = match self.read_block(height)? {
+                Some(block) => block,
+                None => {
+                    warn!(target: "parser", "Skipping unreadable block at height {}", height);
+                    continue;
+                }
+            };
+            let flow = self.on_block(&block, height)?;
+            max_height = max_height.max(height);
+            if flow == BlockFlow::Stop {
+                info!(target: "parser", "Callback stopped at height {}", height);
+                return Ok(height);
+            }
+        }
+        Ok(max_height)
+    }
+
+    /// Reads the block at the given height, timed as `Phase::Read` with `--profile`
+    fn read_block(&mut self, height: u64) -> OpResult<Option<Block>> {
+        let chain_storage = &mut self.chain_storage;
+        profile::measure(Phase::Read, || chain_storage.get_block(height))
+    }
+
+    /// Reads up to `count` consecutive blocks starting at the given height, see `read_block()`.
+    /// More than one block is deserialized in parallel.
+    fn read_blocks(&mut self, height: u64, count: u64) -> OpResult<Vec<Block>> {
+        if count <= 1 {
+            return Ok(self.read_block(height)?.into_iter().collect());
+        }
+        let chain_storage = &mut self.chain_storage;
+        profile::measure(Phase::Read, || chain_storage.get_blocks(height, count))
+    }
+
     /// Returns number of remaining blocks
     pub fn remaining(&self) -> u64 {
-        self.chain_storage
-            .max_height()
-            .saturating_sub(self.cur_height)
+        let max_height = match self.stop_height {
+            Some(height) => self.chain_storage.max_height().min(height - 1),
+            None => self.chain_storage.max_height(),
+        };
+        max_height.saturating_sub(self.cur_height)
+    }
+
+    /// Feeds the UTXO set of the chainstate to the callback
+    /// and continues with the block after the snapshot's best block.
+    fn load_snapshot(&mut self, path: &Path) -> OpResult<()> {
+        let mut chainstate = Chainstate::open(path)?;
+        let best_block = chainstate.best_block()?;
+        let height = self.chain_storage.height_of(&best_block).ok_or_else(|| {
+            OpError::new(OpErrorKind::RuntimeError).join_msg(&format!(
+                "Best block {} of the chainstate is not part of the main chain",
+                best_block
+            ))
+        })?;
+        let callback = &mut self.callback;
+        let count = chainstate
+            .for_each_utxo(self.address_format, |utxo| callback.on_snapshot_utxo(&utxo))?;
+        info!(target: "parser", "Loaded {} unspent outputs up to height {} from chainstate", count, height);
+        self.skip_to(height + 1);
+        Ok(())
+    }
+
+    /// Continues with the block after the checkpoint.
+    /// Fails if the checkpoint block is not part of the main chain, e.g. if it is past the tip.
+    fn resume_from(&mut self, header: CheckpointHeader) -> OpResult<()> {
+        let max_height = self.chain_storage.max_height();
+        if header.height > max_height {
+            return Err(OpError::new(OpErrorKind::RuntimeError).join_msg(&format!(
+                "Checkpoint at height {} is past the chain tip at height {}",
+                header.height, max_height
+            )));
+        }
+        if self.chain_storage.hash_at(header.height) != Some(header.block_hash) {
+            return Err(OpError::new(OpErrorKind::RuntimeError).join_msg(&format!(
+                "Checkpoint block {} at height {} is not part of the main chain",
+                header.block_hash, header.height
+            )));
+        }
+        info!(target: "parser", "Resuming from checkpoint at height {}", header.height);
+        self.last_block = Some(header);
+        self.saved_height = Some(header.height);
+        self.skip_to(header.height + 1);
+        Ok(())
+    }
+
+    /// Moves the start to the given height, a limit set by `--max-blocks` is moved along
+    fn skip_to(&mut self, start_height: u64) {
+        self.stop_height = self
+            .stop_height
+            .map(|stop| stop - self.cur_height + start_height);
+        self.cur_height = start_height;
+        self.stats.last_height = start_height;
+    }
+
+    /// Saves the last processed block and the callback state, unless checkpoints are disabled
+    /// or the block is already saved
+    fn save_checkpoint(&mut self) -> OpResult<()> {
+        if let (Some(checkpoint), Some(header)) = (&self.checkpoint, self.last_block) {
+            if self.saved_height == Some(header.height) {
+                return Ok(());
+            }
+            checkpoint.save(header, &*self.callback)?;
+            self.saved_height = Some(header.height);
+            debug!(target: "parser", "Saved checkpoint at height {} to {}", header.height, checkpoint.path().display());
+        }
+        Ok(())
     }
 
     /// Triggers the on_start() callback and initializes state.
@@ -73,6 +371,7 @@ impl BlockchainParser {
         let now = Instant::now();
         self.stats.started_at = now;
         self.stats.last_log = now;
+        self.stats.start_height = height;
         info!(target: "parser", "Processing blocks starting from height {} ...", height);
         self.callback.on_start(height)?;
         trace!(target: "parser", "on_start() called");
@@ -80,13 +379,39 @@ impl BlockchainParser {
     }
 
     /// Triggers the on_block() callback and updates statistics.
-    fn on_block(&mut self, block: &Block, height: u64) -> OpResult<()> {
-        self.callback.on_block(block, height)?;
-        trace!(target: "parser", "on_block(height={}) called", height);
+    fn on_block(&mut self, block: &Block, height: u64) -> OpResult<BlockFlow> {
+        let callback = &mut self.callback;
+        let flow = profile::measure(Phase::Callback, || -> OpResult<BlockFlow> {
+            let flow = callback.on_block(block, height)?;
+            trace!(target: "parser", "on_block(height={}) called", height);
+            callback.on_txs(select_txs(&**callback, block), height)?;
+            trace!(target: "parser", "on_txs(height={}) called", height);
+            Ok(flow)
+        })?;
+        if let Some(checkpoint) = &self.checkpoint {
+            self.last_block = Some(CheckpointHeader {
+                height,
+                block_hash: block.header.hash,
+            });
+            if height % checkpoint.interval == 0 {
+                self.save_checkpoint()?;
+            }
+        }
+        if let Some(state) = self.state_digest.as_mut() {
+            state.update(block);
+            if height % state.interval == 0 {
+                info!(target: "parser", "State digest at height {}: {}", height, state.digest);
+            }
+        }
         if self.callback.show_progress() {
             self.print_progress(height);
         }
-        Ok(())
+        if let Some(interval) = self.profile {
+            if height % interval == 0 {
+                info!(target: "parser", "Profile at height {}: {}", height, profile::Breakdown::take());
+            }
+        }
+        Ok(flow)
     }
 
     /// Triggers the on_complete() callback and updates statistics.
@@ -94,20 +419,475 @@ impl BlockchainParser {
         info!(target: "parser", "Done. Processed blocks up to height {} in {:.2} minutes.",
         height, (Instant::now() - self.stats.started_at).as_secs_f32() / 60.0);
 
-        self.callback.on_complete(height)?;
+        if let Some(state) = &self.state_digest {
+            info!(target: "parser", "Final state digest at height {}: {}", height, state.digest);
+        }
+        if let Some(fingerprint) = self.chain_storage.fingerprint() {
+            info!(target: "parser", "Chain fingerprint at height {}: {}", height, fingerprint);
+        }
+
+        let callback = &mut self.callback;
+        profile::measure(Phase::Callback, || callback.on_complete(height))?;
         trace!(target: "parser", "on_complete() called");
+        if self.profile.is_some() {
+            let breakdown = profile::Breakdown::take();
+            if breakdown.total() > Duration::ZERO {
+                info!(target: "parser", "Profile at height {}: {}", height, breakdown);
+            }
+        }
         Ok(())
     }
 
     fn print_progress(&mut self, height: u64) {
         let now = Instant::now();
-        let blocks_speed = (height - self.stats.last_height) / self.stats.measure_frame.as_secs();
+        let blocks_speed =
+            height.saturating_sub(self.stats.last_height) / self.stats.measure_frame.as_secs();
 
         if now - self.stats.last_log > self.stats.measure_frame {
-            info!(target: "parser", "Status: {:7} Blocks processed. (remaining: {:7}, speed: {:5.2} blocks/s)",
-              height, self.remaining(), blocks_speed);
+            let remaining = self.remaining();
+            let processed = height.saturating_sub(self.stats.start_height);
+            info!(target: "parser", "Status: {:7} Blocks processed. (remaining: {:7}, speed: {:5.2} blocks/s, {:5.1}%)",
+              height, remaining, blocks_speed, percentage(processed, processed + remaining));
             self.stats.last_log = now;
             self.stats.last_height = height;
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::parser::reader::BlockchainRead;
+    use crate::blockchain::parser::types::CoinType;
+    use crate::blockchain::proto::header::BlockHeader;
+    use crate::blockchain::proto::script::{EvaluatedScript, ScriptPattern};
+    use crate::blockchain::proto::tx::{RawTx, TxInput, TxOutpoint};
+    use crate::blockchain::proto::varuint::VarUint;
+    use crate::common::utils;
+    use clap::{ArgMatches, Command};
+    use std::cell::RefCell;
+    use std::io::Cursor;
+    use std::rc::Rc;
+
+    pub(super) fn genesis() -> Vec<u8> {
+        utils::hex_to_vec(concat!(
+            "f9beb4d91d010000010000000000000000000000000000000000000000000000",
+            "0000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3",
+            "888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c0101000000010000",
+            "000000000000000000000000000000000000000000000000000000000000ffff",
+            "ffff4d04ffff001d0104455468652054696d65732030332f4a616e2f32303039",
+            "204368616e63656c6c6f72206f6e206272696e6b206f66207365636f6e642062",
+            "61696c6f757420666f722062616e6b73ffffffff0100f2052a01000000434104",
+            "678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb6",
+            "49f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5f",
+            "ac00000000",
+        ))
+    }
+
+    pub(super) fn block1() -> Vec<u8> {
+        utils::hex_to_vec(concat!(
+            "f9beb4d9d7000000010000006fe28c0ab6f1b372c1a6a246ae63f74f931e8365",
+            "e15a089c68d6190000000000982051fd1e4ba744bbbe680e1fee14677ba1a3c3",
+            "540bf7b1cdb606e857233e0e61bc6649ffff001d01e362990101000000010000",
+            "000000000000000000000000000000000000000000000000000000000000ffff",
+            "ffff0704ffff001d0104ffffffff0100f2052a0100000043410496b538e85351",
+            "9c726a2c91e61ec11600ae1390813a627c66fb8be7947be63c52da7589379515",
+            "d4e0a604f8141781e62294721166bf621e73a82cbf2342c858eeac00000000",
+        ))
+    }
+
+    /// Returns a copy of block 1 with its prev_hash pointing to the given block.
+    /// The proof of work is invalid, but the block is linked correctly.
+    pub(super) fn child_of(parent: &[u8]) -> Vec<u8> {
+        let mut block = block1();
+        block[12..44].copy_from_slice(&sha256d::Hash::hash(&parent[8..88]).to_byte_array());
+        block
+    }
+
+    /// Records the processed heights and stops after `n` blocks
+    pub(super) struct StopAfter {
+        pub(super) n: usize,
+        pub(super) heights: Rc<RefCell<Vec<u64>>>,
+        pub(super) completed: Rc<RefCell<Option<u64>>>,
+    }
+
+    impl Callback for StopAfter {
+        fn build_subcommand() -> Command {
+            Command::new("stopafter")
+        }
+
+        fn new(_: &ArgMatches) -> OpResult<Self> {
+            Err(OpError::from(String::from(
+                "stopafter is only constructed by the tests",
+            )))
+        }
+
+        fn on_start(&mut self, _: u64) -> OpResult<()> {
+            Ok(())
+        }
+
+        fn on_block(&mut self, _: &Block, block_height: u64) -> OpResult<BlockFlow> {
+            let mut heights = self.heights.borrow_mut();
+            heights.push(block_height);
+            if heights.len() >= self.n {
+                return Ok(BlockFlow::Stop);
+            }
+            Ok(BlockFlow::Continue)
+        }
+
+        fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+            *self.completed.borrow_mut() = Some(block_height);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_callback_stop() {
+        let block1 = block1();
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        for block in [block1.clone(), genesis(), child_of(&block1)] {
+            file.write_all(&block).unwrap();
+        }
+        file.flush().unwrap();
+
+        let run = |n: usize, disk_order: bool| {
+            let mut args = vec!["rusty-blockparser", "--block-file"];
+            args.push(file.path().to_str().unwrap());
+            if disk_order {
+                args.push("--disk-order");
+            }
+            args.push("simplestats");
+            let mut options = crate::parse_args(crate::command().get_matches_from(args)).unwrap();
+            let heights = Rc::new(RefCell::new(Vec::new()));
+            let completed = Rc::new(RefCell::new(None));
+            options.callback = Some(Box::new(StopAfter {
+                n,
+                heights: Rc::clone(&heights),
+                completed: Rc::clone(&completed),
+            }));
+            let chain_storage = ChainStorage::new(&options).unwrap();
+            BlockchainParser::new(options, chain_storage)
+                .start()
+                .unwrap();
+            let heights = heights.borrow().clone();
+            let completed = completed.borrow().unwrap();
+            (heights, completed)
+        };
+        assert_eq!(run(2, false), (vec![0, 1], 1));
+        assert_eq!(run(5, false), (vec![0, 1, 2], 2));
+        // In disk order, the height of the last processed block is passed to on_complete()
+        assert_eq!(run(1, true), (vec![1], 1));
+        assert_eq!(run(2, true), (vec![1, 0], 0));
+    }
+
+    #[test]
+    fn test_threads() {
+        let block1 = block1();
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        for block in [block1.clone(), genesis(), child_of(&block1)] {
+            file.write_all(&block).unwrap();
+        }
+        file.flush().unwrap();
+
+        let dump = |extra_args: &[&str]| {
+            let dump_folder = tempfile::tempdir().unwrap();
+            let mut args = vec!["rusty-blockparser", "--block-file"];
+            args.push(file.path().to_str().unwrap());
+            args.extend_from_slice(extra_args);
+            args.extend(["headers", dump_folder.path().to_str().unwrap()]);
+            let options = crate::parse_args(crate::command().get_matches_from(args)).unwrap();
+            let chain_storage = ChainStorage::new(&options).unwrap();
+            BlockchainParser::new(options, chain_storage)
+                .start()
+                .unwrap();
+            let mut names: Vec<String> = std::fs::read_dir(dump_folder.path())
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+                .collect();
+            assert_eq!(names.len(), 1);
+            let name = names.pop().unwrap();
+            let csv = std::fs::read_to_string(dump_folder.path().join(&name)).unwrap();
+            (name, csv)
+        };
+        // The callback gets the blocks in height order regardless of the batch size
+        let (name, csv) = dump(&[]);
+        assert_eq!(name, "headers-0-2.csv");
+        for threads in ["2", "3", "8"] {
+            assert_eq!(dump(&["--threads", threads]), (name.clone(), csv.clone()));
+        }
+        // Batches don't exceed --max-blocks
+        let (name, csv) = dump(&["--threads", "8", "--max-blocks", "2"]);
+        assert_eq!(name, "headers-0-1.csv");
+        assert_eq!(csv.lines().count(), 3);
+    }
+
+    #[test]
+    fn test_hash_byte_order() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&genesis()).unwrap();
+        file.flush().unwrap();
+
+        let dump = |extra_args: &[&str]| {
+            let dump_folder = tempfile::tempdir().unwrap();
+            let mut args = vec!["rusty-blockparser", "--block-file"];
+            args.push(file.path().to_str().unwrap());
+            args.extend_from_slice(extra_args);
+            args.extend(["headers", dump_folder.path().to_str().unwrap()]);
+            let options = crate::parse_args(crate::command().get_matches_from(args)).unwrap();
+            let chain_storage = ChainStorage::new(&options).unwrap();
+            BlockchainParser::new(options, chain_storage)
+                .start()
+                .unwrap();
+            let csv = std::fs::read_to_string(dump_folder.path().join("headers-0-0.csv")).unwrap();
+            let row = csv.lines().nth(1).unwrap().to_string();
+            row.split(';').nth(1).unwrap().to_string()
+        };
+        let rpc = "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f";
+        assert_eq!(dump(&[]), rpc);
+        assert_eq!(dump(&["--hash-byte-order", "rpc"]), rpc);
+        assert_eq!(
+            dump(&["--hash-byte-order", "internal"]),
+            "6fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d6190000000000"
+        );
+    }
+
+    /// Records the evaluated script of each output
+    struct Outputs {
+        scripts: Rc<RefCell<Vec<EvaluatedScript>>>,
+    }
+
+    impl Callback for Outputs {
+        fn build_subcommand() -> Command {
+            Command::new("outputs")
+        }
+
+        fn new(_: &ArgMatches) -> OpResult<Self> {
+            Err(OpError::from(String::from(
+                "outputs is only constructed by the tests",
+            )))
+        }
+
+        fn on_start(&mut self, _: u64) -> OpResult<()> {
+            Ok(())
+        }
+
+        fn on_block(&mut self, block: &Block, _: u64) -> OpResult<BlockFlow> {
+            let mut scripts = self.scripts.borrow_mut();
+            for tx in &block.txs {
+                scripts.extend(tx.value.outputs.iter().map(|o| o.script.clone()));
+            }
+            Ok(BlockFlow::Continue)
+        }
+
+        fn on_complete(&mut self, _: u64) -> OpResult<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_no_address() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&genesis()).unwrap();
+        file.flush().unwrap();
+
+        let run = |extra_args: &[&str]| {
+            let mut args = vec!["rusty-blockparser", "--block-file"];
+            args.push(file.path().to_str().unwrap());
+            args.extend_from_slice(extra_args);
+            args.push("simplestats");
+            let mut options = crate::parse_args(crate::command().get_matches_from(args)).unwrap();
+            let scripts = Rc::new(RefCell::new(Vec::new()));
+            options.callback = Some(Box::new(Outputs {
+                scripts: Rc::clone(&scripts),
+            }));
+            let chain_storage = ChainStorage::new(&options).unwrap();
+            BlockchainParser::new(options, chain_storage)
+                .start()
+                .unwrap();
+            let scripts = scripts.borrow();
+            assert_eq!(scripts.len(), 1);
+            assert_eq!(scripts[0].pattern, ScriptPattern::Pay2PublicKey);
+            scripts[0].address.clone()
+        };
+        assert_eq!(
+            run(&[]).as_deref(),
+            Some("1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa")
+        );
+        // Scripts are still classified
+        assert_eq!(run(&["--no-address"]), None);
+    }
+
+    #[test]
+    fn test_checkpoint_resume() {
+        let block1 = block1();
+        let blocks = [genesis(), block1.clone(), child_of(&block1)];
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        for block in &blocks {
+            file.write_all(block).unwrap();
+        }
+        file.flush().unwrap();
+        let checkpoint = tempfile::tempdir().unwrap();
+        let checkpoint_path = checkpoint.path().join("balances.checkpoint");
+
+        let run = |path: &Path, dump_folder: &Path, extra: &[&str]| {
+            let mut args = vec!["rusty-blockparser", "--block-file", path.to_str().unwrap()];
+            args.extend_from_slice(extra);
+            args.extend(["balances", dump_folder.to_str().unwrap()]);
+            let options = crate::parse_args(crate::command().get_matches_from(args))?;
+            let chain_storage = ChainStorage::new(&options)?;
+            BlockchainParser::new(options, chain_storage).start()
+        };
+        let full = tempfile::tempdir().unwrap();
+        run(file.path(), full.path(), &[]).unwrap();
+
+        // Interrupted after block 1, the second run only parses block 2
+        let resumed = tempfile::tempdir().unwrap();
+        let args = ["--checkpoint", checkpoint_path.to_str().unwrap()];
+        run(
+            file.path(),
+            resumed.path(),
+            &[&args[..], &["--max-blocks", "2"]].concat(),
+        )
+        .unwrap();
+        run(file.path(), resumed.path(), &args).unwrap();
+        let read_sorted = |dir: &Path| {
+            let csv = std::fs::read_to_string(dir.join("balances-0-2.csv")).unwrap();
+            let mut lines: Vec<String> = csv.lines().map(String::from).collect();
+            lines.sort();
+            lines
+        };
+        assert_eq!(read_sorted(resumed.path()), read_sorted(full.path()));
+
+        // A checkpoint past the tip of a shorter chain is rejected
+        let mut short = tempfile::NamedTempFile::new().unwrap();
+        short.write_all(&[genesis(), block1].concat()).unwrap();
+        short.flush().unwrap();
+        let err = run(short.path(), resumed.path(), &args).unwrap_err();
+        assert!(err.message.contains("past the chain tip"));
+    }
+
+    /// Counts the transactions passed to `on_tx()`
+    struct TxCounter {
+        only_coinbase: bool,
+        n_tx: u64,
+    }
+
+    impl Callback for TxCounter {
+        fn build_subcommand() -> Command {
+            Command::new("txcounter")
+        }
+
+        fn new(_: &ArgMatches) -> OpResult<Self> {
+            Ok(Self {
+                only_coinbase: false,
+                n_tx: 0,
+            })
+        }
+
+        fn on_start(&mut self, _: u64) -> OpResult<()> {
+            Ok(())
+        }
+
+        fn on_block(&mut self, _: &Block, _: u64) -> OpResult<BlockFlow> {
+            Ok(BlockFlow::Continue)
+        }
+
+        fn on_tx(&mut self, _: &Hashed<EvaluatedTx>, _: u64) -> OpResult<()> {
+            self.n_tx += 1;
+            Ok(())
+        }
+
+        fn on_complete(&mut self, _: u64) -> OpResult<()> {
+            Ok(())
+        }
+
+        fn wants_only_coinbase(&self) -> bool {
+            self.only_coinbase
+        }
+    }
+
+    fn block_with_txs(n_tx: u32) -> Block {
+        let header = BlockHeader {
+            version: 1,
+            prev_hash: sha256d::Hash::all_zeros(),
+            merkle_root: sha256d::Hash::all_zeros(),
+            timestamp: 0,
+            bits: 0,
+            nonce: 0,
+        };
+        let txs = (0..n_tx)
+            .map(|i| RawTx {
+                version: 1,
+                in_count: VarUint::from(1u8),
+                inputs: vec![TxInput {
+                    outpoint: TxOutpoint::new(sha256d::Hash::all_zeros(), i),
+                    script_len: VarUint::from(0u8),
+                    script_sig: vec![],
+                    seq_no: 0xFFFFFFFF,
+                    witness: vec![],
+                }],
+                out_count: VarUint::from(0u8),
+                outputs: vec![],
+                locktime: 0,
+                address_format: AddressFormat::default(),
+                witness_size: 0,
+            })
+            .collect::<Vec<_>>();
+        Block::new(0, header, None, VarUint::from(n_tx as u8), txs)
+    }
+
+    #[test]
+    fn test_wants_only_coinbase() {
+        // Blocks without transactions are a no-op
+        let blocks = [
+            block_with_txs(3),
+            block_with_txs(0),
+            block_with_txs(1),
+            block_with_txs(5),
+        ];
+        for only_coinbase in [false, true] {
+            let mut callback = TxCounter {
+                only_coinbase,
+                n_tx: 0,
+            };
+            for (height, block) in blocks.iter().enumerate() {
+                let txs = select_txs(&callback, block);
+                callback.on_txs(txs, height as u64).unwrap();
+            }
+            let expected = if only_coinbase { 3 } else { 9 };
+            assert_eq!(callback.n_tx, expected);
+        }
+    }
+
+    #[test]
+    fn test_percentage() {
+        assert_eq!(percentage(0, 256), 0.0);
+        assert_eq!(percentage(64, 256), 25.0);
+        assert_eq!(percentage(300, 256), 100.0);
+        assert_eq!(percentage(0, 0), 100.0);
+    }
+
+    #[test]
+    fn test_state_digest() {
+        // Bitcoin genesis block
+        let raw_block = crate::common::utils::hex_to_vec(
+            "0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c0101000000010000000000000000000000000000000000000000000000000000000000000000ffffffff4d04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72206f6e206272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73ffffffff0100f2052a01000000434104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac00000000",
+        );
+        let coin = CoinType::default();
+        let block = Cursor::new(&raw_block)
+            .read_block(raw_block.len() as u32, &coin)
+            .unwrap();
+
+        let mut a = StateDigest::new(1);
+        let mut b = StateDigest::new(1);
+        a.update(&block);
+        b.update(&block);
+        assert_eq!(a.digest, b.digest);
+        assert_ne!(a.digest, sha256d::Hash::all_zeros());
+
+        // Folding in the same block again must change the digest
+        let first = a.digest;
+        a.update(&block);
+        assert_ne!(a.digest, first);
+    }
+}
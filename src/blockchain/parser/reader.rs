@@ -1,47 +1,106 @@
 use bitcoin::hashes::{sha256d, Hash};
 use std::borrow::BorrowMut;
-use std::io::{self};
+use std::io;
 
 use crate::blockchain::parser::types::CoinType;
 use byteorder::{LittleEndian, ReadBytesExt};
 
-use crate::blockchain::proto::block::{AuxPowExtension, Block};
+use crate::blockchain::proto::block::{AuxPowExtension, Block, VERSION_AUXPOW};
 use crate::blockchain::proto::header::BlockHeader;
 use crate::blockchain::proto::tx::{RawTx, TxInput, TxOutpoint, TxOutput};
 use crate::blockchain::proto::varuint::VarUint;
-use crate::blockchain::proto::MerkleBranch;
-use crate::errors::OpResult;
+use crate::blockchain::proto::{MerkleBranch, ToRaw};
+use crate::errors::{ParseErrorKind, ParseResult};
+
+/// Upper bound for CompactSize values, like MAX_SIZE in Bitcoin Core
+const MAX_COMPACT_SIZE: u64 = 0x02000000;
+/// A witness item can't be larger than the maximum serialized block size
+const MAX_WITNESS_ITEM_LEN: u64 = 4_000_000;
+
+/// Default maximum length of scriptSigs and scriptPubKeys, see `CoinType::max_script_size`
+pub const DEFAULT_MAX_SCRIPT_SIZE: u64 = 10_000;
+
+/// Checks that the CompactSize doesn't exceed the maximum size
+/// and, if `canonical` is set, that it is minimally encoded.
+fn check_compact_size(varint: &VarUint, canonical: bool) -> ParseResult<()> {
+    if (canonical && !varint.is_canonical()) || varint.value > MAX_COMPACT_SIZE {
+        return Err(ParseErrorKind::BadVarInt(varint.value).into());
+    }
+    Ok(())
+}
+
+/// Reads a single value from `raw` with `read`, all bytes have to be consumed
+pub fn read_exact_from<T, F>(raw: &[u8], read: F) -> ParseResult<T>
+where
+    F: FnOnce(&mut io::Cursor<&[u8]>) -> ParseResult<T>,
+{
+    let mut cursor = io::Cursor::new(raw);
+    let value = read(&mut cursor)?;
+    let trailing = raw.len() as u64 - cursor.position();
+    if trailing > 0 {
+        return Err(ParseErrorKind::TrailingBytes(trailing).into());
+    }
+    Ok(value)
+}
 
 /// Trait for structured reading of blockchain data
 pub trait BlockchainRead: io::Read {
-    fn read_256hash(&mut self) -> OpResult<[u8; 32]> {
+    fn read_256hash(&mut self) -> ParseResult<[u8; 32]> {
         let mut arr = [0u8; 32];
         self.read_exact(arr.borrow_mut())?;
         Ok(arr)
     }
 
-    fn read_u8_vec(&mut self, count: u32) -> OpResult<Vec<u8>> {
+    fn read_u8_vec(&mut self, count: u32) -> ParseResult<Vec<u8>> {
         let mut arr = vec![0u8; count as usize];
         self.read_exact(arr.borrow_mut())?;
         Ok(arr)
     }
 
+    /// Reads a CompactSize, see `check_compact_size()`
+    fn read_compact_size(&mut self, canonical: bool) -> ParseResult<VarUint> {
+        let varint = VarUint::read_from(self)?;
+        check_compact_size(&varint, canonical)?;
+        Ok(varint)
+    }
+
+    /// Reads the length of a script or witness item, which must not exceed `max_len`
+    fn read_script_len(&mut self, max_len: u64, canonical: bool) -> ParseResult<VarUint> {
+        let script_len = self.read_compact_size(canonical)?;
+        if script_len.value > max_len {
+            return Err(ParseErrorKind::ScriptTooLarge(script_len.value).into());
+        }
+        Ok(script_len)
+    }
+
     /// Reads a block as specified here: https://en.bitcoin.it/wiki/Protocol_specification#block
-    fn read_block(&mut self, size: u32, coin: &CoinType) -> OpResult<Block> {
+    fn read_block(&mut self, size: u32, coin: &CoinType) -> ParseResult<Block> {
         let header = self.read_block_header()?;
-        // Parse AuxPow data if present
+        // Parse AuxPow data if present, blocks which aren't merge mined don't set the AuxPow bit
         let aux_pow_extension = match coin.aux_pow_activation_version {
-            Some(version) if header.version >= version => {
-                Some(self.read_aux_pow_extension(coin.version_id)?)
+            Some(version) if header.version >= version && header.version & VERSION_AUXPOW != 0 => {
+                Some(self.read_aux_pow_extension(coin)?)
             }
             _ => None,
         };
-        let tx_count = VarUint::read_from(self)?;
-        let txs = self.read_txs(tx_count.value, coin.version_id)?;
-        Ok(Block::new(size, header, aux_pow_extension, tx_count, txs))
+        let tx_count = self.read_compact_size(coin.canonical_compact_size)?;
+        let txs = if !coin.has_mweb {
+            self.read_txs(tx_count.value, coin)?
+        } else {
+            // Blocks with a HogEx transaction are followed by the MWEB extension block (LIP-0003).
+            // It isn't parsed, the canonical transactions are read up to the block size and the rest is skipped.
+            let header_size = 80 + tx_count.to_bytes().len() as u64;
+            let mut body = io::Read::take(self, (size as u64).saturating_sub(header_size));
+            let txs = body.read_txs(tx_count.value, coin)?;
+            io::copy(&mut body, &mut io::sink())?;
+            txs
+        };
+        let mut block = Block::new(size, header, aux_pow_extension, tx_count, txs);
+        block.genesis = block.header.hash == coin.genesis_hash;
+        Ok(block)
     }
 
-    fn read_block_header(&mut self) -> OpResult<BlockHeader> {
+    fn read_block_header(&mut self) -> ParseResult<BlockHeader> {
         let version = self.read_u32::<LittleEndian>()?;
         let prev_hash = sha256d::Hash::from_byte_array(self.read_256hash()?);
         let merkle_root = sha256d::Hash::from_byte_array(self.read_256hash()?);
@@ -59,38 +118,54 @@ pub trait BlockchainRead: io::Read {
         })
     }
 
-    fn read_txs(&mut self, tx_count: u64, version_id: u8) -> OpResult<Vec<RawTx>> {
-        (0..tx_count).map(|_| self.read_tx(version_id)).collect()
+    fn read_txs(&mut self, tx_count: u64, coin: &CoinType) -> ParseResult<Vec<RawTx>> {
+        (0..tx_count).map(|_| self.read_tx(coin)).collect()
     }
 
     /// Reads a transaction as specified here: https://en.bitcoin.it/wiki/Protocol_specification#tx
-    fn read_tx(&mut self, version_id: u8) -> OpResult<RawTx> {
+    fn read_tx(&mut self, coin: &CoinType) -> ParseResult<RawTx> {
         let mut flags = 0u8;
         let version = self.read_u32::<LittleEndian>()?;
 
         // Parse transaction inputs and check if this transaction contains segwit data
-        let mut in_count = VarUint::read_from(self)?;
+        let mut in_count = self.read_compact_size(coin.canonical_compact_size)?;
         if in_count.value == 0 {
             flags = self.read_u8()?;
-            // TODO: handle segwit data
-            in_count = VarUint::read_from(self)?
+            in_count = self.read_compact_size(coin.canonical_compact_size)?
         }
-        let inputs = self.read_tx_inputs(in_count.value)?;
+        let mut inputs = self.read_tx_inputs(in_count.value, coin)?;
 
         // Parse transaction outputs
-        let out_count = VarUint::read_from(self)?;
-        let outputs = self.read_tx_outputs(out_count.value)?;
+        let out_count = self.read_compact_size(coin.canonical_compact_size)?;
+        let outputs = self.read_tx_outputs(out_count.value, coin)?;
 
+        let mut witness_size = 0;
+        if flags & (1 | 8) > 0 {
+            // Marker and flag
+            witness_size += 2;
+        }
         // Check if the witness flag is present
         if flags & 1 > 0 {
-            for _ in 0..in_count.value {
-                let item_count = VarUint::read_from(self)?;
+            for input in inputs.iter_mut() {
+                let item_count = self.read_compact_size(coin.canonical_compact_size)?;
+                witness_size += item_count.to_bytes().len() as u64;
                 for _ in 0..item_count.value {
-                    let witness_len = VarUint::read_from(self)?;
-                    let _ = self.read_u8_vec(witness_len.value as u32)?;
+                    let witness_len =
+                        self.read_script_len(MAX_WITNESS_ITEM_LEN, coin.canonical_compact_size)?;
+                    input
+                        .witness
+                        .push(self.read_u8_vec(witness_len.value as u32)?);
+                    witness_size += witness_len.to_bytes().len() as u64 + witness_len.value;
                 }
             }
         }
+        // Litecoin MWEB flag (LIP-0002), only the HogEx transaction without MWEB data appears in blocks
+        if flags & 8 > 0 {
+            witness_size += 1;
+            if self.read_u8()? != 0 {
+                return Err(ParseErrorKind::MwebTransaction.into());
+            }
+        }
         let locktime = self.read_u32::<LittleEndian>()?;
         let tx = RawTx {
             version,
@@ -99,23 +174,25 @@ pub trait BlockchainRead: io::Read {
             out_count,
             outputs,
             locktime,
-            version_id,
+            address_format: coin.address_format(),
+            witness_size,
         };
         Ok(tx)
     }
 
-    fn read_tx_outpoint(&mut self) -> OpResult<TxOutpoint> {
+    fn read_tx_outpoint(&mut self) -> ParseResult<TxOutpoint> {
         let txid = sha256d::Hash::from_byte_array(self.read_256hash()?);
         let index = self.read_u32::<LittleEndian>()?;
 
         Ok(TxOutpoint { txid, index })
     }
 
-    fn read_tx_inputs(&mut self, input_count: u64) -> OpResult<Vec<TxInput>> {
+    fn read_tx_inputs(&mut self, input_count: u64, coin: &CoinType) -> ParseResult<Vec<TxInput>> {
         let mut inputs = Vec::with_capacity(input_count as usize);
         for _ in 0..input_count {
             let outpoint = self.read_tx_outpoint()?;
-            let script_len = VarUint::read_from(self)?;
+            let script_len =
+                self.read_script_len(coin.max_script_size, coin.canonical_compact_size)?;
             let script_sig = self.read_u8_vec(script_len.value as u32)?;
             let seq_no = self.read_u32::<LittleEndian>()?;
             inputs.push(TxInput {
@@ -123,16 +200,25 @@ pub trait BlockchainRead: io::Read {
                 script_len,
                 script_sig,
                 seq_no,
+                witness: vec![],
             });
         }
         Ok(inputs)
     }
 
-    fn read_tx_outputs(&mut self, output_count: u64) -> OpResult<Vec<TxOutput>> {
+    fn read_tx_outputs(
+        &mut self,
+        output_count: u64,
+        coin: &CoinType,
+    ) -> ParseResult<Vec<TxOutput>> {
         let mut outputs = Vec::with_capacity(output_count as usize);
         for _ in 0..output_count {
             let value = self.read_u64::<LittleEndian>()?;
-            let script_len = VarUint::read_from(self)?;
+            if value > i64::MAX as u64 {
+                return Err(ParseErrorKind::ValueOverflow(value).into());
+            }
+            let script_len =
+                self.read_script_len(coin.max_script_size, coin.canonical_compact_size)?;
             let script_pubkey = self.read_u8_vec(script_len.value as u32)?;
             outputs.push(TxOutput {
                 value,
@@ -145,22 +231,22 @@ pub trait BlockchainRead: io::Read {
 
     /// Reads a merkle branch as specified here https://en.bitcoin.it/wiki/Merged_mining_specification#Merkle_Branch
     /// This is mainly used for merged mining (AuxPoW).
-    fn read_merkle_branch(&mut self) -> OpResult<MerkleBranch> {
-        let branch_length = VarUint::read_from(self)?;
+    fn read_merkle_branch(&mut self, canonical: bool) -> ParseResult<MerkleBranch> {
+        let branch_length = self.read_compact_size(canonical)?;
         let hashes = (0..branch_length.value)
             .map(|_| self.read_256hash())
-            .collect::<OpResult<Vec<[u8; 32]>>>()?;
+            .collect::<ParseResult<Vec<[u8; 32]>>>()?;
         let side_mask = self.read_u32::<LittleEndian>()?;
         Ok(MerkleBranch::new(hashes, side_mask))
     }
 
     /// Reads the additional AuxPow fields as specified here https://en.bitcoin.it/wiki/Merged_mining_specification#Aux_proof-of-work_block
-    fn read_aux_pow_extension(&mut self, version_id: u8) -> OpResult<AuxPowExtension> {
-        let coinbase_tx = self.read_tx(version_id)?;
+    fn read_aux_pow_extension(&mut self, coin: &CoinType) -> ParseResult<AuxPowExtension> {
+        let coinbase_tx = self.read_tx(coin)?;
         let block_hash = sha256d::Hash::from_byte_array(self.read_256hash()?);
 
-        let coinbase_branch = self.read_merkle_branch()?;
-        let blockchain_branch = self.read_merkle_branch()?;
+        let coinbase_branch = self.read_merkle_branch(coin.canonical_compact_size)?;
+        let blockchain_branch = self.read_merkle_branch(coin.canonical_compact_size)?;
 
         let parent_block = self.read_block_header()?;
 
@@ -181,10 +267,11 @@ impl<R: io::Read + ?Sized> BlockchainRead for R {}
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::blockchain::parser::types::{Bitcoin, Coin, Dogecoin};
+    use crate::blockchain::parser::types::{Bitcoin, Dogecoin, Litecoin, PowAlgorithm};
     use crate::blockchain::proto::script;
     use crate::blockchain::proto::script::ScriptPattern;
-    use crate::blockchain::proto::tx::EvaluatedTx;
+    use crate::blockchain::proto::tx::{EvaluatedTx, SizeBasis};
+    use crate::blockchain::proto::Hashed;
     use crate::common::utils;
     use byteorder::{LittleEndian, ReadBytesExt};
     use seek_bufread::BufReader;
@@ -322,10 +409,40 @@ mod tests {
 
         assert_eq!(
             Some(String::from("1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa")),
-            script::eval_from_bytes(script_pubkey, Bitcoin.version_id()).address
+            script::eval_from_bytes(script_pubkey, CoinType::from(Bitcoin).address_format())
+                .address
         );
     }
 
+    #[test]
+    fn test_parse_zero_tx_block() {
+        let header = BlockHeader {
+            version: 1,
+            prev_hash: sha256d::Hash::all_zeros(),
+            merkle_root: sha256d::Hash::all_zeros(),
+            timestamp: 1231006505,
+            bits: 0x1d00ffff,
+            nonce: 2083236893,
+        };
+        let mut raw_data = header.to_bytes();
+        raw_data.extend(VarUint::from(0u8).to_bytes());
+
+        let mut reader = Cursor::new(&raw_data);
+        let block = reader
+            .read_block(raw_data.len() as u32, &CoinType::default())
+            .unwrap();
+        assert_eq!(block.tx_count.value, 0);
+        assert!(block.txs.is_empty());
+        assert_eq!(reader.position(), raw_data.len() as u64);
+        assert!(Cursor::new(&raw_data[..0])
+            .read_txs(0, &CoinType::default())
+            .unwrap()
+            .is_empty());
+
+        // The merkle root of an empty block is all zeros
+        assert!(block.verify_merkle_root().is_ok());
+    }
+
     #[test]
     fn test_bitcoin_parse_segwit_tx() {
         // See: https://en.bitcoin.it/wiki/Weight_units#Weight_for_segwit_transactions
@@ -368,10 +485,10 @@ mod tests {
             0xea, 0x86, 0xd0, 0x2f, 0xf8, 0xe3, 0x32, 0x8b, 0xbd, 0x02, 0x42, 0xb2, 0x0a, 0xf3,
             0x42, 0x59, 0x90, 0xac, 0x00, 0x00, 0x00, 0x00,
         ];
-        let inner = Cursor::new(raw_data);
+        let inner = Cursor::new(raw_data.clone());
         let mut reader = BufReader::with_capacity(200, inner);
         let txs: Vec<EvaluatedTx> = reader
-            .read_txs(1, 0x00)
+            .read_txs(1, &CoinType::default())
             .unwrap()
             .into_iter()
             .map(|raw| EvaluatedTx::from(raw))
@@ -380,10 +497,30 @@ mod tests {
 
         let tx = txs.first().unwrap();
         assert_eq!(tx.version, 1);
+        // See the weight calculation in the linked wiki article
+        assert_eq!(tx.witness_size, 110);
+        assert_eq!(tx.weight(), 542);
+        assert_eq!(tx.vsize(), 136);
+        assert_eq!(tx.base_size(), 108);
+        assert_eq!(tx.total_size(), 218);
+        assert_eq!(tx.weight(), tx.base_size() * 3 + tx.total_size());
+        for (basis, size) in [
+            ("base", 108),
+            ("total", 218),
+            ("weight", 542),
+            ("vsize", 136),
+        ] {
+            assert_eq!(tx.size(basis.parse().unwrap()), size);
+        }
+        assert!("stripped".parse::<SizeBasis>().is_err());
 
         // Assert inputs
         assert_eq!(tx.in_count.value, 1);
         assert_eq!(tx.inputs.len(), 1);
+        assert!(tx.inputs[0].has_witness());
+        // Signature and public key
+        assert_eq!(tx.inputs[0].witness.len(), 2);
+        assert_eq!(tx.inputs[0].witness[1].len(), 33);
         let prev_hash = [
             0x15, 0xe1, 0x80, 0xdc, 0x28, 0xa2, 0x32, 0x7e, 0x68, 0x7f, 0xac, 0xc3, 0x3f, 0x10,
             0xf2, 0xa2, 0x0d, 0xa7, 0x17, 0xe5, 0x54, 0x84, 0x06, 0xf7, 0xae, 0x8b, 0x4c, 0x81,
@@ -408,9 +545,134 @@ mod tests {
             Some(String::from("13gv9XbKJPxxRF8Zm1LsVKeeiMCFguQPqm"))
         );
 
+        // The txid is computed without marker, flag and witness data, the wtxid over the whole tx
+        let stripped = [&raw_data[..4], &raw_data[6..106], &raw_data[214..]].concat();
+        assert_eq!(tx.to_bytes(), stripped);
+        assert_eq!(tx.to_witness_bytes(), raw_data);
+        let hashed = EvaluatedTx::parse(&raw_data, &CoinType::default()).unwrap();
+        assert_eq!(hashed.hash, sha256d::Hash::hash(&stripped));
+        assert_eq!(hashed.value.wtxid(), sha256d::Hash::hash(&raw_data));
+        assert_ne!(hashed.hash, hashed.value.wtxid());
+        // Without witness data both hashes are equal
+        let legacy = EvaluatedTx::parse(&stripped, &CoinType::default()).unwrap();
+        assert_eq!(legacy.hash, hashed.hash);
+        assert_eq!(legacy.value.wtxid(), legacy.hash);
+
         assert_eq!(tx.locktime, 0);
     }
 
+    #[test]
+    fn test_parse_errors() {
+        let parse = |hex: &str| {
+            Cursor::new(utils::hex_to_vec(hex))
+                .read_tx(&CoinType::default())
+                .map(|_| ())
+        };
+        let tx = |in_count: &str, script_len: &str, value: &str| {
+            format!(
+                "01000000{}{}ffffffff00ffffffff01{}{}00000000",
+                in_count,
+                "00".repeat(32),
+                value,
+                script_len
+            )
+        };
+        let valid = tx("01", "00", "00f2052a01000000");
+        assert!(parse(&valid).is_ok());
+
+        let err = parse(&valid[..valid.len() - 4]).unwrap_err();
+        assert!(matches!(err.kind, ParseErrorKind::UnexpectedEof));
+        // 1 encoded as uint16 and uint64 is only rejected with canonical CompactSizes
+        assert!(parse(&tx("fd0100", "00", "00f2052a01000000")).is_ok());
+        assert!(parse(&tx("ff0100000000000000", "00", "00f2052a01000000")).is_ok());
+        let err = parse(&tx("fe01000002", "00", "00f2052a01000000")).unwrap_err();
+        assert!(matches!(err.kind, ParseErrorKind::BadVarInt(0x02000001)));
+        let err = parse(&tx("01", "fe00000001", "00f2052a01000000")).unwrap_err();
+        assert!(matches!(
+            err.kind,
+            ParseErrorKind::ScriptTooLarge(0x01000000)
+        ));
+        let err = parse(&tx("01", "00", "ffffffffffffffff")).unwrap_err();
+        assert!(matches!(err.kind, ParseErrorKind::ValueOverflow(u64::MAX)));
+    }
+
+    #[test]
+    fn test_check_compact_size() {
+        let minimal = VarUint::read_from(&mut Cursor::new([0x01])).unwrap();
+        let non_minimal = VarUint::read_from(&mut Cursor::new([0xfd, 0x01, 0x00])).unwrap();
+        assert_eq!(minimal.value, non_minimal.value);
+        for canonical in [false, true] {
+            assert!(check_compact_size(&minimal, canonical).is_ok());
+        }
+        assert!(check_compact_size(&non_minimal, false).is_ok());
+        let err = check_compact_size(&non_minimal, true).unwrap_err();
+        assert!(matches!(err.kind, ParseErrorKind::BadVarInt(1)));
+    }
+
+    #[test]
+    fn test_max_script_size() {
+        let parse = |hex: String| {
+            Cursor::new(utils::hex_to_vec(&hex))
+                .read_tx(&CoinType::default())
+                .map(|_| ())
+        };
+        let outpoint = "00".repeat(36);
+        // 10001 bytes are declared, but not present
+        let script_sig = format!("0100000001{}fd1127", outpoint);
+        let err = parse(script_sig).unwrap_err();
+        assert!(matches!(err.kind, ParseErrorKind::ScriptTooLarge(10001)));
+        let script_pubkey = format!("0100000001{}00ffffffff0100f2052a01000000fd1127", outpoint);
+        let err = parse(script_pubkey).unwrap_err();
+        assert!(matches!(err.kind, ParseErrorKind::ScriptTooLarge(10001)));
+
+        // Witness items are only limited by the block size
+        let mut cursor = Cursor::new([0xfd, 0x11, 0x27]);
+        assert!(cursor.read_script_len(MAX_WITNESS_ITEM_LEN, false).is_ok());
+    }
+
+    #[test]
+    fn test_parse_zero_output_txs() {
+        let mut raw_data = Vec::new();
+        // Non-witness tx without outputs
+        raw_data.extend([0x01, 0x00, 0x00, 0x00, 0x01]);
+        raw_data.extend([0x11; 32]);
+        raw_data.extend([0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff]);
+        raw_data.extend([0x00, 0x00, 0x00, 0x00, 0x00]);
+        let legacy_len = raw_data.len();
+        // Witness tx without outputs
+        raw_data.extend([0x02, 0x00, 0x00, 0x00, 0x00, 0x01, 0x01]);
+        raw_data.extend([0x22; 32]);
+        raw_data.extend([0x01, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff]);
+        raw_data.push(0x00);
+        raw_data.extend([0x01, 0x02, 0xaa, 0xbb]);
+        raw_data.extend([0x00, 0x00, 0x00, 0x00]);
+        // Regular tx with one output to detect a desync
+        raw_data.extend([0x01, 0x00, 0x00, 0x00, 0x01]);
+        raw_data.extend([0x33; 32]);
+        raw_data.extend([0x02, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff]);
+        raw_data.extend([0x01, 0xe8, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        raw_data.extend([0x00, 0x00, 0x00, 0x00]);
+
+        let mut reader = Cursor::new(&raw_data);
+        let txs: Vec<Hashed<EvaluatedTx>> = reader
+            .read_txs(3, &CoinType::default())
+            .unwrap()
+            .into_iter()
+            .map(|tx| Hashed::double_sha256(EvaluatedTx::from(tx)))
+            .collect();
+        assert_eq!(reader.position(), raw_data.len() as u64);
+
+        assert_eq!(txs[0].value.out_count.value, 0);
+        assert!(txs[0].value.outputs.is_empty());
+        assert_eq!(txs[0].value.to_bytes(), raw_data[..legacy_len]);
+        assert_eq!(txs[1].value.version, 2);
+        assert_eq!(txs[1].value.out_count.value, 0);
+        assert!(txs[1].value.inputs[0].has_witness());
+        assert_eq!(txs[1].value.witness_size, 6);
+        assert_eq!(txs[2].value.inputs[0].outpoint.index, 2);
+        assert_eq!(txs[2].value.outputs[0].out.value, 1000);
+    }
+
     #[test]
     fn test_namecoin_parse_auxpow_block() {
         let namecoin = CoinType::from_str("namecoin").unwrap();
@@ -562,6 +824,7 @@ mod tests {
             "d8a7c3e01e1e95bcee015e6fcc7583a2ca60b79e5a3aa0a171eddd344ada903d",
             format!("{}", &block.header.hash)
         );
+        assert!(block.verify_pow(PowAlgorithm::Sha256d).is_ok());
 
         // Check against computed merkle root
         //assert_eq!(&block.header.merkle_root, &block.compute_merkle_root());
@@ -623,15 +886,14 @@ mod tests {
             Right: Some("NHk86XHZ77H2uNgESo4ut598orZq8rcVKL")
         assert_eq!(
             Some(String::from("N1hd3xArZM8BaX2PGGvoTWDr7C66Payv7b")),
-            script::eval_from_bytes(script_pubkey, Namecoin.version_id()).address
+            script::eval_from_bytes(script_pubkey, CoinType::from(Namecoin).address_format()).address
         );*/
     }
 
-    #[test]
-    fn test_dogecoin_parse_auxpow_block() {
-        let dogecoin = CoinType::from_str("dogecoin").unwrap();
-        // See:https://dogechain.info/block/3983721
-        let raw_data = vec![
+    /// Dogecoin block 3983721, merge mined with Litecoin
+    /// See: https://dogechain.info/block/3983721
+    fn dogecoin_auxpow_block() -> Vec<u8> {
+        vec![
             0x04, 0x01, 0x62, 0x00, 0xee, 0x00, 0x53, 0x04, 0x59, 0x8f, 0x0f, 0x02, 0xbb, 0x7c,
             0xd4, 0xab, 0xd5, 0x35, 0x87, 0x0d, 0x10, 0x88, 0x6c, 0x8d, 0x15, 0x6e, 0x77, 0xf4,
             0xf0, 0x48, 0xac, 0xd6, 0x85, 0xa0, 0x4f, 0x20, 0xd9, 0x5b, 0xca, 0x86, 0x7d, 0x52,
@@ -834,7 +1096,13 @@ mod tests {
             0x00, 0x00, 0x19, 0x76, 0xa9, 0x14, 0x6c, 0x53, 0xcd, 0x10, 0x02, 0x5e, 0xe8, 0x69,
             0xfc, 0xb8, 0x3d, 0xb9, 0x4d, 0x9f, 0xf5, 0x1e, 0xc8, 0xd3, 0x22, 0xb7, 0x88, 0xac,
             0x65, 0xc9, 0x3c, 0x00,
-        ];
+        ]
+    }
+
+    #[test]
+    fn test_dogecoin_parse_auxpow_block() {
+        let dogecoin = CoinType::from_str("dogecoin").unwrap();
+        let raw_data = dogecoin_auxpow_block();
         let block_size = 2818;
         let inner = Cursor::new(raw_data);
         let mut reader = BufReader::with_capacity(block_size, inner);
@@ -866,8 +1134,15 @@ mod tests {
         assert_eq!(0x1a04a51b, block.header.value.bits);
         assert_eq!(0, block.header.value.nonce);
 
+        // The work is done on the Litecoin parent block
+        assert!(block.verify_pow(PowAlgorithm::Scrypt).is_ok());
+        assert!(!block
+            .header
+            .value
+            .meets_target(&block.header.value.target(), PowAlgorithm::Scrypt));
+
         // AuxPoWBlock
-        let aux_pow_block = block.aux_pow_extension.unwrap();
+        let aux_pow_block = block.aux_pow_extension.as_ref().unwrap();
         // AuxPoWBlock coinbase tx
         assert_eq!(0x01, aux_pow_block.coinbase_tx.version);
         assert_eq!(0x00, aux_pow_block.coinbase_tx.locktime);
@@ -877,6 +1152,11 @@ mod tests {
         );
 
         // TODO: verify AuxPowBlock merkle branches
+        // The merkle tx refers to the parent block
+        assert_eq!(
+            aux_pow_block.block_hash,
+            block.aux_pow_parent_hash().unwrap()
+        );
 
         assert_eq!(
             "bcf46567b86d599288fe672a913762d7292b461a04b891dee88e52196adefd9e",
@@ -919,7 +1199,97 @@ mod tests {
 
         assert_eq!(
             Some(String::from("DEfXb18bE8RoC6edc9jXaMpEpuvVkcjJFq")),
-            script::eval_from_bytes(script_pubkey, Dogecoin.version_id()).address
+            script::eval_from_bytes(script_pubkey, CoinType::from(Dogecoin).address_format())
+                .address
         );
     }
+
+    #[test]
+    fn test_dogecoin_parse_block_without_auxpow() {
+        // Blocks which aren't merge mined don't set the AuxPow bit, even above the activation version.
+        // Built from block 3983721 without its AuxPow extension and the bit cleared.
+        let raw_data = dogecoin_auxpow_block();
+        let mut reader = Cursor::new(&raw_data);
+        let mut header = reader.read_block_header().unwrap();
+        reader
+            .read_aux_pow_extension(&CoinType::from(Dogecoin))
+            .unwrap();
+        let body = &raw_data[reader.position() as usize..];
+        // Versionbits signalling puts the version above the activation version
+        header.version = 0x20620004;
+        let mut raw_data = header.to_bytes();
+        raw_data.extend_from_slice(body);
+
+        let dogecoin = CoinType::from_str("dogecoin").unwrap();
+        let mut reader = Cursor::new(&raw_data);
+        let block = reader.read_block(raw_data.len() as u32, &dogecoin).unwrap();
+        assert!(block.aux_pow_extension.is_none());
+        assert!(block.aux_pow_parent_hash().is_none());
+        assert_eq!(8, block.tx_count.value);
+        assert_eq!(
+            "dc8dbed0461ec54a9524fc12fbed7466e6acb0f0637fcb2a0111174c84753fec",
+            format!("{}", &block.txs[0].hash)
+        );
+        assert!(block.verify_merkle_root().is_ok());
+        assert_eq!(reader.position(), raw_data.len() as u64);
+
+        // With the bit set, the transactions would be read as AuxPow extension
+        header.version |= VERSION_AUXPOW;
+        let mut raw_data = header.to_bytes();
+        raw_data.extend_from_slice(body);
+        assert!(Cursor::new(&raw_data)
+            .read_block(raw_data.len() as u32, &dogecoin)
+            .is_err());
+    }
+
+    #[test]
+    fn test_litecoin_parse_mweb_block() {
+        // Block laid out like LIP-0002/0003: a coinbase, the HogEx transaction with the MWEB flag and
+        // no MWEB data, followed by the extension block (0x01 and an opaque mw::Block payload)
+        let raw_data = utils::hex_to_vec(concat!(
+            "0000002000000000000000000000000000000000000000000000000000000000",
+            "0000000088f030df19f48637201b657f751c326e8b8aea87cb4fa375982c4b0a",
+            "33a1ff20805a95622dcd011a0000000002020000000100000000000000000000",
+            "00000000000000000000000000000000000000000000ffffffff0403a0d023ff",
+            "ffffff01807c814a000000001976a91462e907b15cbf27d5425399ebf6f0fb50",
+            "ebb88f1888ac0000000002000000000801000102030405060708090a0b0c0d0e",
+            "0f101112131415161718191a1b1c1d1e1f0000000000ffffffff0100ca9a3b00",
+            "000000225820abababababababababababababababababababababababababab",
+            "abababababab0000000000015a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a",
+            "5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a",
+            "5a5a5a5a5a5a5a5a5a5a5a",
+        ));
+        let litecoin = CoinType::from(Litecoin);
+        let block = Block::parse(&raw_data, &litecoin).unwrap();
+        assert_eq!(block.tx_count.value, 2);
+        assert_eq!(block.txs.len(), 2);
+        // The HogEx txid doesn't commit to the MWEB flag
+        assert_eq!(
+            "ab189f9b991298fbb0f3b8e3f248d25f35eef97a730cf4cd3b41c7218fda2f26",
+            format!("{}", &block.txs[1].hash)
+        );
+        assert_eq!(block.txs[1].value.witness_size, 3);
+        assert_eq!(
+            &block.header.value.merkle_root,
+            &block.compute_merkle_root()
+        );
+
+        // Reading from a stream stops at the end of the block
+        let mut stream = raw_data.clone();
+        stream.extend_from_slice(&[0xf9, 0xbe]);
+        let mut cursor = Cursor::new(&stream);
+        let block = cursor.read_block(raw_data.len() as u32, &litecoin).unwrap();
+        assert_eq!(block.txs.len(), 2);
+        assert_eq!(cursor.position(), raw_data.len() as u64);
+
+        // Without MWEB support the extension block is left over
+        let err = Block::parse(&raw_data, &CoinType::default()).unwrap_err();
+        assert!(matches!(err.kind, ParseErrorKind::TrailingBytes(64)));
+
+        // MWEB data within a transaction can't be parsed
+        let mut mweb_tx = raw_data.clone();
+        mweb_tx[262] = 0x01;
+        let err = Block::parse(&mweb_tx, &litecoin).unwrap_err();
+        assert!(matches!(err.kind, ParseErrorKind::MwebTransaction));
+    }
 }
@@ -0,0 +1,96 @@
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::blockchain::parser::chain::ChainStorage;
+use crate::blockchain::proto::block::Block;
+use crate::errors::OpResult;
+
+/// Returns an async stream of all blocks starting at `start_height` in order.
+/// Blocks are read and parsed on the blocking threadpool, at most `capacity` parsed blocks
+/// are buffered, so a slow consumer pauses the reader instead of piling up blocks in memory.
+/// Must be called from within a tokio runtime.
+#[allow(dead_code)]
+pub fn block_stream(
+    mut chain_storage: ChainStorage,
+    start_height: u64,
+    capacity: usize,
+) -> ReceiverStream<OpResult<Block>> {
+    let mut height = start_height;
+    spawn_reader(
+        move || {
+            let block = chain_storage.get_block(height)?;
+            height += 1;
+            Ok(block)
+        },
+        capacity,
+    )
+}
+
+/// Calls `next_block` on the blocking threadpool until it yields None or an error
+/// and forwards each result through a bounded channel.
+pub(crate) fn spawn_reader<F>(mut next_block: F, capacity: usize) -> ReceiverStream<OpResult<Block>>
+where
+    F: FnMut() -> OpResult<Option<Block>> + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel(capacity.max(1));
+    tokio::task::spawn_blocking(move || loop {
+        let result = match next_block() {
+            Ok(Some(block)) => Ok(block),
+            Ok(None) => break,
+            Err(err) => Err(err),
+        };
+        let is_err = result.is_err();
+        // Blocks until the consumer catches up, stops if the stream was dropped
+        if tx.blocking_send(result).is_err() || is_err {
+            break;
+        }
+    });
+    ReceiverStream::new(rx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::header::BlockHeader;
+    use crate::blockchain::proto::varuint::VarUint;
+    use bitcoin::hashes::{sha256d, Hash};
+    use tokio_stream::StreamExt;
+
+    fn block_with_timestamp(timestamp: u32) -> Block {
+        let header = BlockHeader {
+            version: 1,
+            prev_hash: sha256d::Hash::all_zeros(),
+            merkle_root: sha256d::Hash::all_zeros(),
+            timestamp,
+            bits: 0,
+            nonce: 0,
+        };
+        Block::new(0, header, None, VarUint::from(0u8), vec![])
+    }
+
+    #[test]
+    fn test_block_stream_order() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let timestamps = runtime.block_on(async {
+            let mut next = 0;
+            let mut stream = spawn_reader(
+                move || {
+                    if next == 100 {
+                        return Ok(None);
+                    }
+                    next += 1;
+                    Ok(Some(block_with_timestamp(next)))
+                },
+                2,
+            );
+            let mut timestamps = Vec::new();
+            while let Some(block) = stream.next().await {
+                timestamps.push(block.unwrap().header.value.timestamp);
+            }
+            timestamps
+        });
+        assert_eq!(timestamps, (1..=100).collect::<Vec<u32>>());
+    }
+}
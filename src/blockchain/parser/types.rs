@@ -3,6 +3,8 @@ use std::convert::From;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
+use crate::blockchain::parser::reader::DEFAULT_MAX_SCRIPT_SIZE;
+use crate::blockchain::proto::script::AddressFormat;
 use crate::errors::{OpError, OpErrorKind, OpResult};
 
 /// Trait to specify the underlying coin of a blockchain
@@ -16,19 +18,45 @@ pub trait Coin {
     fn version_id(&self) -> u8;
     // Returns genesis hash
     fn genesis(&self) -> sha256d::Hash;
-    // Activates AuxPow for the returned version and above
+    // Activates AuxPow for the returned version and above, if the AuxPow version bit is set
     fn aux_pow_activation_version(&self) -> Option<u32> {
         None
     }
+    // Maximum amount of units a single output (or the sum of a transaction's outputs) may hold
+    fn max_money(&self) -> u64 {
+        21_000_000 * 100_000_000
+    }
+    // Hash function of the proof of work, None if it is not supported.
+    // The block hash used to identify blocks is always double SHA256.
+    fn pow_algorithm(&self) -> Option<PowAlgorithm> {
+        Some(PowAlgorithm::Sha256d)
+    }
+    // Human readable part of CashAddr addresses, None if addresses are base58 encoded
+    fn cashaddr_prefix(&self) -> Option<&'static str> {
+        None
+    }
+    // Whether blocks may end with a MWEB extension block (Litecoin, LIP-0003)
+    fn has_mweb(&self) -> bool {
+        false
+    }
     // Default working directory to look for datadir, for example .bitcoin
     fn default_folder(&self) -> PathBuf;
 }
 
+/// Hash functions used for the proof of work of block headers
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PowAlgorithm {
+    Sha256d,
+    // scrypt with N=1024, r=1, p=1 as used by Litecoin
+    Scrypt,
+}
+
 // Implemented blockchain types.
 // If you want to add you own coin, create a struct with a Coin implementation
 // and add the coin name to from_str() below
 pub struct Bitcoin;
 pub struct TestNet3;
+pub struct BitcoinCash;
 pub struct Namecoin;
 pub struct Litecoin;
 pub struct Dogecoin;
@@ -76,6 +104,29 @@ impl Coin for TestNet3 {
     }
 }
 
+/// Bitcoin Cash, which shares the history of Bitcoin up to the fork at height 478559
+impl Coin for BitcoinCash {
+    fn name(&self) -> String {
+        String::from("BitcoinCash")
+    }
+    fn magic(&self) -> u32 {
+        0xe8f3e1e3
+    }
+    fn version_id(&self) -> u8 {
+        0x00
+    }
+    fn genesis(&self) -> sha256d::Hash {
+        sha256d::Hash::from_str("000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f")
+            .unwrap()
+    }
+    fn cashaddr_prefix(&self) -> Option<&'static str> {
+        Some("bitcoincash")
+    }
+    fn default_folder(&self) -> PathBuf {
+        Path::new(".bitcoin").join("blocks")
+    }
+}
+
 impl Coin for Namecoin {
     fn name(&self) -> String {
         String::from("Namecoin")
@@ -112,6 +163,15 @@ impl Coin for Litecoin {
         sha256d::Hash::from_str("12a765e31ffd4059bada1e25190f6e98c99d9714d334efa41a195a7e7e04bfe2")
             .unwrap()
     }
+    fn max_money(&self) -> u64 {
+        84_000_000 * 100_000_000
+    }
+    fn pow_algorithm(&self) -> Option<PowAlgorithm> {
+        Some(PowAlgorithm::Scrypt)
+    }
+    fn has_mweb(&self) -> bool {
+        true
+    }
     fn default_folder(&self) -> PathBuf {
         Path::new(".litecoin").join("blocks")
     }
@@ -134,6 +194,12 @@ impl Coin for Dogecoin {
     fn aux_pow_activation_version(&self) -> Option<u32> {
         Some(0x620102)
     }
+    fn max_money(&self) -> u64 {
+        10_000_000_000 * 100_000_000
+    }
+    fn pow_algorithm(&self) -> Option<PowAlgorithm> {
+        Some(PowAlgorithm::Scrypt)
+    }
     fn default_folder(&self) -> PathBuf {
         Path::new(".dogecoin").join("blocks")
     }
@@ -153,6 +219,13 @@ impl Coin for Myriadcoin {
         sha256d::Hash::from_str("00000ffde4c020b5938441a0ea3d314bf619eff0b38f32f78f7583cffa1ea485")
             .unwrap()
     }
+    fn max_money(&self) -> u64 {
+        2_000_000_000 * 100_000_000
+    }
+    fn pow_algorithm(&self) -> Option<PowAlgorithm> {
+        // Multiple algorithms, selected by the block version
+        None
+    }
     fn default_folder(&self) -> PathBuf {
         Path::new(".myriadcoin").join("blocks")
     }
@@ -172,6 +245,9 @@ impl Coin for Unobtanium {
         sha256d::Hash::from_str("000004c2fc5fffb810dccc197d603690099a68305232e552d96ccbe8e2c52b75")
             .unwrap()
     }
+    fn max_money(&self) -> u64 {
+        250_000 * 100_000_000
+    }
     fn default_folder(&self) -> PathBuf {
         Path::new(".unobtanium").join("blocks")
     }
@@ -191,6 +267,10 @@ impl Coin for NoteBlockchain {
         sha256d::Hash::from_str("270f3e7b185c412d57ba913d10658df54f15201a67d736cb4071a4ec4eb54836")
             .unwrap()
     }
+    fn pow_algorithm(&self) -> Option<PowAlgorithm> {
+        // Unknown
+        None
+    }
     fn default_folder(&self) -> PathBuf {
         Path::new(".notecoin").join("blocks")
     }
@@ -213,7 +293,18 @@ pub struct CoinType {
     pub version_id: u8,
     pub genesis_hash: sha256d::Hash,
     pub aux_pow_activation_version: Option<u32>,
+    pub max_money: u64,
+    pub pow_algorithm: Option<PowAlgorithm>,
+    pub cashaddr_prefix: Option<&'static str>,
+    pub has_mweb: bool,
     pub default_folder: PathBuf,
+    // Disabled with --no-address, see `address_format()`
+    pub resolve_addresses: bool,
+    // Enabled with --strict, rejects CompactSizes which aren't minimally encoded like Bitcoin Core
+    pub canonical_compact_size: bool,
+    // Set with --max-script-size, larger scriptSigs and scriptPubKeys fail with `ScriptTooLarge`
+    // before anything is allocated
+    pub max_script_size: u64,
 }
 
 impl Default for CoinType {
@@ -230,7 +321,25 @@ impl<T: Coin> From<T> for CoinType {
             version_id: coin.version_id(),
             genesis_hash: coin.genesis(),
             aux_pow_activation_version: coin.aux_pow_activation_version(),
+            max_money: coin.max_money(),
+            pow_algorithm: coin.pow_algorithm(),
+            cashaddr_prefix: coin.cashaddr_prefix(),
+            has_mweb: coin.has_mweb(),
             default_folder: coin.default_folder(),
+            resolve_addresses: true,
+            canonical_compact_size: false,
+            max_script_size: DEFAULT_MAX_SCRIPT_SIZE,
+        }
+    }
+}
+
+impl CoinType {
+    /// Returns how the addresses of output scripts are encoded
+    pub fn address_format(&self) -> AddressFormat {
+        AddressFormat {
+            version_id: self.version_id,
+            resolve: self.resolve_addresses,
+            cashaddr_prefix: self.cashaddr_prefix,
         }
     }
 }
@@ -241,6 +350,7 @@ impl FromStr for CoinType {
         match coin_name {
             "bitcoin" => Ok(CoinType::from(Bitcoin)),
             "testnet3" => Ok(CoinType::from(TestNet3)),
+            "bitcoincash" => Ok(CoinType::from(BitcoinCash)),
             "namecoin" => Ok(CoinType::from(Namecoin)),
             "litecoin" => Ok(CoinType::from(Litecoin)),
             "dogecoin" => Ok(CoinType::from(Dogecoin)),
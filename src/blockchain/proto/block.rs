@@ -1,14 +1,23 @@
-use bitcoin::hashes::sha256d;
+use bitcoin::hashes::{sha256d, Hash};
 use std::fmt;
 
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
 
+use crate::blockchain::parser::reader::{self, BlockchainRead};
+use crate::blockchain::parser::types::{CoinType, PowAlgorithm};
 use crate::blockchain::proto::header::BlockHeader;
-use crate::blockchain::proto::tx::{EvaluatedTx, RawTx};
+use crate::blockchain::proto::tx::{EvaluatedTx, RawTx, ResolvedOutput, TxInput};
 use crate::blockchain::proto::varuint::VarUint;
-use crate::blockchain::proto::{Hashed, MerkleBranch};
+use crate::blockchain::proto::{Hashed, MerkleBranch, ToRaw};
+use crate::common::profile::{self, Phase};
 use crate::common::utils;
-use crate::errors::{OpError, OpErrorKind, OpResult};
+use crate::errors::{OpError, OpErrorKind, OpResult, ParseResult};
+
+/// Version bit of merge mined blocks which carry an AuxPow extension after the header
+pub const VERSION_AUXPOW: u32 = 1 << 8;
+
+/// Start of the witness commitment output in the coinbase (BIP141): OP_RETURN, push 36 bytes, 0xaa21a9ed
+const WITNESS_COMMITMENT_HEADER: [u8; 6] = [0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed];
 
 /// Basic block structure which holds all information
 pub struct Block {
@@ -17,6 +26,8 @@ pub struct Block {
     pub aux_pow_extension: Option<AuxPowExtension>,
     pub tx_count: VarUint,
     pub txs: Vec<Hashed<EvaluatedTx>>,
+    // Whether this is the genesis block of the parsed chain, set when read with a CoinType
+    pub genesis: bool,
 }
 
 impl Block {
@@ -27,19 +38,43 @@ impl Block {
         tx_count: VarUint,
         txs: Vec<RawTx>,
     ) -> Block {
-        let txs = txs
-            .into_par_iter()
-            .map(|raw| Hashed::double_sha256(EvaluatedTx::from(raw)))
-            .collect();
+        let txs = profile::measure(Phase::Scripts, || {
+            txs.into_par_iter()
+                .map(|raw| Hashed::double_sha256(EvaluatedTx::from(raw)))
+                .collect()
+        });
         Block {
             size,
             header: Hashed::double_sha256(header),
             aux_pow_extension,
             tx_count,
             txs,
+            genesis: false,
         }
     }
 
+    /// Parses a serialized block without magic and size prefix, e.g. as returned by `getblock <hash> 0`
+    pub fn parse(raw: &[u8], coin: &CoinType) -> ParseResult<Block> {
+        reader::read_exact_from(raw, |cursor| cursor.read_block(raw.len() as u32, coin))
+    }
+
+    /// Returns the stripped size in bytes, without segwit markers, flags and witness data
+    pub fn base_size(&self) -> u64 {
+        let witness_size = self.txs.iter().map(|tx| tx.value.witness_size).sum::<u64>();
+        (self.size as u64).saturating_sub(witness_size)
+    }
+
+    /// Returns the weight as defined in BIP141: base size * 3 + total size.
+    /// Equals size * 4 for blocks without witness data.
+    pub fn weight(&self) -> u64 {
+        self.base_size() * 3 + self.size as u64
+    }
+
+    /// Returns the virtual size in vbytes (weight / 4, rounded up)
+    pub fn vsize(&self) -> u64 {
+        self.weight().div_ceil(4)
+    }
+
     /// Computes merkle root for all containing transactions
     pub fn compute_merkle_root(&self) -> sha256d::Hash {
         let hashes = self
@@ -65,6 +100,144 @@ impl Block {
             Err(OpError::new(OpErrorKind::ValidationError).join_msg(&msg))
         }
     }
+
+    /// Computes the merkle root over the wtxids, the coinbase counts as all-zero hash (BIP141)
+    pub fn compute_witness_merkle_root(&self) -> sha256d::Hash {
+        let hashes = self
+            .txs
+            .iter()
+            .enumerate()
+            .map(|(i, tx)| match i {
+                0 => sha256d::Hash::all_zeros(),
+                _ => tx.value.wtxid(),
+            })
+            .collect::<Vec<sha256d::Hash>>();
+        utils::merkle_root(hashes)
+    }
+
+    /// Returns the witness commitment of the coinbase. If several outputs match, the last one counts.
+    fn witness_commitment(&self) -> Option<&[u8]> {
+        self.txs
+            .first()?
+            .value
+            .outputs
+            .iter()
+            .rev()
+            .map(|output| &output.out.script_pubkey)
+            .find(|script| script.len() >= 38 && script.starts_with(&WITNESS_COMMITMENT_HEADER))
+            .map(|script| &script[6..38])
+    }
+
+    /// Verifies the witness commitment of the coinbase against the wtxids (BIP141).
+    /// Blocks without commitment must not contain any witness data.
+    pub fn verify_witness_commitment(&self) -> OpResult<()> {
+        let commitment = match self.witness_commitment() {
+            Some(commitment) => commitment,
+            None if self
+                .txs
+                .iter()
+                .any(|tx| tx.value.inputs.iter().any(TxInput::has_witness)) =>
+            {
+                let msg = format!(
+                    "Block {} contains witness data but no witness commitment",
+                    &self.header.hash
+                );
+                return Err(OpError::new(OpErrorKind::ValidationError).join_msg(&msg));
+            }
+            None => return Ok(()),
+        };
+        // The witness of the coinbase input holds the 32 byte reserved value
+        let reserved_value = match self.txs[0].value.inputs.first().map(|i| &i.witness[..]) {
+            Some([value]) if value.len() == 32 => value,
+            _ => {
+                let msg = format!(
+                    "Invalid witness reserved value in coinbase of block {}",
+                    &self.header.hash
+                );
+                return Err(OpError::new(OpErrorKind::ValidationError).join_msg(&msg));
+            }
+        };
+        let witness_root = self.compute_witness_merkle_root();
+        let expected = sha256d::Hash::hash(&[&witness_root[..], reserved_value].concat());
+        if commitment == &expected[..] {
+            Ok(())
+        } else {
+            let msg = format!(
+                "Invalid witness commitment!\n  -> expected: {}\n  -> got: {}\n",
+                utils::arr_to_hex(commitment),
+                utils::arr_to_hex(&expected[..])
+            );
+            Err(OpError::new(OpErrorKind::ValidationError).join_msg(&msg))
+        }
+    }
+
+    /// Verifies the merkle root over the txids and the witness commitment.
+    /// Blocks with identical hashes paired in the merkle tree (e.g. duplicated transactions) are rejected,
+    /// as their merkle root matches the valid block.
+    pub fn verify_merkle(&self) -> OpResult<()> {
+        self.verify_merkle_root()?;
+        let txids = self.txs.iter().map(|tx| tx.hash).collect::<Vec<_>>();
+        if utils::is_merkle_mutated(&txids) {
+            let msg = format!(
+                "Mutated merkle tree in block {}, identical hashes are paired",
+                &self.header.hash
+            );
+            return Err(OpError::new(OpErrorKind::ValidationError).join_msg(&msg));
+        }
+        self.verify_witness_commitment()
+    }
+
+    /// Iterates over the outputs of all transactions in block order
+    pub fn resolved_outputs(&self) -> impl Iterator<Item = ResolvedOutput<'_>> {
+        self.txs.iter().flat_map(|tx| tx.resolved_outputs())
+    }
+
+    /// Returns the hash of the parent block for merge mined blocks
+    pub fn aux_pow_parent_hash(&self) -> Option<sha256d::Hash> {
+        self.aux_pow_extension
+            .as_ref()
+            .map(|aux_pow| sha256d::Hash::hash(&aux_pow.parent_block.to_bytes()))
+    }
+
+    /// Verifies that the proof of work meets the target in the header.
+    /// For merge mined blocks the work is done on the parent block.
+    pub fn verify_pow(&self, algorithm: PowAlgorithm) -> OpResult<()> {
+        let header = match &self.aux_pow_extension {
+            Some(aux_pow) => &aux_pow.parent_block,
+            None => &self.header.value,
+        };
+        if header.meets_target(&self.header.value.target(), algorithm) {
+            Ok(())
+        } else {
+            let msg = format!(
+                "Proof of work of block {} doesn't meet target (bits: {:#010x})",
+                &self.header.hash, self.header.value.bits
+            );
+            Err(OpError::new(OpErrorKind::ValidationError).join_msg(&msg))
+        }
+    }
+
+    /// Checks that no output value and no transaction output sum exceeds `max_money`.
+    /// Returns a ValidationError describing the first offending transaction.
+    pub fn check_money_range(&self, max_money: u64) -> OpResult<()> {
+        for tx in &self.txs {
+            let mut total: u64 = 0;
+            for (i, o) in tx.value.outputs.iter().enumerate() {
+                let value = o.out.value;
+                total = match total.checked_add(value) {
+                    Some(total) if value <= max_money && total <= max_money => total,
+                    _ => {
+                        let msg = format!(
+                            "Output value out of range in tx {} (index: {}, value: {}, max: {})",
+                            &tx.hash, i, value, max_money
+                        );
+                        return Err(OpError::new(OpErrorKind::ValidationError).join_msg(&msg));
+                    }
+                };
+            }
+        }
+        Ok(())
+    }
 }
 
 impl fmt::Debug for Block {
@@ -93,7 +266,235 @@ pub fn get_base_reward(block_height: u64) -> u64 {
 
 #[cfg(test)]
 mod tests {
-    use super::get_base_reward;
+    use super::*;
+    use crate::blockchain::parser::reader::BlockchainRead;
+    use crate::blockchain::parser::types::CoinType;
+    use crate::blockchain::proto::script::{AddressFormat, ScriptPattern};
+    use crate::blockchain::proto::tx::{TxOutpoint, TxOutput};
+
+    fn block_with_values(values: &[u64]) -> Block {
+        let header = BlockHeader {
+            version: 1,
+            prev_hash: sha256d::Hash::all_zeros(),
+            merkle_root: sha256d::Hash::all_zeros(),
+            timestamp: 0,
+            bits: 0,
+            nonce: 0,
+        };
+        let outputs = values
+            .iter()
+            .map(|v| TxOutput {
+                value: *v,
+                script_len: VarUint::from(0u8),
+                script_pubkey: vec![],
+            })
+            .collect::<Vec<_>>();
+        let tx = RawTx {
+            version: 1,
+            in_count: VarUint::from(0u8),
+            inputs: vec![],
+            out_count: VarUint::from(outputs.len() as u8),
+            outputs,
+            locktime: 0,
+            address_format: AddressFormat::default(),
+            witness_size: 0,
+        };
+        Block::new(0, header, None, VarUint::from(1u8), vec![tx])
+    }
+
+    #[test]
+    fn test_resolved_outputs() {
+        // Block 1 of the Bitcoin mainnet, without magic and size
+        let raw = utils::hex_to_vec(concat!(
+            "010000006fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d61900",
+            "00000000982051fd1e4ba744bbbe680e1fee14677ba1a3c3540bf7b1cdb606e8",
+            "57233e0e61bc6649ffff001d01e3629901010000000100000000000000000000",
+            "00000000000000000000000000000000000000000000ffffffff0704ffff001d",
+            "0104ffffffff0100f2052a0100000043410496b538e853519c726a2c91e61ec1",
+            "1600ae1390813a627c66fb8be7947be63c52da7589379515d4e0a604f8141781",
+            "e62294721166bf621e73a82cbf2342c858eeac00000000",
+        ));
+        let block = std::io::Cursor::new(&raw)
+            .read_block(raw.len() as u32, &CoinType::default())
+            .unwrap();
+        let outputs = block.resolved_outputs().collect::<Vec<_>>();
+        assert_eq!(outputs.len(), 1);
+        assert_eq!(
+            outputs[0].txid.to_string(),
+            "0e3e2357e806b6cdb1f70b54c3a3a17b6714ee1f0e68bebb44a74b1efd512098"
+        );
+        assert_eq!(outputs[0].vout, 0);
+        assert_eq!(
+            outputs[0].address,
+            Some("12c6DSiU4Rq3P4ZxziKxzrL5LmMBrzjrJX")
+        );
+        assert_eq!(outputs[0].value, 5000000000);
+        assert_eq!(*outputs[0].script_type, ScriptPattern::Pay2PublicKey);
+    }
+
+    #[test]
+    fn test_weight() {
+        // Header and coinbase of block 1 of the Bitcoin mainnet with a P2SH-P2WPKH transaction
+        let raw = utils::hex_to_vec(concat!(
+            "010000006fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d61900",
+            "00000000982051fd1e4ba744bbbe680e1fee14677ba1a3c3540bf7b1cdb606e8",
+            "57233e0e61bc6649ffff001d01e3629902010000000100000000000000000000",
+            "00000000000000000000000000000000000000000000ffffffff0704ffff001d",
+            "0104ffffffff0100f2052a0100000043410496b538e853519c726a2c91e61ec1",
+            "1600ae1390813a627c66fb8be7947be63c52da7589379515d4e0a604f8141781",
+            "e62294721166bf621e73a82cbf2342c858eeac00000000",
+            "0100000000010115e180dc28a2327e687facc33f10f2a20da717e5548406f7ae",
+            "8b4c811072f85603000000171600141d7cd6c75c2e86f4cbf98eaed221b30bd9",
+            "a0b928ffffffff019caef505000000001976a9141d7cd6c75c2e86f4cbf98eae",
+            "d221b30bd9a0b92888ac02483045022100f764287d3e99b1474da9bec7f7ed23",
+            "6d6c81e793b20c4b5aa1f3051b9a7daa63022016a198031d5554dbb855bdbe85",
+            "34776a4be6958bd8d530dc001c32b828f6f0ab0121038262a6c6cec93c2d3ecd",
+            "6c6072efea86d02ff8e3328bbd0242b20af3425990ac00000000",
+        ));
+        let block = std::io::Cursor::new(&raw)
+            .read_block(raw.len() as u32, &CoinType::default())
+            .unwrap();
+        assert_eq!(block.txs.len(), 2);
+        assert_eq!(block.txs[0].value.witness_size, 0);
+        assert_eq!(block.txs[1].value.witness_size, 110);
+
+        assert_eq!(block.size, 433);
+        assert_eq!(block.base_size(), 323);
+        assert_eq!(block.weight(), 323 * 3 + 433);
+        // Header and tx count weigh 4 WU per byte, the transactions add their own weight
+        let tx_weights = block.txs.iter().map(|tx| tx.value.weight()).sum::<u64>();
+        assert_eq!(block.weight(), 81 * 4 + tx_weights);
+        assert_eq!(block.vsize(), 351);
+
+        // Without witness data the weight is simply size * 4
+        let legacy = &raw[..215];
+        let mut legacy = legacy.to_vec();
+        legacy[80] = 0x01;
+        let block = std::io::Cursor::new(&legacy)
+            .read_block(legacy.len() as u32, &CoinType::default())
+            .unwrap();
+        assert_eq!(block.weight(), 215 * 4);
+        assert_eq!(block.vsize(), 215);
+    }
+
+    #[test]
+    fn test_verify_merkle() {
+        // Block 1 of the Bitcoin mainnet, without magic and size
+        let raw = utils::hex_to_vec(concat!(
+            "010000006fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d61900",
+            "00000000982051fd1e4ba744bbbe680e1fee14677ba1a3c3540bf7b1cdb606e8",
+            "57233e0e61bc6649ffff001d01e3629901010000000100000000000000000000",
+            "00000000000000000000000000000000000000000000ffffffff0704ffff001d",
+            "0104ffffffff0100f2052a0100000043410496b538e853519c726a2c91e61ec1",
+            "1600ae1390813a627c66fb8be7947be63c52da7589379515d4e0a604f8141781",
+            "e62294721166bf621e73a82cbf2342c858eeac00000000",
+        ));
+        let parse = || {
+            std::io::Cursor::new(&raw)
+                .read_block(raw.len() as u32, &CoinType::default())
+                .unwrap()
+        };
+        assert!(parse().verify_merkle().is_ok());
+
+        // Identical txids paired in the tree are rejected even if the header matches
+        let mut mutated = parse();
+        mutated.txs.push(parse().txs.remove(0));
+        mutated.header.value.merkle_root = mutated.compute_merkle_root();
+        assert!(mutated.verify_merkle_root().is_ok());
+        let err = mutated.verify_merkle().unwrap_err();
+        assert!(err.message.contains("Mutated merkle tree"));
+    }
+
+    #[test]
+    fn test_verify_witness_commitment() {
+        // Header and coinbase of block 1 of the Bitcoin mainnet with a P2SH-P2WPKH transaction
+        let raw = utils::hex_to_vec(concat!(
+            "010000006fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d61900",
+            "00000000982051fd1e4ba744bbbe680e1fee14677ba1a3c3540bf7b1cdb606e8",
+            "57233e0e61bc6649ffff001d01e3629902010000000100000000000000000000",
+            "00000000000000000000000000000000000000000000ffffffff0704ffff001d",
+            "0104ffffffff0100f2052a0100000043410496b538e853519c726a2c91e61ec1",
+            "1600ae1390813a627c66fb8be7947be63c52da7589379515d4e0a604f8141781",
+            "e62294721166bf621e73a82cbf2342c858eeac00000000",
+            "0100000000010115e180dc28a2327e687facc33f10f2a20da717e5548406f7ae",
+            "8b4c811072f85603000000171600141d7cd6c75c2e86f4cbf98eaed221b30bd9",
+            "a0b928ffffffff019caef505000000001976a9141d7cd6c75c2e86f4cbf98eae",
+            "d221b30bd9a0b92888ac02483045022100f764287d3e99b1474da9bec7f7ed23",
+            "6d6c81e793b20c4b5aa1f3051b9a7daa63022016a198031d5554dbb855bdbe85",
+            "34776a4be6958bd8d530dc001c32b828f6f0ab0121038262a6c6cec93c2d3ecd",
+            "6c6072efea86d02ff8e3328bbd0242b20af3425990ac00000000",
+        ));
+        let mut block = std::io::Cursor::new(&raw)
+            .read_block(raw.len() as u32, &CoinType::default())
+            .unwrap();
+        // The header commits to the coinbase only
+        assert!(block.verify_merkle().is_err());
+        block.header.value.merkle_root = block.compute_merkle_root();
+        let err = block.verify_merkle().unwrap_err();
+        assert!(err.message.contains("no witness commitment"));
+
+        // Replace the coinbase with one committing to the witness data
+        let reserved_value = [0u8; 32];
+        let commitment = sha256d::Hash::hash(
+            &[
+                &block.compute_witness_merkle_root()[..],
+                &reserved_value[..],
+            ]
+            .concat(),
+        );
+        let coinbase = |commitment: &[u8]| {
+            let script_pubkey = [&WITNESS_COMMITMENT_HEADER[..], commitment].concat();
+            Hashed::double_sha256(EvaluatedTx::from(RawTx {
+                version: 1,
+                in_count: VarUint::from(1u8),
+                inputs: vec![TxInput {
+                    outpoint: TxOutpoint::new(sha256d::Hash::all_zeros(), 0xFFFFFFFF),
+                    script_len: VarUint::from(0u8),
+                    script_sig: vec![],
+                    seq_no: 0xFFFFFFFF,
+                    witness: vec![reserved_value.to_vec()],
+                }],
+                out_count: VarUint::from(1u8),
+                outputs: vec![TxOutput {
+                    value: 0,
+                    script_len: VarUint::from(script_pubkey.len() as u8),
+                    script_pubkey,
+                }],
+                locktime: 0,
+                address_format: AddressFormat::default(),
+                witness_size: 0,
+            }))
+        };
+        block.txs[0] = coinbase(&commitment[..]);
+        block.header.value.merkle_root = block.compute_merkle_root();
+        assert!(block.verify_merkle().is_ok());
+
+        let mut invalid = commitment.to_byte_array();
+        invalid[0] ^= 1;
+        block.txs[0] = coinbase(&invalid);
+        block.header.value.merkle_root = block.compute_merkle_root();
+        let err = block.verify_merkle().unwrap_err();
+        assert!(err.message.contains("Invalid witness commitment"));
+    }
+
+    #[test]
+    fn test_check_money_range() {
+        let max_money = 21_000_000 * 100_000_000;
+        assert!(block_with_values(&[50, max_money - 50])
+            .check_money_range(max_money)
+            .is_ok());
+        assert!(block_with_values(&[max_money + 1])
+            .check_money_range(max_money)
+            .is_err());
+        // Each value is in range, but the sum is not
+        assert!(block_with_values(&[max_money, 1])
+            .check_money_range(max_money)
+            .is_err());
+        // Sum overflows u64
+        assert!(block_with_values(&[u64::MAX, u64::MAX])
+            .check_money_range(max_money)
+            .is_err());
+    }
 
     #[test]
     fn test_get_base_reward() {
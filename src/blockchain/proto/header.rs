@@ -1,6 +1,8 @@
 use bitcoin::hashes::{sha256d, Hash};
+use bitcoin::pow::{CompactTarget, Target};
 use std::fmt;
 
+use crate::blockchain::parser::types::PowAlgorithm;
 use crate::blockchain::proto::ToRaw;
 
 /// Block Header definition. Exact 80 bytes long
@@ -14,6 +16,39 @@ pub struct BlockHeader {
     pub nonce: u32,
 }
 
+impl BlockHeader {
+    /// Returns the hash which has to meet the difficulty target.
+    /// Unlike the block hash, this depends on the proof of work algorithm of the coin.
+    pub fn pow_hash(&self, algorithm: PowAlgorithm) -> [u8; 32] {
+        let bytes = self.to_bytes();
+        match algorithm {
+            PowAlgorithm::Sha256d => sha256d::Hash::hash(&bytes).to_byte_array(),
+            PowAlgorithm::Scrypt => {
+                let params = scrypt::Params::new(10, 1, 1, 32).unwrap();
+                let mut hash = [0u8; 32];
+                scrypt::scrypt(&bytes, &bytes, &params, &mut hash).unwrap();
+                hash
+            }
+        }
+    }
+
+    /// Returns true if the proof of work hash is not above the given target.
+    /// The target is passed separately, since merge mined parent blocks are checked against the target of the child.
+    pub fn meets_target(&self, target: &Target, algorithm: PowAlgorithm) -> bool {
+        Target::from_le_bytes(self.pow_hash(algorithm)) <= *target
+    }
+
+    /// Returns the target encoded in `bits`
+    pub fn target(&self) -> Target {
+        Target::from_compact(CompactTarget::from_consensus(self.bits))
+    }
+
+    /// Returns the difficulty as the ratio of the maximum target (`0x1d00ffff`) and the target
+    pub fn difficulty(&self) -> f64 {
+        self.target().difficulty_float()
+    }
+}
+
 impl ToRaw for BlockHeader {
     fn to_bytes(&self) -> Vec<u8> {
         let mut bytes = Vec::with_capacity(80);
@@ -39,3 +74,59 @@ impl fmt::Debug for BlockHeader {
             .finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_bitcoin_genesis_pow() {
+        let header = BlockHeader {
+            version: 1,
+            prev_hash: sha256d::Hash::all_zeros(),
+            merkle_root: sha256d::Hash::from_str(
+                "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b",
+            )
+            .unwrap(),
+            timestamp: 1231006505,
+            bits: 0x1d00ffff,
+            nonce: 2083236893,
+        };
+        assert!(header.meets_target(&header.target(), PowAlgorithm::Sha256d));
+        assert_eq!(header.difficulty(), 1.0);
+        let invalid = BlockHeader {
+            nonce: header.nonce + 1,
+            ..header
+        };
+        assert!(!invalid.meets_target(&invalid.target(), PowAlgorithm::Sha256d));
+    }
+
+    #[test]
+    fn test_litecoin_genesis_pow() {
+        let header = BlockHeader {
+            version: 1,
+            prev_hash: sha256d::Hash::all_zeros(),
+            merkle_root: sha256d::Hash::from_str(
+                "97ddfbbae6be97fd6cdf3e7ca13232a3afff2353e29badfab7f73011edd4ced9",
+            )
+            .unwrap(),
+            timestamp: 1317972665,
+            bits: 0x1e0ffff0,
+            nonce: 2084524493,
+        };
+        // The block hash is double SHA256 and doesn't meet the target, the scrypt hash does
+        assert_eq!(
+            sha256d::Hash::hash(&header.to_bytes()).to_string(),
+            "12a765e31ffd4059bada1e25190f6e98c99d9714d334efa41a195a7e7e04bfe2"
+        );
+        assert!(!header.meets_target(&header.target(), PowAlgorithm::Sha256d));
+        assert!(header.meets_target(&header.target(), PowAlgorithm::Scrypt));
+
+        let invalid = BlockHeader {
+            nonce: header.nonce + 1,
+            ..header
+        };
+        assert!(!invalid.meets_target(&invalid.target(), PowAlgorithm::Scrypt));
+    }
+}
@@ -1,5 +1,8 @@
 /// This custom Script implementation is for all networks other than Bitcoin and Bitcoin Testnet
-use crate::blockchain::proto::script::{EvaluatedScript, ScriptError, ScriptPattern};
+use crate::blockchain::proto::script::disasm::{Script, ScriptOp};
+use crate::blockchain::proto::script::{
+    multisig_pattern, EvaluatedScript, ScriptError, ScriptPattern,
+};
 use crate::common::utils;
 use bitcoin::base58;
 use bitcoin::hashes::{hash160, sha256d, Hash};
@@ -69,85 +72,32 @@ impl fmt::Debug for Stack {
 /// Evaluates scripts
 struct ScriptEvaluator<'a> {
     bytes: &'a [u8],
-    n_bytes: usize,
-    pub ip: usize,
 }
 
 impl<'a> ScriptEvaluator<'a> {
     pub fn new(bytes: &'a [u8]) -> ScriptEvaluator {
-        ScriptEvaluator {
-            bytes,
-            n_bytes: bytes.len(),
-            ip: 0,
-        }
+        ScriptEvaluator { bytes }
     }
 
     /// Evaluates script by loading all data into the stack
     pub fn eval(&mut self) -> Result<Stack, ScriptError> {
         let mut elements = Vec::with_capacity(10);
-        //print!("Script(");
-        while self.ip < self.n_bytes {
-            let opcode = All::from(self.bytes[self.ip]);
-            let class = opcode.classify(ClassifyContext::Legacy);
-            let data_len = self.maybe_push_data(opcode, class)?;
-            self.ip += 1;
-
-            if data_len > 0 {
-                //print!(" ");
-                if self.ip + data_len > self.n_bytes {
-                    //println!("!ip: {}, data_len: {}, n_bytes: {}!", self.ip, data_len, self.n_bytes);
-                    return Err(ScriptError::UnexpectedEof);
-                } else {
-                    let data = Vec::from(&self.bytes[self.ip..self.ip + data_len]);
-                    //print!("{}", utils::arr_to_hex(&data));
-                    elements.push(StackElement::Data(data));
-                    self.ip += data_len;
-                }
-            } else if class != Class::NoOp {
-                elements.push(StackElement::Op(opcode));
-                //print!("{:?} ", opcode);
-            }
-        }
-        //println!(")\n");
-        let pattern = ScriptEvaluator::eval_script_pattern(&elements);
-        Ok(Stack { elements, pattern })
-    }
-
-    /// Checks Opcode if should to push some bytes
-    /// Especially opcodes between 0x00 and 0x4e
-    fn maybe_push_data(&mut self, opcode: All, opcode_class: Class) -> Result<usize, ScriptError> {
-        let data_len = if let Class::PushBytes(n) = opcode_class {
-            n as usize
-        } else {
-            match opcode {
-                all::OP_PUSHDATA1 => {
-                    if self.ip + 1 > self.n_bytes {
-                        return Err(ScriptError::UnexpectedEof);
-                    }
-                    let val = ScriptEvaluator::read_uint(&self.bytes[self.ip..], 1)?;
-                    self.ip += 1;
-                    val
-                }
-                all::OP_PUSHDATA2 => {
-                    if self.ip + 2 > self.n_bytes {
-                        return Err(ScriptError::UnexpectedEof);
+        for op in Script::new(self.bytes).disassemble() {
+            match op {
+                ScriptOp::Push(data) => elements.push(StackElement::Data(data)),
+                ScriptOp::Op(opcode) => {
+                    if opcode.classify(ClassifyContext::Legacy) != Class::NoOp {
+                        elements.push(StackElement::Op(opcode));
                     }
-                    let val = ScriptEvaluator::read_uint(&self.bytes[self.ip..], 2)?;
-                    self.ip += 2;
-                    val
                 }
-                all::OP_PUSHDATA4 => {
-                    if self.ip + 4 > self.n_bytes {
-                        return Err(ScriptError::UnexpectedEof);
-                    }
-                    let val = ScriptEvaluator::read_uint(&self.bytes[self.ip..], 4)?;
-                    self.ip += 4;
-                    val
-                }
-                _ => 0,
+                ScriptOp::Truncated(_) => return Err(ScriptError::UnexpectedEof),
             }
+        }
+        let pattern = match multisig_pattern(self.bytes) {
+            Some(pattern) => pattern,
+            None => ScriptEvaluator::eval_script_pattern(&elements),
         };
-        Ok(data_len)
+        Ok(Stack { elements, pattern })
     }
 
     fn eval_script_pattern(elements: &[StackElement]) -> ScriptPattern {
@@ -195,18 +145,6 @@ impl<'a> ScriptEvaluator<'a> {
             };
         }
 
-        //TODO: implement n to m multisig
-        let multisig_2n3 = [
-            StackElement::Op(all::OP_PUSHNUM_2),
-            StackElement::Data(Vec::new()),
-            StackElement::Data(Vec::new()),
-            StackElement::Data(Vec::new()),
-            StackElement::Op(all::OP_PUSHNUM_3),
-            StackElement::Op(all::OP_CHECKMULTISIG),
-        ];
-        if ScriptEvaluator::match_stack_pattern(elements, &multisig_2n3) {
-            return ScriptPattern::Pay2MultiSig;
-        }
         /* TODO:
         // The Genesis Block, self-payments, and pay-by-IP-address payments look like:
         // 65 BYTES:... CHECKSIG
@@ -218,19 +156,6 @@ impl<'a> ScriptEvaluator<'a> {
         ScriptPattern::NotRecognised
     }
 
-    /// Read a script-encoded unsigned integer.
-    fn read_uint(data: &[u8], size: usize) -> Result<usize, ScriptError> {
-        if data.len() < size {
-            Err(ScriptError::UnexpectedEof)
-        } else {
-            let mut ret = 0;
-            for (i, item) in data.iter().enumerate().take(size) {
-                ret += (*item as usize) << (i * 8);
-            }
-            Ok(ret)
-        }
-    }
-
     /// Matches stack elements against a defined pattern.
     /// For StackElement::Data() we just make a type comparison
     pub fn match_stack_pattern(elements: &[StackElement], pattern: &[StackElement]) -> bool {
@@ -261,6 +186,15 @@ pub fn eval_from_bytes_custom(bytes: &[u8], version_id: u8) -> EvaluatedScript {
     }
 }
 
+/// Classifies the script without encoding its address
+pub fn eval_pattern_custom(bytes: &[u8]) -> EvaluatedScript {
+    match ScriptEvaluator::new(bytes).eval() {
+        Ok(stack) => EvaluatedScript::new(None, stack.pattern),
+        Err(ScriptError::UnexpectedEof) => EvaluatedScript::new(None, ScriptPattern::NotRecognised),
+        Err(err) => EvaluatedScript::new(None, ScriptPattern::Error(err)),
+    }
+}
+
 /// Extracts evaluated address from script stack
 fn compute_stack(stack: Stack, version_id: u8) -> Result<EvaluatedScript, ScriptError> {
     let script = match stack.pattern {
@@ -289,13 +223,10 @@ fn compute_stack(stack: Stack, version_id: u8) -> Result<EvaluatedScript, Script
             address: None,
             pattern: ScriptPattern::OpReturn(data.clone()),
         },
-        ref p @ ScriptPattern::Pay2MultiSig => {
-            stack.elements[1].data()?;
-            EvaluatedScript {
-                address: None,
-                pattern: p.clone(),
-            }
-        }
+        ref p @ ScriptPattern::Pay2MultiSig { .. } => EvaluatedScript {
+            address: None,
+            pattern: p.clone(),
+        },
         ref p @ ScriptPattern::NotRecognised => EvaluatedScript {
             address: None,
             pattern: p.clone(),
@@ -419,7 +350,14 @@ mod tests {
                    03e3818b65bcc73a7d64064106a859cc1a5a728c4345ff0b641209fba0d90de6e9 \
                    021f2f6e1e50cb6a953935c3601284925decd3fd21bc445712576873fb8c6ebc18 OP_PUSHNUM_3 OP_CHECKMULTISIG",
                    format!("{:?}", stack));
-        assert_eq!(stack.pattern, ScriptPattern::Pay2MultiSig);
+        assert!(matches!(
+            stack.pattern,
+            ScriptPattern::Pay2MultiSig {
+                required: 2,
+                total: 3,
+                ..
+            }
+        ));
     }
 
     #[test]
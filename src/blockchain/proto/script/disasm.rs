@@ -0,0 +1,407 @@
+use std::fmt;
+
+use bitcoin::opcodes::{all, All, Class, ClassifyContext};
+
+use crate::common::utils;
+
+/// Single token of a disassembled script
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScriptOp {
+    /// Any opcode which doesn't push data (including OP_0 and OP_1..OP_16)
+    Op(All),
+    /// Data pushed by OP_PUSHBYTES_n or OP_PUSHDATA1/2/4
+    Push(Vec<u8>),
+    /// Remaining bytes of a push which exceeds the end of the script
+    Truncated(Vec<u8>),
+}
+
+impl fmt::Display for ScriptOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ScriptOp::Op(ref op) => write!(f, "{:?}", op),
+            ScriptOp::Push(ref data) => write!(f, "{}", utils::arr_to_hex(data)),
+            ScriptOp::Truncated(_) => write!(f, "[error]"),
+        }
+    }
+}
+
+/// Timelock opcodes which restrict when an output can be spent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LockType {
+    /// OP_CHECKLOCKTIMEVERIFY (BIP65), absolute block height or timestamp
+    Cltv,
+    /// OP_CHECKSEQUENCEVERIFY (BIP112), relative lock time encoded like nSequence
+    Csv,
+}
+
+impl fmt::Display for LockType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LockType::Cltv => write!(f, "cltv"),
+            LockType::Csv => write!(f, "csv"),
+        }
+    }
+}
+
+/// Ordinals inscription, found in an `OP_FALSE OP_IF "ord" <fields> OP_0 <body> OP_ENDIF` envelope
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Inscription {
+    /// Value of the content type field (tag 1), if present
+    pub content_type: Option<Vec<u8>>,
+    /// Concatenated pushes following the body separator
+    pub body: Vec<u8>,
+}
+
+/// Thin wrapper around raw script bytes for tokenizing
+pub struct Script<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Script<'a> {
+    pub fn new(bytes: &'a [u8]) -> Script<'a> {
+        Script { bytes }
+    }
+
+    /// Splits the script into opcodes and pushed data.
+    /// If a push exceeds the script, a final ScriptOp::Truncated is returned.
+    pub fn disassemble(&self) -> Vec<ScriptOp> {
+        let mut ops = Vec::with_capacity(10);
+        let mut ip = 0;
+        while ip < self.bytes.len() {
+            let opcode = All::from(self.bytes[ip]);
+            ip += 1;
+            let data_len = match opcode {
+                all::OP_PUSHDATA1 => Script::read_uint(&self.bytes[ip..], 1),
+                all::OP_PUSHDATA2 => Script::read_uint(&self.bytes[ip..], 2),
+                all::OP_PUSHDATA4 => Script::read_uint(&self.bytes[ip..], 4),
+                _ => match opcode.classify(ClassifyContext::Legacy) {
+                    Class::PushBytes(n) if n > 0 => Some(n as usize),
+                    _ => {
+                        ops.push(ScriptOp::Op(opcode));
+                        continue;
+                    }
+                },
+            };
+            let data_len = match data_len {
+                Some(len) => {
+                    ip += Script::uint_size(opcode);
+                    len
+                }
+                None => {
+                    ops.push(ScriptOp::Truncated(Vec::from(&self.bytes[ip..])));
+                    break;
+                }
+            };
+            if data_len > self.bytes.len() - ip {
+                ops.push(ScriptOp::Truncated(Vec::from(&self.bytes[ip..])));
+                break;
+            }
+            ops.push(ScriptOp::Push(Vec::from(&self.bytes[ip..ip + data_len])));
+            ip += data_len;
+        }
+        ops
+    }
+
+    /// Returns the timelock opcodes of the script along with their lock value,
+    /// which is the number pushed right before the opcode. The value is None if the
+    /// opcode isn't preceded by a number of at most 5 bytes, as required by consensus.
+    /// Scripts which can't be disassembled completely yield no timelocks.
+    pub fn timelocks(&self) -> Vec<(LockType, Option<i64>)> {
+        let ops = self.disassemble();
+        if matches!(ops.last(), Some(ScriptOp::Truncated(_))) {
+            return Vec::new();
+        }
+        let mut timelocks = Vec::new();
+        for (i, op) in ops.iter().enumerate() {
+            let lock_type = match op {
+                ScriptOp::Op(all::OP_CLTV) => LockType::Cltv,
+                ScriptOp::Op(all::OP_CSV) => LockType::Csv,
+                _ => continue,
+            };
+            let value = match i.checked_sub(1).map(|i| &ops[i]) {
+                Some(ScriptOp::Push(data)) if data.len() <= 5 => Some(Script::read_scriptint(data)),
+                Some(ScriptOp::Op(op)) => match op.classify(ClassifyContext::Legacy) {
+                    Class::PushNum(n) => Some(n as i64),
+                    Class::PushBytes(0) => Some(0),
+                    _ => None,
+                },
+                _ => None,
+            };
+            timelocks.push((lock_type, value));
+        }
+        timelocks
+    }
+
+    /// Returns all inscription envelopes of the script in order.
+    /// Envelopes without OP_ENDIF or with opcodes other than pushes inside are skipped.
+    pub fn inscriptions(&self) -> Vec<Inscription> {
+        let ops = self.disassemble();
+        let mut inscriptions = Vec::new();
+        let mut i = 0;
+        while i + 3 <= ops.len() {
+            let is_envelope = Script::push_data(&ops[i]).is_some_and(|data| data.is_empty())
+                && ops[i + 1] == ScriptOp::Op(all::OP_IF)
+                && Script::push_data(&ops[i + 2]).as_deref() == Some(b"ord".as_slice());
+            if is_envelope {
+                if let Some((inscription, len)) = Script::read_envelope(&ops[i + 3..]) {
+                    inscriptions.push(inscription);
+                    i += 3 + len;
+                    continue;
+                }
+            }
+            i += 1;
+        }
+        inscriptions
+    }
+
+    /// Reads the fields and body of an envelope after the `ord` tag.
+    /// Returns the inscription and the number of consumed ops including OP_ENDIF.
+    fn read_envelope(ops: &[ScriptOp]) -> Option<(Inscription, usize)> {
+        let mut content_type = None;
+        let mut body: Option<Vec<u8>> = None;
+        let mut i = 0;
+        while i < ops.len() {
+            if ops[i] == ScriptOp::Op(all::OP_ENDIF) {
+                let body = body.unwrap_or_default();
+                return Some((Inscription { content_type, body }, i + 1));
+            }
+            let data = Script::push_data(&ops[i])?;
+            match body {
+                Some(ref mut body) => body.extend(data),
+                // An empty tag separates the fields from the body
+                None if data.is_empty() => body = Some(Vec::new()),
+                None => {
+                    let value = Script::push_data(ops.get(i + 1)?)?;
+                    if data == [1] && content_type.is_none() {
+                        content_type = Some(value);
+                    }
+                    i += 1;
+                }
+            }
+            i += 1;
+        }
+        None
+    }
+
+    /// Returns the data pushed by the op, OP_0 and OP_1..OP_16 push a single number
+    fn push_data(op: &ScriptOp) -> Option<Vec<u8>> {
+        match op {
+            ScriptOp::Push(data) => Some(data.clone()),
+            ScriptOp::Op(op) => match op.classify(ClassifyContext::Legacy) {
+                Class::PushBytes(0) => Some(Vec::new()),
+                Class::PushNum(n) if n > 0 => Some(vec![n as u8]),
+                _ => None,
+            },
+            ScriptOp::Truncated(_) => None,
+        }
+    }
+
+    /// Decodes a little endian script number with the sign in the most significant bit
+    fn read_scriptint(data: &[u8]) -> i64 {
+        let mut ret: i64 = 0;
+        for (i, item) in data.iter().enumerate() {
+            ret |= (*item as i64) << (i * 8);
+        }
+        match data.last() {
+            Some(last) if last & 0x80 != 0 => -(ret & !(0x80 << ((data.len() - 1) * 8))),
+            _ => ret,
+        }
+    }
+
+    /// Size of the little endian length prefix for OP_PUSHDATA1/2/4
+    fn uint_size(opcode: All) -> usize {
+        match opcode {
+            all::OP_PUSHDATA1 => 1,
+            all::OP_PUSHDATA2 => 2,
+            all::OP_PUSHDATA4 => 4,
+            _ => 0,
+        }
+    }
+
+    /// Read a script-encoded unsigned integer.
+    fn read_uint(data: &[u8], size: usize) -> Option<usize> {
+        if data.len() < size {
+            None
+        } else {
+            let mut ret = 0;
+            for (i, item) in data.iter().enumerate().take(size) {
+                ret += (*item as usize) << (i * 8);
+            }
+            Some(ret)
+        }
+    }
+}
+
+/// Renders the script as asm, e.g. `OP_DUP OP_HASH160 <hex> OP_EQUALVERIFY OP_CHECKSIG`
+impl<'a> fmt::Display for Script<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let asm = self
+            .disassemble()
+            .iter()
+            .map(|op| op.to_string())
+            .collect::<Vec<String>>()
+            .join(" ");
+        write!(f, "{}", asm)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Inscription, LockType, Script, ScriptOp};
+    use bitcoin::opcodes::all;
+
+    #[test]
+    fn test_disassemble_p2pkh() {
+        let bytes = [
+            0x76, 0xa9, 0x14, 0x12, 0xab, 0x8d, 0xc5, 0x88, 0xca, 0x9d, 0x57, 0x87, 0xdd, 0xe7,
+            0xeb, 0x29, 0x56, 0x9d, 0xa6, 0x3c, 0x3a, 0x23, 0x8c, 0x88, 0xac,
+        ];
+        let script = Script::new(&bytes);
+        assert_eq!(script.disassemble().len(), 5);
+        assert_eq!(
+            script.to_string(),
+            "OP_DUP OP_HASH160 12ab8dc588ca9d5787dde7eb29569da63c3a238c OP_EQUALVERIFY OP_CHECKSIG"
+        );
+    }
+
+    #[test]
+    fn test_disassemble_pushdata() {
+        // OP_PUSHDATA1 with 2 bytes
+        let ops = Script::new(&[0x4c, 0x02, 0xab, 0xcd]).disassemble();
+        assert_eq!(ops, vec![ScriptOp::Push(vec![0xab, 0xcd])]);
+
+        // OP_PUSHDATA2 with 256 bytes followed by OP_CHECKSIG
+        let mut bytes = vec![0x4d, 0x00, 0x01];
+        bytes.extend(vec![0x11; 256]);
+        bytes.push(0xac);
+        let ops = Script::new(&bytes).disassemble();
+        assert_eq!(
+            ops,
+            vec![
+                ScriptOp::Push(vec![0x11; 256]),
+                ScriptOp::Op(all::OP_CHECKSIG)
+            ]
+        );
+
+        // OP_PUSHDATA4 with 1 byte
+        let ops = Script::new(&[0x4e, 0x01, 0x00, 0x00, 0x00, 0xff]).disassemble();
+        assert_eq!(ops, vec![ScriptOp::Push(vec![0xff])]);
+
+        // Zero length pushes
+        let ops = Script::new(&[0x00, 0x4c, 0x00]).disassemble();
+        assert_eq!(
+            ops,
+            vec![ScriptOp::Op(all::OP_PUSHBYTES_0), ScriptOp::Push(vec![])]
+        );
+    }
+
+    #[test]
+    fn test_disassemble_truncated() {
+        // Missing length bytes
+        assert_eq!(
+            Script::new(&[0x4c]).disassemble(),
+            vec![ScriptOp::Truncated(vec![])]
+        );
+        assert_eq!(
+            Script::new(&[0x4d, 0x01]).disassemble(),
+            vec![ScriptOp::Truncated(vec![0x01])]
+        );
+        // Push exceeds script by one byte
+        assert_eq!(
+            Script::new(&[0x4c, 0x03, 0x01, 0x02]).disassemble(),
+            vec![ScriptOp::Truncated(vec![0x01, 0x02])]
+        );
+        assert_eq!(
+            Script::new(&[0x6a, 0x05, 0x01]).to_string(),
+            "OP_RETURN [error]"
+        );
+    }
+
+    #[test]
+    fn test_timelocks() {
+        // <1577836800> OP_CHECKLOCKTIMEVERIFY OP_DROP <pubkey> OP_CHECKSIG
+        let script = crate::common::utils::hex_to_vec(concat!(
+            "0400e10b5eb17521",
+            "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798ac"
+        ));
+        assert_eq!(
+            Script::new(&script).timelocks(),
+            vec![(LockType::Cltv, Some(1577836800))]
+        );
+
+        // OP_IF <pubkey> OP_ELSE <144> OP_CHECKSEQUENCEVERIFY OP_DROP <pubkey> OP_ENDIF OP_CHECKSIG
+        let mut script = vec![0x63, 0x21];
+        script.extend([0x02; 33]);
+        script.extend([0x67, 0x02, 0x90, 0x00, 0xb2, 0x75, 0x21]);
+        script.extend([0x03; 33]);
+        script.extend([0x68, 0xac]);
+        assert_eq!(
+            Script::new(&script).timelocks(),
+            vec![(LockType::Csv, Some(144))]
+        );
+
+        // OP_16 OP_CSV, negative number and a lock time without push
+        assert_eq!(
+            Script::new(&[0x60, 0xb2, 0x01, 0x81, 0xb1, 0x76, 0xb1]).timelocks(),
+            vec![
+                (LockType::Csv, Some(16)),
+                (LockType::Cltv, Some(-1)),
+                (LockType::Cltv, None)
+            ]
+        );
+        // Truncated scripts are ignored
+        assert!(Script::new(&[0x51, 0xb1, 0x4c]).timelocks().is_empty());
+    }
+
+    #[test]
+    fn test_inscriptions() {
+        // <pubkey> OP_CHECKSIG OP_FALSE OP_IF "ord" 1 "text/plain" OP_0 "Hello, " "world!" OP_ENDIF
+        let mut script = vec![0x20];
+        script.extend([0x02; 32]);
+        script.extend([0xac, 0x00, 0x63, 0x03]);
+        script.extend(b"ord");
+        script.extend([0x01, 0x01, 0x0a]);
+        script.extend(b"text/plain");
+        script.extend([0x00, 0x07]);
+        script.extend(b"Hello, ");
+        script.extend([0x06]);
+        script.extend(b"world!");
+        script.push(0x68);
+        let inscription = Inscription {
+            content_type: Some(b"text/plain".to_vec()),
+            body: b"Hello, world!".to_vec(),
+        };
+        assert_eq!(
+            Script::new(&script).inscriptions(),
+            vec![inscription.clone()]
+        );
+
+        // Two envelopes, the second one with OP_1 as tag, an unknown field and without body
+        let mut second = vec![0x00, 0x63, 0x03];
+        second.extend(b"ord");
+        second.extend([0x51, 0x01, 0x78, 0x01, 0x05, 0x01, 0xff, 0x68]);
+        let both = [script.clone(), second].concat();
+        assert_eq!(
+            Script::new(&both).inscriptions(),
+            vec![
+                inscription,
+                Inscription {
+                    content_type: Some(b"x".to_vec()),
+                    body: vec![],
+                }
+            ]
+        );
+
+        // Missing OP_ENDIF and non-push opcodes inside the envelope
+        assert!(Script::new(&script[..script.len() - 1])
+            .inscriptions()
+            .is_empty());
+        let mut invalid = script.clone();
+        invalid.insert(invalid.len() - 1, 0xac);
+        assert!(Script::new(&invalid).inscriptions().is_empty());
+        // Other data in OP_FALSE OP_IF
+        assert!(Script::new(&[0x00, 0x63, 0x03, 0x61, 0x62, 0x63, 0x68])
+            .inscriptions()
+            .is_empty());
+    }
+}
@@ -1,14 +1,18 @@
 mod custom;
+pub mod disasm;
 
 use std::convert::From;
 use std::error::Error;
 use std::fmt;
 
-use crate::blockchain::proto::script::custom::eval_from_bytes_custom;
+use crate::blockchain::proto::script::custom::{eval_from_bytes_custom, eval_pattern_custom};
+use crate::blockchain::proto::script::disasm::ScriptOp;
+use crate::common::utils::{self, CashAddrType};
 use bitcoin::address::Payload;
 use bitcoin::blockdata::script::Instruction;
 use bitcoin::hashes::{hash160, Hash};
-use bitcoin::{address, Address, Network, PubkeyHash, Script};
+use bitcoin::opcodes::all::{OP_CHECKMULTISIG, OP_PUSHNUM_1, OP_PUSHNUM_16};
+use bitcoin::{address, Address, Network, PubkeyHash, Script, ScriptHash};
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum ScriptError {
@@ -38,8 +42,12 @@ pub enum ScriptPattern {
     /// Pay to Multisig [BIP11]
     /// Pubkey script: <m> <A pubkey>[B pubkey][C pubkey...] <n> OP_CHECKMULTISIG
     /// Signature script: OP_0 <A sig>[B sig][C sig...]
-    /// TODO: Implement Pay2MultiSig: For now only 2n3 MultiSigs are detected
-    Pay2MultiSig,
+    /// Holds the m-of-n threshold and the public keys in script order.
+    Pay2MultiSig {
+        required: u8,
+        total: u8,
+        pubkeys: Vec<Vec<u8>>,
+    },
 
     /// Pay to Public Key (p2pk) scripts are a simplified form of the p2pkh,
     /// but aren't commonly used in new transactions anymore,
@@ -81,7 +89,7 @@ impl fmt::Display for ScriptPattern {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             ScriptPattern::OpReturn(_) => write!(f, "OpReturn"),
-            ScriptPattern::Pay2MultiSig => write!(f, "Pay2MultiSig"),
+            ScriptPattern::Pay2MultiSig { .. } => write!(f, "Pay2MultiSig"),
             ScriptPattern::Pay2PublicKey => write!(f, "Pay2PublicKey"),
             ScriptPattern::Pay2PublicKeyHash => write!(f, "Pay2PublicKeyHash"),
             ScriptPattern::Pay2ScriptHash => write!(f, "Pay2ScriptHash"),
@@ -96,6 +104,69 @@ impl fmt::Display for ScriptPattern {
     }
 }
 
+/// Fieldless counterpart of `ScriptPattern`, which fits into a single byte.
+/// Used where the script type is stored per output, e.g. for each unspent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ScriptType {
+    OpReturn,
+    Pay2MultiSig,
+    Pay2PublicKey,
+    Pay2PublicKeyHash,
+    Pay2ScriptHash,
+    Pay2WitnessPublicKeyHash,
+    Pay2WitnessScriptHash,
+    WitnessProgram,
+    Pay2Taproot,
+    Unspendable,
+    NotRecognised,
+    Error,
+}
+
+impl TryFrom<u8> for ScriptType {
+    type Error = ScriptError;
+
+    /// Inverse of `script_type as u8`
+    fn try_from(value: u8) -> Result<Self, ScriptError> {
+        const TYPES: [ScriptType; 12] = [
+            ScriptType::OpReturn,
+            ScriptType::Pay2MultiSig,
+            ScriptType::Pay2PublicKey,
+            ScriptType::Pay2PublicKeyHash,
+            ScriptType::Pay2ScriptHash,
+            ScriptType::Pay2WitnessPublicKeyHash,
+            ScriptType::Pay2WitnessScriptHash,
+            ScriptType::WitnessProgram,
+            ScriptType::Pay2Taproot,
+            ScriptType::Unspendable,
+            ScriptType::NotRecognised,
+            ScriptType::Error,
+        ];
+        TYPES
+            .get(value as usize)
+            .copied()
+            .ok_or(ScriptError::InvalidFormat)
+    }
+}
+
+impl From<&ScriptPattern> for ScriptType {
+    fn from(pattern: &ScriptPattern) -> Self {
+        match pattern {
+            ScriptPattern::OpReturn(_) => ScriptType::OpReturn,
+            ScriptPattern::Pay2MultiSig { .. } => ScriptType::Pay2MultiSig,
+            ScriptPattern::Pay2PublicKey => ScriptType::Pay2PublicKey,
+            ScriptPattern::Pay2PublicKeyHash => ScriptType::Pay2PublicKeyHash,
+            ScriptPattern::Pay2ScriptHash => ScriptType::Pay2ScriptHash,
+            ScriptPattern::Pay2WitnessPublicKeyHash => ScriptType::Pay2WitnessPublicKeyHash,
+            ScriptPattern::Pay2WitnessScriptHash => ScriptType::Pay2WitnessScriptHash,
+            ScriptPattern::WitnessProgram => ScriptType::WitnessProgram,
+            ScriptPattern::Pay2Taproot => ScriptType::Pay2Taproot,
+            ScriptPattern::Unspendable => ScriptType::Unspendable,
+            ScriptPattern::NotRecognised => ScriptType::NotRecognised,
+            ScriptPattern::Error(_) => ScriptType::Error,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct EvaluatedScript {
     pub address: Option<String>,
@@ -108,67 +179,210 @@ impl EvaluatedScript {
     }
 }
 
+/// Selects how the addresses of a coin's scripts are encoded, see `CoinType::address_format()`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AddressFormat {
+    pub version_id: u8,
+    /// If false, scripts are only classified and `EvaluatedScript::address` is always None
+    pub resolve: bool,
+    /// Encodes addresses as CashAddr with this prefix, e.g. for Bitcoin Cash
+    pub cashaddr_prefix: Option<&'static str>,
+}
+
+impl Default for AddressFormat {
+    fn default() -> Self {
+        AddressFormat {
+            version_id: 0x00,
+            resolve: true,
+            cashaddr_prefix: None,
+        }
+    }
+}
+
 /// Extracts evaluated address from ScriptPubKey
-pub fn eval_from_bytes(bytes: &[u8], version_id: u8) -> EvaluatedScript {
+pub fn eval_from_bytes(bytes: &[u8], format: AddressFormat) -> EvaluatedScript {
+    if !format.resolve {
+        return eval_pattern_from_bytes(bytes, format.version_id);
+    }
+    match format.version_id {
+        0x00 | 0x6f => eval_from_bytes_bitcoin(bytes, format),
+        _ => eval_from_bytes_custom(bytes, format.version_id),
+    }
+}
+
+/// Classifies the ScriptPubKey without encoding its address
+pub fn eval_pattern_from_bytes(bytes: &[u8], version_id: u8) -> EvaluatedScript {
     match version_id {
-        0x00 | 0x6f => eval_from_bytes_bitcoin(bytes, version_id),
-        _ => eval_from_bytes_custom(bytes, version_id),
+        0x00 | 0x6f => {
+            EvaluatedScript::new(None, bitcoin_script_pattern(Script::from_bytes(bytes)))
+        }
+        _ => eval_pattern_custom(bytes),
+    }
+}
+
+/// Classifies the script using `rust_bitcoin`
+fn bitcoin_script_pattern(script: &Script) -> ScriptPattern {
+    if script.is_op_return() {
+        // OP_RETURN <data> [<data> ...]
+        let data = String::from_utf8(op_return_pushes(script.as_bytes()).concat());
+        ScriptPattern::OpReturn(data.unwrap_or_else(|_| String::from("")))
+    } else if script.is_provably_unspendable() {
+        ScriptPattern::Unspendable
+    } else if script.is_p2pk() {
+        ScriptPattern::Pay2PublicKey
+    } else if script.is_p2pkh() {
+        ScriptPattern::Pay2PublicKeyHash
+    } else if script.is_p2sh() {
+        ScriptPattern::Pay2ScriptHash
+    } else if script.is_v0_p2wpkh() {
+        ScriptPattern::Pay2WitnessPublicKeyHash
+    } else if script.is_v0_p2wsh() {
+        ScriptPattern::Pay2WitnessScriptHash
+    } else if script.is_v1_p2tr() {
+        ScriptPattern::Pay2Taproot
+    } else if script.is_witness_program() {
+        ScriptPattern::WitnessProgram
+    } else if let Some(pattern) = multisig_pattern(script.as_bytes()) {
+        pattern
+    } else {
+        ScriptPattern::NotRecognised
+    }
+}
+
+/// Matches bare multisig scripts `OP_m <pubkey>... OP_n OP_CHECKMULTISIG` with 1 <= m <= n <= 16.
+/// Each key has to be pushed as 33 (compressed) or 65 (uncompressed) bytes.
+pub fn multisig_pattern(bytes: &[u8]) -> Option<ScriptPattern> {
+    let pushnum = |op: &ScriptOp| match op {
+        ScriptOp::Op(op)
+            if (OP_PUSHNUM_1.to_u8()..=OP_PUSHNUM_16.to_u8()).contains(&op.to_u8()) =>
+        {
+            Some(op.to_u8() - OP_PUSHNUM_1.to_u8() + 1)
+        }
+        _ => None,
+    };
+    let ops = disasm::Script::new(bytes).disassemble();
+    let (first, rest) = ops.split_first()?;
+    let (last, rest) = rest.split_last()?;
+    let (n_op, keys) = rest.split_last()?;
+    let (required, total) = (pushnum(first)?, pushnum(n_op)?);
+    if *last != ScriptOp::Op(OP_CHECKMULTISIG) || required > total || keys.len() != total as usize {
+        return None;
+    }
+    let pubkeys = keys
+        .iter()
+        .map(|key| match key {
+            ScriptOp::Push(key) if key.len() == 33 || key.len() == 65 => Some(key.clone()),
+            _ => None,
+        })
+        .collect::<Option<Vec<Vec<u8>>>>()?;
+    Some(ScriptPattern::Pay2MultiSig {
+        required,
+        total,
+        pubkeys,
+    })
+}
+
+/// Returns the data pushes following OP_RETURN, in script order.
+/// Non-push opcodes are skipped and parsing stops at the first malformed push.
+pub fn op_return_pushes(script_pubkey: &[u8]) -> Vec<Vec<u8>> {
+    let mut pushes = Vec::new();
+    for instruction in Script::from_bytes(script_pubkey).instructions().skip(1) {
+        match instruction {
+            Ok(Instruction::PushBytes(bytes)) => pushes.push(bytes.as_bytes().to_vec()),
+            Ok(Instruction::Op(_)) => {}
+            Err(_) => break,
+        }
     }
+    pushes
 }
 
 /// Extracts evaluated address from script using `rust_bitcoin`
-pub fn eval_from_bytes_bitcoin(bytes: &[u8], version_id: u8) -> EvaluatedScript {
-    let network = match version_id {
+pub fn eval_from_bytes_bitcoin(bytes: &[u8], format: AddressFormat) -> EvaluatedScript {
+    let network = match format.version_id {
         0x00 => Network::Bitcoin,
         0x6f => Network::Testnet,
         _ => panic!("invalid network version"),
     };
 
-    let script = Script::from_bytes(bytes);
+    eval_script_bitcoin(Script::from_bytes(bytes), network, format.cashaddr_prefix)
+}
 
-    // For OP_RETURN and provably unspendable scripts there is no point in parsing the address
-    if script.is_op_return() {
-        // OP_RETURN 13 <data>
-        let data = String::from_utf8(script.to_bytes().into_iter().skip(2).collect());
-        let pattern = ScriptPattern::OpReturn(data.unwrap_or_else(|_| String::from("")));
-        return EvaluatedScript::new(None, pattern);
-    } else if script.is_provably_unspendable() {
-        return EvaluatedScript::new(None, ScriptPattern::Unspendable);
+/// Extracts evaluated address from script using `rust_bitcoin`.
+/// P2PK, P2PKH and P2SH addresses are encoded as CashAddr if a prefix is given.
+fn eval_script_bitcoin(
+    script: &Script,
+    network: Network,
+    cashaddr_prefix: Option<&str>,
+) -> EvaluatedScript {
+    let pattern = bitcoin_script_pattern(script);
+    let address = match (&pattern, cashaddr_prefix) {
+        // For OP_RETURN and provably unspendable scripts there is no point in parsing the address
+        (ScriptPattern::OpReturn(_) | ScriptPattern::Unspendable, _) => None,
+        (ScriptPattern::Pay2PublicKey | ScriptPattern::Pay2PublicKeyHash, Some(prefix)) => {
+            pubkey_hash(script.as_bytes())
+                .map(|hash| utils::cashaddr_encode(prefix, CashAddrType::P2PKH, &hash))
+        }
+        (ScriptPattern::Pay2ScriptHash, Some(prefix)) => {
+            let hash = script.as_bytes()[2..22].try_into().unwrap();
+            Some(utils::cashaddr_encode(prefix, CashAddrType::P2SH, &hash))
+        }
+        (ScriptPattern::Pay2PublicKey, None) => p2pk_to_string(script, network),
+        _ => match Address::from_script(script, network) {
+            Ok(address) => Some(format!("{}", address)),
+            Err(err) => {
+                if err != address::Error::UnrecognizedScript {
+                    warn!(target: "script", "Unable to extract evaluated address: {}", err)
+                }
+                None
+            }
+        },
+    };
+    EvaluatedScript::new(address, pattern)
+}
+
+/// Resolves the address spent by an input from its scriptSig.
+/// Only nested SegWit (P2SH-P2WPKH) is recognized, where the scriptSig pushes the
+/// redeemscript `OP_0 <20 bytes>`. The P2SH address is returned, since that's what
+/// the spent output committed to.
+pub fn eval_script_sig(bytes: &[u8], format: AddressFormat) -> Option<String> {
+    if !format.resolve {
+        return None;
+    }
+    let network = match format.version_id {
+        0x00 => Network::Bitcoin,
+        0x6f => Network::Testnet,
+        _ => return None,
+    };
+    match disasm::Script::new(bytes).disassemble().as_slice() {
+        [ScriptOp::Push(redeem_script)] if Script::from_bytes(redeem_script).is_v0_p2wpkh() => {
+            let script_hash = ScriptHash::from_raw_hash(hash160::Hash::hash(redeem_script));
+            Some(Address::new(network, Payload::ScriptHash(script_hash)).to_string())
+        }
+        _ => None,
     }
+}
 
-    let address = match Address::from_script(script, network) {
-        Ok(address) => Some(format!("{}", address)),
-        Err(err) => {
-            if err != address::Error::UnrecognizedScript {
-                warn!(target: "script", "Unable to extract evaluated address: {}", err)
+/// Returns the public key hash a P2PK or P2PKH script pays to.
+/// For P2PK, the key is hashed as it appears in the script, like for its address.
+pub fn pubkey_hash(bytes: &[u8]) -> Option<[u8; 20]> {
+    let script = Script::from_bytes(bytes);
+    if script.is_p2pkh() {
+        bytes[3..23].try_into().ok()
+    } else if script.is_p2pk() {
+        match script.instructions().next() {
+            Some(Ok(Instruction::PushBytes(key))) => {
+                Some(hash160::Hash::hash(key.as_bytes()).to_byte_array())
             }
-            None
+            _ => None,
         }
-    };
-
-    if script.is_p2pk() {
-        EvaluatedScript::new(
-            p2pk_to_string(script, network),
-            ScriptPattern::Pay2PublicKey,
-        )
-    } else if script.is_p2pkh() {
-        EvaluatedScript::new(address, ScriptPattern::Pay2PublicKeyHash)
-    } else if script.is_p2sh() {
-        EvaluatedScript::new(address, ScriptPattern::Pay2ScriptHash)
-    } else if script.is_v0_p2wpkh() {
-        EvaluatedScript::new(address, ScriptPattern::Pay2WitnessPublicKeyHash)
-    } else if script.is_v0_p2wsh() {
-        EvaluatedScript::new(address, ScriptPattern::Pay2WitnessScriptHash)
-    } else if script.is_v1_p2tr() {
-        EvaluatedScript::new(address, ScriptPattern::Pay2Taproot)
-    } else if script.is_witness_program() {
-        EvaluatedScript::new(address, ScriptPattern::WitnessProgram)
     } else {
-        EvaluatedScript::new(address, ScriptPattern::NotRecognised)
+        None
     }
 }
 
-/// Workaround to parse address from p2pk scripts
+/// Workaround to parse address from p2pk scripts.
+/// The P2PKH address is derived from the pushed key as is, so compressed (33 bytes) and
+/// uncompressed (65 bytes) encodings of the same key resolve to different addresses.
 /// See issue https://github.com/rust-bitcoin/rust-bitcoin/issues/441
 fn p2pk_to_string(script: &Script, network: Network) -> Option<String> {
     debug_assert!(script.is_p2pk());
@@ -188,8 +402,13 @@ fn p2pk_to_string(script: &Script, network: Network) -> Option<String> {
 
 #[cfg(test)]
 mod tests {
-    use super::ScriptPattern;
+    use super::{
+        eval_from_bytes, eval_pattern_from_bytes, eval_script_sig, multisig_pattern,
+        op_return_pushes, pubkey_hash, AddressFormat, ScriptPattern, ScriptType,
+    };
+    use crate::blockchain::parser::types::CoinType;
     use crate::blockchain::proto::script::eval_from_bytes_bitcoin;
+    use crate::common::utils;
 
     #[test]
     fn test_bitcoin_script_p2pkh() {
@@ -199,7 +418,7 @@ mod tests {
             0x76, 0xa9, 0x14, 0x12, 0xab, 0x8d, 0xc5, 0x88, 0xca, 0x9d, 0x57, 0x87, 0xdd, 0xe7,
             0xeb, 0x29, 0x56, 0x9d, 0xa6, 0x3c, 0x3a, 0x23, 0x8c, 0x88, 0xac,
         ];
-        let result = eval_from_bytes_bitcoin(&bytes, 0x00);
+        let result = eval_from_bytes_bitcoin(&bytes, AddressFormat::default());
         assert_eq!(
             result.address,
             Some(String::from("12higDjoCCNXSA95xZMWUdPvXNmkAduhWv"))
@@ -220,7 +439,7 @@ mod tests {
             0x40, 0x78, 0xb4, 0x8b, 0xa6, 0x7f, 0xa1, 0x98, 0x78, 0x2e, 0x8b, 0xb6, 0x81, 0x15,
             0xda, 0x0d, 0xaa, 0x8f, 0xde, 0x53, 0x01, 0xf7, 0xf9, 0xac,
         ]; // OP_CHECKSIG
-        let result = eval_from_bytes_bitcoin(&bytes, 0x00);
+        let result = eval_from_bytes_bitcoin(&bytes, AddressFormat::default());
         assert_eq!(
             result.address,
             Some(String::from("1LEWwJkDj8xriE87ALzQYcHjTmD8aqDj1f"))
@@ -228,15 +447,79 @@ mod tests {
         assert_eq!(result.pattern, ScriptPattern::Pay2PublicKey);
     }
 
-    /*
-    // FIXME: See https://github.com/rust-bitcoin/rust-bitcoin/pull/657/files
+    #[test]
+    fn test_bitcoin_script_p2pk_key_encodings() {
+        // Genesis coinbase output, paying to an uncompressed key
+        let genesis = utils::hex_to_vec(concat!(
+            "4104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb6",
+            "49f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac"
+        ));
+        let result = eval_from_bytes_bitcoin(&genesis, AddressFormat::default());
+        assert_eq!(
+            result.address,
+            Some(String::from("1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa"))
+        );
+        assert_eq!(result.pattern, ScriptPattern::Pay2PublicKey);
+
+        // Generator point (private key 1) in both encodings
+        let compressed = utils::hex_to_vec(
+            "210279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798ac",
+        );
+        let uncompressed = utils::hex_to_vec(concat!(
+            "410479be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+            "483ada7726a3c4655da4fbfc0e1108a8fd17b448a68554199c47d08ffb10d4b8ac"
+        ));
+        for (bytes, address) in [
+            (&compressed, "1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH"),
+            (&uncompressed, "1EHNa6Q4Jz2uvNExL497mE43ikXhwF6kZm"),
+        ] {
+            let result = eval_from_bytes_bitcoin(bytes, AddressFormat::default());
+            assert_eq!(result.address, Some(String::from(address)));
+            assert_eq!(result.pattern, ScriptPattern::Pay2PublicKey);
+        }
+    }
+
+    #[test]
+    fn test_pubkey_hash() {
+        let p2pkh = utils::hex_to_vec("76a914751e76e8199196d454941c45d1b3a323f1433bd688ac");
+        let p2pk = utils::hex_to_vec(
+            "210279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798ac",
+        );
+        let p2wpkh = utils::hex_to_vec("0014751e76e8199196d454941c45d1b3a323f1433bd6");
+        let expected = utils::hex_to_vec("751e76e8199196d454941c45d1b3a323f1433bd6");
+        assert_eq!(pubkey_hash(&p2pkh).unwrap().to_vec(), expected);
+        assert_eq!(pubkey_hash(&p2pk).unwrap().to_vec(), expected);
+        assert_eq!(pubkey_hash(&p2wpkh), None);
+    }
+
+    #[test]
+    fn test_eval_pattern_without_address() {
+        let p2pkh = utils::hex_to_vec("76a91412ab8dc588ca9d5787dde7eb29569da63c3a238c88ac");
+        let p2wpkh = utils::hex_to_vec("0014751e76e8199196d454941c45d1b3a323f1433bd6");
+        let op_return = utils::hex_to_vec("6a0568656c6c6f");
+        // Bitcoin (0x00) and Litecoin (0x30) use different script evaluators
+        for (bytes, version_id, pattern) in [
+            (&p2pkh, 0x00, ScriptPattern::Pay2PublicKeyHash),
+            (&p2wpkh, 0x00, ScriptPattern::Pay2WitnessPublicKeyHash),
+            (
+                &op_return,
+                0x00,
+                ScriptPattern::OpReturn(String::from("hello")),
+            ),
+            (&p2pkh, 0x30, ScriptPattern::Pay2PublicKeyHash),
+        ] {
+            let result = eval_pattern_from_bytes(bytes, version_id);
+            assert_eq!(result.address, None);
+            assert_eq!(result.pattern, pattern);
+        }
+    }
+
     #[test]
     fn test_bitcoin_script_p2ms() {
         // 2-of-3 Multi sig output
         // OP_2 33 0x022df8750480ad5b26950b25c7ba79d3e37d75f640f8e5d9bcd5b150a0f85014da
         // 33 0x03e3818b65bcc73a7d64064106a859cc1a5a728c4345ff0b641209fba0d90de6e9
         // 33 0x021f2f6e1e50cb6a953935c3601284925decd3fd21bc445712576873fb8c6ebc18 OP_3 OP_CHECKMULTISIG
-        //TODO: complete this test
         let bytes = [
             0x52, 0x21, 0x02, 0x2d, 0xf8, 0x75, 0x04, 0x80, 0xad, 0x5b, 0x26, 0x95, 0x0b, 0x25,
             0xc7, 0xba, 0x79, 0xd3, 0xe3, 0x7d, 0x75, 0xf6, 0x40, 0xf8, 0xe5, 0xd9, 0xbc, 0xd5,
@@ -247,10 +530,55 @@ mod tests {
             0x84, 0x92, 0x5d, 0xec, 0xd3, 0xfd, 0x21, 0xbc, 0x44, 0x57, 0x12, 0x57, 0x68, 0x73,
             0xfb, 0x8c, 0x6e, 0xbc, 0x18, 0x53, 0xae,
         ];
-        let result = eval_from_bytes_bitcoin(&bytes, 0x00);
-        assert_eq!(result.pattern, ScriptPattern::Pay2MultiSig);
+        let result = eval_from_bytes_bitcoin(&bytes, AddressFormat::default());
+        assert_eq!(result.address, None);
+        match result.pattern {
+            ScriptPattern::Pay2MultiSig {
+                required,
+                total,
+                pubkeys,
+            } => {
+                assert_eq!((required, total), (2, 3));
+                assert_eq!(pubkeys.len(), 3);
+                assert_eq!(pubkeys[1], bytes[36..69]);
+            }
+            p => panic!("unexpected pattern {}", p),
+        }
+    }
+
+    #[test]
+    fn test_bitcoin_script_p2ms_1of2() {
+        // OP_1 <compressed key> <uncompressed key of the genesis coinbase> OP_2 OP_CHECKMULTISIG
+        let compressed =
+            utils::hex_to_vec("022df8750480ad5b26950b25c7ba79d3e37d75f640f8e5d9bcd5b150a0f85014da");
+        let uncompressed = utils::hex_to_vec(
+            "04678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5f",
+        );
+        let bytes = [
+            &[0x51, 0x21][..],
+            &compressed,
+            &[0x41],
+            &uncompressed,
+            &[0x52, 0xae],
+        ]
+        .concat();
+        assert_eq!(
+            eval_from_bytes_bitcoin(&bytes, AddressFormat::default()).pattern,
+            ScriptPattern::Pay2MultiSig {
+                required: 1,
+                total: 2,
+                pubkeys: vec![compressed, uncompressed],
+            }
+        );
+
+        // The number of keys has to match n and m must not exceed n
+        let mut wrong_total = bytes.clone();
+        *wrong_total.iter_mut().rev().nth(1).unwrap() = 0x53;
+        assert_eq!(multisig_pattern(&wrong_total), None);
+        let mut wrong_required = bytes.clone();
+        wrong_required[0] = 0x53;
+        assert_eq!(multisig_pattern(&wrong_required), None);
     }
-    */
 
     #[test]
     fn test_bitcoin_script_p2sh() {
@@ -261,7 +589,7 @@ mod tests {
             0xe9, 0xc3, 0xdd, 0x0c, 0x07, 0xaa, 0xc7, 0x61, 0x79, 0xeb, 0xc7, 0x6a, 0x6c, 0x78,
             0xd4, 0xd6, 0x7c, 0x6c, 0x16, 0x0a, 0x87,
         ]; // OP_EQUAL
-        let result = eval_from_bytes_bitcoin(&bytes, 0x00);
+        let result = eval_from_bytes_bitcoin(&bytes, AddressFormat::default());
         assert_eq!(
             result.address,
             Some(String::from("3P14159f73E4gFr7JterCCQh9QjiTjiZrG"))
@@ -269,6 +597,80 @@ mod tests {
         assert_eq!(result.pattern, ScriptPattern::Pay2ScriptHash);
     }
 
+    #[test]
+    fn test_bitcoin_script_p2tr() {
+        // Raw output script: 5120a60869f0dbcf1dc659c9cecbaf8050135ea9e8cdc487053f1dc6880949dc684c
+        //                    OP_1 OP_PUSHBYTES_32 a60869f0dbcf1dc659c9cecbaf8050135ea9e8cdc487053f1dc6880949dc684c
+        let bytes = utils::hex_to_vec(
+            "5120a60869f0dbcf1dc659c9cecbaf8050135ea9e8cdc487053f1dc6880949dc684c",
+        );
+        let result = eval_from_bytes_bitcoin(&bytes, AddressFormat::default());
+        // Witness version 1 is encoded as bech32m
+        assert_eq!(
+            result.address,
+            Some(String::from(
+                "bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr"
+            ))
+        );
+        assert_eq!(result.pattern, ScriptPattern::Pay2Taproot);
+
+        // Witness version 1 with a 20-byte program is not taproot
+        let bytes = [&[0x51, 0x14][..], &bytes[2..22]].concat();
+        let result = eval_from_bytes_bitcoin(&bytes, AddressFormat::default());
+        assert_eq!(result.pattern, ScriptPattern::WitnessProgram);
+    }
+
+    #[test]
+    fn test_cashaddr_routing() {
+        // Same hash as the CashAddr specification test vectors
+        let p2pkh = utils::hex_to_vec("76a91476a04053bda0a88bda5177b86a15c3b29f55987388ac");
+        let p2sh = utils::hex_to_vec("a91476a04053bda0a88bda5177b86a15c3b29f55987387");
+        let eval = |bytes: &[u8], coin: &str| {
+            let coin: CoinType = coin.parse().unwrap();
+            eval_from_bytes(bytes, coin.address_format()).address
+        };
+        assert_eq!(
+            eval(&p2pkh, "bitcoincash").unwrap(),
+            "bitcoincash:qpm2qsznhks23z7629mms6s4cwef74vcwvy22gdx6a"
+        );
+        assert_eq!(
+            eval(&p2sh, "bitcoincash").unwrap(),
+            "bitcoincash:ppm2qsznhks23z7629mms6s4cwef74vcwvn0h829pq"
+        );
+        // Other coins are unaffected by the prefix of Bitcoin Cash
+        assert_eq!(
+            eval(&p2pkh, "bitcoin").unwrap(),
+            "1BpEi6DfDAUFd7GtittLSdBeYJvcoaVggu"
+        );
+        assert_eq!(
+            eval(&p2sh, "bitcoin").unwrap(),
+            "3CWFddi6m4ndiGyKqzYvsFYagqDLPVMTzC"
+        );
+    }
+
+    #[test]
+    fn test_op_return_pushes() {
+        // OP_RETURN 05 "hello" OP_PUSHDATA1 81 <81 bytes>
+        let mut bytes = utils::hex_to_vec("6a0568656c6c6f4c51");
+        bytes.extend_from_slice(&[0x61; 81]);
+        assert_eq!(
+            op_return_pushes(&bytes),
+            vec![b"hello".to_vec(), vec![0x61; 81]]
+        );
+        let result = eval_from_bytes_bitcoin(&bytes, AddressFormat::default());
+        assert_eq!(
+            result.pattern,
+            ScriptPattern::OpReturn(format!("hello{}", "a".repeat(81)))
+        );
+
+        // The push of OP_0 is empty and a truncated push ends the payload
+        assert_eq!(
+            op_return_pushes(&utils::hex_to_vec("6a00510201")),
+            vec![Vec::<u8>::new()]
+        );
+        assert!(op_return_pushes(&utils::hex_to_vec("6a")).is_empty());
+    }
+
     #[test]
     fn test_bitcoin_script_op_return() {
         // Raw output script: 6a13636861726c6579206c6f766573206865696469
@@ -277,7 +679,7 @@ mod tests {
             0x6a, 0x13, 0x63, 0x68, 0x61, 0x72, 0x6c, 0x65, 0x79, 0x20, 0x6c, 0x6f, 0x76, 0x65,
             0x73, 0x20, 0x68, 0x65, 0x69, 0x64, 0x69,
         ];
-        let result = eval_from_bytes_bitcoin(&bytes, 0x00);
+        let result = eval_from_bytes_bitcoin(&bytes, AddressFormat::default());
         assert_eq!(result.address, None);
         assert_eq!(
             result.pattern,
@@ -290,7 +692,7 @@ mod tests {
         // Raw output script: 736372697074
         //                    OP_IFDUP OP_IF OP_2SWAP OP_VERIFY OP_2OVER OP_DEPTH
         let bytes = [0x73, 0x63, 0x72, 0x69, 0x70, 0x74];
-        let result = eval_from_bytes_bitcoin(&bytes, 0x00);
+        let result = eval_from_bytes_bitcoin(&bytes, AddressFormat::default());
         assert_eq!(result.address, None);
         assert_eq!(result.pattern, ScriptPattern::NotRecognised);
     }
@@ -298,8 +700,51 @@ mod tests {
     #[test]
     fn test_bitcoin_bogus_script() {
         let bytes = [0x4c, 0xFF, 0x00];
-        let result = eval_from_bytes_bitcoin(&bytes, 0x00);
+        let result = eval_from_bytes_bitcoin(&bytes, AddressFormat::default());
         assert_eq!(result.address, None);
         assert_eq!(result.pattern, ScriptPattern::NotRecognised);
     }
+
+    #[test]
+    fn test_eval_script_sig_p2sh_p2wpkh() {
+        // Input of the segwit tx in parser::reader tests, spending a P2SH-P2WPKH output
+        // Raw scriptSig: 1600141d7cd6c75c2e86f4cbf98eaed221b30bd9a0b928
+        //                OP_PUSHBYTES_22 (OP_0 OP_PUSHBYTES_20 1d7cd6c75c2e86f4cbf98eaed221b30bd9a0b928)
+        let bytes = [
+            0x16, 0x00, 0x14, 0x1d, 0x7c, 0xd6, 0xc7, 0x5c, 0x2e, 0x86, 0xf4, 0xcb, 0xf9, 0x8e,
+            0xae, 0xd2, 0x21, 0xb3, 0x0b, 0xd9, 0xa0, 0xb9, 0x28,
+        ];
+        assert_eq!(
+            eval_script_sig(&bytes, AddressFormat::default()),
+            Some(String::from("3Eq1QAi9gU2eJJNdPg2CpZ1wqZpPFSFpzv"))
+        );
+        // Not a witness program
+        assert_eq!(eval_script_sig(&bytes[1..], AddressFormat::default()), None);
+        // Legacy p2pkh spend: <sig> <pubkey>
+        assert_eq!(
+            eval_script_sig(&[0x01, 0x01, 0x01, 0x02], AddressFormat::default()),
+            None
+        );
+    }
+
+    #[test]
+    fn test_script_type() {
+        assert_eq!(std::mem::size_of::<ScriptType>(), 1);
+        assert_eq!(
+            ScriptType::from(&ScriptPattern::OpReturn(String::from("data"))),
+            ScriptType::OpReturn
+        );
+        assert_eq!(
+            ScriptType::from(&ScriptPattern::Pay2Taproot),
+            ScriptType::Pay2Taproot
+        );
+        for script_type in [
+            ScriptType::OpReturn,
+            ScriptType::Pay2Taproot,
+            ScriptType::Error,
+        ] {
+            assert_eq!(ScriptType::try_from(script_type as u8), Ok(script_type));
+        }
+        assert!(ScriptType::try_from(ScriptType::Error as u8 + 1).is_err());
+    }
 }
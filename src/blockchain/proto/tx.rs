@@ -1,11 +1,23 @@
 use bitcoin::hashes::{sha256d, Hash};
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
 use std::fmt;
+use std::str::FromStr;
+use std::sync::OnceLock;
 
-use crate::blockchain::proto::script;
+use crate::blockchain::parser::reader::{self, BlockchainRead};
+use crate::blockchain::parser::types::CoinType;
+use crate::blockchain::proto::script::{self, AddressFormat, ScriptPattern};
 use crate::blockchain::proto::varuint::VarUint;
-use crate::blockchain::proto::ToRaw;
+use crate::blockchain::proto::{Hashed, ToRaw};
 use crate::common::utils;
+use crate::errors::{OpError, OpErrorKind, OpResult, ParseResult};
+
+/// Sequence number of inputs which disables the locktime if all inputs use it
+const SEQUENCE_FINAL: u32 = 0xFFFFFFFF;
+/// Inputs with a lower sequence number signal replaceability (BIP125)
+const SEQUENCE_MAX_NON_RBF: u32 = 0xFFFFFFFE;
+/// If set, the sequence number isn't interpreted as relative locktime (BIP68)
+const SEQUENCE_LOCKTIME_DISABLE_FLAG: u32 = 1 << 31;
 
 pub struct RawTx {
     pub version: u32,
@@ -14,7 +26,9 @@ pub struct RawTx {
     pub out_count: VarUint,
     pub outputs: Vec<TxOutput>,
     pub locktime: u32,
-    pub version_id: u8,
+    pub address_format: AddressFormat,
+    // Size of the segwit marker, flag and witness data in bytes
+    pub witness_size: u64,
 }
 
 /// Simple transaction struct
@@ -26,6 +40,12 @@ pub struct EvaluatedTx {
     pub out_count: VarUint,
     pub outputs: Vec<EvaluatedTxOut>,
     pub locktime: u32,
+    // Size of the segwit marker, flag and witness data in bytes
+    pub witness_size: u64,
+    // Computed on first use, see `wtxid()`
+    wtxid: OnceLock<sha256d::Hash>,
+    // Address format of the coin, see `input_address()`
+    address_format: AddressFormat,
 }
 
 impl EvaluatedTx {
@@ -36,12 +56,12 @@ impl EvaluatedTx {
         out_count: VarUint,
         outputs: Vec<TxOutput>,
         locktime: u32,
-        version_id: u8,
+        address_format: AddressFormat,
     ) -> Self {
         // Evaluate and wrap all outputs to process them later
         let outputs = outputs
             .into_par_iter()
-            .map(|o| EvaluatedTxOut::eval_script(o, version_id))
+            .map(|o| EvaluatedTxOut::eval_script(o, address_format))
             .collect();
         EvaluatedTx {
             version,
@@ -50,6 +70,86 @@ impl EvaluatedTx {
             out_count,
             outputs,
             locktime,
+            witness_size: 0,
+            wtxid: OnceLock::new(),
+            address_format,
+        }
+    }
+
+    /// Parses a serialized transaction, e.g. as returned by `getrawtransaction <txid>`
+    pub fn parse(raw: &[u8], coin: &CoinType) -> ParseResult<Hashed<EvaluatedTx>> {
+        let tx = reader::read_exact_from(raw, |cursor| cursor.read_tx(coin))?;
+        Ok(Hashed::double_sha256(EvaluatedTx::from(tx)))
+    }
+
+    /// Returns the serialization with segwit marker, flag and witness data (BIP144).
+    /// Same as `to_bytes()` if no input has witness data.
+    pub fn to_witness_bytes(&self) -> Vec<u8> {
+        if !self.inputs.iter().any(TxInput::has_witness) {
+            return self.to_bytes();
+        }
+        let bytes = self.to_bytes();
+        let locktime_pos = bytes.len() - 4;
+        let mut witness_bytes = Vec::with_capacity(self.total_size() as usize);
+        witness_bytes.extend_from_slice(&bytes[..4]);
+        witness_bytes.extend_from_slice(&[0x00, 0x01]);
+        witness_bytes.extend_from_slice(&bytes[4..locktime_pos]);
+        for input in &self.inputs {
+            witness_bytes
+                .extend_from_slice(&VarUint::canonical(input.witness.len() as u64).to_bytes());
+            for item in &input.witness {
+                witness_bytes.extend_from_slice(&VarUint::canonical(item.len() as u64).to_bytes());
+                witness_bytes.extend_from_slice(item);
+            }
+        }
+        witness_bytes.extend_from_slice(&bytes[locktime_pos..]);
+        witness_bytes
+    }
+
+    /// Returns the address spent by the given input if it can be derived from the scriptSig alone,
+    /// i.e. for P2SH-P2WPKH spends. See `script::eval_script_sig()`.
+    pub fn input_address(&self, input: &TxInput) -> Option<String> {
+        if self.is_coinbase() {
+            return None;
+        }
+        script::eval_script_sig(&input.script_sig, self.address_format)
+    }
+
+    /// Returns the wtxid (BIP141), which unlike the txid commits to the witness data.
+    /// Equal to the txid for transactions without witness data. Cached after the first call.
+    pub fn wtxid(&self) -> sha256d::Hash {
+        *self
+            .wtxid
+            .get_or_init(|| sha256d::Hash::hash(&self.to_witness_bytes()))
+    }
+
+    /// Returns the stripped size in bytes, without segwit marker, flag and witness data
+    pub fn base_size(&self) -> u64 {
+        self.to_bytes().len() as u64
+    }
+
+    /// Returns the serialized size in bytes including segwit marker, flag and witness data
+    pub fn total_size(&self) -> u64 {
+        self.base_size() + self.witness_size
+    }
+
+    /// Returns the weight as defined in BIP141: non-witness bytes count 4 times, witness bytes once
+    pub fn weight(&self) -> u64 {
+        self.base_size() * 4 + self.witness_size
+    }
+
+    /// Returns the virtual size in vbytes (weight / 4, rounded up)
+    pub fn vsize(&self) -> u64 {
+        self.weight().div_ceil(4)
+    }
+
+    /// Returns the size in the given basis
+    pub fn size(&self, basis: SizeBasis) -> u64 {
+        match basis {
+            SizeBasis::Base => self.base_size(),
+            SizeBasis::Total => self.total_size(),
+            SizeBasis::Weight => self.weight(),
+            SizeBasis::Vsize => self.vsize(),
         }
     }
 
@@ -60,6 +160,90 @@ impl EvaluatedTx {
         }
         false
     }
+
+    /// Whether the transaction isn't time locked, i.e. the locktime is 0 or disabled
+    /// because all inputs have the sequence number 0xffffffff.
+    /// A time locked transaction is final once its locktime has passed, which depends on the block.
+    pub fn is_final(&self) -> bool {
+        self.locktime == 0
+            || self
+                .inputs
+                .iter()
+                .all(|input| input.seq_no == SEQUENCE_FINAL)
+    }
+
+    /// Whether any input has a relative locktime (BIP68), which needs version 2 or above
+    /// and an input sequence number without the disable flag (bit 31)
+    pub fn has_relative_timelock(&self) -> bool {
+        self.version >= 2
+            && self
+                .inputs
+                .iter()
+                .any(|input| input.seq_no & SEQUENCE_LOCKTIME_DISABLE_FLAG == 0)
+    }
+
+    /// Whether the transaction signals replaceability (BIP125),
+    /// i.e. any input has a sequence number below 0xfffffffe
+    pub fn is_rbf_signaling(&self) -> bool {
+        self.inputs
+            .iter()
+            .any(|input| input.seq_no < SEQUENCE_MAX_NON_RBF)
+    }
+}
+
+/// What "size" means for transactions, which is ambiguous since SegWit
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SizeBasis {
+    /// Stripped size in bytes
+    Base,
+    /// Size in bytes including witness data
+    Total,
+    /// Weight units (BIP141)
+    Weight,
+    /// Virtual bytes (BIP141)
+    Vsize,
+}
+
+impl SizeBasis {
+    /// Returns the unit of the size
+    pub fn unit(&self) -> &'static str {
+        match self {
+            SizeBasis::Base | SizeBasis::Total => "bytes",
+            SizeBasis::Weight => "WU",
+            SizeBasis::Vsize => "vbytes",
+        }
+    }
+}
+
+impl FromStr for SizeBasis {
+    type Err = OpError;
+    fn from_str(s: &str) -> OpResult<Self> {
+        match s {
+            "base" => Ok(SizeBasis::Base),
+            "total" => Ok(SizeBasis::Total),
+            "weight" => Ok(SizeBasis::Weight),
+            "vsize" => Ok(SizeBasis::Vsize),
+            n => Err(OpError::new(OpErrorKind::InvalidArgsError)
+                .join_msg(&format!("Unknown size basis `{}`!", n))),
+        }
+    }
+}
+
+impl Hashed<EvaluatedTx> {
+    /// Iterates over all outputs together with their outpoint and the resolved address
+    pub fn resolved_outputs(&self) -> impl Iterator<Item = ResolvedOutput<'_>> {
+        self.value
+            .outputs
+            .iter()
+            .enumerate()
+            .map(move |(i, output)| ResolvedOutput {
+                txid: self.hash,
+                vout: i as u32,
+                address: output.script.address.as_deref(),
+                value: output.out.value,
+                script_type: &output.script.pattern,
+            })
+    }
 }
 
 impl fmt::Debug for EvaluatedTx {
@@ -75,15 +259,18 @@ impl fmt::Debug for EvaluatedTx {
 
 impl From<RawTx> for EvaluatedTx {
     fn from(tx: RawTx) -> Self {
-        Self::new(
-            tx.version,
-            tx.in_count,
-            tx.inputs,
-            tx.out_count,
-            tx.outputs,
-            tx.locktime,
-            tx.version_id,
-        )
+        Self {
+            witness_size: tx.witness_size,
+            ..Self::new(
+                tx.version,
+                tx.in_count,
+                tx.inputs,
+                tx.out_count,
+                tx.outputs,
+                tx.locktime,
+                tx.address_format,
+            )
+        }
     }
 }
 
@@ -111,7 +298,7 @@ impl ToRaw for EvaluatedTx {
 }
 
 /// TxOutpoint references an existing transaction output
-#[derive(PartialEq, Eq, Hash)]
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub struct TxOutpoint {
     pub txid: sha256d::Hash,
     pub index: u32, // 0-based offset within tx
@@ -121,6 +308,17 @@ impl TxOutpoint {
     pub fn new(txid: sha256d::Hash, index: u32) -> Self {
         Self { txid, index }
     }
+
+    /// Inverse of `to_bytes()`, recovers txid and index from a serialized outpoint.
+    /// Panics if `bytes` is shorter than 36 bytes.
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let mut index = [0u8; 4];
+        index.copy_from_slice(&bytes[32..36]);
+        Self {
+            txid: sha256d::Hash::from_slice(&bytes[0..32]).unwrap(),
+            index: u32::from_le_bytes(index),
+        }
+    }
 }
 
 impl ToRaw for TxOutpoint {
@@ -147,6 +345,43 @@ pub struct TxInput {
     pub script_len: VarUint,
     pub script_sig: Vec<u8>,
     pub seq_no: u32,
+    // Witness stack of the input, which is not part of the serialization
+    pub witness: Vec<Vec<u8>>,
+}
+
+impl TxInput {
+    /// Whether the input has witness data
+    #[inline]
+    pub fn has_witness(&self) -> bool {
+        !self.witness.is_empty()
+    }
+
+    /// Returns the witness stack without the annex (BIP341), i.e. a last item starting with 0x50
+    pub fn witness_without_annex(&self) -> &[Vec<u8>] {
+        let witness = self.witness.as_slice();
+        match witness {
+            [.., annex] if witness.len() >= 2 && annex.first() == Some(&0x50) => {
+                &witness[..witness.len() - 1]
+            }
+            _ => witness,
+        }
+    }
+
+    /// Returns the revealed script if the witness looks like a taproot script path spend,
+    /// i.e. it ends with the script and a control block. The spent output isn't known,
+    /// so this is derived from the shape of the witness only.
+    pub fn tapscript(&self) -> Option<&[u8]> {
+        match self.witness_without_annex() {
+            [.., script, control]
+                if control.len() >= 33
+                    && (control.len() - 33) % 32 == 0
+                    && control[0] & 0xfe == 0xc0 =>
+            {
+                Some(script)
+            }
+            _ => None,
+        }
+    }
 }
 
 impl ToRaw for TxInput {
@@ -167,6 +402,7 @@ impl fmt::Debug for TxInput {
             .field("script_len", &self.script_len)
             .field("script_sig", &self.script_sig)
             .field("seq_no", &self.seq_no)
+            .field("witness", &self.witness)
             .finish()
     }
 }
@@ -178,14 +414,25 @@ pub struct EvaluatedTxOut {
 }
 
 impl EvaluatedTxOut {
-    pub fn eval_script(out: TxOutput, version_id: u8) -> EvaluatedTxOut {
+    pub fn eval_script(out: TxOutput, format: AddressFormat) -> EvaluatedTxOut {
         EvaluatedTxOut {
-            script: script::eval_from_bytes(&out.script_pubkey, version_id),
+            script: script::eval_from_bytes(&out.script_pubkey, format),
             out,
         }
     }
 }
 
+/// Output with its outpoint and evaluated script, see `Hashed<EvaluatedTx>::resolved_outputs()`
+#[derive(Debug)]
+pub struct ResolvedOutput<'a> {
+    pub txid: sha256d::Hash,
+    pub vout: u32,
+    // None if no address could be derived from the script
+    pub address: Option<&'a str>,
+    pub value: u64,
+    pub script_type: &'a ScriptPattern,
+}
+
 /// Holds TxOutput informations
 pub struct TxOutput {
     pub value: u64,
@@ -212,3 +459,72 @@ impl fmt::Debug for TxOutput {
             .finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_outpoint_from_bytes() {
+        let outpoint = TxOutpoint::new(
+            sha256d::Hash::from_str(
+                "fff2525b8931402dd09222c50775608f75787bd2b87e56995a7bdd30f79702c4",
+            )
+            .unwrap(),
+            0xdeadbeef,
+        );
+        assert_eq!(TxOutpoint::from_bytes(&outpoint.to_bytes()), outpoint);
+    }
+
+    fn tx(version: u32, locktime: u32, sequences: &[u32]) -> EvaluatedTx {
+        let inputs = sequences
+            .iter()
+            .map(|seq_no| TxInput {
+                outpoint: TxOutpoint::new(sha256d::Hash::all_zeros(), 0),
+                script_len: VarUint::from(0u8),
+                script_sig: vec![],
+                seq_no: *seq_no,
+                witness: vec![],
+            })
+            .collect::<Vec<_>>();
+        EvaluatedTx::new(
+            version,
+            VarUint::from(inputs.len() as u8),
+            inputs,
+            VarUint::from(0u8),
+            vec![],
+            locktime,
+            AddressFormat::default(),
+        )
+    }
+
+    #[test]
+    fn test_is_final() {
+        assert!(tx(1, 0, &[0]).is_final());
+        assert!(tx(1, 500000, &[SEQUENCE_FINAL, SEQUENCE_FINAL]).is_final());
+        assert!(!tx(1, 500000, &[SEQUENCE_FINAL, 0xFFFFFFFE]).is_final());
+        assert!(!tx(2, 1700000000, &[0]).is_final());
+    }
+
+    #[test]
+    fn test_has_relative_timelock() {
+        // 10 blocks
+        assert!(tx(2, 0, &[SEQUENCE_FINAL, 10]).has_relative_timelock());
+        // 512 seconds (type flag, bit 22)
+        assert!(tx(2, 0, &[(1 << 22) | 1]).has_relative_timelock());
+        // Only enforced from version 2
+        assert!(!tx(1, 0, &[10]).has_relative_timelock());
+        // Disable flag
+        assert!(!tx(2, 0, &[(1 << 31) | 10]).has_relative_timelock());
+        assert!(!tx(2, 0, &[0xFFFFFFFE, SEQUENCE_FINAL]).has_relative_timelock());
+    }
+
+    #[test]
+    fn test_is_rbf_signaling() {
+        assert!(tx(2, 0, &[SEQUENCE_FINAL, 0xFFFFFFFD]).is_rbf_signaling());
+        assert!(tx(1, 0, &[0]).is_rbf_signaling());
+        assert!(!tx(2, 0, &[0xFFFFFFFE, SEQUENCE_FINAL]).is_rbf_signaling());
+        assert!(!tx(2, 0, &[]).is_rbf_signaling());
+    }
+}
@@ -30,6 +30,27 @@ impl VarUint {
         };
         Ok(vint)
     }
+
+    /// Encodes the value with the least number of bytes
+    pub fn canonical(value: u64) -> VarUint {
+        match value {
+            0..=0xfc => VarUint::from(value as u8),
+            0xfd..=0xffff => VarUint::from(value as u16),
+            0x10000..=0xffffffff => VarUint::from(value as u32),
+            _ => VarUint::from(value),
+        }
+    }
+
+    /// Returns true if the value is encoded with the least number of bytes
+    pub fn is_canonical(&self) -> bool {
+        let canonical_len = match self.value {
+            0..=0xfc => 1,
+            0xfd..=0xffff => 3,
+            0x10000..=0xffffffff => 5,
+            _ => 9,
+        };
+        self.buf.len() == canonical_len
+    }
 }
 
 impl From<u8> for VarUint {
@@ -136,6 +157,30 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_varuint_canonical() {
+        let encodings: [&[u8]; 4] = [
+            &[0x01],
+            &[0xfd, 0x01, 0x00],
+            &[0xfe, 0x01, 0x00, 0x00, 0x00],
+            &[0xff, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+        ];
+        for (i, encoding) in encodings.iter().enumerate() {
+            let test = VarUint::read_from(&mut io::Cursor::new(encoding)).unwrap();
+            assert_eq!(1, test.value);
+            assert_eq!(encoding.to_vec(), test.to_bytes());
+            assert_eq!(i == 0, test.is_canonical());
+        }
+        assert!(VarUint::from(0xfdu16).is_canonical());
+        assert!(!VarUint::from(0xffffu32).is_canonical());
+        assert!(VarUint::from(0x100000000u64).is_canonical());
+        for value in [0, 0xfc, 0xfd, 0xffff, 0x10000, 0xffffffff, 0x100000000] {
+            let test = VarUint::canonical(value);
+            assert_eq!(value, test.value);
+            assert!(test.is_canonical());
+        }
+    }
+
     #[test]
     fn test_varuint_read() {
         let mut cursor = io::Cursor::new([0xfe, 0x55, 0xa1, 0xae, 0xc6]);
@@ -0,0 +1,213 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::ToRaw;
+use crate::callbacks::compress::{Compression, OutputWriter};
+use crate::callbacks::rotate::{ColumnType, Table};
+use crate::callbacks::{common, BlockFlow, Callback};
+use crate::errors::OpResult;
+
+const ADDRESS_ACTIVITY: Table = Table::csv(
+    "addressactivity",
+    &[
+        ("height", ColumnType::Integer),
+        ("new_addresses", ColumnType::Integer),
+        ("emptied_addresses", ColumnType::Integer),
+    ],
+);
+
+/// Counts per block the addresses which receive an output for the first time
+/// and the addresses whose last unspent output is spent.
+/// An address counts as emptied if it has no unspent output left at the end of the block.
+/// All addresses seen since the start height are kept in memory.
+pub struct AddressActivity {
+    dump_folder: PathBuf,
+    writer: OutputWriter,
+
+    // key: txid + index, value: address
+    unspents: common::Unspents<Arc<str>>,
+    // Number of unspent outputs per address, 0 if all outputs have been spent
+    addresses: HashMap<Arc<str>, u64>,
+
+    start_height: u64,
+    new_count: u64,
+    emptied_count: u64,
+}
+
+impl AddressActivity {
+    /// Returns (new_addresses, emptied_addresses) of the block and updates the unspents
+    fn block_activity(&mut self, block: &Block) -> (u64, u64) {
+        let mut new_addresses = 0;
+        let mut spent_from: HashSet<Arc<str>> = HashSet::new();
+        for tx in &block.txs {
+            for (_, address) in common::take_unspents(tx, &mut self.unspents) {
+                if let Some(count) = self.addresses.get_mut(&address) {
+                    *count -= 1;
+                }
+                spent_from.insert(address);
+            }
+
+            let addresses = &mut self.addresses;
+            let outputs = common::eval_unspents_with(tx, block.genesis, |output| {
+                let address = output.address?;
+                match addresses.get_key_value(address) {
+                    Some((address, _)) => Some(Arc::clone(address)),
+                    None => {
+                        new_addresses += 1;
+                        Some(Arc::from(address))
+                    }
+                }
+            });
+            for (outpoint, address) in outputs {
+                *self.addresses.entry(Arc::clone(&address)).or_insert(0) += 1;
+                self.unspents.insert(outpoint.to_bytes(), address);
+            }
+        }
+        let emptied = spent_from
+            .iter()
+            .filter(|address| self.addresses.get(*address) == Some(&0))
+            .count() as u64;
+        (new_addresses, emptied)
+    }
+}
+
+impl Callback for AddressActivity {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        let cmd = Command::new("addressactivity")
+            .about("Dumps the number of new and emptied addresses per block to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            );
+        Compression::add_args(cmd)
+    }
+
+    fn schema(_: &ArgMatches) -> Vec<Table>
+    where
+        Self: Sized,
+    {
+        vec![ADDRESS_ACTIVITY]
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let dump_folder = &PathBuf::from(matches.get_one::<String>("dump-folder").unwrap());
+        let cb = AddressActivity {
+            dump_folder: PathBuf::from(dump_folder),
+            writer: Compression::from_matches(matches)?
+                .create(&dump_folder.join("addressactivity.csv.tmp"))?,
+            unspents: HashMap::with_capacity(10000000),
+            addresses: HashMap::with_capacity(10000000),
+            start_height: 0,
+            new_count: 0,
+            emptied_count: 0,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing addressactivity with dump folder: {} ...", &self.dump_folder.display());
+        self.writer
+            .write_all(ADDRESS_ACTIVITY.header().as_bytes())?;
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<BlockFlow> {
+        let (new_addresses, emptied) = self.block_activity(block);
+        self.new_count += new_addresses;
+        self.emptied_count += emptied;
+        self.writer
+            .write_all(format!("{};{};{}\n", block_height, new_addresses, emptied).as_bytes())?;
+        Ok(BlockFlow::Continue)
+    }
+
+    fn tracks_utxos(&self) -> bool {
+        true
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.writer.finish()?;
+        fs::rename(
+            self.dump_folder.as_path().join("addressactivity.csv.tmp"),
+            self.dump_folder
+                .as_path()
+                .join(self.writer.file_name(format!(
+                    "addressactivity-{}-{}.csv",
+                    self.start_height, block_height
+                ))),
+        )?;
+        info!(target: "callback", "Done.\nDumped address activity from height {} to {}:\n\
+                                   \t-> new addresses:     {:9}\n\
+                                   \t-> emptied addresses: {:9}",
+             self.start_height, block_height, self.new_count, self.emptied_count);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::tx::TxOutpoint;
+    use crate::callbacks::common::{test_block, test_script};
+    use bitcoin::hashes::{sha256d, Hash};
+
+    #[test]
+    fn test_address_activity() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let matches = AddressActivity::build_subcommand()
+            .get_matches_from(["addressactivity", tmp_dir.path().to_str().unwrap()]);
+        let mut callback = AddressActivity::new(&matches).unwrap();
+        callback.on_start(1).unwrap();
+
+        // 1 and 2 are funded
+        let coinbase = TxOutpoint::new(sha256d::Hash::all_zeros(), 0xFFFFFFFF);
+        let block1 = test_block(
+            vec![coinbase],
+            &[(test_script(1), 100), (test_script(2), 50)],
+        );
+        callback.on_block(&block1, 1).unwrap();
+        // 1 is emptied, 3 is new and 2 gets a second output
+        let funding = block1.txs[0].hash;
+        let block2 = test_block(
+            vec![TxOutpoint::new(funding, 0)],
+            &[(test_script(2), 30), (test_script(3), 60)],
+        );
+        callback.on_block(&block2, 2).unwrap();
+        // 2 is emptied and 1 is funded again, so it isn't new
+        let block3 = test_block(
+            vec![
+                TxOutpoint::new(funding, 1),
+                TxOutpoint::new(block2.txs[0].hash, 0),
+            ],
+            &[(test_script(1), 70)],
+        );
+        callback.on_block(&block3, 3).unwrap();
+        callback.on_complete(3).unwrap();
+
+        let csv = fs::read_to_string(tmp_dir.path().join("addressactivity-1-3.csv")).unwrap();
+        ADDRESS_ACTIVITY.assert_csv(&csv);
+        assert_eq!(
+            csv,
+            "height;new_addresses;emptied_addresses\n\
+             1;2;0\n\
+             2;1;1\n\
+             3;0;1\n"
+        );
+    }
+}
@@ -0,0 +1,316 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+use clap::{Arg, ArgGroup, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::ToRaw;
+use crate::callbacks::compress::{Compression, OutputWriter};
+use crate::callbacks::rotate::{ColumnType, Table};
+use crate::callbacks::{common, BlockFlow, Callback};
+use crate::common::utils::HashByteOrder;
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+const ADDRESS_FILTER: Table = Table::csv(
+    "addressfilter",
+    &[
+        ("height", ColumnType::Integer),
+        ("txid", ColumnType::String),
+        ("direction", ColumnType::String),
+        ("address", ColumnType::String),
+        ("value", ColumnType::Integer),
+    ],
+);
+
+/// Dumps the inputs and outputs of all transactions touching the watchlisted addresses.
+/// Only outputs paying to watchlisted addresses are tracked, spending inputs are resolved from them.
+pub struct AddressFilter {
+    dump_folder: PathBuf,
+    writer: OutputWriter,
+    hash_byte_order: HashByteOrder,
+    addresses: HashSet<String>,
+
+    // key: txid + index, value: (address, value) of watchlisted outputs
+    unspents: common::Unspents<(String, u64)>,
+
+    start_height: u64,
+    row_count: u64,
+}
+
+impl AddressFilter {
+    fn write_row(
+        &mut self,
+        block_height: u64,
+        txid: &str,
+        direction: &str,
+        address: &str,
+        value: Option<u64>,
+    ) -> OpResult<()> {
+        let value = value.map(|value| value.to_string()).unwrap_or_default();
+        self.writer.write_all(
+            format!(
+                "{};{};{};{};{}\n",
+                block_height, txid, direction, address, value
+            )
+            .as_bytes(),
+        )?;
+        self.row_count += 1;
+        Ok(())
+    }
+}
+
+impl Callback for AddressFilter {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        let cmd = Command::new("addressfilter")
+            .about("Dumps the inputs and outputs of the watchlisted addresses to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            )
+            .arg(
+                Arg::new("addresses")
+                    .long("addresses")
+                    .value_name("FILE")
+                    .help("File with one address per line"),
+            )
+            .arg(
+                Arg::new("address")
+                    .long("address")
+                    .value_name("ADDR")
+                    .action(clap::ArgAction::Append)
+                    .help("Address to watch, can be repeated"),
+            )
+            .group(
+                ArgGroup::new("watchlist")
+                    .args(["addresses", "address"])
+                    .multiple(true)
+                    .required(true),
+            );
+        Compression::add_args(cmd)
+    }
+
+    fn schema(_: &ArgMatches) -> Vec<Table>
+    where
+        Self: Sized,
+    {
+        vec![ADDRESS_FILTER]
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let dump_folder = &PathBuf::from(matches.get_one::<String>("dump-folder").unwrap());
+        let mut addresses = match matches.get_one::<String>("addresses") {
+            Some(path) => common::parse_watchlist(&fs::read_to_string(path)?),
+            None => HashSet::new(),
+        };
+        if let Some(values) = matches.get_many::<String>("address") {
+            addresses.extend(values.cloned());
+        }
+        if addresses.is_empty() {
+            return Err(OpError::new(OpErrorKind::InvalidArgsError)
+                .join_msg("The watchlist doesn't contain any addresses"));
+        }
+        let cb = AddressFilter {
+            dump_folder: PathBuf::from(dump_folder),
+            writer: Compression::from_matches(matches)?
+                .create(&dump_folder.join("addressfilter.csv.tmp"))?,
+            hash_byte_order: HashByteOrder::from_matches(matches)?,
+            addresses,
+            unspents: HashMap::new(),
+            start_height: 0,
+            row_count: 0,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing addressfilter for {} addresses with dump folder: {} ...",
+              self.addresses.len(), &self.dump_folder.display());
+        self.writer.write_all(ADDRESS_FILTER.header().as_bytes())?;
+        Ok(())
+    }
+
+    /// Writes spent inputs first, then the received outputs of each transaction
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<BlockFlow> {
+        for tx in &block.txs {
+            let txid = self.hash_byte_order.hex(&tx.hash);
+            for input in &tx.value.inputs {
+                match self.unspents.remove(&input.outpoint.to_bytes()) {
+                    Some((address, value)) => {
+                        self.write_row(block_height, &txid, "in", &address, Some(value))?
+                    }
+                    // Created before the start height, only nested SegWit spends reveal the address
+                    None => {
+                        let address = tx.value.input_address(input);
+                        if let Some(address) = address.filter(|a| self.addresses.contains(a)) {
+                            self.write_row(block_height, &txid, "in", &address, None)?;
+                        }
+                    }
+                }
+            }
+
+            let addresses = &self.addresses;
+            let received = common::eval_unspents_with(tx, block.genesis, |output| {
+                output
+                    .address
+                    .filter(|address| addresses.contains(*address))
+                    .map(|address| (address.to_string(), output.value))
+            });
+            for (outpoint, (address, value)) in received {
+                self.write_row(block_height, &txid, "out", &address, Some(value))?;
+                self.unspents.insert(outpoint.to_bytes(), (address, value));
+            }
+        }
+        Ok(BlockFlow::Continue)
+    }
+
+    fn tracks_utxos(&self) -> bool {
+        true
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.writer.finish()?;
+        fs::rename(
+            self.dump_folder.as_path().join("addressfilter.csv.tmp"),
+            self.dump_folder
+                .as_path()
+                .join(self.writer.file_name(format!(
+                    "addressfilter-{}-{}.csv",
+                    self.start_height, block_height
+                ))),
+        )?;
+        info!(target: "callback", "Done.\nDumped {} inputs and outputs from height {} to {}.",
+             self.row_count, self.start_height, block_height);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::tx::TxOutpoint;
+    use crate::callbacks::common::test_block;
+    use crate::common::utils;
+    use bitcoin::hashes::{sha256d, Hash};
+
+    // P2PKH to 1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa
+    const WATCHED: &str = "76a91462e907b15cbf27d5425399ebf6f0fb50ebb88f1888ac";
+    // P2PKH to 1KFHE7w8BhaENAswwryaoccDb6qcT6DbYY
+    const OTHER: &str = "76a914c825a1ecf2a6830c4401620c3a16f1995057c2ab88ac";
+
+    #[test]
+    fn test_address_filter() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let watchlist = tmp_dir.path().join("watchlist.txt");
+        fs::write(
+            &watchlist,
+            "# satoshi\n1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa\n",
+        )
+        .unwrap();
+        let matches = AddressFilter::build_subcommand().get_matches_from([
+            "addressfilter",
+            tmp_dir.path().to_str().unwrap(),
+            "--addresses",
+            watchlist.to_str().unwrap(),
+            "--address",
+            "1BoatSLRHtKNngkdXEeobR76b53LETtpyT",
+        ]);
+        let mut callback = AddressFilter::new(&matches).unwrap();
+        assert_eq!(callback.addresses.len(), 2);
+        callback.on_start(1).unwrap();
+
+        let receive = test_block(vec![], &[(WATCHED, 100), (OTHER, 50), (WATCHED, 20)]);
+        callback.on_block(&receive, 1).unwrap();
+        // Spends a watched and an unwatched output
+        let spend = test_block(
+            vec![
+                TxOutpoint::new(receive.txs[0].hash, 0),
+                TxOutpoint::new(receive.txs[0].hash, 1),
+            ],
+            &[(OTHER, 110), (WATCHED, 30)],
+        );
+        callback.on_block(&spend, 2).unwrap();
+        callback.on_complete(2).unwrap();
+
+        let receive_txid = HashByteOrder::Rpc.hex(&receive.txs[0].hash);
+        let spend_txid = HashByteOrder::Rpc.hex(&spend.txs[0].hash);
+        let address = "1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa";
+        let csv = fs::read_to_string(tmp_dir.path().join("addressfilter-1-2.csv")).unwrap();
+        ADDRESS_FILTER.assert_csv(&csv);
+        assert_eq!(
+            csv,
+            format!(
+                "height;txid;direction;address;value\n\
+                 1;{receive_txid};out;{address};100\n\
+                 1;{receive_txid};out;{address};20\n\
+                 2;{spend_txid};in;{address};100\n\
+                 2;{spend_txid};out;{address};30\n"
+            )
+        );
+        // The unspent watched outputs remain tracked
+        assert_eq!(callback.unspents.len(), 2);
+    }
+
+    #[test]
+    fn test_address_filter_nested_segwit() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let matches = AddressFilter::build_subcommand().get_matches_from([
+            "addressfilter",
+            tmp_dir.path().to_str().unwrap(),
+            "--address",
+            "3Eq1QAi9gU2eJJNdPg2CpZ1wqZpPFSFpzv",
+        ]);
+        let mut callback = AddressFilter::new(&matches).unwrap();
+        callback.on_start(1).unwrap();
+
+        // Spends an output created before the start height
+        let outpoint = TxOutpoint::new(sha256d::Hash::hash(b"unknown"), 0);
+        let mut spend = test_block(vec![outpoint], &[(OTHER, 50)]);
+        spend.txs[0].value.inputs[0].script_sig = utils::hex_to_vec(common::P2SH_P2WPKH_SCRIPT_SIG);
+        callback.on_block(&spend, 1).unwrap();
+        callback.on_complete(1).unwrap();
+
+        // The address is resolved from the scriptSig, the value is unknown
+        let csv = fs::read_to_string(tmp_dir.path().join("addressfilter-1-1.csv")).unwrap();
+        ADDRESS_FILTER.assert_csv(&csv);
+        assert_eq!(
+            csv,
+            format!(
+                "height;txid;direction;address;value\n\
+                 1;{};in;3Eq1QAi9gU2eJJNdPg2CpZ1wqZpPFSFpzv;\n",
+                HashByteOrder::Rpc.hex(&spend.txs[0].hash)
+            )
+        );
+    }
+
+    #[test]
+    fn test_address_filter_args() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let dump_folder = tmp_dir.path().to_str().unwrap();
+        assert!(AddressFilter::build_subcommand()
+            .try_get_matches_from(["addressfilter", dump_folder])
+            .is_err());
+
+        let watchlist = tmp_dir.path().join("watchlist.txt");
+        fs::write(&watchlist, "# empty\n").unwrap();
+        let matches = AddressFilter::build_subcommand().get_matches_from([
+            "addressfilter",
+            dump_folder,
+            "--addresses",
+            watchlist.to_str().unwrap(),
+        ]);
+        assert!(AddressFilter::new(&matches).is_err());
+    }
+}
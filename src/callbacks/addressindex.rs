@@ -0,0 +1,260 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::ToRaw;
+use crate::callbacks::compress::Compression;
+use crate::callbacks::rotate::{ColumnType, Table};
+use crate::callbacks::{common, BlockFlow, Callback};
+use crate::errors::OpResult;
+
+const ADDRESS_INDEX: Table = Table::csv(
+    "addressindex",
+    &[
+        ("address", ColumnType::String),
+        ("first_seen", ColumnType::Integer),
+        ("last_seen", ColumnType::Integer),
+        ("received", ColumnType::Integer),
+        ("sent", ColumnType::Integer),
+    ],
+);
+
+/// Activity of an address since the start height
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct AddressStats {
+    // Height of the first output paying to the address
+    first_seen: u64,
+    // Height of the last output paying to the address or spending from it
+    last_seen: u64,
+    received: u64,
+    sent: u64,
+}
+
+/// Dumps per address the height where it first received funds, the height of its last activity
+/// and the total received and sent value. Spent change counts as sent and received again.
+/// All addresses seen since the start height are kept in memory.
+pub struct AddressIndex {
+    dump_folder: PathBuf,
+    compression: Compression,
+    // Addresses which received less are not written
+    min_received: u64,
+
+    // key: txid + index, value: (address, value)
+    unspents: common::Unspents<(Arc<str>, u64)>,
+    addresses: HashMap<Arc<str>, AddressStats>,
+
+    start_height: u64,
+}
+
+impl AddressIndex {
+    /// Returns (address, stats) of all addresses which received at least `min_received`,
+    /// sorted by first seen height and address
+    fn index(&self) -> Vec<(&str, AddressStats)> {
+        let mut index: Vec<(&str, AddressStats)> = self
+            .addresses
+            .iter()
+            .filter(|(_, stats)| stats.received >= self.min_received)
+            .map(|(address, stats)| (address.as_ref(), *stats))
+            .collect();
+        index.sort_unstable_by_key(|(address, stats)| (stats.first_seen, *address));
+        index
+    }
+}
+
+impl Callback for AddressIndex {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        let cmd = Command::new("addressindex")
+            .about("Dumps the first and last seen height and the total received and sent value per address to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            )
+            .arg(
+                Arg::new("min-received")
+                    .long("min-received")
+                    .value_name("SATS")
+                    .value_parser(clap::value_parser!(u64))
+                    .default_value("0")
+                    .help("Omits addresses which received less than SATS in total"),
+            );
+        Compression::add_args(cmd)
+    }
+
+    fn schema(_: &ArgMatches) -> Vec<Table>
+    where
+        Self: Sized,
+    {
+        vec![ADDRESS_INDEX]
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let dump_folder = &PathBuf::from(matches.get_one::<String>("dump-folder").unwrap());
+        let cb = AddressIndex {
+            dump_folder: PathBuf::from(dump_folder),
+            compression: Compression::from_matches(matches)?,
+            min_received: *matches.get_one::<u64>("min-received").unwrap(),
+            unspents: HashMap::with_capacity(10000000),
+            addresses: HashMap::with_capacity(10000000),
+            start_height: 0,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing addressindex with dump folder: {} ...", &self.dump_folder.display());
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<BlockFlow> {
+        for tx in &block.txs {
+            for (_, (address, value)) in common::take_unspents(tx, &mut self.unspents) {
+                if let Some(stats) = self.addresses.get_mut(&address) {
+                    stats.sent += value;
+                    stats.last_seen = block_height;
+                }
+            }
+
+            let addresses = &mut self.addresses;
+            let outputs = common::eval_unspents_with(tx, block.genesis, |output| {
+                let address = output.address?;
+                let address = match addresses.get_key_value(address) {
+                    Some((address, _)) => Arc::clone(address),
+                    None => Arc::from(address),
+                };
+                let stats = addresses
+                    .entry(Arc::clone(&address))
+                    .or_insert(AddressStats {
+                        first_seen: block_height,
+                        last_seen: block_height,
+                        received: 0,
+                        sent: 0,
+                    });
+                stats.received += output.value;
+                stats.last_seen = block_height;
+                Some((address, output.value))
+            });
+            for (outpoint, unspent) in outputs {
+                self.unspents.insert(outpoint.to_bytes(), unspent);
+            }
+        }
+        Ok(BlockFlow::Continue)
+    }
+
+    fn tracks_utxos(&self) -> bool {
+        true
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        // The unspents aren't needed anymore, free them before sorting
+        self.unspents = HashMap::new();
+        let total = self.addresses.len();
+
+        let path = self.dump_folder.join(format!(
+            "addressindex-{}-{}.csv{}",
+            self.start_height,
+            block_height,
+            self.compression.suffix()
+        ));
+        let mut writer = self.compression.create(&path)?;
+        writer.write_all(ADDRESS_INDEX.header().as_bytes())?;
+        let index = self.index();
+        for (address, stats) in &index {
+            writer.write_all(
+                format!(
+                    "{};{};{};{};{}\n",
+                    address, stats.first_seen, stats.last_seen, stats.received, stats.sent
+                )
+                .as_bytes(),
+            )?;
+        }
+        writer.finish()?;
+        info!(target: "callback", "Done.\nDumped address index from height {} to {}:\n\
+                                   \t-> addresses: {:9}\n\
+                                   \t-> omitted:   {:9}",
+             self.start_height, block_height, index.len(), total - index.len());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::tx::TxOutpoint;
+    use crate::callbacks::common::{test_block, test_script};
+    use bitcoin::hashes::{sha256d, Hash};
+    use std::fs;
+
+    fn run(args: &[&str]) -> (tempfile::TempDir, String) {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut argv = vec!["addressindex", tmp_dir.path().to_str().unwrap()];
+        argv.extend_from_slice(args);
+        let matches = AddressIndex::build_subcommand().get_matches_from(argv);
+        let mut callback = AddressIndex::new(&matches).unwrap();
+        callback.on_start(1).unwrap();
+
+        // 3 and 1 are funded
+        let coinbase = TxOutpoint::new(sha256d::Hash::all_zeros(), 0xFFFFFFFF);
+        let block1 = test_block(
+            vec![coinbase],
+            &[(test_script(3), 100), (test_script(1), 50)],
+        );
+        callback.on_block(&block1, 1).unwrap();
+        // 3 pays 2 and gets change, 1 stays untouched
+        let block2 = test_block(
+            vec![TxOutpoint::new(block1.txs[0].hash, 0)],
+            &[(test_script(2), 5), (test_script(3), 90)],
+        );
+        callback.on_block(&block2, 2).unwrap();
+        callback.on_complete(2).unwrap();
+
+        let csv = fs::read_to_string(tmp_dir.path().join("addressindex-1-2.csv")).unwrap();
+        ADDRESS_INDEX.assert_csv(&csv);
+        (tmp_dir, csv)
+    }
+
+    #[test]
+    fn test_address_index() {
+        let address = |n: u8| {
+            bitcoin::Address::new(
+                bitcoin::Network::Bitcoin,
+                bitcoin::address::Payload::PubkeyHash(bitcoin::PubkeyHash::from_byte_array(
+                    [n; 20],
+                )),
+            )
+            .to_string()
+        };
+        // Addresses first seen in the same block are sorted by address
+        let mut first_block = vec![
+            format!("{};1;1;50;0", address(1)),
+            format!("{};1;2;190;100", address(3)),
+        ];
+        first_block.sort();
+
+        let (_, csv) = run(&[]);
+        let rows: Vec<&str> = csv.lines().collect();
+        assert_eq!(rows[0], "address;first_seen;last_seen;received;sent");
+        assert_eq!(rows[1..3], first_block);
+        assert_eq!(rows[3], format!("{};2;2;5;0", address(2)));
+        assert_eq!(rows.len(), 4);
+
+        // 2 received less than the threshold
+        let (_, csv) = run(&["--min-received", "50"]);
+        let rows: Vec<&str> = csv.lines().collect();
+        assert_eq!(rows[1..], first_block);
+    }
+}
@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::PathBuf;
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::tx::EvaluatedTx;
+use crate::blockchain::proto::Hashed;
+use crate::callbacks::compress::Compression;
+use crate::callbacks::rotate::{ColumnType, Table};
+use crate::callbacks::{BlockFlow, Callback};
+use crate::errors::OpResult;
+
+const ADDRESS_REUSE: Table = Table::csv(
+    "addressreuse",
+    &[
+        ("receive_count", ColumnType::String),
+        ("addresses", ColumnType::Integer),
+    ],
+);
+
+/// Counts how often each address receives funds and dumps a histogram of the receive counts.
+/// Each output counts as one receive. Every address is stored exactly once as map key,
+/// so a boxed str with a u32 counter is the most compact representation without interning.
+pub struct AddressReuse {
+    dump_folder: PathBuf,
+    compression: Compression,
+    receives: HashMap<Box<str>, u32>,
+
+    start_height: u64,
+}
+
+impl AddressReuse {
+    /// Returns (label, addresses) for each receive count bucket: 1, 2, 3-4, 5-8, 9-16, ...
+    fn histogram(&self) -> Vec<(String, u64)> {
+        let mut buckets: Vec<u64> = Vec::new();
+        for count in self.receives.values() {
+            let idx = bucket(*count);
+            if buckets.len() <= idx {
+                buckets.resize(idx + 1, 0);
+            }
+            buckets[idx] += 1;
+        }
+        buckets
+            .into_iter()
+            .enumerate()
+            .map(|(idx, addresses)| (bucket_label(idx), addresses))
+            .collect()
+    }
+
+    /// Returns the percentage of addresses which received funds more than once
+    fn reuse_percentage(&self) -> f64 {
+        if self.receives.is_empty() {
+            return 0.0;
+        }
+        let reused = self.receives.values().filter(|c| **c > 1).count();
+        reused as f64 * 100.0 / self.receives.len() as f64
+    }
+}
+
+/// Bucket 0 holds count 1, bucket n holds counts from 2^(n-1) + 1 to 2^n
+fn bucket(count: u32) -> usize {
+    (u32::BITS - (count.max(1) - 1).leading_zeros()) as usize
+}
+
+fn bucket_label(idx: usize) -> String {
+    match idx {
+        0 => String::from("1"),
+        1 => String::from("2"),
+        n => format!("{}-{}", (1u64 << (n - 1)) + 1, 1u64 << n),
+    }
+}
+
+impl Callback for AddressReuse {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        let cmd = Command::new("addressreuse")
+            .about("Dumps a histogram of how often addresses receive funds to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            );
+        Compression::add_args(cmd)
+    }
+
+    fn schema(_: &ArgMatches) -> Vec<Table>
+    where
+        Self: Sized,
+    {
+        vec![ADDRESS_REUSE]
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let dump_folder = &PathBuf::from(matches.get_one::<String>("dump-folder").unwrap());
+        let cb = AddressReuse {
+            dump_folder: PathBuf::from(dump_folder),
+            compression: Compression::from_matches(matches)?,
+            receives: HashMap::with_capacity(10000000),
+            start_height: 0,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing addressreuse with dump folder: {} ...", &self.dump_folder.display());
+        Ok(())
+    }
+
+    fn on_block(&mut self, _: &Block, _: u64) -> OpResult<BlockFlow> {
+        Ok(BlockFlow::Continue)
+    }
+
+    fn on_tx(&mut self, tx: &Hashed<EvaluatedTx>, _: u64) -> OpResult<()> {
+        for output in &tx.value.outputs {
+            if let Some(address) = &output.script.address {
+                match self.receives.get_mut(address.as_str()) {
+                    Some(count) => *count = count.saturating_add(1),
+                    None => {
+                        self.receives.insert(Box::from(address.as_str()), 1);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        let path = self.dump_folder.join(format!(
+            "addressreuse-{}-{}.csv{}",
+            self.start_height,
+            block_height,
+            self.compression.suffix()
+        ));
+        let mut writer = self.compression.create(&path)?;
+        writer.write_all(ADDRESS_REUSE.header().as_bytes())?;
+        for (label, addresses) in self.histogram() {
+            writer.write_all(format!("{};{}\n", label, addresses).as_bytes())?;
+        }
+        writer.finish()?;
+        info!(target: "callback", "Done.\nDumped address reuse from height {} to {}:\n\
+                                   \t-> addresses: {:9}\n\
+                                   \t-> reused:    {:8.2}%",
+             self.start_height, block_height, self.receives.len(), self.reuse_percentage());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buckets() {
+        let buckets = [1, 2, 3, 4, 5, 8, 9, 16, 17]
+            .iter()
+            .map(|c| bucket_label(bucket(*c)))
+            .collect::<Vec<String>>();
+        assert_eq!(
+            buckets,
+            vec!["1", "2", "3-4", "3-4", "5-8", "5-8", "9-16", "9-16", "17-32"]
+        );
+        assert_eq!(bucket_label(bucket(u32::MAX)), "2147483649-4294967296");
+    }
+
+    #[test]
+    fn test_histogram() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let matches = AddressReuse::build_subcommand()
+            .get_matches_from(["addressreuse", tmp_dir.path().to_str().unwrap()]);
+        let mut callback = AddressReuse::new(&matches).unwrap();
+        for (address, count) in [("a", 1), ("b", 1), ("c", 2), ("d", 7)] {
+            callback.receives.insert(Box::from(address), count);
+        }
+        assert_eq!(
+            callback.histogram(),
+            vec![
+                (String::from("1"), 2),
+                (String::from("2"), 1),
+                (String::from("3-4"), 0),
+                (String::from("5-8"), 1),
+            ]
+        );
+        assert_eq!(callback.reuse_percentage(), 50.0);
+    }
+}
@@ -0,0 +1,274 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::tx::TxOutpoint;
+use crate::blockchain::proto::ToRaw;
+use crate::callbacks::compress::{Compression, OutputWriter};
+use crate::callbacks::rotate::{ColumnType, Table};
+use crate::callbacks::{common, BlockFlow, Callback};
+use crate::common::utils::HashByteOrder;
+use crate::errors::OpResult;
+
+const AWAKENED: Table = Table::csv(
+    "awakened",
+    &[
+        ("txid", ColumnType::String),
+        ("height", ColumnType::Integer),
+        ("spent_txid", ColumnType::String),
+        ("spent_index", ColumnType::Integer),
+        ("age", ColumnType::Integer),
+        ("value", ColumnType::Integer),
+    ],
+);
+
+/// 5 years of blocks
+const DEFAULT_MIN_AGE: &str = "262800";
+
+/// A spent output which was older than the threshold
+struct Awakened {
+    txid: String,
+    block_height: u64,
+    outpoint: TxOutpoint,
+    age: u64,
+    value: u64,
+}
+
+impl Awakened {
+    fn to_csv(&self, order: HashByteOrder) -> String {
+        format!(
+            "{};{};{};{};{};{}\n",
+            self.txid,
+            self.block_height,
+            order.hex(&self.outpoint.txid),
+            self.outpoint.index,
+            self.age,
+            self.value
+        )
+    }
+}
+
+/// Dumps transactions which spend outputs older than `--min-age-blocks`
+pub struct AwakenedCoins {
+    dump_folder: PathBuf,
+    writer: OutputWriter,
+    hash_byte_order: HashByteOrder,
+    min_age: u64,
+    // Rows are only buffered if they get sorted at the end
+    sorted: Option<Vec<Awakened>>,
+
+    // key: txid + index
+    unspents: HashMap<Vec<u8>, common::UnspentValue>,
+    interner: common::AddressInterner,
+
+    start_height: u64,
+    awakened_count: u64,
+}
+
+impl AwakenedCoins {
+    fn write(&mut self, awakened: Awakened) -> OpResult<()> {
+        self.awakened_count += 1;
+        match self.sorted.as_mut() {
+            Some(rows) => rows.push(awakened),
+            None => self
+                .writer
+                .write_all(awakened.to_csv(self.hash_byte_order).as_bytes())?,
+        }
+        Ok(())
+    }
+}
+
+impl Callback for AwakenedCoins {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        let cmd = Command::new("awakened")
+            .about("Dumps transactions spending outputs older than the given age to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            )
+            .arg(
+                Arg::new("min-age-blocks")
+                    .long("min-age-blocks")
+                    .value_name("NUM")
+                    .value_parser(clap::value_parser!(u64))
+                    .default_value(DEFAULT_MIN_AGE)
+                    .help("Minimum age in blocks of spent outputs (default: 5 years)"),
+            )
+            .arg(
+                Arg::new("sort-by-age")
+                    .long("sort-by-age")
+                    .action(clap::ArgAction::SetTrue)
+                    .help("Sorts the rows by age descending (keeps all rows in memory)"),
+            )
+            .arg(
+                Arg::new("dedup-addresses")
+                    .long("dedup-addresses")
+                    .action(clap::ArgAction::SetTrue)
+                    .help("Shares memory between unspents paying to the same address"),
+            );
+        Compression::add_args(cmd)
+    }
+
+    fn schema(_: &ArgMatches) -> Vec<Table>
+    where
+        Self: Sized,
+    {
+        vec![AWAKENED]
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let dump_folder = &PathBuf::from(matches.get_one::<String>("dump-folder").unwrap());
+        let cb = AwakenedCoins {
+            dump_folder: PathBuf::from(dump_folder),
+            writer: Compression::from_matches(matches)?
+                .create(&dump_folder.join("awakened.csv.tmp"))?,
+            hash_byte_order: HashByteOrder::from_matches(matches)?,
+            min_age: *matches.get_one::<u64>("min-age-blocks").unwrap(),
+            sorted: matches.get_flag("sort-by-age").then(Vec::new),
+            unspents: HashMap::with_capacity(10000000),
+            interner: common::AddressInterner::new(matches.get_flag("dedup-addresses")),
+            start_height: 0,
+            awakened_count: 0,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing awakened with dump folder: {} ...", &self.dump_folder.display());
+        self.writer.write_all(AWAKENED.header().as_bytes())?;
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<BlockFlow> {
+        for tx in &block.txs {
+            for input in &tx.value.inputs {
+                let unspent = match self.unspents.remove(&input.outpoint.to_bytes()) {
+                    Some(unspent) => unspent,
+                    None => continue,
+                };
+                let age = block_height.saturating_sub(unspent.block_height);
+                if age >= self.min_age {
+                    self.write(Awakened {
+                        txid: self.hash_byte_order.hex(&tx.hash),
+                        block_height,
+                        outpoint: input.outpoint.clone(),
+                        age,
+                        value: unspent.value,
+                    })?;
+                }
+            }
+            common::insert_unspents(
+                tx,
+                block_height,
+                block.genesis,
+                &mut self.unspents,
+                &mut self.interner,
+            );
+        }
+        if block_height % 10000 == 0 {
+            self.interner.prune();
+        }
+        Ok(BlockFlow::Continue)
+    }
+
+    fn tracks_utxos(&self) -> bool {
+        true
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        if let Some(mut rows) = self.sorted.take() {
+            rows.sort_by_key(|row| std::cmp::Reverse(row.age));
+            for row in rows {
+                self.writer
+                    .write_all(row.to_csv(self.hash_byte_order).as_bytes())?;
+            }
+        }
+        self.writer.finish()?;
+        fs::rename(
+            self.dump_folder.as_path().join("awakened.csv.tmp"),
+            self.dump_folder
+                .as_path()
+                .join(self.writer.file_name(format!(
+                    "awakened-{}-{}.csv",
+                    self.start_height, block_height
+                ))),
+        )?;
+        info!(target: "callback", "Done.\nDumped awakened coins from height {} to {}:\n\
+                                   \t-> spent outputs: {:9}",
+             self.start_height, block_height, self.awakened_count);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::callbacks::common::test_block;
+
+    // P2PKH to 1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa
+    const P2PKH: &str = "76a91462e907b15cbf27d5425399ebf6f0fb50ebb88f1888ac";
+
+    #[test]
+    fn test_awakened() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let matches = AwakenedCoins::build_subcommand().get_matches_from([
+            "awakened",
+            tmp_dir.path().to_str().unwrap(),
+            "--min-age-blocks",
+            "1000",
+            "--sort-by-age",
+        ]);
+        let mut callback = AwakenedCoins::new(&matches).unwrap();
+        callback.on_start(1).unwrap();
+
+        let old = test_block(vec![], &[(P2PKH, 100)]);
+        callback.on_block(&old, 1).unwrap();
+        let older = test_block(vec![], &[(P2PKH, 200)]);
+        callback.on_block(&older, 2).unwrap();
+        let young = test_block(vec![], &[(P2PKH, 300)]);
+        callback.on_block(&young, 500).unwrap();
+
+        // Spends all three outputs, the young one is below the threshold
+        let spend = test_block(
+            vec![
+                TxOutpoint::new(old.txs[0].hash, 0),
+                TxOutpoint::new(young.txs[0].hash, 0),
+                TxOutpoint::new(older.txs[0].hash, 0),
+            ],
+            &[(P2PKH, 500)],
+        );
+        callback.on_block(&spend, 1200).unwrap();
+        callback.on_complete(1200).unwrap();
+
+        let txid = HashByteOrder::Rpc.hex(&spend.txs[0].hash);
+        let csv = fs::read_to_string(tmp_dir.path().join("awakened-1-1200.csv")).unwrap();
+        AWAKENED.assert_csv(&csv);
+        assert_eq!(
+            csv,
+            format!(
+                "txid;height;spent_txid;spent_index;age;value\n\
+                 {};1200;{};0;1199;100\n\
+                 {};1200;{};0;1198;200\n",
+                txid,
+                HashByteOrder::Rpc.hex(&old.txs[0].hash),
+                txid,
+                HashByteOrder::Rpc.hex(&older.txs[0].hash),
+            )
+        );
+    }
+}
@@ -1,58 +1,124 @@
 use std::collections::HashMap;
-use std::fs::{self, File};
-use std::io::{BufWriter, Write};
+use std::fs;
+use std::io::{Read, Write};
 use std::path::PathBuf;
 
 use clap::{Arg, ArgMatches, Command};
 
+use crate::blockchain::parser::chainstate::SnapshotUtxo;
 use crate::blockchain::proto::block::Block;
-use crate::callbacks::{common, Callback};
-use crate::errors::OpResult;
+#[cfg(feature = "sqlite")]
+use crate::blockchain::proto::ToRaw;
+use crate::callbacks::compress::{Compression, OutputWriter};
+use crate::callbacks::rotate::{ColumnType, Table};
+#[cfg(feature = "sqlite")]
+use crate::callbacks::sqlite::SqliteBalances;
+use crate::callbacks::{common, BlockFlow, Callback};
+use crate::errors::{OpError, OpErrorKind, OpResult};
 
-/// Dumps all addresses with non-zero balance in a csv file
+const BALANCES: Table = Table::csv(
+    "balances",
+    &[
+        ("address", ColumnType::String),
+        ("balance", ColumnType::Integer),
+    ],
+);
+
+enum Output {
+    /// Writes `balances-<start>-<end>.csv` to the dump folder
+    Csv {
+        dump_folder: PathBuf,
+        writer: OutputWriter,
+    },
+    /// Upserts the balance changes of each block
+    #[cfg(feature = "sqlite")]
+    Sqlite { path: PathBuf, db: SqliteBalances },
+}
+
+/// Dumps all addresses with non-zero balance in a csv file or a SQLite database
 pub struct Balances {
-    dump_folder: PathBuf,
-    writer: BufWriter<File>,
+    output: Output,
 
     // key: txid + index
-    unspents: HashMap<Vec<u8>, common::UnspentValue>,
+    unspents: common::ShardedUnspents,
+    interner: common::AddressInterner,
 
     start_height: u64,
     end_height: u64,
 }
 
-impl Balances {
-    fn create_writer(cap: usize, path: PathBuf) -> OpResult<BufWriter<File>> {
-        Ok(BufWriter::with_capacity(cap, File::create(path)?))
-    }
-}
-
 impl Callback for Balances {
     fn build_subcommand() -> Command
     where
         Self: Sized,
     {
-        Command::new("balances")
-            .about("Dumps all addresses with non-zero balance to CSV file")
+        let cmd = Command::new("balances")
+            .about("Dumps all addresses with non-zero balance to CSV file or SQLite database")
             .version("0.1")
             .author("gcarq <egger.m@protonmail.com>")
             .arg(
                 Arg::new("dump-folder")
                     .help("Folder to store csv file")
                     .index(1)
-                    .required(true),
+                    .required_unless_present("sqlite"),
+            )
+            .arg(
+                Arg::new("sqlite")
+                    .long("sqlite")
+                    .value_name("PATH")
+                    .conflicts_with("dump-folder")
+                    .help("Upserts the balances into a SQLite database instead (requires the sqlite feature)"),
             )
+            .arg(
+                Arg::new("dedup-addresses")
+                    .long("dedup-addresses")
+                    .action(clap::ArgAction::SetTrue)
+                    .help("Shares memory between unspents paying to the same address"),
+            );
+        Compression::add_args(common::add_utxo_shards_arg(cmd))
+    }
+
+    fn schema(matches: &ArgMatches) -> Vec<Table>
+    where
+        Self: Sized,
+    {
+        match matches.contains_id("sqlite") {
+            true => Vec::new(),
+            false => vec![BALANCES],
+        }
     }
 
     fn new(matches: &ArgMatches) -> OpResult<Self>
     where
         Self: Sized,
     {
-        let dump_folder = &PathBuf::from(matches.get_one::<String>("dump-folder").unwrap());
+        if matches.contains_id("sqlite") && !cfg!(feature = "sqlite") {
+            return Err(OpError::new(OpErrorKind::InvalidArgsError).join_msg(
+                "--sqlite requires rusty-blockparser to be built with `--features sqlite`",
+            ));
+        }
+        let output = match matches.get_one::<String>("sqlite") {
+            #[cfg(feature = "sqlite")]
+            Some(path) => {
+                let path = PathBuf::from(path);
+                Output::Sqlite {
+                    db: SqliteBalances::open(&path)?,
+                    path,
+                }
+            }
+            _ => {
+                let dump_folder = PathBuf::from(matches.get_one::<String>("dump-folder").unwrap());
+                Output::Csv {
+                    writer: Compression::from_matches(matches)?
+                        .create(&dump_folder.join("balances.csv.tmp"))?,
+                    dump_folder,
+                }
+            }
+        };
         let cb = Balances {
-            dump_folder: PathBuf::from(dump_folder),
-            writer: Balances::create_writer(4000000, dump_folder.join("balances.csv.tmp"))?,
-            unspents: HashMap::with_capacity(10000000),
+            output,
+            unspents: common::ShardedUnspents::from_matches(matches, 10000000),
+            interner: common::AddressInterner::new(matches.get_flag("dedup-addresses")),
             start_height: 0,
             end_height: 0,
         };
@@ -61,7 +127,15 @@ impl Callback for Balances {
 
     fn on_start(&mut self, block_height: u64) -> OpResult<()> {
         self.start_height = block_height;
-        info!(target: "callback", "Executing balances with dump folder: {} ...", &self.dump_folder.display());
+        match &self.output {
+            Output::Csv { dump_folder, .. } => {
+                info!(target: "callback", "Executing balances with dump folder: {} ...", dump_folder.display())
+            }
+            #[cfg(feature = "sqlite")]
+            Output::Sqlite { path, .. } => {
+                info!(target: "callback", "Executing balances with SQLite database: {} ...", path.display())
+            }
+        }
         Ok(())
     }
 
@@ -72,38 +146,93 @@ impl Callback for Balances {
     ///   * block height as "last modified"
     ///   * output_val
     ///   * address
-    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
-        for tx in &block.txs {
-            common::remove_unspents(tx, &mut self.unspents);
-            common::insert_unspents(tx, block_height, &mut self.unspents);
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<BlockFlow> {
+        // The spent outputs are looked up before they are removed
+        #[cfg(feature = "sqlite")]
+        if let Output::Sqlite { db, .. } = &mut self.output {
+            for (address, delta) in block_deltas(&self.unspents, block) {
+                db.upsert(&address, delta, block_height)?;
+            }
+        }
+        common::apply_unspents(
+            &block.txs,
+            block_height,
+            block.genesis,
+            &mut self.unspents,
+            &mut self.interner,
+        );
+        if block_height % 10000 == 0 {
+            self.interner.prune();
+        }
+        Ok(BlockFlow::Continue)
+    }
+
+    fn supports_utxo_snapshot(&self) -> bool {
+        true
+    }
+
+    /// The database may already contain blocks after the checkpoint, which would be counted twice on resume
+    fn supports_checkpoint(&self) -> bool {
+        matches!(self.output, Output::Csv { .. })
+    }
+
+    fn save_state(&self, writer: &mut dyn Write) -> OpResult<()> {
+        common::write_utxo_state(writer, self.start_height, &self.unspents)
+    }
+
+    fn load_state(&mut self, reader: &mut dyn Read) -> OpResult<()> {
+        self.start_height =
+            common::read_utxo_state(reader, &mut self.unspents, &mut self.interner)?;
+        Ok(())
+    }
+
+    fn on_snapshot_utxo(&mut self, utxo: &SnapshotUtxo) -> OpResult<()> {
+        let inserted = common::insert_snapshot_utxo(utxo, &mut self.unspents, &mut self.interner);
+        #[cfg(feature = "sqlite")]
+        if let (true, Output::Sqlite { db, .. }) = (inserted, &mut self.output) {
+            let address = utxo.output.script.address.as_deref().unwrap();
+            db.upsert(address, utxo.output.out.value as i64, utxo.height)?;
         }
+        #[cfg(not(feature = "sqlite"))]
+        let _ = inserted;
         Ok(())
     }
 
     fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
         self.end_height = block_height;
+        let (dump_folder, writer) = match &mut self.output {
+            Output::Csv {
+                dump_folder,
+                writer,
+            } => (dump_folder, writer),
+            #[cfg(feature = "sqlite")]
+            Output::Sqlite { db, .. } => {
+                let count = db.finish()?;
+                info!(target: "callback", "Done.\nStored {} addresses.", count);
+                return Ok(());
+            }
+        };
 
-        self.writer
-            .write_all(format!("{};{}\n", "address", "balance").as_bytes())?;
+        writer.write_all(BALANCES.header().as_bytes())?;
 
         // Collect balances for each address
         let mut balances: HashMap<&str, u64> = HashMap::new();
         for unspent in self.unspents.values() {
-            let entry = balances.entry(&unspent.address).or_insert(0);
+            let entry = balances.entry(unspent.address()).or_insert(0);
             *entry += unspent.value
         }
 
         for (address, balance) in balances.iter() {
-            self.writer
-                .write_all(format!("{};{}\n", address, balance).as_bytes())?;
+            writer.write_all(format!("{};{}\n", address, balance).as_bytes())?;
         }
 
+        writer.finish()?;
         fs::rename(
-            self.dump_folder.as_path().join("balances.csv.tmp"),
-            self.dump_folder.as_path().join(format!(
+            dump_folder.as_path().join("balances.csv.tmp"),
+            dump_folder.as_path().join(writer.file_name(format!(
                 "balances-{}-{}.csv",
                 self.start_height, self.end_height
-            )),
+            ))),
         )
         .expect("Unable to rename tmp file!");
 
@@ -111,3 +240,142 @@ impl Callback for Balances {
         Ok(())
     }
 }
+
+/// Returns the balance change of each address receiving or spending an output in the block.
+/// Spends of outputs created earlier in the same block are resolved as well.
+#[cfg(feature = "sqlite")]
+fn block_deltas(unspents: &common::ShardedUnspents, block: &Block) -> HashMap<String, i64> {
+    let mut deltas: HashMap<String, i64> = HashMap::new();
+    // key: txid + index of outputs which aren't in `unspents` yet
+    let mut created: HashMap<Vec<u8>, (String, u64)> = HashMap::new();
+    for tx in &block.txs {
+        for input in &tx.value.inputs {
+            let key = input.outpoint.to_bytes();
+            let spent = created.remove(&key).or_else(|| {
+                unspents
+                    .get(&key)
+                    .map(|unspent| (String::from(unspent.address()), unspent.value))
+            });
+            if let Some((address, value)) = spent {
+                *deltas.entry(address).or_insert(0) -= value as i64;
+            }
+        }
+        let outputs = common::eval_unspents_with(tx, block.genesis, |output| {
+            output
+                .address
+                .map(|address| (String::from(address), output.value))
+        });
+        for (outpoint, (address, value)) in outputs {
+            *deltas.entry(address.clone()).or_insert(0) += value as i64;
+            created.insert(outpoint.to_bytes(), (address, value));
+        }
+    }
+    deltas
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::tx::TxOutpoint;
+    use crate::callbacks::common::test_block;
+
+    // P2PKH to 1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa
+    const P2PKH: &str = "76a91462e907b15cbf27d5425399ebf6f0fb50ebb88f1888ac";
+    // P2SH with the same hash
+    const P2SH: &str = "a91462e907b15cbf27d5425399ebf6f0fb50ebb88f1887";
+
+    /// Pays 100 and 200 to the P2PKH address, then moves the first output to the P2SH address
+    fn blocks() -> Vec<Block> {
+        let first = test_block(vec![], &[(P2PKH, 100)]);
+        let second = test_block(vec![], &[(P2PKH, 200)]);
+        let spend = test_block(vec![TxOutpoint::new(first.txs[0].hash, 0)], &[(P2SH, 100)]);
+        vec![first, second, spend]
+    }
+
+    #[test]
+    fn test_balances() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let matches = Balances::build_subcommand()
+            .get_matches_from(["balances", tmp_dir.path().to_str().unwrap()]);
+        let mut callback = Balances::new(&matches).unwrap();
+        assert!(callback.supports_checkpoint());
+        callback.on_start(1).unwrap();
+        for (height, block) in blocks().iter().enumerate() {
+            callback.on_block(block, height as u64 + 1).unwrap();
+        }
+        callback.on_complete(3).unwrap();
+
+        let csv = fs::read_to_string(tmp_dir.path().join("balances-1-3.csv")).unwrap();
+        BALANCES.assert_csv(&csv);
+        let mut lines: Vec<&str> = csv.lines().skip(1).collect();
+        lines.sort();
+        assert_eq!(
+            lines,
+            vec![
+                "1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa;200",
+                "3Ai1JZ8pdJb2ksieUV8FsxSNVJCpoPi8W6;100"
+            ]
+        );
+    }
+
+    #[cfg(not(feature = "sqlite"))]
+    #[test]
+    fn test_sqlite_requires_feature() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("balances.sqlite");
+        let matches = Balances::build_subcommand().get_matches_from([
+            "balances",
+            "--sqlite",
+            path.to_str().unwrap(),
+        ]);
+        assert!(Balances::new(&matches).is_err());
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_sqlite() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("balances.sqlite");
+        let matches = Balances::build_subcommand().get_matches_from([
+            "balances",
+            "--sqlite",
+            path.to_str().unwrap(),
+        ]);
+        assert!(Balances::schema(&matches).is_empty());
+        let mut callback = Balances::new(&matches).unwrap();
+        assert!(!callback.supports_checkpoint());
+        callback.on_start(1).unwrap();
+        for (height, block) in blocks().iter().enumerate() {
+            callback.on_block(block, height as u64 + 1).unwrap();
+        }
+        callback.on_complete(3).unwrap();
+
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        let mut stmt = conn
+            .prepare("SELECT address, balance, last_seen_height FROM balances ORDER BY balance")
+            .unwrap();
+        let rows: Vec<(String, i64, i64)> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                (String::from("3Ai1JZ8pdJb2ksieUV8FsxSNVJCpoPi8W6"), 100, 3),
+                (String::from("1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa"), 200, 3),
+            ]
+        );
+        let indexed: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM sqlite_master WHERE type = 'index' AND name = 'balances_balance'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(indexed, 1);
+
+        // The table isn't appended to
+        assert!(Balances::new(&matches).is_err());
+    }
+}
@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::callbacks::compress::{Compression, OutputWriter};
+use crate::callbacks::rotate::{ColumnType, Table};
+use crate::callbacks::{common, BlockFlow, Callback};
+use crate::errors::OpResult;
+
+const BLOCK_FEES: Table = Table::csv(
+    "blockfees",
+    &[
+        ("block_height", ColumnType::Integer),
+        ("tx_count", ColumnType::Integer),
+        ("total_out_value", ColumnType::Integer),
+        ("total_fees", ColumnType::Integer),
+        ("subsidy", ColumnType::Integer),
+    ],
+);
+
+/// Dumps the fees and the subsidy of each block, reconstructed from the spent output values.
+/// The fees are the input value minus the output value of all non-coinbase transactions.
+/// The coinbase claims subsidy and fees, so the subsidy is its output value minus the fees
+/// (0 if the miner claimed less than the fees). `total_out_value` includes the coinbase.
+/// If a transaction spends an output created before the start height, the fees and the subsidy of the block are empty.
+pub struct BlockFees {
+    dump_folder: PathBuf,
+    writer: OutputWriter,
+
+    // key: txid + index, value: output value in satoshis
+    unspents: common::Unspents<u64>,
+
+    start_height: u64,
+    total_fees: u64,
+    unknown_count: u64,
+}
+
+impl BlockFees {
+    /// Returns (total_out_value, total_fees, subsidy) of the block and updates the unspents.
+    /// Fees and subsidy are None if a spent output is unknown.
+    fn block_fees(&mut self, block: &Block) -> (u64, Option<u64>, Option<u64>) {
+        let mut out_value = 0;
+        let mut fees = Some(0u64);
+        let mut coinbase_value = 0;
+        for tx in &block.txs {
+            let fee = common::apply_fee(tx, block.genesis, &mut self.unspents);
+            let tx_out_value: u64 = tx.value.outputs.iter().map(|o| o.out.value).sum();
+            out_value += tx_out_value;
+            if tx.value.is_coinbase() {
+                coinbase_value += tx_out_value;
+            } else {
+                fees = fees.zip(fee).map(|(sum, fee)| sum + fee);
+            }
+        }
+        let subsidy = fees.map(|fees| coinbase_value.saturating_sub(fees));
+        (out_value, fees, subsidy)
+    }
+}
+
+impl Callback for BlockFees {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        let cmd = Command::new("blockfees")
+            .about("Dumps the fees and subsidy of each block to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            );
+        Compression::add_args(cmd)
+    }
+
+    fn schema(_: &ArgMatches) -> Vec<Table>
+    where
+        Self: Sized,
+    {
+        vec![BLOCK_FEES]
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let dump_folder = &PathBuf::from(matches.get_one::<String>("dump-folder").unwrap());
+        let cb = BlockFees {
+            dump_folder: PathBuf::from(dump_folder),
+            writer: Compression::from_matches(matches)?
+                .create(&dump_folder.join("blockfees.csv.tmp"))?,
+            unspents: HashMap::with_capacity(10000000),
+            start_height: 0,
+            total_fees: 0,
+            unknown_count: 0,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing blockfees with dump folder: {} ...", &self.dump_folder.display());
+        self.writer.write_all(BLOCK_FEES.header().as_bytes())?;
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<BlockFlow> {
+        let (out_value, fees, subsidy) = self.block_fees(block);
+        match fees {
+            Some(fees) => self.total_fees += fees,
+            None => self.unknown_count += 1,
+        }
+        self.writer.write_all(
+            format!(
+                "{};{};{};{};{}\n",
+                block_height,
+                block.tx_count.value,
+                out_value,
+                fees.map_or(String::new(), |fees| fees.to_string()),
+                subsidy.map_or(String::new(), |subsidy| subsidy.to_string())
+            )
+            .as_bytes(),
+        )?;
+        Ok(BlockFlow::Continue)
+    }
+
+    fn needs_addresses(&self) -> bool {
+        false
+    }
+
+    fn tracks_utxos(&self) -> bool {
+        true
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.writer.finish()?;
+        fs::rename(
+            self.dump_folder.as_path().join("blockfees.csv.tmp"),
+            self.dump_folder
+                .as_path()
+                .join(self.writer.file_name(format!(
+                    "blockfees-{}-{}.csv",
+                    self.start_height, block_height
+                ))),
+        )?;
+        info!(target: "callback", "Done.\nDumped block fees from height {} to {}:\n\
+                                   \t-> total fees:                {:20}\n\
+                                   \t-> blocks with unknown fees:  {:20}",
+             self.start_height, block_height, self.total_fees, self.unknown_count);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::header::BlockHeader;
+    use crate::blockchain::proto::script::AddressFormat;
+    use crate::blockchain::proto::tx::{RawTx, TxInput, TxOutpoint, TxOutput};
+    use crate::blockchain::proto::varuint::VarUint;
+    use bitcoin::hashes::{sha256d, Hash};
+
+    fn tx(outpoints: Vec<TxOutpoint>, values: &[u64]) -> RawTx {
+        RawTx {
+            version: 1,
+            in_count: VarUint::from(outpoints.len() as u8),
+            inputs: outpoints
+                .into_iter()
+                .map(|outpoint| TxInput {
+                    outpoint,
+                    script_len: VarUint::from(0u8),
+                    script_sig: vec![],
+                    seq_no: 0xFFFFFFFF,
+                    witness: vec![],
+                })
+                .collect(),
+            out_count: VarUint::from(values.len() as u8),
+            outputs: values
+                .iter()
+                .map(|value| TxOutput {
+                    value: *value,
+                    script_len: VarUint::from(0u8),
+                    script_pubkey: vec![],
+                })
+                .collect(),
+            locktime: 0,
+            address_format: AddressFormat::default(),
+            witness_size: 0,
+        }
+    }
+
+    fn block(txs: Vec<RawTx>) -> Block {
+        let header = BlockHeader {
+            version: 1,
+            prev_hash: sha256d::Hash::all_zeros(),
+            merkle_root: sha256d::Hash::all_zeros(),
+            timestamp: 0,
+            bits: 0,
+            nonce: 0,
+        };
+        Block::new(0, header, None, VarUint::from(txs.len() as u8), txs)
+    }
+
+    #[test]
+    fn test_block_fees() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let matches = BlockFees::build_subcommand()
+            .get_matches_from(["blockfees", tmp_dir.path().to_str().unwrap()]);
+        let mut callback = BlockFees::new(&matches).unwrap();
+        callback.on_start(1).unwrap();
+
+        let coinbase = || TxOutpoint::new(sha256d::Hash::all_zeros(), 0xFFFFFFFF);
+        let block1 = block(vec![tx(vec![coinbase()], &[5000, 3000])]);
+        callback.on_block(&block1, 1).unwrap();
+        let funding = block1.txs[0].hash;
+
+        // Fees of 300 and 200, of which the miner claims 400
+        let block2 = block(vec![
+            tx(vec![coinbase()], &[5400]),
+            tx(vec![TxOutpoint::new(funding, 0)], &[4700]),
+            tx(vec![TxOutpoint::new(funding, 1)], &[1000, 1800]),
+        ]);
+        callback.on_block(&block2, 2).unwrap();
+        // Spends an output created before the start height
+        let unknown = TxOutpoint::new(sha256d::Hash::hash(&[1]), 0);
+        let block3 = block(vec![
+            tx(vec![coinbase()], &[5000]),
+            tx(vec![unknown], &[100]),
+        ]);
+        callback.on_block(&block3, 3).unwrap();
+        callback.on_complete(3).unwrap();
+        assert_eq!(callback.total_fees, 500);
+        assert_eq!(callback.unknown_count, 1);
+
+        let csv = fs::read_to_string(tmp_dir.path().join("blockfees-1-3.csv")).unwrap();
+        BLOCK_FEES.assert_csv(&csv);
+        assert_eq!(
+            csv,
+            "block_height;tx_count;total_out_value;total_fees;subsidy\n\
+             1;1;8000;0;8000\n\
+             2;3;12900;500;4900\n\
+             3;2;5100;;\n"
+        );
+    }
+}
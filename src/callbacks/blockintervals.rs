@@ -0,0 +1,185 @@
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::callbacks::compress::{Compression, OutputWriter};
+use crate::callbacks::rotate::{ColumnType, Table};
+use crate::callbacks::{BlockFlow, Callback};
+use crate::errors::OpResult;
+
+const BLOCK_INTERVALS: Table = Table::csv(
+    "blockintervals",
+    &[
+        ("height", ColumnType::Integer),
+        ("timestamp", ColumnType::Integer),
+        ("parent_timestamp", ColumnType::Integer),
+        ("delta_seconds", ColumnType::Integer),
+    ],
+);
+
+/// Dumps the signed time difference between each block and its parent.
+/// Consensus only requires a timestamp above the median of the previous 11 blocks,
+/// so the delta can be negative. The parent of the first block in range (e.g. genesis)
+/// is unknown, its `parent_timestamp` and `delta_seconds` fields are left empty.
+pub struct BlockIntervals {
+    dump_folder: PathBuf,
+    writer: OutputWriter,
+
+    parent_timestamp: Option<u32>,
+    start_height: u64,
+    block_count: u64,
+    negative_count: u64,
+    min_delta: Option<i64>,
+}
+
+impl Callback for BlockIntervals {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        let cmd = Command::new("blockintervals")
+            .about("Dumps the time difference between each block and its parent to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            );
+        Compression::add_args(cmd)
+    }
+
+    fn schema(_: &ArgMatches) -> Vec<Table>
+    where
+        Self: Sized,
+    {
+        vec![BLOCK_INTERVALS]
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let dump_folder = &PathBuf::from(matches.get_one::<String>("dump-folder").unwrap());
+        let cb = BlockIntervals {
+            dump_folder: PathBuf::from(dump_folder),
+            writer: Compression::from_matches(matches)?
+                .create(&dump_folder.join("blockintervals.csv.tmp"))?,
+            parent_timestamp: None,
+            start_height: 0,
+            block_count: 0,
+            negative_count: 0,
+            min_delta: None,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing blockintervals with dump folder: {} ...", &self.dump_folder.display());
+        self.writer.write_all(BLOCK_INTERVALS.header().as_bytes())?;
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<BlockFlow> {
+        let timestamp = block.header.value.timestamp;
+        let (parent_timestamp, delta) = match self.parent_timestamp {
+            Some(parent) => {
+                let delta = timestamp as i64 - parent as i64;
+                if delta < 0 {
+                    self.negative_count += 1;
+                }
+                self.min_delta = Some(self.min_delta.map_or(delta, |min| min.min(delta)));
+                (parent.to_string(), delta.to_string())
+            }
+            None => (String::new(), String::new()),
+        };
+        self.writer.write_all(
+            format!(
+                "{};{};{};{}\n",
+                block_height, timestamp, parent_timestamp, delta
+            )
+            .as_bytes(),
+        )?;
+        self.parent_timestamp = Some(timestamp);
+        self.block_count += 1;
+        Ok(BlockFlow::Continue)
+    }
+
+    fn needs_addresses(&self) -> bool {
+        false
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.writer.finish()?;
+        fs::rename(
+            self.dump_folder.as_path().join("blockintervals.csv.tmp"),
+            self.dump_folder
+                .as_path()
+                .join(self.writer.file_name(format!(
+                    "blockintervals-{}-{}.csv",
+                    self.start_height, block_height
+                ))),
+        )?;
+        info!(target: "callback", "Done.\nDumped block intervals from height {} to {}:\n\
+                                   \t-> blocks:             {:9}\n\
+                                   \t-> negative intervals: {:9}\n\
+                                   \t-> min delta:          {:>9}",
+             self.start_height, block_height, self.block_count, self.negative_count,
+             self.min_delta.map_or(String::from("-"), |delta| delta.to_string()));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::header::BlockHeader;
+    use crate::blockchain::proto::varuint::VarUint;
+    use bitcoin::hashes::{sha256d, Hash};
+
+    fn block(timestamp: u32) -> Block {
+        let header = BlockHeader {
+            version: 1,
+            prev_hash: sha256d::Hash::all_zeros(),
+            merkle_root: sha256d::Hash::all_zeros(),
+            timestamp,
+            bits: 0,
+            nonce: 0,
+        };
+        Block::new(0, header, None, VarUint::from(0u8), vec![])
+    }
+
+    #[test]
+    fn test_block_intervals() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let matches = BlockIntervals::build_subcommand()
+            .get_matches_from(["blockintervals", tmp_dir.path().to_str().unwrap()]);
+        let mut callback = BlockIntervals::new(&matches).unwrap();
+        callback.on_start(0).unwrap();
+        for (height, timestamp) in [1231006505, 1231469665, 1231469744, 1231469700]
+            .into_iter()
+            .enumerate()
+        {
+            callback.on_block(&block(timestamp), height as u64).unwrap();
+        }
+        callback.on_complete(3).unwrap();
+        assert_eq!(callback.negative_count, 1);
+        assert_eq!(callback.min_delta, Some(-44));
+
+        let csv = fs::read_to_string(tmp_dir.path().join("blockintervals-0-3.csv")).unwrap();
+        BLOCK_INTERVALS.assert_csv(&csv);
+        assert_eq!(
+            csv,
+            "height;timestamp;parent_timestamp;delta_seconds\n\
+             0;1231006505;;\n\
+             1;1231469665;1231006505;463160\n\
+             2;1231469744;1231469665;79\n\
+             3;1231469700;1231469744;-44\n"
+        );
+    }
+}
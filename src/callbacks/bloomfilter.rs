@@ -0,0 +1,326 @@
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use bitcoin::hashes::{sha256, Hash};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::callbacks::{BlockFlow, Callback};
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+const BLOOM_MAGIC: &[u8; 4] = b"RBPB";
+const BLOOM_VERSION: u32 = 1;
+/// Size of the serialized fields before the bits
+const BLOOM_HEADER_SIZE: u64 = 4 + 4 + 8 + 8 + 4 + 8;
+
+/// Simple bloom filter using double hashing on top of sha256.
+/// Serialized format (little endian):
+///   magic (4 bytes) | version (u32) | item count (u64) | fpr (f64) | hash count (u32) | bit count (u64) | bits
+pub struct BloomFilter {
+    bits: Vec<u8>,
+    n_bits: u64,
+    n_hashes: u32,
+    n_items: u64,
+    fpr: f64,
+}
+
+impl BloomFilter {
+    /// Creates an empty filter sized for `n_items` with the given false positive rate
+    pub fn new(n_items: u64, fpr: f64) -> Self {
+        let n = n_items.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let n_bits = ((-n * fpr.ln()) / (ln2 * ln2)).ceil().max(8.0) as u64;
+        let n_hashes = ((n_bits as f64 / n) * ln2).round().max(1.0) as u32;
+        Self {
+            bits: vec![0u8; n_bits.div_ceil(8) as usize],
+            n_bits,
+            n_hashes,
+            n_items: 0,
+            fpr,
+        }
+    }
+
+    fn indexes(&self, item: &[u8]) -> impl Iterator<Item = u64> + '_ {
+        let hash = sha256::Hash::hash(item);
+        let bytes = hash.as_byte_array();
+        let h1 = u64::from_le_bytes(bytes[0..8].try_into().unwrap());
+        let h2 = u64::from_le_bytes(bytes[8..16].try_into().unwrap());
+        (0..self.n_hashes as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % self.n_bits)
+    }
+
+    pub fn insert(&mut self, item: &[u8]) {
+        let indexes = self.indexes(item).collect::<Vec<u64>>();
+        for idx in indexes {
+            self.bits[(idx / 8) as usize] |= 1 << (idx % 8);
+        }
+        self.n_items += 1;
+    }
+
+    pub fn contains(&self, item: &[u8]) -> bool {
+        self.indexes(item)
+            .all(|idx| self.bits[(idx / 8) as usize] & (1 << (idx % 8)) != 0)
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> OpResult<()> {
+        writer.write_all(BLOOM_MAGIC)?;
+        writer.write_u32::<LittleEndian>(BLOOM_VERSION)?;
+        writer.write_u64::<LittleEndian>(self.n_items)?;
+        writer.write_f64::<LittleEndian>(self.fpr)?;
+        writer.write_u32::<LittleEndian>(self.n_hashes)?;
+        writer.write_u64::<LittleEndian>(self.n_bits)?;
+        writer.write_all(&self.bits)?;
+        Ok(())
+    }
+
+    /// Reads a serialized filter of `len` bytes, the sizes in the header are checked against it before allocating
+    pub fn read_from<R: Read>(reader: &mut R, len: u64) -> OpResult<Self> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        let version = reader.read_u32::<LittleEndian>()?;
+        if &magic != BLOOM_MAGIC || version != BLOOM_VERSION {
+            return Err(OpError::new(OpErrorKind::RuntimeError)
+                .join_msg("Unsupported bloom filter file format!"));
+        }
+        let n_items = reader.read_u64::<LittleEndian>()?;
+        let fpr = reader.read_f64::<LittleEndian>()?;
+        let n_hashes = reader.read_u32::<LittleEndian>()?;
+        let n_bits = reader.read_u64::<LittleEndian>()?;
+        if n_bits == 0
+            || n_hashes == 0
+            || n_hashes as u64 > n_bits
+            || Some(n_bits.div_ceil(8)) != len.checked_sub(BLOOM_HEADER_SIZE)
+        {
+            return Err(OpError::new(OpErrorKind::RuntimeError)
+                .join_msg("Corrupt bloom filter file, sizes don't match the file length!"));
+        }
+        let mut bits = vec![0u8; n_bits.div_ceil(8) as usize];
+        reader.read_exact(&mut bits)?;
+        Ok(Self {
+            bits,
+            n_bits,
+            n_hashes,
+            n_items,
+            fpr,
+        })
+    }
+
+    /// Loads a filter previously written by the `bloomfilter` callback
+    pub fn load(path: &Path) -> OpResult<Self> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        Self::read_from(&mut BufReader::new(file), len)
+    }
+
+    /// Returns the false positive rate given the number of inserted items,
+    /// which exceeds the configured rate once more items are inserted than the filter was sized for
+    pub fn estimated_fpr(&self) -> f64 {
+        let fill = -(self.n_hashes as f64 * self.n_items as f64) / self.n_bits as f64;
+        (1.0 - fill.exp()).powi(self.n_hashes as i32)
+    }
+}
+
+/// Loads the filter at `path` and checks whether it may contain each of the given addresses
+pub fn query(path: &Path, addresses: &[String]) -> OpResult<serde_json::Value> {
+    let filter = BloomFilter::load(path)?;
+    let matches: serde_json::Map<String, serde_json::Value> = addresses
+        .iter()
+        .map(|address| (address.clone(), filter.contains(address.as_bytes()).into()))
+        .collect();
+    Ok(serde_json::json!({
+        "items": filter.n_items,
+        "fpr": filter.estimated_fpr(),
+        "matches": matches,
+    }))
+}
+
+/// Builds a bloom filter over all addresses seen in outputs.
+/// The filter is allocated for `--bloom-items` addresses up front, so memory stays
+/// constant no matter how many addresses are seen.
+pub struct BloomFilterDump {
+    out: PathBuf,
+    // Number of addresses the filter is sized for
+    capacity: u64,
+    filter: BloomFilter,
+}
+
+impl Callback for BloomFilterDump {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        Command::new("bloomfilter")
+            .about("Builds a bloom filter over all seen addresses")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("bloom-out")
+                    .long("bloom-out")
+                    .value_name("PATH")
+                    .help("File to store the serialized bloom filter")
+                    .required(true),
+            )
+            .arg(
+                Arg::new("bloom-fpr")
+                    .long("bloom-fpr")
+                    .value_name("RATE")
+                    .value_parser(clap::value_parser!(f64))
+                    .default_value("0.001")
+                    .help("False positive rate of the filter"),
+            )
+            .arg(
+                Arg::new("bloom-items")
+                    .long("bloom-items")
+                    .value_name("N")
+                    .value_parser(clap::value_parser!(u64).range(1..))
+                    .default_value("10000000")
+                    .help("Expected number of distinct addresses the filter is sized for"),
+            )
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let fpr = *matches.get_one::<f64>("bloom-fpr").unwrap();
+        if fpr <= 0.0 || fpr >= 1.0 {
+            return Err(OpError::new(OpErrorKind::InvalidArgsError)
+                .join_msg("--bloom-fpr must be between 0 and 1"));
+        }
+        let capacity = *matches.get_one::<u64>("bloom-items").unwrap();
+        Ok(Self {
+            out: PathBuf::from(matches.get_one::<String>("bloom-out").unwrap()),
+            capacity,
+            filter: BloomFilter::new(capacity, fpr),
+        })
+    }
+
+    fn on_start(&mut self, _: u64) -> OpResult<()> {
+        info!(target: "callback", "Executing bloomfilter with output: {} ...", &self.out.display());
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, _: u64) -> OpResult<BlockFlow> {
+        for tx in &block.txs {
+            for output in &tx.value.outputs {
+                if let Some(address) = &output.script.address {
+                    // Skipping known addresses keeps the item count close to the distinct addresses
+                    if !self.filter.contains(address.as_bytes()) {
+                        self.filter.insert(address.as_bytes());
+                    }
+                }
+            }
+        }
+        Ok(BlockFlow::Continue)
+    }
+
+    fn on_complete(&mut self, _: u64) -> OpResult<()> {
+        let filter = &self.filter;
+        if filter.n_items > self.capacity {
+            warn!(target: "callback", "Bloom filter holds more addresses than --bloom-items, false positive rate is {:.6}", filter.estimated_fpr());
+        }
+        let mut writer = BufWriter::new(File::create(&self.out)?);
+        filter.write_to(&mut writer)?;
+        writer.flush()?;
+        info!(target: "callback", "Done.\nAdded {} addresses to bloom filter ({} bits, {} hashes, fpr: {}).",
+              filter.n_items, filter.n_bits, filter.n_hashes, filter.fpr);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::callbacks::common::test_block;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_bloom_filter() {
+        let mut filter = BloomFilter::new(100, 0.01);
+        filter.insert(b"1JqDybm2nWTENrHvMyafbSXXtTk5Uv5QAn");
+        filter.insert(b"1EYXXHs5gV4pc7QAddmDj5z7m14QPHGvWL");
+        assert!(filter.contains(b"1JqDybm2nWTENrHvMyafbSXXtTk5Uv5QAn"));
+        assert!(filter.contains(b"1EYXXHs5gV4pc7QAddmDj5z7m14QPHGvWL"));
+        assert!(!filter.contains(b"1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa"));
+
+        let mut buffer = Vec::new();
+        filter.write_to(&mut buffer).unwrap();
+        let len = buffer.len() as u64;
+        let loaded = BloomFilter::read_from(&mut Cursor::new(&buffer), len).unwrap();
+        assert_eq!(loaded.n_items, 2);
+        assert_eq!(loaded.n_bits, filter.n_bits);
+        assert_eq!(loaded.n_hashes, filter.n_hashes);
+        assert!(loaded.contains(b"1JqDybm2nWTENrHvMyafbSXXtTk5Uv5QAn"));
+        assert!(!loaded.contains(b"1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa"));
+
+        // Truncated file
+        assert!(BloomFilter::read_from(&mut Cursor::new(&buffer[..40]), 40).is_err());
+        // Bit count exceeding the file
+        let mut corrupt = buffer.clone();
+        corrupt[28..36].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(BloomFilter::read_from(&mut Cursor::new(&corrupt), len).is_err());
+        // No hash functions
+        let mut corrupt = buffer;
+        corrupt[24..28].copy_from_slice(&0u32.to_le_bytes());
+        assert!(BloomFilter::read_from(&mut Cursor::new(&corrupt), len).is_err());
+    }
+
+    #[test]
+    fn test_bloom_items() {
+        let parse = |items: &str| {
+            BloomFilterDump::build_subcommand().try_get_matches_from([
+                "bloomfilter",
+                "--bloom-out",
+                "addresses.bloom",
+                "--bloom-items",
+                items,
+            ])
+        };
+        assert!(parse("0").is_err());
+        assert!(parse("1").is_ok());
+    }
+
+    #[test]
+    fn test_query() {
+        // 1Q2TWHE3GMdB6BZKafqwxXtWAWgFt5Jvm3 and bc1qr47dd36u96r0fjle36hdygdnp0v6pwfg2lppam
+        let p2pkh = "76a914fc916f213a3d7f1369313d5fa30f6168f9446a2d88ac";
+        let p2wpkh = "00141d7cd6c75c2e86f4cbf98eaed221b30bd9a0b928";
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("addresses.bloom");
+        let matches = BloomFilterDump::build_subcommand().get_matches_from([
+            "bloomfilter",
+            "--bloom-out",
+            path.to_str().unwrap(),
+            "--bloom-items",
+            "100",
+        ]);
+        let mut callback = BloomFilterDump::new(&matches).unwrap();
+        callback.on_start(0).unwrap();
+        let block = test_block(vec![], &[(p2pkh, 1), (p2wpkh, 2), (p2pkh, 3)]);
+        callback.on_block(&block, 0).unwrap();
+        callback.on_complete(0).unwrap();
+
+        let addresses = [
+            "1Q2TWHE3GMdB6BZKafqwxXtWAWgFt5Jvm3",
+            "bc1qr47dd36u96r0fjle36hdygdnp0v6pwfg2lppam",
+            "1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa",
+        ]
+        .map(String::from);
+        let result = query(&path, &addresses).unwrap();
+        // The duplicate output address is only counted once
+        assert_eq!(result["items"], 2);
+        assert!(result["fpr"].as_f64().unwrap() < 0.001);
+        assert_eq!(
+            result["matches"],
+            serde_json::json!({
+                "1Q2TWHE3GMdB6BZKafqwxXtWAWgFt5Jvm3": true,
+                "bc1qr47dd36u96r0fjle36hdygdnp0v6pwfg2lppam": true,
+                "1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa": false,
+            })
+        );
+        assert!(query(&tmp_dir.path().join("missing.bloom"), &addresses).is_err());
+    }
+}
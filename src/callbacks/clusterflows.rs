@@ -0,0 +1,288 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::ToRaw;
+use crate::callbacks::compress::{Compression, OutputWriter};
+use crate::callbacks::rotate::{ColumnType, Table};
+use crate::callbacks::{common, BlockFlow, Callback};
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+const CLUSTER_FLOWS: Table = Table::csv(
+    "clusterflows",
+    &[
+        ("src_cluster", ColumnType::Integer),
+        ("dst_cluster", ColumnType::Integer),
+        ("total_value", ColumnType::Integer),
+        ("tx_count", ColumnType::Integer),
+    ],
+);
+
+#[derive(Default)]
+struct Flow {
+    total_value: u64,
+    tx_count: u64,
+}
+
+/// Parses the `address;cluster_id` CSV written by the `clusters` callback
+fn parse_clusters(content: &str) -> OpResult<HashMap<String, u32>> {
+    let mut clusters = HashMap::new();
+    for line in content.lines().skip(1).filter(|line| !line.is_empty()) {
+        let (address, cluster_id) = line
+            .split_once(';')
+            .and_then(|(address, id)| Some((address, id.parse::<u32>().ok()?)))
+            .ok_or_else(|| {
+                OpError::new(OpErrorKind::InvalidArgsError)
+                    .join_msg(&format!("Invalid line in clusters file: {}", line))
+            })?;
+        clusters.insert(address.to_string(), cluster_id);
+    }
+    Ok(clusters)
+}
+
+/// Sums the value flowing between address clusters.
+/// This is the second of two passes: the cluster assignment has to be known when an output is spent,
+/// so the clusters are computed beforehand with the `clusters` callback over the same range of blocks.
+/// The output value of each spending transaction is split between the input clusters in proportion
+/// to their input value (truncated). With clusters from the same range, all addressed inputs of a transaction
+/// belong to one cluster. Fees, inputs and outputs without address or cluster are not attributed.
+pub struct ClusterFlows {
+    dump_folder: PathBuf,
+    writer: OutputWriter,
+    clusters: HashMap<String, u32>,
+
+    // key: txid + index, value: (cluster id, value)
+    unspents: common::Unspents<(u32, u64)>,
+    // key: (src cluster, dst cluster)
+    flows: HashMap<(u32, u32), Flow>,
+
+    start_height: u64,
+    unclustered_count: u64,
+}
+
+impl Callback for ClusterFlows {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        let cmd = Command::new("clusterflows")
+            .about("Dumps the value flowing between address clusters to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            )
+            .arg(
+                Arg::new("clusters")
+                    .long("clusters")
+                    .value_name("PATH")
+                    .required(true)
+                    .help("CSV file written by the clusters callback"),
+            );
+        Compression::add_args(cmd)
+    }
+
+    fn schema(_: &ArgMatches) -> Vec<Table>
+    where
+        Self: Sized,
+    {
+        vec![CLUSTER_FLOWS]
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let dump_folder = &PathBuf::from(matches.get_one::<String>("dump-folder").unwrap());
+        let clusters = parse_clusters(&fs::read_to_string(
+            matches.get_one::<String>("clusters").unwrap(),
+        )?)?;
+        let cb = ClusterFlows {
+            dump_folder: PathBuf::from(dump_folder),
+            writer: Compression::from_matches(matches)?
+                .create(&dump_folder.join("clusterflows.csv.tmp"))?,
+            clusters,
+            unspents: HashMap::with_capacity(10000000),
+            flows: HashMap::new(),
+            start_height: 0,
+            unclustered_count: 0,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing clusterflows for {} addresses with dump folder: {} ...",
+              self.clusters.len(), &self.dump_folder.display());
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, _: u64) -> OpResult<BlockFlow> {
+        for tx in &block.txs {
+            // Input value per source cluster
+            let mut sources: Vec<(u32, u64)> = Vec::new();
+            for (_, (cluster_id, value)) in common::take_unspents(tx, &mut self.unspents) {
+                match sources.iter_mut().find(|(id, _)| *id == cluster_id) {
+                    Some((_, sum)) => *sum += value,
+                    None => sources.push((cluster_id, value)),
+                }
+            }
+
+            let clusters = &self.clusters;
+            let mut unclustered = 0;
+            let outputs = common::eval_unspents_with(tx, block.genesis, |output| {
+                let address = output.address?;
+                match clusters.get(address) {
+                    Some(cluster_id) => Some((*cluster_id, output.value)),
+                    None => {
+                        unclustered += 1;
+                        None
+                    }
+                }
+            });
+            self.unclustered_count += unclustered;
+
+            let input_value: u64 = sources.iter().map(|(_, value)| value).sum();
+            if input_value > 0 {
+                let mut tx_flows: HashMap<(u32, u32), u64> = HashMap::new();
+                for (_, (dst, value)) in &outputs {
+                    for (src, src_value) in &sources {
+                        let share =
+                            (*value as u128 * *src_value as u128 / input_value as u128) as u64;
+                        *tx_flows.entry((*src, *dst)).or_insert(0) += share;
+                    }
+                }
+                for (key, value) in tx_flows {
+                    let flow = self.flows.entry(key).or_default();
+                    flow.total_value += value;
+                    flow.tx_count += 1;
+                }
+            }
+
+            for (outpoint, unspent) in outputs {
+                self.unspents.insert(outpoint.to_bytes(), unspent);
+            }
+        }
+        Ok(BlockFlow::Continue)
+    }
+
+    fn tracks_utxos(&self) -> bool {
+        true
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.writer.write_all(CLUSTER_FLOWS.header().as_bytes())?;
+
+        // Largest flows first
+        let mut flows: Vec<_> = self.flows.iter().collect();
+        flows.sort_by(|a, b| b.1.total_value.cmp(&a.1.total_value).then(a.0.cmp(b.0)));
+        for ((src, dst), flow) in flows {
+            self.writer.write_all(
+                format!("{};{};{};{}\n", src, dst, flow.total_value, flow.tx_count).as_bytes(),
+            )?;
+        }
+        self.writer.finish()?;
+        fs::rename(
+            self.dump_folder.as_path().join("clusterflows.csv.tmp"),
+            self.dump_folder
+                .as_path()
+                .join(self.writer.file_name(format!(
+                    "clusterflows-{}-{}.csv",
+                    self.start_height, block_height
+                ))),
+        )?;
+        info!(target: "callback", "Done.\nDumped cluster flows from height {} to {}:\n\
+                                   \t-> flows:               {:9}\n\
+                                   \t-> unclustered outputs: {:9}",
+             self.start_height, block_height, self.flows.len(), self.unclustered_count);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::tx::TxOutpoint;
+    use crate::callbacks::common::{test_block, test_script};
+    use crate::common::utils;
+
+    #[test]
+    fn test_parse_clusters() {
+        let clusters = parse_clusters("address;cluster_id\nA;0\nB;0\n").unwrap();
+        assert_eq!(clusters.len(), 2);
+        assert_eq!(clusters["B"], 0);
+        assert!(parse_clusters("address;cluster_id\nA\n").is_err());
+    }
+
+    #[test]
+    fn test_cluster_flows() {
+        let address = |n: u8| {
+            let script = utils::hex_to_vec(&test_script(n));
+            bitcoin::Address::from_script(
+                bitcoin::Script::from_bytes(&script),
+                bitcoin::Network::Bitcoin,
+            )
+            .unwrap()
+            .to_string()
+        };
+        let tmp_dir = tempfile::tempdir().unwrap();
+        // 1 and 2 belong to cluster 0, 3 to cluster 2, 4 is not clustered
+        let clusters = tmp_dir.path().join("clusters.csv");
+        fs::write(
+            &clusters,
+            format!(
+                "address;cluster_id\n{};0\n{};0\n{};2\n",
+                address(1),
+                address(2),
+                address(3)
+            ),
+        )
+        .unwrap();
+        let matches = ClusterFlows::build_subcommand().get_matches_from([
+            "clusterflows",
+            tmp_dir.path().to_str().unwrap(),
+            "--clusters",
+            clusters.to_str().unwrap(),
+        ]);
+        let mut callback = ClusterFlows::new(&matches).unwrap();
+        callback.on_start(1).unwrap();
+
+        let funding = test_block(vec![], &[(test_script(1), 60), (test_script(2), 40)]);
+        callback.on_block(&funding, 1).unwrap();
+        let txid = funding.txs[0].hash;
+        // Pays 70 to cluster 2, 20 back to cluster 0 and 5 to an unclustered address
+        let spend = test_block(
+            vec![TxOutpoint::new(txid, 0), TxOutpoint::new(txid, 1)],
+            &[
+                (test_script(3), 70),
+                (test_script(1), 20),
+                (test_script(4), 5),
+            ],
+        );
+        callback.on_block(&spend, 2).unwrap();
+        let forward = test_block(
+            vec![TxOutpoint::new(spend.txs[0].hash, 0)],
+            &[(test_script(2), 65)],
+        );
+        callback.on_block(&forward, 3).unwrap();
+        callback.on_complete(3).unwrap();
+        assert_eq!(callback.unclustered_count, 1);
+
+        let csv = fs::read_to_string(tmp_dir.path().join("clusterflows-1-3.csv")).unwrap();
+        CLUSTER_FLOWS.assert_csv(&csv);
+        assert_eq!(
+            csv,
+            "src_cluster;dst_cluster;total_value;tx_count\n\
+             0;2;70;1\n\
+             2;0;65;1\n\
+             0;0;20;1\n"
+        );
+    }
+}
@@ -0,0 +1,248 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::ToRaw;
+use crate::callbacks::compress::{Compression, OutputWriter};
+use crate::callbacks::rotate::{ColumnType, Table};
+use crate::callbacks::{common, BlockFlow, Callback};
+use crate::errors::OpResult;
+
+const CLUSTERS: Table = Table::csv(
+    "clusters",
+    &[
+        ("address", ColumnType::String),
+        ("cluster_id", ColumnType::Integer),
+    ],
+);
+
+/// Disjoint sets over address ids with union by size and path halving
+#[derive(Default)]
+struct UnionFind {
+    parents: Vec<u32>,
+    sizes: Vec<u32>,
+}
+
+impl UnionFind {
+    /// Adds a new singleton set and returns its id
+    fn push(&mut self) -> u32 {
+        let id = self.parents.len() as u32;
+        self.parents.push(id);
+        self.sizes.push(1);
+        id
+    }
+
+    fn find(&mut self, mut id: u32) -> u32 {
+        while self.parents[id as usize] != id {
+            let parent = self.parents[id as usize];
+            self.parents[id as usize] = self.parents[parent as usize];
+            id = parent;
+        }
+        id
+    }
+
+    fn union(&mut self, a: u32, b: u32) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a == b {
+            return;
+        }
+        let (large, small) = if self.sizes[a as usize] >= self.sizes[b as usize] {
+            (a, b)
+        } else {
+            (b, a)
+        };
+        self.parents[small as usize] = large;
+        self.sizes[large as usize] += self.sizes[small as usize];
+    }
+}
+
+/// Clusters addresses with the common-input-ownership heuristic:
+/// all addresses spent together in one transaction are assumed to belong to the same entity.
+/// Each cluster is identified by the id of its first seen address (0-based, in chain order),
+/// so the ids are stable for the same range of blocks.
+pub struct Clusters {
+    dump_folder: PathBuf,
+    writer: OutputWriter,
+
+    // Address ids in order of first appearance
+    address_ids: HashMap<String, u32>,
+    sets: UnionFind,
+    // key: txid + index, value: address id
+    unspents: common::Unspents<u32>,
+
+    start_height: u64,
+}
+
+impl Clusters {
+    fn address_id(&mut self, address: &str) -> u32 {
+        match self.address_ids.get(address) {
+            Some(id) => *id,
+            None => {
+                let id = self.sets.push();
+                self.address_ids.insert(address.to_string(), id);
+                id
+            }
+        }
+    }
+}
+
+impl Callback for Clusters {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        let cmd = Command::new("clusters")
+            .about("Dumps address clusters based on common input ownership to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            );
+        Compression::add_args(cmd)
+    }
+
+    fn schema(_: &ArgMatches) -> Vec<Table>
+    where
+        Self: Sized,
+    {
+        vec![CLUSTERS]
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let dump_folder = &PathBuf::from(matches.get_one::<String>("dump-folder").unwrap());
+        let cb = Clusters {
+            dump_folder: PathBuf::from(dump_folder),
+            writer: Compression::from_matches(matches)?
+                .create(&dump_folder.join("clusters.csv.tmp"))?,
+            address_ids: HashMap::new(),
+            sets: UnionFind::default(),
+            unspents: HashMap::with_capacity(10000000),
+            start_height: 0,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing clusters with dump folder: {} ...", &self.dump_folder.display());
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, _: u64) -> OpResult<BlockFlow> {
+        for tx in &block.txs {
+            let spent: Vec<u32> = common::take_unspents(tx, &mut self.unspents)
+                .map(|(_, id)| id)
+                .collect();
+            for id in spent.iter().skip(1) {
+                self.sets.union(spent[0], *id);
+            }
+
+            let created = common::eval_unspents_with(tx, block.genesis, |output| {
+                output.address.map(String::from)
+            });
+            for (outpoint, address) in created {
+                let id = self.address_id(&address);
+                self.unspents.insert(outpoint.to_bytes(), id);
+            }
+        }
+        Ok(BlockFlow::Continue)
+    }
+
+    fn tracks_utxos(&self) -> bool {
+        true
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        // The smallest address id of each cluster becomes the cluster id
+        let mut cluster_ids: HashMap<u32, u32> = HashMap::new();
+        let mut addresses: Vec<(&String, &u32)> = self.address_ids.iter().collect();
+        addresses.sort_unstable_by_key(|(_, id)| **id);
+
+        self.writer.write_all(CLUSTERS.header().as_bytes())?;
+        for (address, id) in addresses {
+            let root = self.sets.find(*id);
+            let cluster_id = *cluster_ids.entry(root).or_insert(*id);
+            self.writer
+                .write_all(format!("{};{}\n", address, cluster_id).as_bytes())?;
+        }
+        self.writer.finish()?;
+        fs::rename(
+            self.dump_folder.as_path().join("clusters.csv.tmp"),
+            self.dump_folder
+                .as_path()
+                .join(self.writer.file_name(format!(
+                    "clusters-{}-{}.csv",
+                    self.start_height, block_height
+                ))),
+        )?;
+        info!(target: "callback", "Done.\nDumped clusters from height {} to {}:\n\
+                                   \t-> addresses: {:9}\n\
+                                   \t-> clusters:  {:9}",
+             self.start_height, block_height, self.address_ids.len(), cluster_ids.len());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::tx::TxOutpoint;
+    use crate::callbacks::common::{test_block, test_script};
+
+    #[test]
+    fn test_union_find() {
+        let mut sets = UnionFind::default();
+        let ids: Vec<u32> = (0..4).map(|_| sets.push()).collect();
+        sets.union(ids[0], ids[1]);
+        sets.union(ids[3], ids[1]);
+        assert_eq!(sets.find(ids[3]), sets.find(ids[0]));
+        assert_ne!(sets.find(ids[2]), sets.find(ids[0]));
+    }
+
+    #[test]
+    fn test_clusters() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let matches = Clusters::build_subcommand()
+            .get_matches_from(["clusters", tmp_dir.path().to_str().unwrap()]);
+        let mut callback = Clusters::new(&matches).unwrap();
+        callback.on_start(1).unwrap();
+
+        let funding = test_block(
+            vec![],
+            &[
+                (test_script(1), 10),
+                (test_script(2), 20),
+                (test_script(3), 30),
+            ],
+        );
+        callback.on_block(&funding, 1).unwrap();
+        // Spends the outputs of the first two addresses together
+        let txid = funding.txs[0].hash;
+        let spend = test_block(
+            vec![TxOutpoint::new(txid, 1), TxOutpoint::new(txid, 0)],
+            &[(test_script(4), 25)],
+        );
+        callback.on_block(&spend, 2).unwrap();
+        callback.on_complete(2).unwrap();
+        assert!(callback
+            .unspents
+            .contains_key(&TxOutpoint::new(txid, 2).to_bytes()));
+
+        let csv = fs::read_to_string(tmp_dir.path().join("clusters-1-2.csv")).unwrap();
+        CLUSTERS.assert_csv(&csv);
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some("address;cluster_id"));
+        let cluster_ids: Vec<&str> = lines.map(|line| line.split(';').nth(1).unwrap()).collect();
+        assert_eq!(cluster_ids, ["0", "0", "2", "3"]);
+    }
+}
@@ -0,0 +1,237 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::tx::EvaluatedTx;
+use crate::blockchain::proto::Hashed;
+use crate::callbacks::compress::{Compression, OutputWriter};
+use crate::callbacks::rotate::{ColumnType, Table};
+use crate::callbacks::{BlockFlow, Callback};
+use crate::errors::OpResult;
+
+const COINBASE_ADDRESSES: Table = Table::csv(
+    "coinbaseaddresses",
+    &[
+        ("address", ColumnType::String),
+        ("total_coinbase_value", ColumnType::Integer),
+        ("block_count", ColumnType::Integer),
+    ],
+);
+
+#[derive(Default)]
+struct Payouts {
+    total_value: u64,
+    block_count: u64,
+    // Last height with a payout, so multiple outputs in the same coinbase count as one block
+    last_height: Option<u64>,
+}
+
+/// Tallies the coinbase value (subsidy + fees) received per address.
+/// Each output is attributed to its own address, outputs without an address are skipped.
+pub struct CoinbaseAddresses {
+    dump_folder: PathBuf,
+    writer: OutputWriter,
+
+    payouts: HashMap<String, Payouts>,
+
+    start_height: u64,
+    total_value: u64,
+    unattributed_value: u64,
+}
+
+impl Callback for CoinbaseAddresses {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        let cmd = Command::new("coinbaseaddresses")
+            .about("Dumps the total coinbase value received per address to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            );
+        Compression::add_args(cmd)
+    }
+
+    fn schema(_: &ArgMatches) -> Vec<Table>
+    where
+        Self: Sized,
+    {
+        vec![COINBASE_ADDRESSES]
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let dump_folder = &PathBuf::from(matches.get_one::<String>("dump-folder").unwrap());
+        let cb = CoinbaseAddresses {
+            dump_folder: PathBuf::from(dump_folder),
+            writer: Compression::from_matches(matches)?
+                .create(&dump_folder.join("coinbaseaddresses.csv.tmp"))?,
+            payouts: HashMap::new(),
+            start_height: 0,
+            total_value: 0,
+            unattributed_value: 0,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing coinbaseaddresses with dump folder: {} ...", &self.dump_folder.display());
+        Ok(())
+    }
+
+    fn on_block(&mut self, _: &Block, _: u64) -> OpResult<BlockFlow> {
+        Ok(BlockFlow::Continue)
+    }
+
+    fn on_tx(&mut self, tx: &Hashed<EvaluatedTx>, block_height: u64) -> OpResult<()> {
+        for output in tx.resolved_outputs() {
+            self.total_value += output.value;
+            let address = match output.address {
+                Some(address) => address,
+                None => {
+                    self.unattributed_value += output.value;
+                    continue;
+                }
+            };
+            let payouts = match self.payouts.get_mut(address) {
+                Some(payouts) => payouts,
+                None => self.payouts.entry(address.to_string()).or_default(),
+            };
+            payouts.total_value += output.value;
+            if payouts.last_height != Some(block_height) {
+                payouts.block_count += 1;
+                payouts.last_height = Some(block_height);
+            }
+        }
+        Ok(())
+    }
+
+    fn wants_only_coinbase(&self) -> bool {
+        true
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.writer
+            .write_all(COINBASE_ADDRESSES.header().as_bytes())?;
+
+        // Largest receivers first
+        let mut payouts: Vec<_> = self.payouts.iter().collect();
+        payouts.sort_by(|a, b| b.1.total_value.cmp(&a.1.total_value).then(a.0.cmp(b.0)));
+        for (address, payout) in payouts {
+            self.writer.write_all(
+                format!(
+                    "{};{};{}\n",
+                    address, payout.total_value, payout.block_count
+                )
+                .as_bytes(),
+            )?;
+        }
+        self.writer.finish()?;
+        fs::rename(
+            self.dump_folder.as_path().join("coinbaseaddresses.csv.tmp"),
+            self.dump_folder
+                .as_path()
+                .join(self.writer.file_name(format!(
+                    "coinbaseaddresses-{}-{}.csv",
+                    self.start_height, block_height
+                ))),
+        )?;
+        info!(target: "callback", "Done.\nDumped coinbase addresses from height {} to {}:\n\
+                                   \t-> addresses:          {:9}\n\
+                                   \t-> total value:        {:9}\n\
+                                   \t-> unattributed value: {:9}",
+             self.start_height, block_height, self.payouts.len(), self.total_value, self.unattributed_value);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::script::AddressFormat;
+    use crate::blockchain::proto::tx::{RawTx, TxInput, TxOutpoint, TxOutput};
+    use crate::blockchain::proto::varuint::VarUint;
+    use crate::common::utils;
+    use bitcoin::hashes::{sha256d, Hash};
+
+    fn coinbase(outputs: &[(&str, u64)], height: u8) -> Hashed<EvaluatedTx> {
+        let input = TxInput {
+            outpoint: TxOutpoint::new(sha256d::Hash::all_zeros(), 0xFFFFFFFF),
+            script_len: VarUint::from(2u8),
+            script_sig: vec![0x01, height],
+            seq_no: 0xFFFFFFFF,
+            witness: vec![],
+        };
+        Hashed::double_sha256(EvaluatedTx::from(RawTx {
+            version: 1,
+            in_count: VarUint::from(1u8),
+            inputs: vec![input],
+            out_count: VarUint::from(outputs.len() as u8),
+            outputs: outputs
+                .iter()
+                .map(|(script, value)| {
+                    let script_pubkey = utils::hex_to_vec(script);
+                    TxOutput {
+                        value: *value,
+                        script_len: VarUint::from(script_pubkey.len() as u8),
+                        script_pubkey,
+                    }
+                })
+                .collect(),
+            locktime: 0,
+            address_format: AddressFormat::default(),
+            witness_size: 0,
+        }))
+    }
+
+    #[test]
+    fn test_coinbase_addresses() {
+        // F2Pool: 1KFHE7w8BhaENAswwryaoccDb6qcT6DbYY
+        let f2pool = "76a914c825a1ecf2a6830c4401620c3a16f1995057c2ab88ac";
+        // 1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa
+        let other = "76a91462e907b15cbf27d5425399ebf6f0fb50ebb88f1888ac";
+        let witness_commitment =
+            "6a24aa21a9ed0000000000000000000000000000000000000000000000000000000000000000";
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let matches = CoinbaseAddresses::build_subcommand()
+            .get_matches_from(["coinbaseaddresses", tmp_dir.path().to_str().unwrap()]);
+        let mut callback = CoinbaseAddresses::new(&matches).unwrap();
+        callback.on_start(1).unwrap();
+        // Two outputs to the same address within one block count as one block
+        callback
+            .on_txs(
+                &[coinbase(
+                    &[(f2pool, 300), (f2pool, 200), (witness_commitment, 0)],
+                    1,
+                )],
+                1,
+            )
+            .unwrap();
+        callback
+            .on_txs(&[coinbase(&[(other, 400), (f2pool, 100)], 2)], 2)
+            .unwrap();
+        callback.on_complete(2).unwrap();
+
+        let csv = fs::read_to_string(tmp_dir.path().join("coinbaseaddresses-1-2.csv")).unwrap();
+        COINBASE_ADDRESSES.assert_csv(&csv);
+        assert_eq!(
+            csv,
+            "address;total_coinbase_value;block_count\n\
+             1KFHE7w8BhaENAswwryaoccDb6qcT6DbYY;600;2\n\
+             1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa;400;1\n"
+        );
+    }
+}
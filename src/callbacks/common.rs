@@ -1,27 +1,297 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Write};
+use std::sync::Arc;
 
-use crate::blockchain::proto::tx::EvaluatedTx;
-use crate::blockchain::proto::tx::TxOutpoint;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use clap::{Arg, ArgMatches, Command};
+use rayon::prelude::*;
+
+use crate::blockchain::parser::chainstate::SnapshotUtxo;
+use crate::blockchain::proto::script::ScriptType;
+use crate::blockchain::proto::tx::{EvaluatedTx, ResolvedOutput, TxOutpoint};
 use crate::blockchain::proto::Hashed;
 use crate::blockchain::proto::ToRaw;
+use crate::common::profile::{self, Phase};
+use crate::errors::{OpError, OpErrorKind, OpResult};
 
 pub struct UnspentValue {
     pub block_height: u64,
     pub value: u64,
-    pub address: String,
+    pub script_type: ScriptType,
+    address: Arc<str>,
 }
 
-/// Iterates over transaction inputs and removes spent outputs from HashMap.
-/// Returns the total number of processed inputs.
-pub fn remove_unspents(
-    tx: &Hashed<EvaluatedTx>,
-    unspents: &mut HashMap<Vec<u8>, UnspentValue>,
+impl UnspentValue {
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+}
+
+/// Deduplicates address strings so that unspents paying to the same address share one allocation.
+/// If disabled, each unspent gets its own copy of the address.
+#[derive(Default)]
+pub struct AddressInterner {
+    enabled: bool,
+    addresses: HashSet<Arc<str>>,
+}
+
+impl AddressInterner {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            addresses: HashSet::new(),
+        }
+    }
+
+    /// Returns a shared handle for the given address
+    pub fn intern(&mut self, address: &str) -> Arc<str> {
+        if !self.enabled {
+            return Arc::from(address);
+        }
+        match self.addresses.get(address) {
+            Some(interned) => Arc::clone(interned),
+            None => {
+                let interned: Arc<str> = Arc::from(address);
+                self.addresses.insert(Arc::clone(&interned));
+                interned
+            }
+        }
+    }
+
+    /// Drops addresses which are no longer referenced by any unspent
+    pub fn prune(&mut self) {
+        self.addresses
+            .retain(|address| Arc::strong_count(address) > 1);
+        trace!(target: "callback", "Interned addresses after pruning: {}", self.len());
+    }
+
+    pub fn len(&self) -> usize {
+        self.addresses.len()
+    }
+}
+
+/// Unspent outputs keyed by the serialized outpoint (txid + index).
+/// Callbacks needing other per-output data can use their own payload type with the `*_with` functions.
+pub type Unspents<V = UnspentValue> = HashMap<Vec<u8>, V>;
+
+/// A pending change to a shard of `ShardedUnspents`
+enum UnspentUpdate<V> {
+    Remove(Vec<u8>),
+    Insert(Vec<u8>, V),
+}
+
+/// Unspent outputs split into shards by the first byte of the txid.
+/// `apply_txs()` updates each shard on its own thread, which speeds up the UTXO-building phase
+/// for large sets. With a single shard, it behaves like a plain `Unspents` map.
+pub struct ShardedUnspents<V = UnspentValue> {
+    shards: Vec<Unspents<V>>,
+}
+
+impl<V: Send> ShardedUnspents<V> {
+    /// Distributes `capacity` evenly across the shards
+    pub fn new(shards: usize, capacity: usize) -> Self {
+        let shards = shards.clamp(1, 256);
+        Self {
+            shards: (0..shards)
+                .map(|_| HashMap::with_capacity(capacity / shards))
+                .collect(),
+        }
+    }
+
+    pub fn from_matches(matches: &ArgMatches, capacity: usize) -> Self {
+        let shards = matches.get_one::<u64>("utxo-shards").copied().unwrap_or(1);
+        Self::new(shards as usize, capacity)
+    }
+
+    #[inline]
+    fn shard_of(&self, key: &[u8]) -> usize {
+        key[0] as usize % self.shards.len()
+    }
+
+    pub fn insert(&mut self, key: Vec<u8>, value: V) -> Option<V> {
+        let shard = self.shard_of(&key);
+        self.shards[shard].insert(key, value)
+    }
+
+    #[cfg(feature = "sqlite")]
+    pub fn get(&self, key: &[u8]) -> Option<&V> {
+        self.shards[self.shard_of(key)].get(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(HashMap::len).sum()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Vec<u8>, &V)> {
+        self.shards.iter().flat_map(HashMap::iter)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.shards.iter().flat_map(HashMap::values)
+    }
+
+    /// Removes the outputs spent by the transactions and adds the outputs for which `make` returns a payload.
+    /// The payloads are evaluated in order on the calling thread and the updates are split by shard,
+    /// keeping the block order: the inputs of a transaction come before its outputs, which come before
+    /// the inputs of later transactions spending them. Updates of the same outpoint always end up in the
+    /// same shard, so applying each shard in parallel gives the same result as applying the transactions one by one.
+    /// Returns the number of inserted outputs.
+    pub fn apply_txs<F>(&mut self, txs: &[Hashed<EvaluatedTx>], genesis: bool, mut make: F) -> u64
+    where
+        F: FnMut(&ResolvedOutput) -> Option<V>,
+    {
+        let mut updates: Vec<Vec<UnspentUpdate<V>>> =
+            (0..self.shards.len()).map(|_| Vec::new()).collect();
+        let mut count = 0;
+        for tx in txs {
+            for input in &tx.value.inputs {
+                let key = input.outpoint.to_bytes();
+                updates[self.shard_of(&key)].push(UnspentUpdate::Remove(key));
+            }
+            for (outpoint, value) in eval_unspents_with(tx, genesis, &mut make) {
+                let key = outpoint.to_bytes();
+                updates[self.shard_of(&key)].push(UnspentUpdate::Insert(key, value));
+                count += 1;
+            }
+        }
+        self.shards
+            .par_iter_mut()
+            .zip(updates)
+            .for_each(|(shard, updates)| {
+                for update in updates {
+                    match update {
+                        UnspentUpdate::Remove(key) => {
+                            shard.remove(&key);
+                        }
+                        UnspentUpdate::Insert(key, value) => {
+                            shard.insert(key, value);
+                        }
+                    }
+                }
+            });
+        count
+    }
+}
+
+/// Adds `--utxo-shards` to the given subcommand
+pub fn add_utxo_shards_arg(cmd: Command) -> Command {
+    cmd.arg(
+        Arg::new("utxo-shards")
+            .long("utxo-shards")
+            .value_name("K")
+            .value_parser(clap::value_parser!(u64).range(1..=256))
+            .default_value("1")
+            .help("Splits the UTXO set into K shards which are updated in parallel"),
+    )
+}
+
+/// Applies the transactions of a block to the sharded unspents, see `ShardedUnspents::apply_txs()`.
+/// Returns the number of inserted outputs.
+pub fn apply_unspents(
+    txs: &[Hashed<EvaluatedTx>],
+    block_height: u64,
+    genesis: bool,
+    unspents: &mut ShardedUnspents,
+    interner: &mut AddressInterner,
 ) -> u64 {
-    for input in &tx.value.inputs {
-        let key = input.outpoint.to_bytes();
-        unspents.remove(&key);
+    profile::measure(Phase::Utxo, || {
+        unspents.apply_txs(txs, genesis, |output| {
+            unspent_value(output, block_height, interner)
+        })
+    })
+}
+
+/// Removes the outputs spent by the transaction inputs and yields them with their outpoint.
+/// Inputs spending unknown outputs are skipped. Outputs are only removed while iterating.
+pub fn take_unspents<'a, V>(
+    tx: &'a Hashed<EvaluatedTx>,
+    unspents: &'a mut Unspents<V>,
+) -> impl Iterator<Item = (&'a TxOutpoint, V)> + 'a {
+    tx.value.inputs.iter().filter_map(move |input| {
+        unspents
+            .remove(&input.outpoint.to_bytes())
+            .map(|value| (&input.outpoint, value))
+    })
+}
+
+/// Moves the transaction through the unspents, which hold the value of each output, and returns its fee.
+/// The fee is None for the coinbase, if a spent output is unknown or if the outputs exceed the inputs.
+pub fn apply_fee(
+    tx: &Hashed<EvaluatedTx>,
+    genesis: bool,
+    unspents: &mut Unspents<u64>,
+) -> Option<u64> {
+    // Always remove the spent outputs, even if the fee can't be computed
+    let spent: Vec<u64> = take_unspents(tx, unspents)
+        .map(|(_, value)| value)
+        .collect();
+    let fee = if tx.value.is_coinbase() || spent.len() != tx.value.inputs.len() {
+        None
+    } else {
+        let out_value: u64 = tx.value.outputs.iter().map(|o| o.out.value).sum();
+        spent.iter().sum::<u64>().checked_sub(out_value)
+    };
+    insert_unspents_with(tx, genesis, unspents, |output| Some(output.value));
+    fee
+}
+
+/// Evaluates all transaction outputs with a valid address.
+/// `genesis` tells whether the transaction is part of the genesis block of the chain (see `Block::genesis`).
+/// The genesis coinbase is skipped, its output can't be spent and isn't part of the UTXO set in Bitcoin Core.
+pub fn eval_unspents(
+    tx: &Hashed<EvaluatedTx>,
+    block_height: u64,
+    genesis: bool,
+    interner: &mut AddressInterner,
+) -> Vec<(TxOutpoint, UnspentValue)> {
+    eval_unspents_with(tx, genesis, |output| {
+        unspent_value(output, block_height, interner)
+    })
+}
+
+/// Evaluates all transaction outputs for which `make` returns a payload.
+/// Like `eval_unspents()`, the genesis coinbase is skipped.
+pub fn eval_unspents_with<V, F>(
+    tx: &Hashed<EvaluatedTx>,
+    genesis: bool,
+    mut make: F,
+) -> Vec<(TxOutpoint, V)>
+where
+    F: FnMut(&ResolvedOutput) -> Option<V>,
+{
+    if genesis && tx.value.is_coinbase() {
+        return Vec::new();
+    }
+    tx.resolved_outputs()
+        .filter_map(|output| {
+            make(&output).map(|value| (TxOutpoint::new(output.txid, output.vout), value))
+        })
+        .collect()
+}
+
+/// Returns the default payload for outputs with a valid address
+fn unspent_value(
+    output: &ResolvedOutput,
+    block_height: u64,
+    interner: &mut AddressInterner,
+) -> Option<UnspentValue> {
+    match output.address {
+        Some(address) => Some(UnspentValue {
+            block_height,
+            address: interner.intern(address),
+            value: output.value,
+            script_type: ScriptType::from(output.script_type),
+        }),
+        None => {
+            debug!(
+                target: "callback", "Ignoring invalid utxo in: {} ({})",
+                &output.txid,
+                output.script_type
+            );
+            None
+        }
     }
-    tx.value.in_count.value
 }
 
 /// Iterates over transaction outputs and adds valid unspents to HashMap.
@@ -29,48 +299,210 @@ pub fn remove_unspents(
 pub fn insert_unspents(
     tx: &Hashed<EvaluatedTx>,
     block_height: u64,
-    unspents: &mut HashMap<Vec<u8>, UnspentValue>,
+    genesis: bool,
+    unspents: &mut Unspents,
+    interner: &mut AddressInterner,
 ) -> u64 {
-    let mut count = 0;
-    for (i, output) in tx.value.outputs.iter().enumerate() {
-        match &output.script.address {
-            Some(address) => {
-                let unspent = UnspentValue {
-                    block_height,
-                    address: address.clone(),
-                    value: output.out.value,
-                };
-
-                let key = TxOutpoint::new(tx.hash, i as u32).to_bytes();
-                unspents.insert(key, unspent);
-                count += 1;
-            }
-            None => {
-                debug!(
-                    target: "callback", "Ignoring invalid utxo in: {} ({})",
-                    &tx.hash,
-                    output.script.pattern
-                );
-            }
+    insert_unspents_with(tx, genesis, unspents, |output| {
+        unspent_value(output, block_height, interner)
+    })
+}
+
+/// Iterates over transaction outputs and adds the payloads returned by `make` to HashMap.
+/// Returns the number of inserted outputs.
+pub fn insert_unspents_with<V, F>(
+    tx: &Hashed<EvaluatedTx>,
+    genesis: bool,
+    unspents: &mut Unspents<V>,
+    make: F,
+) -> u64
+where
+    F: FnMut(&ResolvedOutput) -> Option<V>,
+{
+    profile::measure(Phase::Utxo, || {
+        let mut count = 0;
+        for (outpoint, value) in eval_unspents_with(tx, genesis, make) {
+            unspents.insert(outpoint.to_bytes(), value);
+            count += 1;
         }
+        count
+    })
+}
+
+/// Writes the start height and the unspents of a callback to a checkpoint, see `read_utxo_state()`
+pub fn write_utxo_state(
+    writer: &mut dyn Write,
+    start_height: u64,
+    unspents: &ShardedUnspents,
+) -> OpResult<()> {
+    writer.write_u64::<LittleEndian>(start_height)?;
+    writer.write_u64::<LittleEndian>(unspents.len() as u64)?;
+    for (key, value) in unspents.iter() {
+        writer.write_u8(key.len() as u8)?;
+        writer.write_all(key)?;
+        writer.write_u64::<LittleEndian>(value.block_height)?;
+        writer.write_u64::<LittleEndian>(value.value)?;
+        writer.write_u8(value.script_type as u8)?;
+        writer.write_u32::<LittleEndian>(value.address.len() as u32)?;
+        writer.write_all(value.address.as_bytes())?;
     }
-    count
+    Ok(())
+}
+
+/// Restores the unspents written by `write_utxo_state()` and returns the start height.
+/// The unspents are distributed across the current shards, so `--utxo-shards` may differ between runs.
+pub fn read_utxo_state(
+    reader: &mut dyn Read,
+    unspents: &mut ShardedUnspents,
+    interner: &mut AddressInterner,
+) -> OpResult<u64> {
+    let start_height = reader.read_u64::<LittleEndian>()?;
+    let count = reader.read_u64::<LittleEndian>()?;
+    for _ in 0..count {
+        let mut key = vec![0u8; reader.read_u8()? as usize];
+        reader.read_exact(&mut key)?;
+        let block_height = reader.read_u64::<LittleEndian>()?;
+        let value = reader.read_u64::<LittleEndian>()?;
+        let script_type = ScriptType::try_from(reader.read_u8()?).map_err(|_| {
+            OpError::new(OpErrorKind::RuntimeError).join_msg("Invalid script type in checkpoint")
+        })?;
+        let mut address = vec![0u8; reader.read_u32::<LittleEndian>()? as usize];
+        reader.read_exact(&mut address)?;
+        let value = UnspentValue {
+            block_height,
+            value,
+            script_type,
+            address: interner.intern(&String::from_utf8(address)?),
+        };
+        unspents.insert(key, value);
+    }
+    Ok(start_height)
+}
+
+/// Parses a watchlist with one address per line, empty lines and lines starting with `#` are ignored
+pub fn parse_watchlist(content: &str) -> HashSet<String> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect()
+}
+
+/// Adds an unspent output from a UTXO snapshot if it has a valid address.
+/// Returns true if the output was inserted.
+pub fn insert_snapshot_utxo(
+    utxo: &SnapshotUtxo,
+    unspents: &mut ShardedUnspents,
+    interner: &mut AddressInterner,
+) -> bool {
+    let output = ResolvedOutput {
+        txid: utxo.outpoint.txid,
+        vout: utxo.outpoint.index,
+        address: utxo.output.script.address.as_deref(),
+        value: utxo.output.out.value,
+        script_type: &utxo.output.script.pattern,
+    };
+    match unspent_value(&output, utxo.height, interner) {
+        Some(value) => {
+            unspents.insert(utxo.outpoint.to_bytes(), value);
+            true
+        }
+        None => false,
+    }
+}
+
+/// scriptSig of a P2SH-P2WPKH spend from 3Eq1QAi9gU2eJJNdPg2CpZ1wqZpPFSFpzv
+#[cfg(test)]
+pub const P2SH_P2WPKH_SCRIPT_SIG: &str = "1600141d7cd6c75c2e86f4cbf98eaed221b30bd9a0b928";
+
+/// Returns the hex encoded P2PKH script for the given hash160 byte
+#[cfg(test)]
+pub fn test_script(n: u8) -> String {
+    format!("76a914{}88ac", crate::common::utils::arr_to_hex(&[n; 20]))
+}
+
+/// Returns a block with a single transaction spending `inputs` and paying to the hex encoded output scripts
+#[cfg(test)]
+pub fn test_block<S: AsRef<str>>(
+    inputs: Vec<TxOutpoint>,
+    outputs: &[(S, u64)],
+) -> crate::blockchain::proto::block::Block {
+    use crate::blockchain::proto::block::Block;
+    use crate::blockchain::proto::header::BlockHeader;
+    use crate::blockchain::proto::script::AddressFormat;
+    use crate::blockchain::proto::tx::{RawTx, TxInput, TxOutput};
+    use crate::blockchain::proto::varuint::VarUint;
+    use crate::common::utils;
+    use bitcoin::hashes::{sha256d, Hash};
+
+    let tx = RawTx {
+        version: 1,
+        in_count: VarUint::from(inputs.len() as u8),
+        inputs: inputs
+            .into_iter()
+            .map(|outpoint| TxInput {
+                outpoint,
+                script_len: VarUint::from(0u8),
+                script_sig: vec![],
+                seq_no: 0xFFFFFFFF,
+                witness: vec![],
+            })
+            .collect(),
+        out_count: VarUint::from(outputs.len() as u8),
+        outputs: outputs
+            .iter()
+            .map(|(script, value)| {
+                let script_pubkey = utils::hex_to_vec(script.as_ref());
+                TxOutput {
+                    value: *value,
+                    script_len: VarUint::from(script_pubkey.len() as u8),
+                    script_pubkey,
+                }
+            })
+            .collect(),
+        locktime: 0,
+        address_format: AddressFormat::default(),
+        witness_size: 0,
+    };
+    let header = BlockHeader {
+        version: 1,
+        prev_hash: sha256d::Hash::all_zeros(),
+        merkle_root: sha256d::Hash::all_zeros(),
+        timestamp: 0,
+        bits: 0,
+        nonce: 0,
+    };
+    Block::new(0, header, None, VarUint::from(1u8), vec![tx])
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::blockchain::parser::reader::BlockchainRead;
+    use crate::blockchain::parser::types::CoinType;
     use crate::blockchain::proto::block::Block;
     use crate::blockchain::proto::header::BlockHeader;
+    use crate::blockchain::proto::script::{AddressFormat, ScriptPattern};
+    use crate::blockchain::proto::tx::{RawTx, TxInput, TxOutput};
     use crate::blockchain::proto::varuint::VarUint;
+    use crate::common::utils;
 
     use bitcoin::hashes::{sha256d, Hash};
     use std::io::{BufReader, Cursor};
+    use std::str::FromStr;
+
+    #[test]
+    fn test_parse_watchlist() {
+        let watchlist = parse_watchlist("# pools\n\n  1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa \n");
+        assert_eq!(watchlist.len(), 1);
+        assert!(watchlist.contains("1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa"));
+    }
 
     #[test]
     fn test_callback() {
         let mut unspents: HashMap<Vec<u8>, UnspentValue> = HashMap::new();
+        let mut interner = AddressInterner::new(true);
         let header = BlockHeader {
             version: 0,
             prev_hash: sha256d::Hash::all_zeros(),
@@ -104,19 +536,19 @@ mod tests {
             0x7c, 0x88, 0xac, 0x00, 0x00, 0x00, 0x00,
         ];
         let mut reader = BufReader::new(Cursor::new(raw_data));
-        let txs = reader.read_txs(1, 0x00).unwrap();
+        let txs = reader.read_txs(1, &CoinType::default()).unwrap();
         let block1 = Block::new(0, header.clone(), None, VarUint::from(1u8), txs);
 
         for tx in &block1.txs {
-            remove_unspents(&tx, &mut unspents);
-            insert_unspents(&tx, 100000, &mut unspents);
+            take_unspents(&tx, &mut unspents).for_each(drop);
+            insert_unspents(&tx, 100000, false, &mut unspents, &mut interner);
         }
         let value = unspents
             .get(&TxOutpoint::new(block1.txs[0].hash, 0).to_bytes())
             .unwrap();
         assert_eq!(value.block_height, 100000);
         assert_eq!(value.value, 556000000);
-        assert_eq!(value.address, "1JqDybm2nWTENrHvMyafbSXXtTk5Uv5QAn");
+        assert_eq!(value.address(), "1JqDybm2nWTENrHvMyafbSXXtTk5Uv5QAn");
 
         // Create a mock of txid 5aa8e36f9423ee5fcf17c1d0d45d6988b8a5773eae8ad25d945bf34352040009,
         // which decreases balance of address 1JqDybm2nWTENrHvMyafbSXXtTk5Uv5QAn by 5.56 BTC.
@@ -242,12 +674,12 @@ mod tests {
             0x72, 0xdc, 0x35, 0x92, 0x88, 0xac, 0x00, 0x00, 0x00, 0x00,
         ];
         let mut reader = BufReader::new(Cursor::new(raw_data));
-        let txs = reader.read_txs(1, 0x00).unwrap();
+        let txs = reader.read_txs(1, &CoinType::default()).unwrap();
         let block2 = Block::new(0, header.clone(), None, VarUint::from(1u8), txs);
 
         for tx in &block2.txs {
-            remove_unspents(&tx, &mut unspents);
-            insert_unspents(&tx, 105001, &mut unspents);
+            take_unspents(&tx, &mut unspents).for_each(drop);
+            insert_unspents(&tx, 105001, false, &mut unspents, &mut interner);
         }
 
         // Original unspent should no longer exist in the hashmap
@@ -261,6 +693,323 @@ mod tests {
 
         assert_eq!(value.block_height, 105001);
         assert_eq!(value.value, 9070000000);
-        assert_eq!(value.address, "1EYXXHs5gV4pc7QAddmDj5z7m14QPHGvWL");
+        assert_eq!(value.address(), "1EYXXHs5gV4pc7QAddmDj5z7m14QPHGvWL");
+        assert_eq!(value.script_type, ScriptType::Pay2PublicKeyHash);
+
+        // The spent address is dropped from the interner, the remaining two unspents are kept
+        interner.prune();
+        assert_eq!(interner.len(), 2);
+    }
+
+    #[test]
+    fn test_address_interner() {
+        let mut interner = AddressInterner::new(true);
+        let a = interner.intern("1JqDybm2nWTENrHvMyafbSXXtTk5Uv5QAn");
+        let b = interner.intern("1JqDybm2nWTENrHvMyafbSXXtTk5Uv5QAn");
+        assert!(Arc::ptr_eq(&a, &b));
+        assert_eq!(interner.len(), 1);
+
+        let mut interner = AddressInterner::new(false);
+        let a = interner.intern("1JqDybm2nWTENrHvMyafbSXXtTk5Uv5QAn");
+        let b = interner.intern("1JqDybm2nWTENrHvMyafbSXXtTk5Uv5QAn");
+        assert!(!Arc::ptr_eq(&a, &b));
+        assert_eq!(interner.len(), 0);
+    }
+
+    #[test]
+    fn test_eval_unspents() {
+        // Coinbase of block 100000 (txid 8c14f0db3df150123e6f3dbbf30f8b955a8249b62ac1d1ff16284aefa3d06d87)
+        let raw_data = vec![
+            0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0x08,
+            0x04, 0x4c, 0x86, 0x04, 0x1b, 0x02, 0x06, 0x02, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00,
+            0xf2, 0x05, 0x2a, 0x01, 0x00, 0x00, 0x00, 0x43, 0x41, 0x04, 0x1b, 0x0e, 0x8c, 0x25,
+            0x67, 0xc1, 0x25, 0x36, 0xaa, 0x13, 0x35, 0x7b, 0x79, 0xa0, 0x73, 0xdc, 0x44, 0x44,
+            0xac, 0xb8, 0x3c, 0x4e, 0xc7, 0xa0, 0xe2, 0xf9, 0x9d, 0xd7, 0x45, 0x75, 0x16, 0xc5,
+            0x81, 0x72, 0x42, 0xda, 0x79, 0x69, 0x24, 0xca, 0x4e, 0x99, 0x94, 0x7d, 0x08, 0x7f,
+            0xed, 0xf9, 0xce, 0x46, 0x7c, 0xb9, 0xf7, 0xc6, 0x28, 0x70, 0x78, 0xf8, 0x01, 0xdf,
+            0x27, 0x6f, 0xdf, 0x84, 0xac, 0x00, 0x00, 0x00, 0x00,
+        ];
+        let mut reader = BufReader::new(Cursor::new(raw_data));
+        let txs = reader.read_txs(1, &CoinType::default()).unwrap();
+        let header = BlockHeader {
+            version: 0,
+            prev_hash: sha256d::Hash::all_zeros(),
+            merkle_root: sha256d::Hash::all_zeros(),
+            timestamp: 0,
+            bits: 0,
+            nonce: 0,
+        };
+        let block = Block::new(0, header, None, VarUint::from(1u8), txs);
+        let tx = &block.txs[0];
+
+        // The evaluated stream must match what a full run inserts into the unspent set
+        let mut interner = AddressInterner::default();
+        let mut unspents = HashMap::new();
+        insert_unspents(tx, 100000, false, &mut unspents, &mut interner);
+        let evaluated = eval_unspents(tx, 100000, false, &mut interner);
+        assert_eq!(evaluated.len(), unspents.len());
+        for (outpoint, unspent) in &evaluated {
+            let inserted = unspents.get(&outpoint.to_bytes()).unwrap();
+            assert_eq!(inserted.block_height, unspent.block_height);
+            assert_eq!(inserted.value, unspent.value);
+            assert_eq!(inserted.address(), unspent.address());
+        }
+        assert_eq!(evaluated[0].1.value, 5000000000);
+    }
+
+    #[test]
+    fn test_skip_genesis_coinbase() {
+        let raw_block = crate::common::utils::hex_to_vec(concat!(
+            "0100000000000000000000000000000000000000000000000000000000000000",
+            "000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa",
+            "4b1e5e4a29ab5f49ffff001d1dac2b7c01010000000100000000000000000000",
+            "00000000000000000000000000000000000000000000ffffffff4d04ffff001d",
+            "0104455468652054696d65732030332f4a616e2f32303039204368616e63656c",
+            "6c6f72206f6e206272696e6b206f66207365636f6e64206261696c6f75742066",
+            "6f722062616e6b73ffffffff0100f2052a01000000434104678afdb0fe554827",
+            "1967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4",
+            "f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac00000000",
+        ));
+        let block = Cursor::new(&raw_block)
+            .read_block(raw_block.len() as u32, &Default::default())
+            .unwrap();
+        let tx = &block.txs[0];
+        assert!(block.genesis);
+
+        let mut interner = AddressInterner::default();
+        let mut unspents = HashMap::new();
+        assert_eq!(
+            insert_unspents(tx, 0, block.genesis, &mut unspents, &mut interner),
+            0
+        );
+        assert!(unspents.is_empty());
+        assert!(eval_unspents(tx, 0, block.genesis, &mut interner).is_empty());
+
+        // Read as another chain, the block isn't its genesis block
+        let litecoin = CoinType::from_str("litecoin").unwrap();
+        let block = Cursor::new(&raw_block)
+            .read_block(raw_block.len() as u32, &litecoin)
+            .unwrap();
+        assert!(!block.genesis);
+
+        // The first block of a --block-file range also has height 0, but it isn't skipped
+        assert_eq!(
+            insert_unspents(tx, 0, block.genesis, &mut unspents, &mut interner),
+            1
+        );
+    }
+
+    #[test]
+    fn test_custom_payload() {
+        #[derive(Debug, PartialEq)]
+        struct Flagged {
+            value: u64,
+            p2pk: bool,
+        }
+
+        let raw_block = crate::common::utils::hex_to_vec(concat!(
+            "0100000000000000000000000000000000000000000000000000000000000000",
+            "000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa",
+            "4b1e5e4a29ab5f49ffff001d1dac2b7c01010000000100000000000000000000",
+            "00000000000000000000000000000000000000000000ffffffff4d04ffff001d",
+            "0104455468652054696d65732030332f4a616e2f32303039204368616e63656c",
+            "6c6f72206f6e206272696e6b206f66207365636f6e64206261696c6f75742066",
+            "6f722062616e6b73ffffffff0100f2052a01000000434104678afdb0fe554827",
+            "1967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4",
+            "f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac00000000",
+        ));
+        let block = Cursor::new(&raw_block)
+            .read_block(raw_block.len() as u32, &Default::default())
+            .unwrap();
+        let tx = &block.txs[0];
+
+        let mut unspents: Unspents<Flagged> = HashMap::new();
+        let count = insert_unspents_with(tx, false, &mut unspents, |output| {
+            Some(Flagged {
+                value: output.value,
+                p2pk: matches!(output.script_type, ScriptPattern::Pay2PublicKey),
+            })
+        });
+        assert_eq!(count, 1);
+        let key = TxOutpoint::new(tx.hash, 0).to_bytes();
+        assert_eq!(
+            unspents.get(&key),
+            Some(&Flagged {
+                value: 5000000000,
+                p2pk: true
+            })
+        );
+
+        // Outputs without a payload are not tracked
+        let mut skipped: Unspents<()> = HashMap::new();
+        assert_eq!(insert_unspents_with(tx, false, &mut skipped, |_| None), 0);
+        assert!(skipped.is_empty());
+    }
+
+    #[test]
+    fn test_zero_output_tx() {
+        // Spends one output without creating any
+        let mut raw_tx = vec![0x01, 0x00, 0x00, 0x00, 0x01];
+        raw_tx.extend([0x11; 32]);
+        raw_tx.extend([0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff]);
+        raw_tx.extend([0x00, 0x00, 0x00, 0x00, 0x00]);
+        let tx = Cursor::new(&raw_tx).read_tx(&CoinType::default()).unwrap();
+        let tx = Hashed::double_sha256(EvaluatedTx::from(tx));
+
+        let mut interner = AddressInterner::default();
+        let mut unspents = HashMap::new();
+        let spent = TxOutpoint::new(sha256d::Hash::from_byte_array([0x11; 32]), 0);
+        unspents.insert(
+            spent.to_bytes(),
+            UnspentValue {
+                block_height: 0,
+                value: 1,
+                script_type: ScriptType::Pay2PublicKeyHash,
+                address: interner.intern("a"),
+            },
+        );
+        assert_eq!(take_unspents(&tx, &mut unspents).count(), 1);
+        assert_eq!(
+            insert_unspents(&tx, 1, false, &mut unspents, &mut interner),
+            0
+        );
+        assert!(unspents.is_empty());
+    }
+
+    fn tx(outpoints: &[TxOutpoint], values: &[u64], locktime: u32) -> Hashed<EvaluatedTx> {
+        let script_pubkey = utils::hex_to_vec("76a91462e907b15cbf27d5425399ebf6f0fb50ebb88f1888ac");
+        Hashed::double_sha256(EvaluatedTx::from(RawTx {
+            version: 1,
+            in_count: VarUint::from(outpoints.len() as u8),
+            inputs: outpoints
+                .iter()
+                .map(|outpoint| TxInput {
+                    outpoint: outpoint.clone(),
+                    script_len: VarUint::from(0u8),
+                    script_sig: vec![],
+                    seq_no: 0xFFFFFFFF,
+                    witness: vec![],
+                })
+                .collect(),
+            out_count: VarUint::from(values.len() as u8),
+            outputs: values
+                .iter()
+                .map(|value| TxOutput {
+                    value: *value,
+                    script_len: VarUint::from(script_pubkey.len() as u8),
+                    script_pubkey: script_pubkey.clone(),
+                })
+                .collect(),
+            locktime,
+            address_format: AddressFormat::default(),
+            witness_size: 0,
+        }))
+    }
+
+    #[test]
+    fn test_sharded_unspents() {
+        let coinbase = TxOutpoint::new(sha256d::Hash::all_zeros(), 0xFFFFFFFF);
+        // Block 1 creates 16 transactions with two outputs each, half of the first outputs
+        // are spent by a later transaction of the same block
+        let mut block1: Vec<Hashed<EvaluatedTx>> = (0..16)
+            .map(|i| tx(std::slice::from_ref(&coinbase), &[i + 1, 100 + i], i as u32))
+            .collect();
+        let spends: Vec<TxOutpoint> = block1[..8]
+            .iter()
+            .map(|tx| TxOutpoint::new(tx.hash, 0))
+            .collect();
+        block1.push(tx(&spends, &[1000, 2000], 0));
+        // Block 2 spends all second outputs and an output created by the same-block spend
+        let mut spends: Vec<TxOutpoint> = block1[..16]
+            .iter()
+            .map(|tx| TxOutpoint::new(tx.hash, 1))
+            .collect();
+        spends.push(TxOutpoint::new(block1[16].hash, 1));
+        let block2 = vec![tx(&spends, &[5000], 0)];
+        let blocks = [(1, block1), (2, block2)];
+
+        let mut interner = AddressInterner::default();
+        let mut expected = HashMap::new();
+        for (height, txs) in &blocks {
+            for tx in txs {
+                take_unspents(tx, &mut expected).for_each(drop);
+                insert_unspents(tx, *height, false, &mut expected, &mut interner);
+            }
+        }
+        let mut expected: Vec<(Vec<u8>, u64, u64)> = expected
+            .into_iter()
+            .map(|(key, value)| (key, value.block_height, value.value))
+            .collect();
+        expected.sort();
+        assert_eq!(expected.len(), 10);
+
+        for shards in [1, 3, 16] {
+            let mut unspents = ShardedUnspents::new(shards, 100);
+            let mut inserted = 0;
+            for (height, txs) in &blocks {
+                inserted += apply_unspents(txs, *height, false, &mut unspents, &mut interner);
+            }
+            assert_eq!(inserted, 35);
+            let mut actual: Vec<(Vec<u8>, u64, u64)> = unspents
+                .iter()
+                .map(|(key, value)| (key.clone(), value.block_height, value.value))
+                .collect();
+            actual.sort();
+            assert_eq!(actual, expected);
+            assert_eq!(unspents.len(), expected.len());
+        }
+    }
+
+    #[test]
+    fn test_same_block_spend_chain() {
+        // Each transaction spends the first output of the previous one within the same block
+        let mut txs = vec![tx(
+            &[TxOutpoint::new(sha256d::Hash::all_zeros(), 0xFFFFFFFF)],
+            &[1000, 1],
+            0,
+        )];
+        for i in 1..20 {
+            let prev = TxOutpoint::new(txs[i - 1].hash, 0);
+            txs.push(tx(&[prev], &[1000 - i as u64, 1], 0));
+        }
+
+        let mut interner = AddressInterner::default();
+        for shards in [1, 2, 7, 256] {
+            let mut unspents = ShardedUnspents::new(shards, 0);
+            assert_eq!(
+                apply_unspents(&txs, 1, false, &mut unspents, &mut interner),
+                40
+            );
+            let mut values: Vec<u64> = unspents.values().map(|value| value.value).collect();
+            values.sort();
+            assert_eq!(values, [vec![1; 20], vec![981]].concat());
+        }
+    }
+
+    #[test]
+    fn test_apply_fee() {
+        let coinbase = tx(
+            &[TxOutpoint::new(sha256d::Hash::all_zeros(), 0xFFFFFFFF)],
+            &[1000, 500],
+            0,
+        );
+        let mut unspents = HashMap::new();
+        assert_eq!(apply_fee(&coinbase, false, &mut unspents), None);
+        assert_eq!(unspents.len(), 2);
+
+        let spend = tx(&[TxOutpoint::new(coinbase.hash, 0)], &[900], 0);
+        assert_eq!(apply_fee(&spend, false, &mut unspents), Some(100));
+        // Spending the same output again, its value is unknown
+        let double_spend = tx(&[TxOutpoint::new(coinbase.hash, 0)], &[800], 0);
+        assert_eq!(apply_fee(&double_spend, false, &mut unspents), None);
+
+        // Outputs exceeding the inputs have no fee, the spent output is removed anyway
+        let key = TxOutpoint::new(coinbase.hash, 1).to_bytes();
+        let overspend = tx(&[TxOutpoint::new(coinbase.hash, 1)], &[600], 0);
+        assert_eq!(apply_fee(&overspend, false, &mut unspents), None);
+        assert!(!unspents.contains_key(&key));
+        assert_eq!(unspents.len(), 3);
     }
 }
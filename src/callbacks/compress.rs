@@ -0,0 +1,263 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+/// Compression of the written files.
+/// gzip and zstd are only available if the crate is built with the `gzip` or `zstd` feature.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Adds `--compress` to the given subcommand
+    pub fn add_args(cmd: Command) -> Command {
+        cmd.arg(
+            Arg::new("compress")
+                .long("compress")
+                .value_name("FORMAT")
+                .value_parser(["none", "gzip", "zstd"])
+                .default_value("none")
+                .help("Compresses the written files and appends .gz or .zst to their names"),
+        )
+    }
+
+    /// Returns an error if the selected compression isn't enabled in this build
+    pub fn from_matches(matches: &ArgMatches) -> OpResult<Self> {
+        let compression = match matches.get_one::<String>("compress").map(String::as_str) {
+            Some("gzip") => Compression::Gzip,
+            Some("zstd") => Compression::Zstd,
+            _ => Compression::None,
+        };
+        if !compression.is_enabled() {
+            let msg = format!(
+                "--compress {} requires rusty-blockparser to be built with `--features {}`",
+                compression.name(),
+                compression.name()
+            );
+            return Err(OpError::new(OpErrorKind::InvalidArgsError).join_msg(&msg));
+        }
+        Ok(compression)
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Compression::None => "none",
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+        }
+    }
+
+    fn is_enabled(&self) -> bool {
+        match self {
+            Compression::None => true,
+            Compression::Gzip => cfg!(feature = "gzip"),
+            Compression::Zstd => cfg!(feature = "zstd"),
+        }
+    }
+
+    /// Suffix which is appended to the file names, including the dot
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            Compression::None => "",
+            Compression::Gzip => ".gz",
+            Compression::Zstd => ".zst",
+        }
+    }
+
+    /// Shell command which writes the decompressed content of a file to stdout
+    pub fn decompress_command(&self) -> Option<&'static str> {
+        match self {
+            Compression::None => None,
+            Compression::Gzip => Some("gzip -dc"),
+            Compression::Zstd => Some("zstd -qdc"),
+        }
+    }
+
+    /// Creates the file at the given path and returns a writer compressing into it
+    pub fn create(&self, path: &Path) -> OpResult<OutputWriter> {
+        let file = BufWriter::with_capacity(4000000, File::create(path)?);
+        let encoder: Box<dyn Encoder> = match self {
+            Compression::None => Box::new(file),
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => Box::new(flate2::write::GzEncoder::new(
+                file,
+                flate2::Compression::default(),
+            )),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Box::new(zstd::stream::write::Encoder::new(file, 0)?),
+            #[allow(unreachable_patterns)]
+            _ => {
+                return Err(OpError::new(OpErrorKind::InvalidArgsError)
+                    .join_msg(&format!("{} compression is not enabled", self.name())))
+            }
+        };
+        Ok(OutputWriter {
+            encoder,
+            compression: *self,
+            finished: false,
+        })
+    }
+}
+
+/// Writer which can be finalized, e.g. to write the trailer of the compressed format
+trait Encoder: Write {
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+impl Encoder for BufWriter<File> {
+    fn finish(&mut self) -> io::Result<()> {
+        self.flush()
+    }
+}
+
+#[cfg(feature = "gzip")]
+impl Encoder for flate2::write::GzEncoder<BufWriter<File>> {
+    fn finish(&mut self) -> io::Result<()> {
+        self.try_finish()?;
+        self.get_mut().flush()
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl Encoder for zstd::stream::write::Encoder<'static, BufWriter<File>> {
+    fn finish(&mut self) -> io::Result<()> {
+        self.do_finish()?;
+        self.get_mut().flush()
+    }
+}
+
+/// Writes to a file, optionally compressed.
+/// `finish` has to be called after the last write to catch errors, otherwise the file is finalized on drop.
+pub struct OutputWriter {
+    encoder: Box<dyn Encoder>,
+    compression: Compression,
+    finished: bool,
+}
+
+impl OutputWriter {
+    /// Appends the suffix of the compression to the given file name
+    pub fn file_name(&self, name: String) -> String {
+        name + self.compression.suffix()
+    }
+
+    /// Writes the remaining data and the trailer of the compressed format
+    pub fn finish(&mut self) -> OpResult<()> {
+        if !self.finished {
+            self.finished = true;
+            self.encoder.finish()?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for OutputWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.encoder.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.encoder.flush()
+    }
+}
+
+impl Drop for OutputWriter {
+    /// Keeps the data written so far readable if `finish` wasn't reached, e.g. on errors
+    fn drop(&mut self) {
+        if let Err(err) = self.finish() {
+            warn!(target: "callback", "Unable to finish output file: {}", err);
+        }
+    }
+}
+
+/// Reads a file written with the compression given by its extension
+#[cfg(all(test, any(feature = "gzip", feature = "zstd")))]
+pub fn read_to_string(path: &Path) -> String {
+    use std::io::Read;
+
+    let file = File::open(path).unwrap();
+    let mut content = String::new();
+    match path.extension().and_then(|ext| ext.to_str()) {
+        #[cfg(feature = "gzip")]
+        Some("gz") => flate2::read::GzDecoder::new(file)
+            .read_to_string(&mut content)
+            .unwrap(),
+        #[cfg(feature = "zstd")]
+        Some("zst") => zstd::stream::read::Decoder::new(file)
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap(),
+        _ => io::BufReader::new(file)
+            .read_to_string(&mut content)
+            .unwrap(),
+    };
+    content
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(any(feature = "gzip", feature = "zstd"))]
+    fn roundtrip(compression: Compression) {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir
+            .path()
+            .join(format!("out.csv{}", compression.suffix()));
+        let content = "height;txid\n".to_string() + &"1;abc\n".repeat(1000);
+
+        let mut writer = compression.create(&path).unwrap();
+        writer.write_all(content.as_bytes()).unwrap();
+        writer.finish().unwrap();
+        assert!(std::fs::metadata(&path).unwrap().len() < content.len() as u64);
+        assert_eq!(read_to_string(&path), content);
+
+        // Dropping an unfinished writer still produces a complete file
+        let mut writer = compression.create(&path).unwrap();
+        writer.write_all(content.as_bytes()).unwrap();
+        drop(writer);
+        assert_eq!(read_to_string(&path), content);
+    }
+
+    #[test]
+    #[cfg(feature = "gzip")]
+    fn test_gzip_roundtrip() {
+        roundtrip(Compression::Gzip);
+    }
+
+    #[test]
+    #[cfg(feature = "zstd")]
+    fn test_zstd_roundtrip() {
+        roundtrip(Compression::Zstd);
+    }
+
+    #[test]
+    fn test_no_compression() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("out.csv");
+        let mut writer = Compression::None.create(&path).unwrap();
+        writer.write_all(b"a;b\n").unwrap();
+        writer.finish().unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "a;b\n");
+
+        let parse = |format: &str| {
+            let matches = Compression::add_args(Command::new("test")).get_matches_from([
+                "test",
+                "--compress",
+                format,
+            ]);
+            Compression::from_matches(&matches)
+        };
+        assert_eq!(parse("none").unwrap(), Compression::None);
+        assert_eq!(parse("zstd").is_ok(), cfg!(feature = "zstd"));
+        assert_eq!(parse("gzip").is_ok(), cfg!(feature = "gzip"));
+        assert_eq!(Compression::Zstd.suffix(), ".zst");
+    }
+}
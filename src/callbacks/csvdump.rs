@@ -1,24 +1,374 @@
-use std::fs::{self, File};
-use std::io::{BufWriter, Write};
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
 
+use bitcoin::hashes::{hash160, Hash};
 use clap::{Arg, ArgMatches, Command};
 
 use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::script::ScriptPattern;
 use crate::blockchain::proto::tx::{EvaluatedTx, EvaluatedTxOut, TxInput};
-use crate::blockchain::proto::Hashed;
-use crate::callbacks::Callback;
-use crate::common::utils;
+use crate::blockchain::proto::{Hashed, ToRaw};
+use crate::callbacks::common;
+use crate::callbacks::compress::Compression;
+use crate::callbacks::rotate::{ColumnType, FileFormat, RotatingWriter, Rotation, Table};
+use crate::callbacks::{BlockFlow, Callback};
+use crate::common::json;
+use crate::common::utils::{self, HashByteOrder};
 use crate::errors::OpResult;
 
-/// Dumps the whole blockchain into csv files
+/// Format of the rows in the csv files
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RowFormat {
+    /// Separated by semicolons, missing values are empty
+    Csv,
+    /// Text format of Postgres' `COPY ... FROM`: separated by tabs, missing values are `\N`
+    PgCopy,
+}
+
+impl RowFormat {
+    fn row(&self, fields: &[Option<String>]) -> String {
+        let fields: Vec<String> = match self {
+            RowFormat::Csv => fields
+                .iter()
+                .map(|field| field.clone().unwrap_or_default())
+                .collect(),
+            RowFormat::PgCopy => fields
+                .iter()
+                .map(|field| match field {
+                    Some(value) => pg_escape(value),
+                    None => String::from("\\N"),
+                })
+                .collect(),
+        };
+        let separator = match self {
+            RowFormat::Csv => ";",
+            RowFormat::PgCopy => "\t",
+        };
+        let mut row = fields.join(separator);
+        row.push('\n');
+        row
+    }
+}
+
+/// Escapes a value for the text format of COPY. Backslashes, the tab separator, line breaks
+/// and other control characters are written as backslash sequences.
+fn pg_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c if c.is_ascii_control() => escaped.push_str(&format!("\\x{:02x}", c as u8)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Returns the CREATE TABLE statement of each table followed by COPY statements for its files
+fn pg_schema(
+    tables: &[(&str, Vec<(&str, &str)>)],
+    paths: &[Vec<PathBuf>],
+    compression: Compression,
+) -> OpResult<String> {
+    let mut sql = String::new();
+    for ((name, columns), paths) in tables.iter().zip(paths) {
+        let columns: Vec<String> = columns
+            .iter()
+            .map(|(column, kind)| format!("  {} {}", column, kind))
+            .collect();
+        sql.push_str(&format!(
+            "CREATE TABLE IF NOT EXISTS {} (\n{}\n);\n",
+            name,
+            columns.join(",\n")
+        ));
+        for path in paths {
+            let path = fs::canonicalize(path)?.display().to_string();
+            // Compressed files are read through the decompressor on the database server
+            let source = match compression.decompress_command() {
+                Some(command) => {
+                    // Single quoted for the shell, so $, ` and \ in the path aren't expanded
+                    let program = format!("{} '{}'", command, path.replace('\'', "'\\''"));
+                    format!("PROGRAM '{}'", program.replace('\'', "''"))
+                }
+                None => format!("'{}'", path.replace('\'', "''")),
+            };
+            sql.push_str(&format!(
+                "COPY {} FROM {} WITH (FORMAT text);\n",
+                name, source
+            ));
+        }
+        sql.push('\n');
+    }
+    Ok(sql)
+}
+
+/// Columns which are only written if requested
+#[derive(Clone, Copy, Debug, Default)]
+struct OptionalColumns {
+    // Adds the wtxid to each transaction
+    wtxid: bool,
+    // Adds whether each transaction signals replaceability (BIP125)
+    rbf: bool,
+    // Adds the hash160 of each key of bare multisig outputs
+    multisig_keys: bool,
+}
+
+impl OptionalColumns {
+    fn from_matches(matches: &ArgMatches) -> Self {
+        OptionalColumns {
+            wtxid: matches.get_flag("with-wtxid"),
+            rbf: matches.get_flag("with-rbf"),
+            multisig_keys: matches.get_flag("with-multisig-keys"),
+        }
+    }
+}
+
+// The csv files are written without header
+const BLOCKS: Table = Table::csv(
+    "blocks",
+    &[
+        ("hash", ColumnType::String),
+        ("height", ColumnType::Integer),
+        ("version", ColumnType::Integer),
+        ("size", ColumnType::Integer),
+        ("prev_hash", ColumnType::String),
+        ("merkle_root", ColumnType::String),
+        ("timestamp", ColumnType::Integer),
+        ("bits", ColumnType::Integer),
+        ("nonce", ColumnType::Integer),
+    ],
+);
+const TRANSACTIONS: Table = Table::csv(
+    "transactions",
+    &[
+        ("txid", ColumnType::String),
+        ("block_hash", ColumnType::String),
+        ("version", ColumnType::Integer),
+        ("locktime", ColumnType::Integer),
+    ],
+);
+const TX_IN: Table = Table::csv(
+    "tx_in",
+    &[
+        ("txid", ColumnType::String),
+        ("prev_txid", ColumnType::String),
+        ("prev_index", ColumnType::Integer),
+        ("script_sig", ColumnType::String),
+        ("sequence", ColumnType::Integer),
+    ],
+);
+const TX_OUT: Table = Table::csv(
+    "tx_out",
+    &[
+        ("txid", ColumnType::String),
+        ("vout", ColumnType::Integer),
+        ("value", ColumnType::Integer),
+        ("script_pubkey", ColumnType::String),
+        ("address", ColumnType::String),
+    ],
+);
+
+// Fields of the objects in transactions.jsonl, see `json::tx_json()`
+const TX_JSON: Table = Table::jsonl(
+    "transactions",
+    &[
+        ("txid", ColumnType::String),
+        ("height", ColumnType::Integer),
+        ("version", ColumnType::Integer),
+        ("inputs", ColumnType::Array),
+        ("outputs", ColumnType::Array),
+        ("locktime", ColumnType::Integer),
+        ("weight", ColumnType::Integer),
+    ],
+);
+const TX_IN_JSON: Table = Table::jsonl(
+    "transactions.inputs",
+    &[
+        ("txid", ColumnType::String),
+        ("index", ColumnType::Integer),
+        ("script_sig", ColumnType::String),
+        ("seq_no", ColumnType::Integer),
+        ("has_witness", ColumnType::Boolean),
+    ],
+);
+const TX_OUT_JSON: Table = Table::jsonl(
+    "transactions.outputs",
+    &[
+        ("value", ColumnType::Integer),
+        ("script_pubkey", ColumnType::String),
+        ("address", ColumnType::String),
+        ("pattern", ColumnType::String),
+    ],
+);
+
+/// Returns the csv tables in the order of `Output::writers()` followed by the JSON Lines tables
+fn tables(columns: OptionalColumns, resolve_inputs: bool) -> Vec<Table> {
+    let (mut transactions, mut tx_json) = (TRANSACTIONS, TX_JSON);
+    if columns.wtxid {
+        transactions = transactions.with_columns(&[("wtxid", ColumnType::String)]);
+        tx_json = tx_json.with_columns(&[("wtxid", ColumnType::String)]);
+    }
+    if columns.rbf {
+        // Written as 0 or 1 to the csv file
+        transactions = transactions.with_columns(&[("rbf", ColumnType::Integer)]);
+        tx_json = tx_json.with_columns(&[("rbf", ColumnType::Boolean)]);
+    }
+    let (mut tx_in, mut tx_in_json) = (TX_IN, TX_IN_JSON);
+    if resolve_inputs {
+        let resolved = [
+            ("address", ColumnType::String),
+            ("value", ColumnType::Integer),
+        ];
+        tx_in = tx_in.with_columns(&resolved);
+        tx_in_json = tx_in_json.with_columns(&resolved);
+    }
+    let (mut tx_out, mut tx_out_json) = (TX_OUT, TX_OUT_JSON);
+    if columns.multisig_keys {
+        // Joined by commas in the csv file
+        tx_out = tx_out.with_columns(&[("multisig_keys", ColumnType::String)]);
+        tx_out_json = tx_out_json.with_columns(&[("multisig_keys", ColumnType::Array)]);
+    }
+    vec![
+        BLOCKS,
+        transactions,
+        tx_in,
+        tx_out,
+        tx_json,
+        tx_in_json,
+        tx_out_json,
+    ]
+}
+
+// Each structure gets stored in a separate csv file
+struct CsvWriters {
+    format: RowFormat,
+    compression: Compression,
+    block_writer: RotatingWriter,
+    tx_writer: RotatingWriter,
+    txin_writer: RotatingWriter,
+    txout_writer: RotatingWriter,
+}
+
+impl CsvWriters {
+    fn new(
+        folder: &Path,
+        format: RowFormat,
+        rotation: Rotation,
+        compression: Compression,
+    ) -> OpResult<Self> {
+        let extension = match format {
+            RowFormat::Csv => "csv",
+            RowFormat::PgCopy => "tsv",
+        };
+        let writer = |name| {
+            RotatingWriter::with_extension(folder, name, extension, None, rotation, compression)
+        };
+        Ok(CsvWriters {
+            format,
+            compression,
+            block_writer: writer("blocks")?,
+            tx_writer: writer("transactions")?,
+            txin_writer: writer("tx_in")?,
+            txout_writer: writer("tx_out")?,
+        })
+    }
+
+    /// `spent` holds the resolved output for each input of each transaction, if inputs are resolved
+    fn write_block(
+        &mut self,
+        block: &Block,
+        block_height: u64,
+        columns: OptionalColumns,
+        spent: Option<&[Vec<ResolvedInput>]>,
+        order: HashByteOrder,
+    ) -> OpResult<()> {
+        let format = self.format;
+        // serialize block
+        self.block_writer
+            .write_row(&format.row(&block.as_row(block_height, order)))?;
+
+        // serialize transaction
+        let block_hash = order.hex(&block.header.hash);
+        for (t, tx) in block.txs.iter().enumerate() {
+            let txid_str = order.hex(&tx.hash);
+            self.tx_writer.write_row(&format.row(&tx.as_row(
+                &txid_str,
+                &block_hash,
+                columns,
+                order,
+            )))?;
+
+            // serialize inputs
+            for (i, input) in tx.value.inputs.iter().enumerate() {
+                let mut row = input.as_row(&txid_str, order);
+                if let Some(spent) = spent {
+                    // (..., address, value)
+                    let spent = &spent[t][i];
+                    row.push(spent.address.clone());
+                    row.push(spent.value.map(|value| value.to_string()));
+                }
+                self.txin_writer.write_row(&format.row(&row))?;
+            }
+
+            // serialize outputs
+            for (i, output) in tx.value.outputs.iter().enumerate() {
+                self.txout_writer.write_row(&format.row(&output.as_row(
+                    &txid_str,
+                    i as u32,
+                    columns.multisig_keys,
+                )))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+enum Output {
+    Csv(Box<CsvWriters>),
+    // One JSON object per transaction with its inputs and outputs
+    Jsonl(RotatingWriter),
+}
+
+impl Output {
+    fn writers(&mut self) -> Vec<&mut RotatingWriter> {
+        match self {
+            Output::Csv(csv) => vec![
+                &mut csv.block_writer,
+                &mut csv.tx_writer,
+                &mut csv.txin_writer,
+                &mut csv.txout_writer,
+            ],
+            Output::Jsonl(tx_writer) => vec![tx_writer],
+        }
+    }
+}
+
+/// Address and value of an output which can be spent by later inputs
+struct SpentOutput {
+    // None if no address could be derived from the script
+    address: Option<String>,
+    value: u64,
+}
+
+/// Address and value of the output spent by an input, None if unknown
+struct ResolvedInput {
+    address: Option<String>,
+    value: Option<u64>,
+}
+
+/// Dumps the whole blockchain into csv files, or the transactions into a JSON Lines file.
+/// With `--pg-copy`, the files are written for Postgres' COPY together with a matching schema.sql.
 pub struct CsvDump {
-    // Each structure gets stored in a separate csv file
     dump_folder: PathBuf,
-    block_writer: BufWriter<File>,
-    tx_writer: BufWriter<File>,
-    txin_writer: BufWriter<File>,
-    txout_writer: BufWriter<File>,
+    output: Output,
+    columns: OptionalColumns,
+    hash_byte_order: HashByteOrder,
+    // Outputs which can be spent by later inputs, only kept with --resolve-inputs
+    unspents: Option<common::Unspents<SpentOutput>>,
 
     start_height: u64,
     tx_count: u64,
@@ -27,8 +377,102 @@ pub struct CsvDump {
 }
 
 impl CsvDump {
-    fn create_writer(cap: usize, path: PathBuf) -> OpResult<BufWriter<File>> {
-        Ok(BufWriter::with_capacity(cap, File::create(path)?))
+    /// Returns the output spent by each input of each transaction. For outputs which weren't seen
+    /// (e.g. created before the start height) only the address of P2SH-P2WPKH spends is resolved from the scriptSig.
+    /// Returns None if inputs aren't resolved.
+    /// The outputs of a transaction are added after its inputs, so spends within the block are resolved too.
+    /// Outputs without address are kept with their value, bare multisig gets the same synthetic address as in tx_out.
+    fn resolve_inputs(&mut self, block: &Block) -> Option<Vec<Vec<ResolvedInput>>> {
+        let unspents = self.unspents.as_mut()?;
+        let spent = block
+            .txs
+            .iter()
+            .map(|tx| {
+                let spent = tx
+                    .value
+                    .inputs
+                    .iter()
+                    .map(|input| match unspents.remove(&input.outpoint.to_bytes()) {
+                        Some(spent) => ResolvedInput {
+                            address: spent.address,
+                            value: Some(spent.value),
+                        },
+                        None => ResolvedInput {
+                            address: tx.value.input_address(input),
+                            value: None,
+                        },
+                    })
+                    .collect();
+                let outputs = common::eval_unspents_with(tx, block.genesis, |output| {
+                    let address = output.address.map(String::from).or_else(|| {
+                        multisig_address(output.script_type).map(|(address, _)| address)
+                    });
+                    Some(SpentOutput {
+                        address,
+                        value: output.value,
+                    })
+                });
+                for (outpoint, output) in outputs {
+                    unspents.insert(outpoint.to_bytes(), output);
+                }
+                spent
+            })
+            .collect();
+        Some(spent)
+    }
+
+    /// Postgres columns of the written files, in the order of `Output::writers()`
+    fn pg_tables(&self) -> Vec<(&'static str, Vec<(&'static str, &'static str)>)> {
+        let blocks = vec![
+            ("hash", "TEXT NOT NULL"),
+            ("height", "BIGINT NOT NULL"),
+            ("version", "BIGINT NOT NULL"),
+            ("size", "BIGINT NOT NULL"),
+            ("prev_hash", "TEXT NOT NULL"),
+            ("merkle_root", "TEXT NOT NULL"),
+            ("timestamp", "BIGINT NOT NULL"),
+            ("bits", "BIGINT NOT NULL"),
+            ("nonce", "BIGINT NOT NULL"),
+        ];
+        let mut transactions = vec![
+            ("txid", "TEXT NOT NULL"),
+            ("block_hash", "TEXT NOT NULL"),
+            ("version", "BIGINT NOT NULL"),
+            ("locktime", "BIGINT NOT NULL"),
+        ];
+        if self.columns.wtxid {
+            transactions.push(("wtxid", "TEXT NOT NULL"));
+        }
+        if self.columns.rbf {
+            transactions.push(("rbf", "BOOLEAN NOT NULL"));
+        }
+        let mut tx_in = vec![
+            ("txid", "TEXT NOT NULL"),
+            ("prev_txid", "TEXT NOT NULL"),
+            ("prev_index", "BIGINT NOT NULL"),
+            ("script_sig", "TEXT NOT NULL"),
+            ("sequence", "BIGINT NOT NULL"),
+        ];
+        if self.unspents.is_some() {
+            tx_in.push(("address", "TEXT"));
+            tx_in.push(("value", "BIGINT"));
+        }
+        let mut tx_out = vec![
+            ("txid", "TEXT NOT NULL"),
+            ("vout", "BIGINT NOT NULL"),
+            ("value", "BIGINT NOT NULL"),
+            ("script_pubkey", "TEXT NOT NULL"),
+            ("address", "TEXT"),
+        ];
+        if self.columns.multisig_keys {
+            tx_out.push(("multisig_keys", "TEXT"));
+        }
+        vec![
+            ("blocks", blocks),
+            ("transactions", transactions),
+            ("tx_in", tx_in),
+            ("tx_out", tx_out),
+        ]
     }
 }
 
@@ -37,7 +481,7 @@ impl Callback for CsvDump {
     where
         Self: Sized,
     {
-        Command::new("csvdump")
+        let cmd = Command::new("csvdump")
             .about("Dumps the whole blockchain into CSV files")
             .version("0.1")
             .author("gcarq <egger.m@protonmail.com>")
@@ -47,6 +491,61 @@ impl Callback for CsvDump {
                     .index(1)
                     .required(true),
             )
+            .arg(
+                Arg::new("format")
+                    .long("format")
+                    .value_name("FORMAT")
+                    .value_parser(clap::builder::PossibleValuesParser::new(["csv", "jsonl"]))
+                    .default_value("csv")
+                    .help("Writes csv files or a transactions.jsonl file with one JSON object per transaction"),
+            )
+            .arg(
+                Arg::new("pg-copy")
+                    .long("pg-copy")
+                    .action(clap::ArgAction::SetTrue)
+                    .conflicts_with("format")
+                    .help("Writes tab separated files for Postgres' COPY and a schema.sql to create and load the tables"),
+            )
+            .arg(
+                Arg::new("with-wtxid")
+                    .long("with-wtxid")
+                    .action(clap::ArgAction::SetTrue)
+                    .help("Adds the wtxid of each transaction as last column (or field with --format jsonl)"),
+            )
+            .arg(
+                Arg::new("with-rbf")
+                    .long("with-rbf")
+                    .action(clap::ArgAction::SetTrue)
+                    .help("Adds 1 if the transaction signals replaceability (BIP125), otherwise 0, as last column (or field with --format jsonl)"),
+            )
+            .arg(
+                Arg::new("with-multisig-keys")
+                    .long("with-multisig-keys")
+                    .action(clap::ArgAction::SetTrue)
+                    .help("Adds the hash160 of each key of bare multisig outputs as last column (or field with --format jsonl)"),
+            )
+            .arg(
+                Arg::new("resolve-inputs")
+                    .long("resolve-inputs")
+                    .action(clap::ArgAction::SetTrue)
+                    .help("Adds the address and value of the spent output to each input (keeps the UTXO set in memory)"),
+            );
+        Compression::add_args(Rotation::add_args(cmd))
+    }
+
+    fn schema(matches: &ArgMatches) -> Vec<Table>
+    where
+        Self: Sized,
+    {
+        let mut tables = tables(
+            OptionalColumns::from_matches(matches),
+            matches.get_flag("resolve-inputs"),
+        );
+        // The tab separated files are described by the written schema.sql
+        if matches.get_flag("pg-copy") {
+            tables.retain(|table| table.format != FileFormat::Csv);
+        }
+        tables
     }
 
     fn new(matches: &ArgMatches) -> OpResult<Self>
@@ -54,13 +553,38 @@ impl Callback for CsvDump {
         Self: Sized,
     {
         let dump_folder = &PathBuf::from(matches.get_one::<String>("dump-folder").unwrap());
-        let cap = 4000000;
+        let rotation = Rotation::from_matches(matches);
+        let compression = Compression::from_matches(matches)?;
+        let output = match matches.get_one::<String>("format").unwrap().as_str() {
+            "jsonl" => Output::Jsonl(RotatingWriter::with_extension(
+                dump_folder,
+                "transactions",
+                "jsonl",
+                None,
+                rotation,
+                compression,
+            )?),
+            _ if matches.get_flag("pg-copy") => Output::Csv(Box::new(CsvWriters::new(
+                dump_folder,
+                RowFormat::PgCopy,
+                rotation,
+                compression,
+            )?)),
+            _ => Output::Csv(Box::new(CsvWriters::new(
+                dump_folder,
+                RowFormat::Csv,
+                rotation,
+                compression,
+            )?)),
+        };
         let cb = CsvDump {
             dump_folder: PathBuf::from(dump_folder),
-            block_writer: CsvDump::create_writer(cap, dump_folder.join("blocks.csv.tmp"))?,
-            tx_writer: CsvDump::create_writer(cap, dump_folder.join("transactions.csv.tmp"))?,
-            txin_writer: CsvDump::create_writer(cap, dump_folder.join("tx_in.csv.tmp"))?,
-            txout_writer: CsvDump::create_writer(cap, dump_folder.join("tx_out.csv.tmp"))?,
+            output,
+            columns: OptionalColumns::from_matches(matches),
+            hash_byte_order: HashByteOrder::from_matches(matches)?,
+            unspents: matches
+                .get_flag("resolve-inputs")
+                .then(|| HashMap::with_capacity(10000000)),
             start_height: 0,
             tx_count: 0,
             in_count: 0,
@@ -75,46 +599,77 @@ impl Callback for CsvDump {
         Ok(())
     }
 
-    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
-        // serialize block
-        self.block_writer
-            .write_all(block.as_csv(block_height).as_bytes())?;
-
-        // serialize transaction
-        let block_hash = format!("{}", &block.header.hash);
-        for tx in &block.txs {
-            self.tx_writer
-                .write_all(tx.as_csv(&block_hash).as_bytes())?;
-            let txid_str = format!("{}", &tx.hash);
-
-            // serialize inputs
-            for input in &tx.value.inputs {
-                self.txin_writer
-                    .write_all(input.as_csv(&txid_str).as_bytes())?;
-            }
-            self.in_count += tx.value.in_count.value;
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<BlockFlow> {
+        for writer in self.output.writers() {
+            writer.start_block(block_height)?;
+        }
+        self.in_count += block
+            .txs
+            .iter()
+            .map(|tx| tx.value.in_count.value)
+            .sum::<u64>();
+        self.out_count += block
+            .txs
+            .iter()
+            .map(|tx| tx.value.out_count.value)
+            .sum::<u64>();
+        self.tx_count += block.tx_count.value;
 
-            // serialize outputs
-            for (i, output) in tx.value.outputs.iter().enumerate() {
-                self.txout_writer
-                    .write_all(output.as_csv(&txid_str, i as u32).as_bytes())?;
+        let spent = self.resolve_inputs(block);
+        match &mut self.output {
+            Output::Csv(csv) => csv.write_block(
+                block,
+                block_height,
+                self.columns,
+                spent.as_deref(),
+                self.hash_byte_order,
+            )?,
+            Output::Jsonl(tx_writer) => {
+                for (t, tx) in block.txs.iter().enumerate() {
+                    let mut value = json::tx_json(tx, Some(block_height), self.hash_byte_order);
+                    if let Some(spent) = &spent {
+                        for (i, spent) in spent[t].iter().enumerate() {
+                            value["inputs"][i]["address"] = spent.address.clone().into();
+                            value["inputs"][i]["value"] = spent.value.into();
+                        }
+                    }
+                    if self.columns.wtxid {
+                        value["wtxid"] = self.hash_byte_order.hex(&tx.value.wtxid()).into();
+                    }
+                    if self.columns.rbf {
+                        value["rbf"] = tx.value.is_rbf_signaling().into();
+                    }
+                    for (i, output) in tx.value.outputs.iter().enumerate() {
+                        if let Some((address, keys)) = multisig_address(&output.script.pattern) {
+                            value["outputs"][i]["address"] = address.into();
+                            if self.columns.multisig_keys {
+                                value["outputs"][i]["multisig_keys"] = keys.into();
+                            }
+                        }
+                    }
+                    let mut row = value.to_string();
+                    row.push('\n');
+                    tx_writer.write_row(&row)?;
+                }
             }
-            self.out_count += tx.value.out_count.value;
         }
-        self.tx_count += block.tx_count.value;
-        Ok(())
+        Ok(BlockFlow::Continue)
+    }
+
+    fn tracks_utxos(&self) -> bool {
+        self.unspents.is_some()
     }
 
     fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
-        // Keep in sync with c'tor
-        for f in ["blocks", "transactions", "tx_in", "tx_out"] {
-            // Rename temp files
-            fs::rename(
-                self.dump_folder.as_path().join(format!("{}.csv.tmp", f)),
-                self.dump_folder
-                    .as_path()
-                    .join(format!("{}-{}-{}.csv", f, self.start_height, block_height)),
-            )?;
+        let mut paths = Vec::new();
+        for writer in self.output.writers() {
+            paths.push(writer.finish(self.start_height, block_height)?);
+        }
+        if let Output::Csv(csv) = &self.output {
+            if csv.format == RowFormat::PgCopy {
+                let schema = pg_schema(&self.pg_tables(), &paths, csv.compression)?;
+                fs::write(self.dump_folder.join("schema.sql"), schema)?;
+            }
         }
 
         info!(target: "callback", "Done.\nDumped blocks from height {} to {}:\n\
@@ -127,65 +682,574 @@ impl Callback for CsvDump {
 }
 
 impl Block {
-    fn as_csv(&self, block_height: u64) -> String {
+    fn as_row(&self, block_height: u64, order: HashByteOrder) -> Vec<Option<String>> {
         // (@hash, height, version, blocksize, @hashPrev, @hashMerkleRoot, nTime, nBits, nNonce)
-        format!(
-            "{};{};{};{};{};{};{};{};{}\n",
-            &self.header.hash,
-            &block_height,
-            &self.header.value.version,
-            &self.size,
-            &self.header.value.prev_hash,
-            &self.header.value.merkle_root,
-            &self.header.value.timestamp,
-            &self.header.value.bits,
-            &self.header.value.nonce
-        )
+        vec![
+            Some(order.hex(&self.header.hash)),
+            Some(block_height.to_string()),
+            Some(self.header.value.version.to_string()),
+            Some(self.size.to_string()),
+            Some(order.hex(&self.header.value.prev_hash)),
+            Some(order.hex(&self.header.value.merkle_root)),
+            Some(self.header.value.timestamp.to_string()),
+            Some(self.header.value.bits.to_string()),
+            Some(self.header.value.nonce.to_string()),
+        ]
     }
 }
 
 impl Hashed<EvaluatedTx> {
-    fn as_csv(&self, block_hash: &str) -> String {
-        // (@txid, @hashBlock, version, lockTime)
-        format!(
-            "{};{};{};{}\n",
-            &self.hash, &block_hash, &self.value.version, &self.value.locktime
-        )
+    fn as_row(
+        &self,
+        txid: &str,
+        block_hash: &str,
+        columns: OptionalColumns,
+        order: HashByteOrder,
+    ) -> Vec<Option<String>> {
+        // (@txid, @hashBlock, version, lockTime[, @wtxid][, rbf])
+        let mut row = vec![
+            Some(String::from(txid)),
+            Some(String::from(block_hash)),
+            Some(self.value.version.to_string()),
+            Some(self.value.locktime.to_string()),
+        ];
+        if columns.wtxid {
+            row.push(Some(order.hex(&self.value.wtxid())));
+        }
+        if columns.rbf {
+            row.push(Some(u8::from(self.value.is_rbf_signaling()).to_string()));
+        }
+        row
     }
 }
 
 impl TxInput {
-    fn as_csv(&self, txid: &str) -> String {
+    fn as_row(&self, txid: &str, order: HashByteOrder) -> Vec<Option<String>> {
         // (@txid, @hashPrevOut, indexPrevOut, scriptSig, sequence)
-        format!(
-            "{};{};{};{};{}\n",
-            &txid,
-            &self.outpoint.txid,
-            &self.outpoint.index,
-            &utils::arr_to_hex(&self.script_sig),
-            &self.seq_no
-        )
+        vec![
+            Some(String::from(txid)),
+            Some(order.hex(&self.outpoint.txid)),
+            Some(self.outpoint.index.to_string()),
+            Some(utils::arr_to_hex(&self.script_sig)),
+            Some(self.seq_no.to_string()),
+        ]
+    }
+}
+
+/// Returns a synthetic address like `multisig:2of3` and the hash160 of each key for bare multisig outputs
+fn multisig_address(pattern: &ScriptPattern) -> Option<(String, Vec<String>)> {
+    match pattern {
+        ScriptPattern::Pay2MultiSig {
+            required,
+            total,
+            pubkeys,
+        } => {
+            let keys = pubkeys
+                .iter()
+                .map(|key| utils::arr_to_hex(hash160::Hash::hash(key).as_byte_array()))
+                .collect();
+            Some((format!("multisig:{}of{}", required, total), keys))
+        }
+        _ => None,
     }
 }
 
 impl EvaluatedTxOut {
-    fn as_csv(&self, txid: &str, index: u32) -> String {
-        let address = match self.script.address.clone() {
-            Some(address) => address,
-            None => {
+    fn as_row(&self, txid: &str, index: u32, with_multisig_keys: bool) -> Vec<Option<String>> {
+        let multisig = multisig_address(&self.script.pattern);
+        let address = match (self.script.address.clone(), &multisig) {
+            (Some(address), _) => Some(address),
+            (None, Some((address, _))) => Some(address.clone()),
+            (None, None) => {
                 debug!(target: "csvdump", "Unable to evaluate address for utxo in txid: {} ({})", txid, self.script.pattern);
-                String::new()
+                None
             }
         };
 
-        // (@txid, indexOut, value, @scriptPubKey, address)
-        format!(
-            "{};{};{};{};{}\n",
-            &txid,
-            &index,
-            &self.out.value,
-            &utils::arr_to_hex(&self.out.script_pubkey),
-            &address
+        // (@txid, indexOut, value, @scriptPubKey, address[, multisigKeys])
+        let mut row = vec![
+            Some(String::from(txid)),
+            Some(index.to_string()),
+            Some(self.out.value.to_string()),
+            Some(utils::arr_to_hex(&self.out.script_pubkey)),
+            address,
+        ];
+        if with_multisig_keys {
+            row.push(multisig.map(|(_, keys)| keys.join(",")));
+        }
+        row
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::header::BlockHeader;
+    use crate::blockchain::proto::script::AddressFormat;
+    use crate::blockchain::proto::tx::{RawTx, TxOutpoint, TxOutput};
+    use crate::blockchain::proto::varuint::VarUint;
+    use bitcoin::hashes::{sha256d, Hash};
+    use serde_json::Value;
+    use std::fs;
+
+    // 1-of-2 bare multisig with a compressed and an uncompressed key
+    const MULTISIG_KEYS: [&str; 2] = [
+        "022df8750480ad5b26950b25c7ba79d3e37d75f640f8e5d9bcd5b150a0f85014da",
+        "04678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5f",
+    ];
+
+    fn block() -> Block {
+        let script_pubkey = utils::hex_to_vec("00141d7cd6c75c2e86f4cbf98eaed221b30bd9a0b928");
+        let multisig = utils::hex_to_vec(&format!(
+            "5121{}41{}52ae",
+            MULTISIG_KEYS[0], MULTISIG_KEYS[1]
+        ));
+        let tx = RawTx {
+            version: 2,
+            in_count: VarUint::from(1u8),
+            inputs: vec![TxInput {
+                outpoint: TxOutpoint::new(sha256d::Hash::all_zeros(), 0xFFFFFFFF),
+                script_len: VarUint::from(0u8),
+                script_sig: vec![],
+                seq_no: 0xFFFFFFFF,
+                // Witness reserved value of the coinbase
+                witness: vec![vec![0; 32]],
+            }],
+            out_count: VarUint::from(3u8),
+            outputs: vec![
+                TxOutput {
+                    value: 5000,
+                    script_len: VarUint::from(script_pubkey.len() as u8),
+                    script_pubkey,
+                },
+                TxOutput {
+                    value: 0,
+                    script_len: VarUint::from(1u8),
+                    script_pubkey: vec![0x6a],
+                },
+                TxOutput {
+                    value: 1000,
+                    script_len: VarUint::from(multisig.len() as u8),
+                    script_pubkey: multisig,
+                },
+            ],
+            locktime: 7,
+            address_format: AddressFormat::default(),
+            witness_size: 0,
+        };
+        let header = BlockHeader {
+            version: 1,
+            prev_hash: sha256d::Hash::all_zeros(),
+            merkle_root: sha256d::Hash::all_zeros(),
+            timestamp: 0,
+            bits: 0,
+            nonce: 0,
+        };
+        Block::new(0, header, None, VarUint::from(1u8), vec![tx])
+    }
+
+    fn run(args: &[&str]) -> tempfile::TempDir {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut argv = vec!["csvdump", tmp_dir.path().to_str().unwrap()];
+        argv.extend_from_slice(args);
+        let matches = CsvDump::build_subcommand().get_matches_from(argv);
+        let mut callback = CsvDump::new(&matches).unwrap();
+        callback.on_start(5).unwrap();
+        callback.on_block(&block(), 5).unwrap();
+        callback.on_block(&block(), 6).unwrap();
+        callback.on_complete(6).unwrap();
+        tmp_dir
+    }
+
+    fn file_names(dir: &tempfile::TempDir) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_csv_format() {
+        let tmp_dir = run(&[]);
+        assert_eq!(
+            file_names(&tmp_dir),
+            vec![
+                "blocks-5-6.csv",
+                "transactions-5-6.csv",
+                "tx_in-5-6.csv",
+                "tx_out-5-6.csv"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_jsonl_format() {
+        let tmp_dir = run(&["--format", "jsonl"]);
+        assert_eq!(file_names(&tmp_dir), vec!["transactions-5-6.jsonl"]);
+
+        let jsonl = fs::read_to_string(tmp_dir.path().join("transactions-5-6.jsonl")).unwrap();
+        let rows: Vec<Value> = jsonl
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(rows.len(), 2);
+        let txid = HashByteOrder::Rpc.hex(&block().txs[0].hash);
+        assert_eq!(rows[0]["txid"], txid);
+        assert_eq!(rows[0]["height"], 5);
+        assert_eq!(rows[1]["height"], 6);
+        assert_eq!(rows[0]["version"], 2);
+        assert_eq!(rows[0]["locktime"], 7);
+        assert_eq!(rows[0]["inputs"][0]["index"], 0xFFFFFFFFu32);
+        assert_eq!(
+            rows[0]["outputs"][0]["address"],
+            "bc1qr47dd36u96r0fjle36hdygdnp0v6pwfg2lppam"
+        );
+        assert_eq!(rows[0]["outputs"][0]["value"], 5000);
+        assert!(rows[0]["outputs"][1]["address"].is_null());
+    }
+
+    #[test]
+    fn test_with_wtxid() {
+        let tx = &block().txs[0];
+        let txid = HashByteOrder::Rpc.hex(&tx.hash);
+        let wtxid = HashByteOrder::Rpc.hex(&tx.value.wtxid());
+        assert_ne!(txid, wtxid);
+
+        let tmp_dir = run(&["--with-wtxid"]);
+        let csv = fs::read_to_string(tmp_dir.path().join("transactions-5-6.csv")).unwrap();
+        let row: Vec<&str> = csv.lines().next().unwrap().split(';').collect();
+        assert_eq!(row.len(), 5);
+        assert_eq!(row[0], txid);
+        assert_eq!(row[4], wtxid);
+        // Without the flag the column is omitted
+        let tmp_dir = run(&[]);
+        let csv = fs::read_to_string(tmp_dir.path().join("transactions-5-6.csv")).unwrap();
+        assert_eq!(csv.lines().next().unwrap().split(';').count(), 4);
+
+        let tmp_dir = run(&["--format", "jsonl", "--with-wtxid"]);
+        let jsonl = fs::read_to_string(tmp_dir.path().join("transactions-5-6.jsonl")).unwrap();
+        let row: Value = serde_json::from_str(jsonl.lines().next().unwrap()).unwrap();
+        assert_eq!(row["wtxid"], wtxid);
+    }
+
+    #[test]
+    fn test_with_rbf() {
+        let tmp_dir = run(&["--with-wtxid", "--with-rbf"]);
+        let csv = fs::read_to_string(tmp_dir.path().join("transactions-5-6.csv")).unwrap();
+        let row: Vec<&str> = csv.lines().next().unwrap().split(';').collect();
+        assert_eq!(row.len(), 6);
+        // The coinbase input has a final sequence number
+        assert_eq!(row[5], "0");
+
+        let tmp_dir = run(&["--format", "jsonl", "--with-rbf"]);
+        let jsonl = fs::read_to_string(tmp_dir.path().join("transactions-5-6.jsonl")).unwrap();
+        let row: Value = serde_json::from_str(jsonl.lines().next().unwrap()).unwrap();
+        assert_eq!(row["rbf"], false);
+    }
+
+    #[test]
+    fn test_with_multisig_keys() {
+        let hashes: Vec<String> = MULTISIG_KEYS
+            .iter()
+            .map(|key| {
+                utils::arr_to_hex(hash160::Hash::hash(&utils::hex_to_vec(key)).as_byte_array())
+            })
+            .collect();
+
+        let tmp_dir = run(&["--with-multisig-keys"]);
+        let csv = fs::read_to_string(tmp_dir.path().join("tx_out-5-6.csv")).unwrap();
+        let rows: Vec<Vec<&str>> = csv.lines().map(|l| l.split(';').collect()).collect();
+        assert_eq!(rows[0].len(), 6);
+        assert_eq!(rows[0][5], "");
+        assert_eq!(rows[2][4], "multisig:1of2");
+        assert_eq!(rows[2][5], hashes.join(","));
+        // Without the flag only the synthetic address is written
+        let tmp_dir = run(&[]);
+        let csv = fs::read_to_string(tmp_dir.path().join("tx_out-5-6.csv")).unwrap();
+        let row: Vec<&str> = csv.lines().nth(2).unwrap().split(';').collect();
+        assert_eq!(row, vec![row[0], "2", "1000", row[3], "multisig:1of2"]);
+
+        let tmp_dir = run(&["--format", "jsonl", "--with-multisig-keys"]);
+        let jsonl = fs::read_to_string(tmp_dir.path().join("transactions-5-6.jsonl")).unwrap();
+        let row: Value = serde_json::from_str(jsonl.lines().next().unwrap()).unwrap();
+        assert_eq!(row["outputs"][2]["address"], "multisig:1of2");
+        assert_eq!(row["outputs"][2]["multisig_keys"], Value::from(hashes));
+        assert!(row["outputs"][0].get("multisig_keys").is_none());
+    }
+
+    #[test]
+    fn test_schema() {
+        // All optional columns and fields are described
+        let args = [
+            "--with-wtxid",
+            "--with-rbf",
+            "--with-multisig-keys",
+            "--resolve-inputs",
+        ];
+        let mut argv = vec!["csvdump", "dump"];
+        argv.extend(args);
+        let tables = CsvDump::schema(&CsvDump::build_subcommand().get_matches_from(argv));
+
+        let tmp_dir = run(&args);
+        let csv_tables: Vec<&Table> = tables
+            .iter()
+            .filter(|table| table.format == FileFormat::Csv)
+            .collect();
+        assert_eq!(csv_tables.len(), 4);
+        for table in csv_tables {
+            let path = tmp_dir.path().join(format!("{}-5-6.csv", table.name));
+            table.assert_rows(&fs::read_to_string(path).unwrap());
+        }
+
+        let mut argv = args.to_vec();
+        argv.extend(["--format", "jsonl"]);
+        let tmp_dir = run(&argv);
+        let jsonl = fs::read_to_string(tmp_dir.path().join("transactions-5-6.jsonl")).unwrap();
+        let table = tables
+            .iter()
+            .find(|table| table.format == FileFormat::Jsonl && table.name == "transactions")
+            .unwrap();
+        for line in jsonl.lines() {
+            let row: Value = serde_json::from_str(line).unwrap();
+            assert_eq!(row.as_object().unwrap().len(), table.columns.len());
+            table.assert_json(&row, &tables);
+        }
+
+        // The tab separated files are described by schema.sql instead
+        let matches =
+            CsvDump::build_subcommand().get_matches_from(["csvdump", "dump", "--pg-copy"]);
+        assert!(CsvDump::schema(&matches)
+            .iter()
+            .all(|table| table.format == FileFormat::Jsonl));
+    }
+
+    #[test]
+    fn test_pg_escape() {
+        assert_eq!(pg_escape("76a914"), "76a914");
+        assert_eq!(
+            pg_escape("a\tb\nc\rd\\e\x01f\x7f"),
+            "a\\tb\\nc\\rd\\\\e\\x01f\\x7f"
+        );
+        // An escaped backslash can't be mistaken for a null
+        assert_eq!(pg_escape("\\N"), "\\\\N");
+    }
+
+    #[test]
+    fn test_pg_copy_row() {
+        let row = |fields: &[Option<&str>]| {
+            let fields: Vec<Option<String>> =
+                fields.iter().map(|field| field.map(String::from)).collect();
+            RowFormat::PgCopy.row(&fields)
+        };
+        // An empty address stays an empty string, a missing one is null
+        assert_eq!(row(&[Some("txid"), Some("")]), "txid\t\n");
+        assert_eq!(row(&[Some("txid"), None]), "txid\t\\N\n");
+        assert_eq!(row(&[Some("6a\t01"), Some("x")]), "6a\\t01\tx\n");
+        assert_eq!(
+            RowFormat::Csv.row(&[Some(String::from("txid")), None]),
+            "txid;\n"
+        );
+    }
+
+    #[test]
+    fn test_pg_copy() {
+        let tmp_dir = run(&["--pg-copy", "--with-multisig-keys"]);
+        assert_eq!(
+            file_names(&tmp_dir),
+            vec![
+                "blocks-5-6.tsv",
+                "schema.sql",
+                "transactions-5-6.tsv",
+                "tx_in-5-6.tsv",
+                "tx_out-5-6.tsv"
+            ]
+        );
+        let tsv = fs::read_to_string(tmp_dir.path().join("tx_out-5-6.tsv")).unwrap();
+        let rows: Vec<Vec<&str>> = tsv.lines().map(|l| l.split('\t').collect()).collect();
+        assert_eq!(rows.len(), 6);
+        assert_eq!(rows[0][4], "bc1qr47dd36u96r0fjle36hdygdnp0v6pwfg2lppam");
+        assert_eq!(rows[0][5], "\\N");
+        // OP_RETURN without address
+        assert_eq!(rows[1][1..], ["1", "0", "6a", "\\N", "\\N"]);
+
+        let schema = fs::read_to_string(tmp_dir.path().join("schema.sql")).unwrap();
+        let dump_folder = fs::canonicalize(tmp_dir.path()).unwrap();
+        assert!(schema.contains(
+            "CREATE TABLE IF NOT EXISTS tx_out (\n  txid TEXT NOT NULL,\n  vout BIGINT NOT NULL,\n  \
+             value BIGINT NOT NULL,\n  script_pubkey TEXT NOT NULL,\n  address TEXT,\n  multisig_keys TEXT\n);\n"
+        ));
+        for name in ["blocks", "transactions", "tx_in", "tx_out"] {
+            assert!(schema.contains(&format!(
+                "COPY {} FROM '{}' WITH (FORMAT text);\n",
+                name,
+                dump_folder.join(format!("{}-5-6.tsv", name)).display()
+            )));
+        }
+        // The file formats exclude each other
+        assert!(CsvDump::build_subcommand()
+            .try_get_matches_from(["csvdump", "dump", "--pg-copy", "--format", "jsonl"])
+            .is_err());
+    }
+
+    /// Returns a block with a transaction spending the given outpoints
+    fn spend_block(outpoints: Vec<TxOutpoint>) -> Block {
+        common::test_block(outpoints, &[("6a", 0)])
+    }
+
+    #[test]
+    fn test_resolve_inputs() {
+        let first = block();
+        let txid = first.txs[0].hash;
+        let unknown = TxOutpoint::new(sha256d::Hash::hash(b"unknown"), 0);
+        let nested = TxOutpoint::new(sha256d::Hash::hash(b"unknown"), 1);
+        let mut second = spend_block(vec![
+            TxOutpoint::new(txid, 0),
+            TxOutpoint::new(txid, 1),
+            TxOutpoint::new(txid, 2),
+            unknown,
+            nested,
+        ]);
+        second.txs[0].value.inputs[4].script_sig =
+            utils::hex_to_vec(common::P2SH_P2WPKH_SCRIPT_SIG);
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let matches = CsvDump::build_subcommand().get_matches_from([
+            "csvdump",
+            tmp_dir.path().to_str().unwrap(),
+            "--resolve-inputs",
+        ]);
+        let mut callback = CsvDump::new(&matches).unwrap();
+        assert!(callback.tracks_utxos());
+        callback.on_start(1).unwrap();
+        callback.on_block(&first, 1).unwrap();
+        callback.on_block(&second, 2).unwrap();
+        callback.on_complete(2).unwrap();
+
+        let csv = fs::read_to_string(tmp_dir.path().join("tx_in-1-2.csv")).unwrap();
+        let rows: Vec<Vec<&str>> = csv.lines().map(|l| l.split(';').collect()).collect();
+        assert_eq!(rows.len(), 6);
+        // The coinbase input and the input spending an unknown output stay unresolved
+        assert_eq!(rows[0][5..], ["", ""]);
+        assert_eq!(rows[1][1], HashByteOrder::Rpc.hex(&txid));
+        assert_eq!(
+            rows[1][5..],
+            ["bc1qr47dd36u96r0fjle36hdygdnp0v6pwfg2lppam", "5000"]
+        );
+        // Outputs without address are resolved to their value
+        assert_eq!(rows[2][5..], ["", "0"]);
+        assert_eq!(rows[3][5..], ["multisig:1of2", "1000"]);
+        assert_eq!(rows[4][5..], ["", ""]);
+        // Nested SegWit spends reveal the address, but not the value
+        assert_eq!(rows[5][5..], ["3Eq1QAi9gU2eJJNdPg2CpZ1wqZpPFSFpzv", ""]);
+        // The spent outputs are removed, the OP_RETURN output of the spending tx is kept
+        assert_eq!(callback.unspents.as_ref().unwrap().len(), 1);
+
+        // Without the flag the UTXO set isn't kept
+        assert!(!CsvDump::new(
+            &CsvDump::build_subcommand()
+                .get_matches_from(["csvdump", tmp_dir.path().to_str().unwrap(),])
         )
+        .unwrap()
+        .tracks_utxos());
+    }
+
+    #[test]
+    fn test_resolve_inputs_jsonl() {
+        let first = block();
+        let second = spend_block(vec![
+            TxOutpoint::new(first.txs[0].hash, 0),
+            TxOutpoint::new(first.txs[0].hash, 1),
+        ]);
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let matches = CsvDump::build_subcommand().get_matches_from([
+            "csvdump",
+            tmp_dir.path().to_str().unwrap(),
+            "--format",
+            "jsonl",
+            "--resolve-inputs",
+        ]);
+        let mut callback = CsvDump::new(&matches).unwrap();
+        callback.on_start(1).unwrap();
+        callback.on_block(&first, 1).unwrap();
+        callback.on_block(&second, 2).unwrap();
+        callback.on_complete(2).unwrap();
+
+        let jsonl = fs::read_to_string(tmp_dir.path().join("transactions-1-2.jsonl")).unwrap();
+        let rows: Vec<Value> = jsonl
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(rows[0]["inputs"][0]["address"], Value::Null);
+        assert_eq!(
+            rows[1]["inputs"][0]["address"],
+            "bc1qr47dd36u96r0fjle36hdygdnp0v6pwfg2lppam"
+        );
+        assert_eq!(rows[1]["inputs"][0]["value"], 5000);
+        assert_eq!(rows[1]["inputs"][1]["address"], Value::Null);
+        assert_eq!(rows[1]["inputs"][1]["value"], 0);
+    }
+
+    #[test]
+    fn test_pg_schema_quoting() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("it's $HOME.tsv.gz");
+        fs::write(&path, "").unwrap();
+        let dir = fs::canonicalize(tmp_dir.path()).unwrap();
+        let tables = [("blocks", vec![("hash", "TEXT")])];
+        let paths = [vec![path]];
+
+        let schema = pg_schema(&tables, &paths, Compression::None).unwrap();
+        assert!(schema.contains(&format!(
+            "COPY blocks FROM '{}/it''s $HOME.tsv.gz' WITH (FORMAT text);\n",
+            dir.display()
+        )));
+        // The shell command `gzip -dc '<dir>/it'\''s $HOME.tsv.gz'` as SQL string literal
+        let schema = pg_schema(&tables, &paths, Compression::Gzip).unwrap();
+        assert!(schema.contains(&format!(
+            "COPY blocks FROM PROGRAM 'gzip -dc ''{}/it''\\''''s $HOME.tsv.gz''' WITH (FORMAT text);\n",
+            dir.display()
+        )));
+    }
+
+    #[test]
+    #[cfg(feature = "gzip")]
+    fn test_compress() {
+        let tmp_dir = run(&[]);
+        let csv = fs::read_to_string(tmp_dir.path().join("tx_out-5-6.csv")).unwrap();
+
+        let tmp_dir = run(&["--compress", "gzip"]);
+        assert_eq!(
+            file_names(&tmp_dir),
+            vec![
+                "blocks-5-6.csv.gz",
+                "transactions-5-6.csv.gz",
+                "tx_in-5-6.csv.gz",
+                "tx_out-5-6.csv.gz"
+            ]
+        );
+        assert_eq!(
+            crate::callbacks::compress::read_to_string(&tmp_dir.path().join("tx_out-5-6.csv.gz")),
+            csv
+        );
+
+        // Postgres reads the compressed files through gzip
+        let tmp_dir = run(&["--pg-copy", "--compress", "gzip"]);
+        let schema = fs::read_to_string(tmp_dir.path().join("schema.sql")).unwrap();
+        let path = fs::canonicalize(tmp_dir.path().join("blocks-5-6.tsv.gz")).unwrap();
+        assert!(schema.contains(&format!(
+            "COPY blocks FROM PROGRAM 'gzip -dc ''{}''' WITH (FORMAT text);\n",
+            path.display()
+        )));
+    }
+
+    #[test]
+    fn test_jsonl_rotation() {
+        let tmp_dir = run(&["--format", "jsonl", "--rotate-every", "1"]);
+        assert_eq!(
+            file_names(&tmp_dir),
+            vec!["transactions.00001.jsonl", "transactions.00002.jsonl"]
+        );
     }
 }
@@ -0,0 +1,219 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::callbacks::compress::{Compression, OutputWriter};
+use crate::callbacks::rotate::{ColumnType, Table};
+use crate::callbacks::{BlockFlow, Callback};
+use crate::errors::OpResult;
+
+const DIFFICULTY: Table = Table::csv(
+    "difficulty",
+    &[
+        ("height", ColumnType::Integer),
+        ("timestamp", ColumnType::Integer),
+        ("difficulty", ColumnType::Float),
+        ("hashrate_estimate", ColumnType::Float),
+    ],
+);
+
+/// Dumps the difficulty of each block and estimates the network hashrate over a window of blocks.
+/// Like `getnetworkhashps` in Bitcoin Core, the estimate is the work of the last `--window` blocks
+/// divided by the time between the earliest and latest timestamp in the window (including the block before).
+/// The work is summed per block (difficulty * 2^32 hashes), so windows spanning a difficulty adjustment
+/// weight each block with its own target.
+pub struct Difficulty {
+    dump_folder: PathBuf,
+    writer: OutputWriter,
+    window: usize,
+
+    // (timestamp, expected hashes) of the last window + 1 blocks
+    recent: VecDeque<(u32, f64)>,
+
+    start_height: u64,
+    max_difficulty: f64,
+}
+
+impl Difficulty {
+    /// Returns the estimated hashes per second over the current window.
+    /// None if there are less than two blocks or the timestamps don't span any time.
+    fn hashrate(&self) -> Option<f64> {
+        if self.recent.len() < 2 {
+            return None;
+        }
+        let min_time = self.recent.iter().map(|(t, _)| *t).min().unwrap();
+        let max_time = self.recent.iter().map(|(t, _)| *t).max().unwrap();
+        if max_time == min_time {
+            return None;
+        }
+        // The first block only marks the start of the window
+        let work: f64 = self.recent.iter().skip(1).map(|(_, w)| *w).sum();
+        Some(work / (max_time - min_time) as f64)
+    }
+}
+
+impl Callback for Difficulty {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        let cmd = Command::new("difficulty")
+            .about("Dumps the difficulty and estimated network hashrate of each block to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            )
+            .arg(
+                Arg::new("window")
+                    .long("window")
+                    .value_name("NUM")
+                    .value_parser(clap::value_parser!(u64).range(1..))
+                    .default_value("144")
+                    .help("Number of blocks to estimate the hashrate over"),
+            );
+        Compression::add_args(cmd)
+    }
+
+    fn schema(_: &ArgMatches) -> Vec<Table>
+    where
+        Self: Sized,
+    {
+        vec![DIFFICULTY]
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let dump_folder = &PathBuf::from(matches.get_one::<String>("dump-folder").unwrap());
+        let window = *matches.get_one::<u64>("window").unwrap() as usize;
+        let cb = Difficulty {
+            dump_folder: PathBuf::from(dump_folder),
+            writer: Compression::from_matches(matches)?
+                .create(&dump_folder.join("difficulty.csv.tmp"))?,
+            window,
+            recent: VecDeque::with_capacity(window + 1),
+            start_height: 0,
+            max_difficulty: 0.0,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing difficulty with dump folder: {} ...", &self.dump_folder.display());
+        self.writer.write_all(DIFFICULTY.header().as_bytes())?;
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<BlockFlow> {
+        let header = &block.header.value;
+        let difficulty = header.difficulty();
+        self.max_difficulty = self.max_difficulty.max(difficulty);
+
+        if self.recent.len() > self.window {
+            self.recent.pop_front();
+        }
+        self.recent
+            .push_back((header.timestamp, difficulty * 4294967296.0));
+        let hashrate = match self.hashrate() {
+            Some(hashrate) => format!("{:.0}", hashrate),
+            None => String::new(),
+        };
+        self.writer.write_all(
+            format!(
+                "{};{};{:.8};{}\n",
+                block_height, header.timestamp, difficulty, hashrate
+            )
+            .as_bytes(),
+        )?;
+        Ok(BlockFlow::Continue)
+    }
+
+    fn needs_addresses(&self) -> bool {
+        false
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.writer.finish()?;
+        fs::rename(
+            self.dump_folder.as_path().join("difficulty.csv.tmp"),
+            self.dump_folder
+                .as_path()
+                .join(self.writer.file_name(format!(
+                    "difficulty-{}-{}.csv",
+                    self.start_height, block_height
+                ))),
+        )?;
+        info!(target: "callback", "Done.\nDumped difficulty from height {} to {}:\n\
+                                   \t-> max difficulty: {:.2}",
+             self.start_height, block_height, self.max_difficulty);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::header::BlockHeader;
+    use crate::blockchain::proto::varuint::VarUint;
+    use bitcoin::hashes::{sha256d, Hash};
+
+    fn block(timestamp: u32, bits: u32) -> Block {
+        let header = BlockHeader {
+            version: 1,
+            prev_hash: sha256d::Hash::all_zeros(),
+            merkle_root: sha256d::Hash::all_zeros(),
+            timestamp,
+            bits,
+            nonce: 0,
+        };
+        Block::new(0, header, None, VarUint::from(0u8), vec![])
+    }
+
+    #[test]
+    fn test_difficulty() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let matches = Difficulty::build_subcommand().get_matches_from([
+            "difficulty",
+            tmp_dir.path().to_str().unwrap(),
+            "--window",
+            "2",
+        ]);
+        let mut callback = Difficulty::new(&matches).unwrap();
+        callback.on_start(0).unwrap();
+        // Difficulty doubles at height 2 (0x1c7fff80 is half the target of 0x1d00ffff)
+        let blocks = [
+            block(1000, 0x1d00ffff),
+            block(1600, 0x1d00ffff),
+            block(2200, 0x1c7fff80),
+            block(2100, 0x1c7fff80),
+            block(3400, 0x1c7fff80),
+        ];
+        for (height, block) in blocks.iter().enumerate() {
+            callback.on_block(block, height as u64).unwrap();
+        }
+        callback.on_complete(4).unwrap();
+
+        // 2^32 hashes per difficulty, e.g. height 2: (1 + 2) * 2^32 / 1200s
+        let csv = fs::read_to_string(tmp_dir.path().join("difficulty-0-4.csv")).unwrap();
+        DIFFICULTY.assert_csv(&csv);
+        assert_eq!(
+            csv,
+            "height;timestamp;difficulty;hashrate_estimate\n\
+             0;1000;1.00000000;\n\
+             1;1600;1.00000000;7158279\n\
+             2;2200;2.00000000;10737418\n\
+             3;2100;2.00000000;28633115\n\
+             4;3400;2.00000000;13215284\n"
+        );
+    }
+}
@@ -0,0 +1,285 @@
+use std::convert::TryInto;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+use bitcoin::hashes::{sha256, Hash};
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::callbacks::compress::{Compression, OutputWriter};
+use crate::callbacks::rotate::{ColumnType, Table};
+use crate::callbacks::{BlockFlow, Callback};
+use crate::errors::OpResult;
+
+const DISTINCT_ADDRESSES: Table = Table::csv(
+    "distinctaddresses",
+    &[
+        ("period", ColumnType::Integer),
+        ("estimated_distinct_addresses", ColumnType::Integer),
+    ],
+);
+
+/// HyperLogLog cardinality estimator with 2^precision registers on top of sha256.
+/// The standard error is about 1.04 / sqrt(2^precision), small cardinalities are estimated with linear counting.
+struct HyperLogLog {
+    precision: u8,
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    fn new(precision: u8) -> Self {
+        Self {
+            precision,
+            registers: vec![0u8; 1 << precision],
+        }
+    }
+
+    fn insert(&mut self, item: &[u8]) {
+        let hash = sha256::Hash::hash(item);
+        let hash = u64::from_le_bytes(hash.as_byte_array()[0..8].try_into().unwrap());
+        let index = (hash >> (64 - self.precision)) as usize;
+        // Position of the first set bit in the remaining bits
+        let rank =
+            ((hash << self.precision) | (1 << (self.precision - 1))).leading_zeros() as u8 + 1;
+        if rank > self.registers[index] {
+            self.registers[index] = rank;
+        }
+    }
+
+    fn estimate(&self) -> f64 {
+        let m = self.registers.len() as f64;
+        let alpha = match self.registers.len() {
+            16 => 0.673,
+            32 => 0.697,
+            64 => 0.709,
+            _ => 0.7213 / (1.0 + 1.079 / m),
+        };
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|register| 2f64.powi(-(*register as i32)))
+            .sum();
+        let estimate = alpha * m * m / sum;
+        let zeros = self
+            .registers
+            .iter()
+            .filter(|register| **register == 0)
+            .count();
+        if estimate <= 2.5 * m && zeros > 0 {
+            m * (m / zeros as f64).ln()
+        } else {
+            estimate
+        }
+    }
+}
+
+/// Estimates the cumulative number of distinct output addresses at the end of each period of blocks
+/// with a fixed amount of memory, independent of the number of addresses.
+pub struct DistinctAddresses {
+    dump_folder: PathBuf,
+    writer: OutputWriter,
+    period: u64,
+    hll: HyperLogLog,
+
+    // First height of the current period
+    cur_period: u64,
+    start_height: u64,
+    output_count: u64,
+}
+
+impl DistinctAddresses {
+    fn write_period(&mut self) -> OpResult<()> {
+        self.writer
+            .write_all(format!("{};{:.0}\n", self.cur_period, self.hll.estimate()).as_bytes())?;
+        Ok(())
+    }
+}
+
+impl Callback for DistinctAddresses {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        let cmd = Command::new("distinctaddresses")
+            .about("Dumps an estimate of the cumulative number of distinct addresses per period to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            )
+            .arg(
+                Arg::new("period")
+                    .long("period")
+                    .value_name("NUM")
+                    .value_parser(clap::value_parser!(u64).range(1..))
+                    .default_value("2016")
+                    .help("Number of blocks per period"),
+            )
+            .arg(
+                Arg::new("hll-precision")
+                    .long("hll-precision")
+                    .value_name("BITS")
+                    .value_parser(clap::value_parser!(u8).range(4..=18))
+                    .default_value("14")
+                    .help("Uses 2^BITS registers, higher values are more accurate but use more memory"),
+            );
+        Compression::add_args(cmd)
+    }
+
+    fn schema(_: &ArgMatches) -> Vec<Table>
+    where
+        Self: Sized,
+    {
+        vec![DISTINCT_ADDRESSES]
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let dump_folder = &PathBuf::from(matches.get_one::<String>("dump-folder").unwrap());
+        let cb = DistinctAddresses {
+            dump_folder: PathBuf::from(dump_folder),
+            writer: Compression::from_matches(matches)?
+                .create(&dump_folder.join("distinctaddresses.csv.tmp"))?,
+            period: *matches.get_one::<u64>("period").unwrap(),
+            hll: HyperLogLog::new(*matches.get_one::<u8>("hll-precision").unwrap()),
+            cur_period: 0,
+            start_height: 0,
+            output_count: 0,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        self.cur_period = block_height - block_height % self.period;
+        info!(target: "callback", "Executing distinctaddresses with dump folder: {} ...", &self.dump_folder.display());
+        self.writer
+            .write_all(DISTINCT_ADDRESSES.header().as_bytes())?;
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<BlockFlow> {
+        let period = block_height - block_height % self.period;
+        if period != self.cur_period {
+            self.write_period()?;
+            self.cur_period = period;
+        }
+        for tx in &block.txs {
+            for output in &tx.value.outputs {
+                if let Some(address) = &output.script.address {
+                    self.hll.insert(address.as_bytes());
+                    self.output_count += 1;
+                }
+            }
+        }
+        Ok(BlockFlow::Continue)
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.write_period()?;
+        self.writer.finish()?;
+        fs::rename(
+            self.dump_folder.as_path().join("distinctaddresses.csv.tmp"),
+            self.dump_folder
+                .as_path()
+                .join(self.writer.file_name(format!(
+                    "distinctaddresses-{}-{}.csv",
+                    self.start_height, block_height
+                ))),
+        )?;
+        info!(target: "callback", "Done.\nEstimated distinct addresses from height {} to {}:\n\
+                                   \t-> outputs with address: {:12}\n\
+                                   \t-> distinct addresses:   {:12.0}",
+             self.start_height, block_height, self.output_count, self.hll.estimate());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::header::BlockHeader;
+    use crate::blockchain::proto::script::AddressFormat;
+    use crate::blockchain::proto::tx::{RawTx, TxOutput};
+    use crate::blockchain::proto::varuint::VarUint;
+    use crate::callbacks::common::test_script;
+    use crate::common::utils;
+    use bitcoin::hashes::sha256d;
+
+    fn block(scripts: &[u8]) -> Block {
+        let tx = RawTx {
+            version: 1,
+            in_count: VarUint::from(0u8),
+            inputs: vec![],
+            out_count: VarUint::from(scripts.len() as u8),
+            outputs: scripts
+                .iter()
+                .map(|n| TxOutput {
+                    value: 1,
+                    script_len: VarUint::from(25u8),
+                    script_pubkey: utils::hex_to_vec(&test_script(*n)),
+                })
+                .collect(),
+            locktime: 0,
+            address_format: AddressFormat::default(),
+            witness_size: 0,
+        };
+        let header = BlockHeader {
+            version: 1,
+            prev_hash: sha256d::Hash::all_zeros(),
+            merkle_root: sha256d::Hash::all_zeros(),
+            timestamp: 0,
+            bits: 0,
+            nonce: 0,
+        };
+        Block::new(0, header, None, VarUint::from(1u8), vec![tx])
+    }
+
+    #[test]
+    fn test_hyperloglog() {
+        for (precision, tolerance) in [(10, 0.1), (14, 0.03)] {
+            let mut hll = HyperLogLog::new(precision);
+            for i in 0..50000u32 {
+                hll.insert(&i.to_le_bytes());
+                // Duplicates don't change the estimate
+                hll.insert(&(i / 2).to_le_bytes());
+            }
+            let error = (hll.estimate() - 50000.0).abs() / 50000.0;
+            assert!(
+                error < tolerance,
+                "error {} at precision {}",
+                error,
+                precision
+            );
+        }
+    }
+
+    #[test]
+    fn test_distinct_addresses() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let matches = DistinctAddresses::build_subcommand().get_matches_from([
+            "distinctaddresses",
+            tmp_dir.path().to_str().unwrap(),
+            "--period",
+            "2",
+        ]);
+        let mut callback = DistinctAddresses::new(&matches).unwrap();
+        callback.on_start(1).unwrap();
+        callback.on_block(&block(&[1, 2, 1]), 1).unwrap();
+        callback.on_block(&block(&[2, 3]), 2).unwrap();
+        callback.on_block(&block(&[4, 5, 1]), 3).unwrap();
+        callback.on_block(&block(&[6]), 4).unwrap();
+        callback.on_complete(4).unwrap();
+        assert_eq!(callback.output_count, 9);
+
+        let csv = fs::read_to_string(tmp_dir.path().join("distinctaddresses-1-4.csv")).unwrap();
+        DISTINCT_ADDRESSES.assert_csv(&csv);
+        assert_eq!(csv, "period;estimated_distinct_addresses\n0;2\n2;5\n4;6\n");
+    }
+}
@@ -0,0 +1,237 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::PathBuf;
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::parser::chainstate::SnapshotUtxo;
+use crate::blockchain::proto::block::Block;
+use crate::callbacks::compress::Compression;
+use crate::callbacks::dustbreakdown::spend_cost;
+use crate::callbacks::rotate::{ColumnType, Table};
+use crate::callbacks::{common, BlockFlow, Callback};
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+const DUST_ADDRESSES: Table = Table::csv(
+    "dustaddresses",
+    &[
+        ("address", ColumnType::String),
+        ("dust_utxo_count", ColumnType::Integer),
+        ("dust_total_value", ColumnType::Integer),
+    ],
+);
+
+#[derive(Default)]
+struct Balance {
+    dust_count: u64,
+    dust_value: u64,
+    // Set if any unspent of the address is worth spending
+    has_economic: bool,
+}
+
+/// Dumps the addresses whose unspents at the last parsed height are all dust,
+/// i.e. no output is worth more than the fee to spend it at `--feerate` (see `dustbreakdown`).
+pub struct DustAddresses {
+    dump_folder: PathBuf,
+    compression: Compression,
+    // Fee rate in sat/vByte
+    feerate: f64,
+
+    // key: txid + index
+    unspents: common::ShardedUnspents,
+    interner: common::AddressInterner,
+
+    start_height: u64,
+}
+
+impl DustAddresses {
+    /// Returns (address, dust_utxo_count, dust_total_value) of all dust-only addresses, sorted by address
+    fn dust_addresses(&self) -> Vec<(&str, u64, u64)> {
+        let mut balances: HashMap<&str, Balance> = HashMap::new();
+        for unspent in self.unspents.values() {
+            let balance = balances.entry(unspent.address()).or_default();
+            if unspent.value <= spend_cost(unspent.script_type, self.feerate) {
+                balance.dust_count += 1;
+                balance.dust_value += unspent.value;
+            } else {
+                balance.has_economic = true;
+            }
+        }
+        let mut addresses: Vec<(&str, u64, u64)> = balances
+            .into_iter()
+            .filter(|(_, balance)| !balance.has_economic)
+            .map(|(address, balance)| (address, balance.dust_count, balance.dust_value))
+            .collect();
+        addresses.sort_unstable();
+        addresses
+    }
+}
+
+impl Callback for DustAddresses {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        let cmd = Command::new("dustaddresses")
+            .about(
+                "Dumps the addresses holding only dust UTXOs at the last parsed height to CSV file",
+            )
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            )
+            .arg(
+                Arg::new("feerate")
+                    .long("feerate")
+                    .value_name("SAT/VB")
+                    .value_parser(clap::value_parser!(f64))
+                    .default_value("3")
+                    .help("Fee rate in sat/vByte at which outputs are spent"),
+            )
+            .arg(
+                Arg::new("dedup-addresses")
+                    .long("dedup-addresses")
+                    .action(clap::ArgAction::SetTrue)
+                    .help("Shares memory between unspents paying to the same address"),
+            );
+        Compression::add_args(common::add_utxo_shards_arg(cmd))
+    }
+
+    fn schema(_: &ArgMatches) -> Vec<Table>
+    where
+        Self: Sized,
+    {
+        vec![DUST_ADDRESSES]
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let dump_folder = &PathBuf::from(matches.get_one::<String>("dump-folder").unwrap());
+        let feerate = *matches.get_one::<f64>("feerate").unwrap();
+        if !feerate.is_finite() || feerate < 0.0 {
+            return Err(OpError::new(OpErrorKind::InvalidArgsError)
+                .join_msg("--feerate must be a non-negative number"));
+        }
+        let cb = DustAddresses {
+            dump_folder: PathBuf::from(dump_folder),
+            compression: Compression::from_matches(matches)?,
+            feerate,
+            unspents: common::ShardedUnspents::from_matches(matches, 10000000),
+            interner: common::AddressInterner::new(matches.get_flag("dedup-addresses")),
+            start_height: 0,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing dustaddresses with dump folder: {} ...", &self.dump_folder.display());
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<BlockFlow> {
+        common::apply_unspents(
+            &block.txs,
+            block_height,
+            block.genesis,
+            &mut self.unspents,
+            &mut self.interner,
+        );
+        if block_height % 10000 == 0 {
+            self.interner.prune();
+        }
+        Ok(BlockFlow::Continue)
+    }
+
+    fn supports_utxo_snapshot(&self) -> bool {
+        true
+    }
+
+    fn on_snapshot_utxo(&mut self, utxo: &SnapshotUtxo) -> OpResult<()> {
+        common::insert_snapshot_utxo(utxo, &mut self.unspents, &mut self.interner);
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        let path = self.dump_folder.join(format!(
+            "dustaddresses-{}-{}.csv{}",
+            self.start_height,
+            block_height,
+            self.compression.suffix()
+        ));
+        let addresses = self.dust_addresses();
+        let mut writer = self.compression.create(&path)?;
+        writer.write_all(DUST_ADDRESSES.header().as_bytes())?;
+        for (address, count, value) in &addresses {
+            writer.write_all(format!("{};{};{}\n", address, count, value).as_bytes())?;
+        }
+        writer.finish()?;
+        info!(target: "callback", "Done.\nDumped dust-only addresses at height {} and {} sat/vByte:\n\
+                                   \t-> addresses: {:9}",
+             block_height, self.feerate, addresses.len());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::tx::TxOutpoint;
+    use crate::callbacks::common::test_block;
+    use bitcoin::hashes::{sha256d, Hash};
+    use std::fs;
+
+    #[test]
+    fn test_dust_addresses() {
+        // 1Q2TWHE3GMdB6BZKafqwxXtWAWgFt5Jvm3 and bc1qr47dd36u96r0fjle36hdygdnp0v6pwfg2lppam
+        let p2pkh = "76a914fc916f213a3d7f1369313d5fa30f6168f9446a2d88ac";
+        let p2wpkh = "00141d7cd6c75c2e86f4cbf98eaed221b30bd9a0b928";
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let matches = DustAddresses::build_subcommand().get_matches_from([
+            "dustaddresses",
+            tmp_dir.path().to_str().unwrap(),
+            "--feerate",
+            "4",
+        ]);
+        let mut callback = DustAddresses::new(&matches).unwrap();
+        callback.on_start(1).unwrap();
+
+        // At 4 sat/vByte, spending costs 592 sats for P2PKH and 272 sats for P2WPKH
+        let coinbase = TxOutpoint::new(sha256d::Hash::all_zeros(), 0xFFFFFFFF);
+        let block1 = test_block(
+            vec![coinbase],
+            &[(p2pkh, 500), (p2wpkh, 200), (p2pkh, 10000), (p2wpkh, 272)],
+        );
+        callback.on_block(&block1, 1).unwrap();
+        // The P2PKH address is only left with dust after spending its economic output
+        let funding = block1.txs[0].hash;
+        let block2 = test_block(vec![TxOutpoint::new(funding, 2)], &[(p2pkh, 592)]);
+        callback.on_block(&block2, 2).unwrap();
+        callback.on_complete(2).unwrap();
+
+        let csv = fs::read_to_string(tmp_dir.path().join("dustaddresses-1-2.csv")).unwrap();
+        DUST_ADDRESSES.assert_csv(&csv);
+        assert_eq!(
+            csv,
+            "address;dust_utxo_count;dust_total_value\n\
+             1Q2TWHE3GMdB6BZKafqwxXtWAWgFt5Jvm3;2;1092\n\
+             bc1qr47dd36u96r0fjle36hdygdnp0v6pwfg2lppam;2;472\n"
+        );
+
+        // A single economic output excludes the address
+        let block3 = test_block(vec![], &[(p2wpkh, 1000)]);
+        callback.on_block(&block3, 3).unwrap();
+        let addresses = callback.dust_addresses();
+        assert_eq!(
+            addresses,
+            vec![("1Q2TWHE3GMdB6BZKafqwxXtWAWgFt5Jvm3", 2, 1092)]
+        );
+    }
+}
@@ -0,0 +1,249 @@
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::parser::chainstate::SnapshotUtxo;
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::script::ScriptType;
+use crate::callbacks::compress::Compression;
+use crate::callbacks::rotate::{ColumnType, Table};
+use crate::callbacks::{common, BlockFlow, Callback};
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+const DUST_BREAKDOWN: Table = Table::csv(
+    "dustbreakdown",
+    &[
+        ("bucket", ColumnType::String),
+        ("utxo_count", ColumnType::Integer),
+        ("total_value", ColumnType::Integer),
+    ],
+);
+
+/// Returns the estimated vsize of an input spending an output of the given script type.
+/// Scripts which are only revealed when spending are assumed to be as cheap to spend as P2PKH
+/// (P2SH, bare scripts) or P2WPKH (P2WSH, other witness programs), like the dust threshold of Bitcoin Core.
+fn input_vsize(script_type: ScriptType) -> f64 {
+    match script_type {
+        ScriptType::Pay2PublicKey => 114.0,
+        ScriptType::Pay2WitnessPublicKeyHash
+        | ScriptType::Pay2WitnessScriptHash
+        | ScriptType::WitnessProgram => 68.0,
+        ScriptType::Pay2Taproot => 57.5,
+        _ => 148.0,
+    }
+}
+
+/// Returns the fee in satoshis to spend an output of the given script type at `feerate` sat/vByte.
+/// Outputs whose value doesn't exceed it are dust.
+pub fn spend_cost(script_type: ScriptType, feerate: f64) -> u64 {
+    (input_vsize(script_type) * feerate).ceil() as u64
+}
+
+/// Splits the UTXO set at the last parsed height into economic outputs, whose value exceeds
+/// the fee to spend them at `--feerate`, and uneconomic (dust) outputs.
+pub struct DustBreakdown {
+    dump_folder: PathBuf,
+    compression: Compression,
+    // Fee rate in sat/vByte
+    feerate: f64,
+
+    // key: txid + index
+    unspents: common::ShardedUnspents,
+    interner: common::AddressInterner,
+
+    start_height: u64,
+}
+
+impl DustBreakdown {
+    /// Returns (utxo_count, total_value) of the economic and of the uneconomic outputs
+    fn breakdown(&self) -> [(u64, u64); 2] {
+        let mut buckets = [(0u64, 0u64); 2];
+        for unspent in self.unspents.values() {
+            let idx = usize::from(unspent.value <= spend_cost(unspent.script_type, self.feerate));
+            buckets[idx].0 += 1;
+            buckets[idx].1 += unspent.value;
+        }
+        buckets
+    }
+}
+
+impl Callback for DustBreakdown {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        let cmd = Command::new("dustbreakdown")
+            .about("Dumps the economic and uneconomic (dust) UTXOs at the last parsed height to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            )
+            .arg(
+                Arg::new("feerate")
+                    .long("feerate")
+                    .value_name("SAT/VB")
+                    .value_parser(clap::value_parser!(f64))
+                    .default_value("3")
+                    .help("Fee rate in sat/vByte at which outputs are spent"),
+            )
+            .arg(
+                Arg::new("dedup-addresses")
+                    .long("dedup-addresses")
+                    .action(clap::ArgAction::SetTrue)
+                    .help("Shares memory between unspents paying to the same address"),
+            );
+        Compression::add_args(common::add_utxo_shards_arg(cmd))
+    }
+
+    fn schema(_: &ArgMatches) -> Vec<Table>
+    where
+        Self: Sized,
+    {
+        vec![DUST_BREAKDOWN]
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let dump_folder = &PathBuf::from(matches.get_one::<String>("dump-folder").unwrap());
+        let feerate = *matches.get_one::<f64>("feerate").unwrap();
+        if !feerate.is_finite() || feerate < 0.0 {
+            return Err(OpError::new(OpErrorKind::InvalidArgsError)
+                .join_msg("--feerate must be a non-negative number"));
+        }
+        let cb = DustBreakdown {
+            dump_folder: PathBuf::from(dump_folder),
+            compression: Compression::from_matches(matches)?,
+            feerate,
+            unspents: common::ShardedUnspents::from_matches(matches, 10000000),
+            interner: common::AddressInterner::new(matches.get_flag("dedup-addresses")),
+            start_height: 0,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing dustbreakdown with dump folder: {} ...", &self.dump_folder.display());
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<BlockFlow> {
+        common::apply_unspents(
+            &block.txs,
+            block_height,
+            block.genesis,
+            &mut self.unspents,
+            &mut self.interner,
+        );
+        if block_height % 10000 == 0 {
+            self.interner.prune();
+        }
+        Ok(BlockFlow::Continue)
+    }
+
+    fn supports_utxo_snapshot(&self) -> bool {
+        true
+    }
+
+    fn supports_checkpoint(&self) -> bool {
+        true
+    }
+
+    fn save_state(&self, writer: &mut dyn Write) -> OpResult<()> {
+        common::write_utxo_state(writer, self.start_height, &self.unspents)
+    }
+
+    fn load_state(&mut self, reader: &mut dyn Read) -> OpResult<()> {
+        self.start_height =
+            common::read_utxo_state(reader, &mut self.unspents, &mut self.interner)?;
+        Ok(())
+    }
+
+    fn on_snapshot_utxo(&mut self, utxo: &SnapshotUtxo) -> OpResult<()> {
+        common::insert_snapshot_utxo(utxo, &mut self.unspents, &mut self.interner);
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        let path = self.dump_folder.join(format!(
+            "dustbreakdown-{}-{}.csv{}",
+            self.start_height,
+            block_height,
+            self.compression.suffix()
+        ));
+        let [economic, uneconomic] = self.breakdown();
+        let mut writer = self.compression.create(&path)?;
+        writer.write_all(DUST_BREAKDOWN.header().as_bytes())?;
+        for (label, (count, value)) in [("economic", economic), ("uneconomic", uneconomic)] {
+            writer.write_all(format!("{};{};{}\n", label, count, value).as_bytes())?;
+        }
+        writer.finish()?;
+        info!(target: "callback", "Done.\nDumped dust breakdown at height {} and {} sat/vByte:\n\
+                                   \t-> economic:   {:9}\n\
+                                   \t-> uneconomic: {:9}",
+             block_height, self.feerate, economic.0, uneconomic.0);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::tx::TxOutpoint;
+    use crate::callbacks::common::test_block;
+    use bitcoin::hashes::{sha256d, Hash};
+    use std::fs;
+
+    #[test]
+    fn test_dust_breakdown() {
+        let p2pkh = "76a91462e907b15cbf27d5425399ebf6f0fb50ebb88f1888ac";
+        let p2wpkh = "00141d7cd6c75c2e86f4cbf98eaed221b30bd9a0b928";
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let matches = DustBreakdown::build_subcommand().get_matches_from([
+            "dustbreakdown",
+            tmp_dir.path().to_str().unwrap(),
+            "--feerate",
+            "4",
+        ]);
+        let mut callback = DustBreakdown::new(&matches).unwrap();
+        callback.on_start(1).unwrap();
+
+        // At 4 sat/vByte, spending costs 592 sats for P2PKH and 272 sats for P2WPKH
+        let coinbase = TxOutpoint::new(sha256d::Hash::all_zeros(), 0xFFFFFFFF);
+        let block1 = test_block(
+            vec![coinbase],
+            &[(p2pkh, 500), (p2wpkh, 500), (p2pkh, 10000), (p2wpkh, 272)],
+        );
+        callback.on_block(&block1, 1).unwrap();
+        let funding = block1.txs[0].hash;
+        let block2 = test_block(vec![TxOutpoint::new(funding, 2)], &[(p2pkh, 592)]);
+        callback.on_block(&block2, 2).unwrap();
+        callback.on_complete(2).unwrap();
+
+        let csv = fs::read_to_string(tmp_dir.path().join("dustbreakdown-1-2.csv")).unwrap();
+        DUST_BREAKDOWN.assert_csv(&csv);
+        assert_eq!(
+            csv,
+            "bucket;utxo_count;total_value\neconomic;1;500\nuneconomic;3;1364\n"
+        );
+    }
+
+    #[test]
+    fn test_invalid_feerate() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let matches = DustBreakdown::build_subcommand().get_matches_from([
+            "dustbreakdown",
+            tmp_dir.path().to_str().unwrap(),
+            "--feerate=-1",
+        ]);
+        assert!(DustBreakdown::new(&matches).is_err());
+    }
+}
@@ -0,0 +1,320 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::callbacks::compress::{Compression, OutputWriter};
+use crate::callbacks::feerates::format_rate;
+use crate::callbacks::rotate::{ColumnType, Table};
+use crate::callbacks::{common, BlockFlow, Callback};
+use crate::errors::OpResult;
+
+const FEE_PERCENTILES: Table = Table::csv(
+    "feepercentiles",
+    &[
+        ("window_end_height", ColumnType::Integer),
+        ("p10", ColumnType::Float),
+        ("p50", ColumnType::Float),
+        ("p90", ColumnType::Float),
+    ],
+);
+
+/// Percentiles which are dumped for each window
+const PERCENTILES: [u64; 3] = [10, 50, 90];
+
+/// Dumps the 10th, 50th and 90th percentile fee rate over a sliding window of blocks.
+/// Fee rates are given in sat/vByte like in `feerates`. With `--weight-by-vsize`, each transaction
+/// counts with its vsize, so the percentiles refer to block space instead of the number of transactions.
+/// The first rows cover less than `--window` blocks. Transactions spending outputs created before
+/// the start height have no known fee and are skipped.
+pub struct FeePercentiles {
+    dump_folder: PathBuf,
+    writer: OutputWriter,
+    window: usize,
+    weight_by_vsize: bool,
+
+    // key: txid + index, value: output value in satoshis
+    unspents: common::Unspents<u64>,
+    // (fee rate, weight) of the transactions in each block of the window
+    recent: VecDeque<Vec<(u64, u64)>>,
+    // Total weight per fee rate in the window
+    rates: BTreeMap<u64, u64>,
+    total_weight: u64,
+
+    start_height: u64,
+    tx_count: u64,
+}
+
+impl FeePercentiles {
+    /// Returns (fee rate in hundredths of sat/vByte, weight) of all non-coinbase transactions with known fees
+    fn block_rates(&mut self, block: &Block) -> Vec<(u64, u64)> {
+        let mut rates = Vec::with_capacity(block.txs.len());
+        for tx in &block.txs {
+            if let Some(fee) = common::apply_fee(tx, block.genesis, &mut self.unspents) {
+                let vsize = tx.value.vsize();
+                let weight = if self.weight_by_vsize { vsize } else { 1 };
+                rates.push((fee * 100 / vsize, weight));
+            }
+        }
+        rates
+    }
+
+    /// Adds the rates of a block to the window and drops the oldest block if the window is full
+    fn push_block(&mut self, rates: Vec<(u64, u64)>) {
+        if self.recent.len() == self.window {
+            for (rate, weight) in self.recent.pop_front().unwrap() {
+                let entry = self.rates.get_mut(&rate).unwrap();
+                *entry -= weight;
+                if *entry == 0 {
+                    self.rates.remove(&rate);
+                }
+                self.total_weight -= weight;
+            }
+        }
+        for (rate, weight) in &rates {
+            *self.rates.entry(*rate).or_insert(0) += weight;
+            self.total_weight += weight;
+        }
+        self.recent.push_back(rates);
+    }
+
+    /// Returns the lowest rate at which the cumulative weight reaches `percentile` percent
+    /// of the total weight in the window (nearest rank). None if the window is empty.
+    fn percentile(&self, percentile: u64) -> Option<u64> {
+        let mut cumulative = 0;
+        for (rate, weight) in &self.rates {
+            cumulative += weight;
+            if cumulative * 100 >= percentile * self.total_weight {
+                return Some(*rate);
+            }
+        }
+        None
+    }
+}
+
+impl Callback for FeePercentiles {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        let cmd = Command::new("feepercentiles")
+            .about("Dumps fee rate percentiles (sat/vByte) over a sliding window of blocks to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            )
+            .arg(
+                Arg::new("window")
+                    .long("window")
+                    .value_name("NUM")
+                    .value_parser(clap::value_parser!(u64).range(1..))
+                    .default_value("144")
+                    .help("Number of blocks to compute the percentiles over"),
+            )
+            .arg(
+                Arg::new("weight-by-vsize")
+                    .long("weight-by-vsize")
+                    .action(clap::ArgAction::SetTrue)
+                    .help("Weights each transaction by its vsize instead of counting transactions"),
+            );
+        Compression::add_args(cmd)
+    }
+
+    fn schema(_: &ArgMatches) -> Vec<Table>
+    where
+        Self: Sized,
+    {
+        vec![FEE_PERCENTILES]
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let dump_folder = &PathBuf::from(matches.get_one::<String>("dump-folder").unwrap());
+        let window = *matches.get_one::<u64>("window").unwrap() as usize;
+        let cb = FeePercentiles {
+            dump_folder: PathBuf::from(dump_folder),
+            writer: Compression::from_matches(matches)?
+                .create(&dump_folder.join("feepercentiles.csv.tmp"))?,
+            window,
+            weight_by_vsize: matches.get_flag("weight-by-vsize"),
+            unspents: HashMap::with_capacity(10000000),
+            recent: VecDeque::with_capacity(window),
+            rates: BTreeMap::new(),
+            total_weight: 0,
+            start_height: 0,
+            tx_count: 0,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing feepercentiles with dump folder: {} ...", &self.dump_folder.display());
+        self.writer.write_all(FEE_PERCENTILES.header().as_bytes())?;
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<BlockFlow> {
+        let rates = self.block_rates(block);
+        self.tx_count += rates.len() as u64;
+        self.push_block(rates);
+
+        let percentiles: Vec<String> = PERCENTILES
+            .iter()
+            .map(|p| self.percentile(*p).map_or(String::new(), format_rate))
+            .collect();
+        self.writer
+            .write_all(format!("{};{}\n", block_height, percentiles.join(";")).as_bytes())?;
+        Ok(BlockFlow::Continue)
+    }
+
+    fn needs_addresses(&self) -> bool {
+        false
+    }
+
+    fn tracks_utxos(&self) -> bool {
+        true
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.writer.finish()?;
+        fs::rename(
+            self.dump_folder.as_path().join("feepercentiles.csv.tmp"),
+            self.dump_folder
+                .as_path()
+                .join(self.writer.file_name(format!(
+                    "feepercentiles-{}-{}.csv",
+                    self.start_height, block_height
+                ))),
+        )?;
+        info!(target: "callback", "Done.\nDumped fee rate percentiles from height {} to {}:\n\
+                                   \t-> transactions: {:9}",
+             self.start_height, block_height, self.tx_count);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::header::BlockHeader;
+    use crate::blockchain::proto::script::AddressFormat;
+    use crate::blockchain::proto::tx::{RawTx, TxInput, TxOutpoint, TxOutput};
+    use crate::blockchain::proto::varuint::VarUint;
+    use bitcoin::hashes::{sha256d, Hash};
+
+    fn tx(outpoints: &[TxOutpoint], values: &[u64], locktime: u32) -> RawTx {
+        RawTx {
+            version: 1,
+            in_count: VarUint::from(outpoints.len() as u8),
+            inputs: outpoints
+                .iter()
+                .map(|outpoint| TxInput {
+                    outpoint: outpoint.clone(),
+                    script_len: VarUint::from(0u8),
+                    script_sig: vec![],
+                    seq_no: 0xFFFFFFFF,
+                    witness: vec![],
+                })
+                .collect(),
+            out_count: VarUint::from(values.len() as u8),
+            outputs: values
+                .iter()
+                .map(|value| TxOutput {
+                    value: *value,
+                    script_len: VarUint::from(0u8),
+                    script_pubkey: vec![],
+                })
+                .collect(),
+            locktime,
+            address_format: AddressFormat::default(),
+            witness_size: 0,
+        }
+    }
+
+    fn block(txs: Vec<RawTx>) -> Block {
+        let header = BlockHeader {
+            version: 1,
+            prev_hash: sha256d::Hash::all_zeros(),
+            merkle_root: sha256d::Hash::all_zeros(),
+            timestamp: 0,
+            bits: 0,
+            nonce: 0,
+        };
+        Block::new(0, header, None, VarUint::from(txs.len() as u8), txs)
+    }
+
+    fn run(args: &[&str]) -> String {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut argv = vec!["feepercentiles", tmp_dir.path().to_str().unwrap()];
+        argv.extend_from_slice(args);
+        let matches = FeePercentiles::build_subcommand().get_matches_from(argv);
+        let mut callback = FeePercentiles::new(&matches).unwrap();
+        callback.on_start(1).unwrap();
+
+        let coinbase_input = TxOutpoint::new(sha256d::Hash::all_zeros(), 0xFFFFFFFF);
+        let block1 = block(vec![tx(
+            std::slice::from_ref(&coinbase_input),
+            &[10000, 10000, 10000, 10000],
+            1,
+        )]);
+        callback.on_block(&block1, 1).unwrap();
+
+        // Spending txs have 60 vbytes with one output and 69 vbytes with two outputs
+        let funding = block1.txs[0].hash;
+        let block2 = block(vec![
+            tx(std::slice::from_ref(&coinbase_input), &[5000], 2),
+            tx(&[TxOutpoint::new(funding, 0)], &[9000], 0),
+            tx(&[TxOutpoint::new(funding, 1)], &[9500], 0),
+        ]);
+        callback.on_block(&block2, 2).unwrap();
+        let block3 = block(vec![
+            tx(std::slice::from_ref(&coinbase_input), &[5000], 3),
+            tx(&[TxOutpoint::new(funding, 2)], &[9800], 0),
+            tx(&[TxOutpoint::new(funding, 3)], &[1000, 8000], 0),
+        ]);
+        assert_eq!(block3.txs[2].value.vsize(), 69);
+        callback.on_block(&block3, 3).unwrap();
+        callback.on_complete(3).unwrap();
+
+        let csv = fs::read_to_string(tmp_dir.path().join("feepercentiles-1-3.csv")).unwrap();
+        FEE_PERCENTILES.assert_csv(&csv);
+        csv
+    }
+
+    #[test]
+    fn test_fee_percentiles() {
+        // Fee rates: 16.66 and 8.33 at height 2, 3.33 and 14.49 at height 3
+        assert_eq!(
+            run(&["--window", "2"]),
+            "window_end_height;p10;p50;p90\n\
+             1;;;\n\
+             2;8.33;8.33;16.66\n\
+             3;3.33;8.33;16.66\n"
+        );
+        // The window only contains height 3
+        assert_eq!(
+            run(&["--window", "1"]),
+            "window_end_height;p10;p50;p90\n\
+             1;;;\n\
+             2;8.33;8.33;16.66\n\
+             3;3.33;3.33;14.49\n"
+        );
+    }
+
+    #[test]
+    fn test_fee_percentiles_weighted() {
+        // 60 vbytes at 3.33 and 69 vbytes at 14.49, so the median is at the larger tx
+        let csv = run(&["--window", "1", "--weight-by-vsize"]);
+        assert_eq!(csv.lines().last().unwrap(), "3;3.33;14.49;14.49");
+    }
+}
@@ -0,0 +1,262 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::callbacks::common;
+use crate::callbacks::compress::{Compression, OutputWriter};
+use crate::callbacks::rotate::{ColumnType, Table};
+use crate::callbacks::{BlockFlow, Callback};
+use crate::errors::OpResult;
+
+const FEE_RATES: Table = Table::csv(
+    "feerates",
+    &[
+        ("height", ColumnType::Integer),
+        ("min_feerate", ColumnType::Float),
+        ("median_feerate", ColumnType::Float),
+        ("max_feerate", ColumnType::Float),
+        ("tx_count", ColumnType::Integer),
+    ],
+);
+
+/// Dumps the min, median and max fee rate of the non-coinbase transactions in each block.
+/// Fee rates are given in sat/vByte with two decimals, truncated (not rounded) from fee * 100 / vsize.
+/// The median of an even number of transactions is the mean of the two middle rates, truncated as well.
+/// Transactions spending outputs created before the start height have no known fee and are skipped.
+pub struct FeeRates {
+    dump_folder: PathBuf,
+    writer: OutputWriter,
+
+    // key: txid + index, value: output value in satoshis
+    unspents: common::Unspents<u64>,
+
+    start_height: u64,
+    tx_count: u64,
+}
+
+impl FeeRates {
+    /// Returns the fee rates of all non-coinbase transactions with known fees
+    /// in hundredths of sat/vByte
+    fn block_rates(&mut self, block: &Block) -> Vec<u64> {
+        let mut rates = Vec::with_capacity(block.txs.len());
+        for tx in &block.txs {
+            if let Some(fee) = common::apply_fee(tx, block.genesis, &mut self.unspents) {
+                rates.push(fee * 100 / tx.value.vsize());
+            }
+        }
+        rates
+    }
+}
+
+/// Returns (min, median, max) of the given rates
+fn summarize(rates: &mut [u64]) -> Option<(u64, u64, u64)> {
+    if rates.is_empty() {
+        return None;
+    }
+    rates.sort_unstable();
+    let mid = rates.len() / 2;
+    let median = if rates.len() % 2 == 0 {
+        (rates[mid - 1] + rates[mid]) / 2
+    } else {
+        rates[mid]
+    };
+    Some((rates[0], median, rates[rates.len() - 1]))
+}
+
+/// Formats hundredths of sat/vByte with two decimals
+pub(crate) fn format_rate(rate: u64) -> String {
+    format!("{}.{:02}", rate / 100, rate % 100)
+}
+
+impl Callback for FeeRates {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        let cmd = Command::new("feerates")
+            .about("Dumps the min, median and max fee rate (sat/vByte) of each block to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            );
+        Compression::add_args(cmd)
+    }
+
+    fn schema(_: &ArgMatches) -> Vec<Table>
+    where
+        Self: Sized,
+    {
+        vec![FEE_RATES]
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let dump_folder = &PathBuf::from(matches.get_one::<String>("dump-folder").unwrap());
+        let cb = FeeRates {
+            dump_folder: PathBuf::from(dump_folder),
+            writer: Compression::from_matches(matches)?
+                .create(&dump_folder.join("feerates.csv.tmp"))?,
+            unspents: HashMap::with_capacity(10000000),
+            start_height: 0,
+            tx_count: 0,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing feerates with dump folder: {} ...", &self.dump_folder.display());
+        self.writer.write_all(FEE_RATES.header().as_bytes())?;
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<BlockFlow> {
+        let mut rates = self.block_rates(block);
+        self.tx_count += rates.len() as u64;
+        let row = match summarize(&mut rates) {
+            Some((min, median, max)) => format!(
+                "{};{};{};{};{}\n",
+                block_height,
+                format_rate(min),
+                format_rate(median),
+                format_rate(max),
+                rates.len()
+            ),
+            None => format!("{};;;;0\n", block_height),
+        };
+        self.writer.write_all(row.as_bytes())?;
+        Ok(BlockFlow::Continue)
+    }
+
+    fn needs_addresses(&self) -> bool {
+        false
+    }
+
+    fn tracks_utxos(&self) -> bool {
+        true
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.writer.finish()?;
+        fs::rename(
+            self.dump_folder.as_path().join("feerates.csv.tmp"),
+            self.dump_folder
+                .as_path()
+                .join(self.writer.file_name(format!(
+                    "feerates-{}-{}.csv",
+                    self.start_height, block_height
+                ))),
+        )?;
+        info!(target: "callback", "Done.\nDumped fee rates from height {} to {}:\n\
+                                   \t-> transactions: {:9}",
+             self.start_height, block_height, self.tx_count);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::header::BlockHeader;
+    use crate::blockchain::proto::script::AddressFormat;
+    use crate::blockchain::proto::tx::{RawTx, TxInput, TxOutpoint, TxOutput};
+    use crate::blockchain::proto::varuint::VarUint;
+    use bitcoin::hashes::{sha256d, Hash};
+
+    fn tx(outpoints: &[TxOutpoint], values: &[u64], locktime: u32) -> RawTx {
+        RawTx {
+            version: 1,
+            in_count: VarUint::from(outpoints.len() as u8),
+            inputs: outpoints
+                .iter()
+                .map(|outpoint| TxInput {
+                    outpoint: outpoint.clone(),
+                    script_len: VarUint::from(0u8),
+                    script_sig: vec![],
+                    seq_no: 0xFFFFFFFF,
+                    witness: vec![],
+                })
+                .collect(),
+            out_count: VarUint::from(values.len() as u8),
+            outputs: values
+                .iter()
+                .map(|value| TxOutput {
+                    value: *value,
+                    script_len: VarUint::from(0u8),
+                    script_pubkey: vec![],
+                })
+                .collect(),
+            locktime,
+            address_format: AddressFormat::default(),
+            witness_size: 0,
+        }
+    }
+
+    fn block(txs: Vec<RawTx>) -> Block {
+        let header = BlockHeader {
+            version: 1,
+            prev_hash: sha256d::Hash::all_zeros(),
+            merkle_root: sha256d::Hash::all_zeros(),
+            timestamp: 0,
+            bits: 0,
+            nonce: 0,
+        };
+        Block::new(0, header, None, VarUint::from(txs.len() as u8), txs)
+    }
+
+    #[test]
+    fn test_summarize() {
+        assert_eq!(summarize(&mut []), None);
+        assert_eq!(summarize(&mut [5]), Some((5, 5, 5)));
+        assert_eq!(summarize(&mut [7, 1, 4, 2]), Some((1, 3, 7)));
+        assert_eq!(format_rate(1666), "16.66");
+        assert_eq!(format_rate(5), "0.05");
+    }
+
+    #[test]
+    fn test_fee_rates() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let matches = FeeRates::build_subcommand()
+            .get_matches_from(["feerates", tmp_dir.path().to_str().unwrap()]);
+        let mut callback = FeeRates::new(&matches).unwrap();
+        callback.on_start(0).unwrap();
+
+        let coinbase_input = TxOutpoint::new(sha256d::Hash::all_zeros(), 0xFFFFFFFF);
+        let block0 = block(vec![tx(
+            std::slice::from_ref(&coinbase_input),
+            &[10000, 10000, 10000],
+            0,
+        )]);
+        callback.on_block(&block0, 0).unwrap();
+
+        // Each spending tx has a size of 60 vbytes
+        let funding = block0.txs[0].hash;
+        let block1 = block(vec![
+            tx(&[coinbase_input], &[5000], 1),
+            tx(&[TxOutpoint::new(funding, 0)], &[9000], 0),
+            tx(&[TxOutpoint::new(funding, 1)], &[9500], 0),
+            tx(&[TxOutpoint::new(funding, 2)], &[9800], 0),
+        ]);
+        assert_eq!(block1.txs[1].value.vsize(), 60);
+        callback.on_block(&block1, 1).unwrap();
+        callback.on_complete(1).unwrap();
+
+        // Fees of 1000, 500 and 200 sats
+        let csv = fs::read_to_string(tmp_dir.path().join("feerates-0-1.csv")).unwrap();
+        FEE_RATES.assert_csv(&csv);
+        assert_eq!(
+            csv,
+            "height;min_feerate;median_feerate;max_feerate;tx_count\n0;;;;0\n1;3.33;8.33;16.66;3\n"
+        );
+    }
+}
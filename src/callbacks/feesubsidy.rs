@@ -0,0 +1,277 @@
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::callbacks::compress::{Compression, OutputWriter};
+use crate::callbacks::rotate::{ColumnType, Table};
+use crate::callbacks::{BlockFlow, Callback};
+use crate::errors::OpResult;
+
+const FEE_SUBSIDY: Table = Table::csv(
+    "feesubsidy",
+    &[
+        ("period", ColumnType::Integer),
+        ("total_subsidy", ColumnType::Integer),
+        ("total_fees", ColumnType::Integer),
+        ("fee_to_subsidy_ratio", ColumnType::Float),
+    ],
+);
+
+/// Returns the block subsidy in satoshis, which is halved every `halving_interval` blocks
+fn block_subsidy(block_height: u64, initial_subsidy: u64, halving_interval: u64) -> u64 {
+    let halvings = block_height / halving_interval;
+    if halvings >= 64 {
+        return 0;
+    }
+    initial_subsidy >> halvings
+}
+
+/// Sums the block subsidy and the fees per period of blocks.
+/// The fees of a block are the coinbase value exceeding the subsidy, so no UTXO set is needed.
+/// Fees which were not claimed by the miner are not counted.
+pub struct FeeSubsidy {
+    dump_folder: PathBuf,
+    writer: OutputWriter,
+    period: u64,
+    initial_subsidy: u64,
+    halving_interval: u64,
+
+    // First height of the current period
+    cur_period: u64,
+    subsidy: u64,
+    fees: u64,
+
+    start_height: u64,
+    total_subsidy: u64,
+    total_fees: u64,
+}
+
+impl FeeSubsidy {
+    /// Writes the sums of the current period and resets them
+    fn flush_period(&mut self) -> OpResult<()> {
+        let ratio = match self.subsidy {
+            0 => String::new(),
+            subsidy => format!("{:.6}", self.fees as f64 / subsidy as f64),
+        };
+        self.writer.write_all(
+            format!(
+                "{};{};{};{}\n",
+                self.cur_period, self.subsidy, self.fees, ratio
+            )
+            .as_bytes(),
+        )?;
+        self.total_subsidy += self.subsidy;
+        self.total_fees += self.fees;
+        self.subsidy = 0;
+        self.fees = 0;
+        Ok(())
+    }
+}
+
+impl Callback for FeeSubsidy {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        let cmd = Command::new("feesubsidy")
+            .about("Dumps the total fees and block subsidy per period to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            )
+            .arg(
+                Arg::new("period")
+                    .long("period")
+                    .value_name("NUM")
+                    .value_parser(clap::value_parser!(u64).range(1..))
+                    .default_value("2016")
+                    .help("Number of blocks per period"),
+            )
+            .arg(
+                Arg::new("initial-subsidy")
+                    .long("initial-subsidy")
+                    .value_name("SATS")
+                    .value_parser(clap::value_parser!(u64))
+                    .default_value("5000000000")
+                    .help("Subsidy of the first block in satoshis"),
+            )
+            .arg(
+                Arg::new("halving-interval")
+                    .long("halving-interval")
+                    .value_name("NUM")
+                    .value_parser(clap::value_parser!(u64).range(1..))
+                    .default_value("210000")
+                    .help("Number of blocks after which the subsidy is halved"),
+            );
+        Compression::add_args(cmd)
+    }
+
+    fn schema(_: &ArgMatches) -> Vec<Table>
+    where
+        Self: Sized,
+    {
+        vec![FEE_SUBSIDY]
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let dump_folder = &PathBuf::from(matches.get_one::<String>("dump-folder").unwrap());
+        let cb = FeeSubsidy {
+            dump_folder: PathBuf::from(dump_folder),
+            writer: Compression::from_matches(matches)?
+                .create(&dump_folder.join("feesubsidy.csv.tmp"))?,
+            period: *matches.get_one::<u64>("period").unwrap(),
+            initial_subsidy: *matches.get_one::<u64>("initial-subsidy").unwrap(),
+            halving_interval: *matches.get_one::<u64>("halving-interval").unwrap(),
+            cur_period: 0,
+            subsidy: 0,
+            fees: 0,
+            start_height: 0,
+            total_subsidy: 0,
+            total_fees: 0,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        self.cur_period = block_height - block_height % self.period;
+        info!(target: "callback", "Executing feesubsidy with dump folder: {} ...", &self.dump_folder.display());
+        self.writer.write_all(FEE_SUBSIDY.header().as_bytes())?;
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<BlockFlow> {
+        let period = block_height - block_height % self.period;
+        if period != self.cur_period {
+            self.flush_period()?;
+            self.cur_period = period;
+        }
+        let subsidy = block_subsidy(block_height, self.initial_subsidy, self.halving_interval);
+        let coinbase_value: u64 = block
+            .txs
+            .first()
+            .map_or(0, |tx| tx.value.outputs.iter().map(|o| o.out.value).sum());
+        self.subsidy += subsidy;
+        self.fees += coinbase_value.saturating_sub(subsidy);
+        Ok(BlockFlow::Continue)
+    }
+
+    fn needs_addresses(&self) -> bool {
+        false
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.flush_period()?;
+        self.writer.finish()?;
+        fs::rename(
+            self.dump_folder.as_path().join("feesubsidy.csv.tmp"),
+            self.dump_folder
+                .as_path()
+                .join(self.writer.file_name(format!(
+                    "feesubsidy-{}-{}.csv",
+                    self.start_height, block_height
+                ))),
+        )?;
+        info!(target: "callback", "Done.\nDumped fees and subsidy from height {} to {}:\n\
+                                   \t-> subsidy: {:20}\n\
+                                   \t-> fees:    {:20}",
+             self.start_height, block_height, self.total_subsidy, self.total_fees);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::header::BlockHeader;
+    use crate::blockchain::proto::script::AddressFormat;
+    use crate::blockchain::proto::tx::{RawTx, TxInput, TxOutpoint, TxOutput};
+    use crate::blockchain::proto::varuint::VarUint;
+    use bitcoin::hashes::{sha256d, Hash};
+
+    fn block(coinbase_value: u64) -> Block {
+        let coinbase = RawTx {
+            version: 1,
+            in_count: VarUint::from(1u8),
+            inputs: vec![TxInput {
+                outpoint: TxOutpoint::new(sha256d::Hash::all_zeros(), 0xFFFFFFFF),
+                script_len: VarUint::from(0u8),
+                script_sig: vec![],
+                seq_no: 0xFFFFFFFF,
+                witness: vec![],
+            }],
+            out_count: VarUint::from(1u8),
+            outputs: vec![TxOutput {
+                value: coinbase_value,
+                script_len: VarUint::from(0u8),
+                script_pubkey: vec![],
+            }],
+            locktime: 0,
+            address_format: AddressFormat::default(),
+            witness_size: 0,
+        };
+        let header = BlockHeader {
+            version: 1,
+            prev_hash: sha256d::Hash::all_zeros(),
+            merkle_root: sha256d::Hash::all_zeros(),
+            timestamp: 0,
+            bits: 0,
+            nonce: 0,
+        };
+        Block::new(0, header, None, VarUint::from(1u8), vec![coinbase])
+    }
+
+    #[test]
+    fn test_block_subsidy() {
+        assert_eq!(block_subsidy(0, 5000000000, 210000), 5000000000);
+        assert_eq!(block_subsidy(209999, 5000000000, 210000), 5000000000);
+        assert_eq!(block_subsidy(210000, 5000000000, 210000), 2500000000);
+        assert_eq!(block_subsidy(840000, 5000000000, 210000), 312500000);
+        assert_eq!(block_subsidy(6930000, 5000000000, 210000), 0);
+        assert_eq!(block_subsidy(u64::MAX, 5000000000, 210000), 0);
+    }
+
+    #[test]
+    fn test_fee_subsidy() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let matches = FeeSubsidy::build_subcommand().get_matches_from([
+            "feesubsidy",
+            tmp_dir.path().to_str().unwrap(),
+            "--period",
+            "4",
+            "--halving-interval",
+            "6",
+        ]);
+        let mut callback = FeeSubsidy::new(&matches).unwrap();
+        callback.on_start(2).unwrap();
+        // Heights 2 and 3, the second block doesn't claim the full subsidy
+        callback.on_block(&block(5500000000), 2).unwrap();
+        callback.on_block(&block(4000000000), 3).unwrap();
+        // Heights 4 to 7 span the first halving at height 6
+        callback.on_block(&block(5000000000), 4).unwrap();
+        callback.on_block(&block(5250000000), 5).unwrap();
+        callback.on_block(&block(2750000000), 6).unwrap();
+        callback.on_block(&block(2500000000), 7).unwrap();
+        callback.on_complete(7).unwrap();
+        assert_eq!(callback.total_fees, 1000000000);
+
+        let csv = fs::read_to_string(tmp_dir.path().join("feesubsidy-2-7.csv")).unwrap();
+        FEE_SUBSIDY.assert_csv(&csv);
+        assert_eq!(
+            csv,
+            "period;total_subsidy;total_fees;fee_to_subsidy_ratio\n\
+             0;10000000000;500000000;0.050000\n\
+             4;15000000000;500000000;0.033333\n"
+        );
+    }
+}
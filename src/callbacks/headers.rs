@@ -0,0 +1,174 @@
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::callbacks::compress::{Compression, OutputWriter};
+use crate::callbacks::rotate::{ColumnType, Table};
+use crate::callbacks::{BlockFlow, Callback};
+use crate::common::utils::HashByteOrder;
+use crate::errors::OpResult;
+
+const HEADERS: Table = Table::csv(
+    "headers",
+    &[
+        ("height", ColumnType::Integer),
+        ("hash", ColumnType::String),
+        ("version", ColumnType::Integer),
+        ("prev_hash", ColumnType::String),
+        ("merkle_root", ColumnType::String),
+        ("timestamp", ColumnType::Integer),
+        ("bits", ColumnType::Integer),
+        ("nonce", ColumnType::Integer),
+        ("tx_count", ColumnType::Integer),
+    ],
+);
+
+/// Dumps the header fields of each block, transactions are not looked at
+pub struct Headers {
+    dump_folder: PathBuf,
+    writer: OutputWriter,
+    hash_byte_order: HashByteOrder,
+
+    start_height: u64,
+    block_count: u64,
+}
+
+impl Callback for Headers {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        let cmd = Command::new("headers")
+            .about("Dumps the header fields of each block to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            );
+        Compression::add_args(cmd)
+    }
+
+    fn schema(_: &ArgMatches) -> Vec<Table>
+    where
+        Self: Sized,
+    {
+        vec![HEADERS]
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let dump_folder = &PathBuf::from(matches.get_one::<String>("dump-folder").unwrap());
+        let cb = Headers {
+            dump_folder: PathBuf::from(dump_folder),
+            writer: Compression::from_matches(matches)?
+                .create(&dump_folder.join("headers.csv.tmp"))?,
+            hash_byte_order: HashByteOrder::from_matches(matches)?,
+            start_height: 0,
+            block_count: 0,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing headers with dump folder: {} ...", &self.dump_folder.display());
+        self.writer.write_all(HEADERS.header().as_bytes())?;
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<BlockFlow> {
+        let header = &block.header.value;
+        self.writer.write_all(
+            format!(
+                "{};{};{};{};{};{};{};{};{}\n",
+                block_height,
+                self.hash_byte_order.hex(&block.header.hash),
+                header.version,
+                self.hash_byte_order.hex(&header.prev_hash),
+                self.hash_byte_order.hex(&header.merkle_root),
+                header.timestamp,
+                header.bits,
+                header.nonce,
+                block.tx_count.value
+            )
+            .as_bytes(),
+        )?;
+        self.block_count += 1;
+        Ok(BlockFlow::Continue)
+    }
+
+    fn needs_addresses(&self) -> bool {
+        false
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.writer.finish()?;
+        fs::rename(
+            self.dump_folder.as_path().join("headers.csv.tmp"),
+            self.dump_folder
+                .as_path()
+                .join(self.writer.file_name(format!(
+                    "headers-{}-{}.csv",
+                    self.start_height, block_height
+                ))),
+        )?;
+        info!(target: "callback", "Done.\nDumped headers from height {} to {}:\n\
+                                   \t-> blocks: {:9}",
+             self.start_height, block_height, self.block_count);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::parser::reader::BlockchainRead;
+    use crate::common::utils;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_headers() {
+        // Bitcoin genesis block
+        let raw_block = utils::hex_to_vec(concat!(
+            "0100000000000000000000000000000000000000000000000000000000000000",
+            "000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa",
+            "4b1e5e4a29ab5f49ffff001d1dac2b7c01010000000100000000000000000000",
+            "00000000000000000000000000000000000000000000ffffffff4d04ffff001d",
+            "0104455468652054696d65732030332f4a616e2f32303039204368616e63656c",
+            "6c6f72206f6e206272696e6b206f66207365636f6e64206261696c6f75742066",
+            "6f722062616e6b73ffffffff0100f2052a01000000434104678afdb0fe554827",
+            "1967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4",
+            "f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac00000000",
+        ));
+        let block = Cursor::new(&raw_block)
+            .read_block(raw_block.len() as u32, &Default::default())
+            .unwrap();
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let matches = Headers::build_subcommand()
+            .get_matches_from(["headers", tmp_dir.path().to_str().unwrap()]);
+        let mut callback = Headers::new(&matches).unwrap();
+        callback.on_start(0).unwrap();
+        callback.on_block(&block, 0).unwrap();
+        callback.on_complete(0).unwrap();
+
+        let csv = fs::read_to_string(tmp_dir.path().join("headers-0-0.csv")).unwrap();
+        HEADERS.assert_csv(&csv);
+        assert_eq!(
+            csv,
+            "height;hash;version;prev_hash;merkle_root;timestamp;bits;nonce;tx_count\n\
+             0;000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f;1;\
+             0000000000000000000000000000000000000000000000000000000000000000;\
+             4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b;\
+             1231006505;486604799;2083236893;1\n"
+        );
+    }
+}
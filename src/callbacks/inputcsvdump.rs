@@ -0,0 +1,218 @@
+use std::path::PathBuf;
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::callbacks::compress::Compression;
+use crate::callbacks::rotate::{ColumnType, RotatingWriter, Rotation, Table};
+use crate::callbacks::{BlockFlow, Callback};
+use crate::common::utils::HashByteOrder;
+use crate::errors::OpResult;
+
+const INPUTS: Table = Table::csv(
+    "inputs",
+    &[
+        ("spending_txid", ColumnType::String),
+        ("input_index", ColumnType::Integer),
+        ("prev_txid", ColumnType::String),
+        ("prev_vout", ColumnType::Integer),
+        ("script_sig_size", ColumnType::Integer),
+        ("sequence", ColumnType::Integer),
+        ("has_witness", ColumnType::Integer),
+    ],
+);
+
+/// Dumps every input along with its position in the spending transaction.
+/// Coinbase inputs are included with their all-zero prevout.
+pub struct InputCsvDump {
+    dump_folder: PathBuf,
+    writer: RotatingWriter,
+    hash_byte_order: HashByteOrder,
+
+    start_height: u64,
+    in_count: u64,
+    witness_count: u64,
+}
+
+impl Callback for InputCsvDump {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        let cmd = Command::new("inputcsvdump")
+            .about("Dumps all inputs with their prevout, scriptSig size and sequence to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            );
+        Compression::add_args(Rotation::add_args(cmd))
+    }
+
+    fn schema(_: &ArgMatches) -> Vec<Table>
+    where
+        Self: Sized,
+    {
+        vec![INPUTS]
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let dump_folder = &PathBuf::from(matches.get_one::<String>("dump-folder").unwrap());
+        let cb = InputCsvDump {
+            dump_folder: PathBuf::from(dump_folder),
+            writer: RotatingWriter::new(
+                dump_folder,
+                "inputs",
+                Some(&INPUTS.header()),
+                Rotation::from_matches(matches),
+                Compression::from_matches(matches)?,
+            )?,
+            hash_byte_order: HashByteOrder::from_matches(matches)?,
+            start_height: 0,
+            in_count: 0,
+            witness_count: 0,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing inputcsvdump with dump folder: {} ...", &self.dump_folder.display());
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<BlockFlow> {
+        self.writer.start_block(block_height)?;
+        for tx in &block.txs {
+            let txid = self.hash_byte_order.hex(&tx.hash);
+            for (i, input) in tx.value.inputs.iter().enumerate() {
+                self.writer.write_row(&format!(
+                    "{};{};{};{};{};{};{}\n",
+                    txid,
+                    i,
+                    self.hash_byte_order.hex(&input.outpoint.txid),
+                    input.outpoint.index,
+                    input.script_sig.len(),
+                    input.seq_no,
+                    u8::from(input.has_witness())
+                ))?;
+                self.in_count += 1;
+                self.witness_count += u64::from(input.has_witness());
+            }
+        }
+        Ok(BlockFlow::Continue)
+    }
+
+    fn needs_addresses(&self) -> bool {
+        false
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.writer.finish(self.start_height, block_height)?;
+        info!(target: "callback", "Done.\nDumped inputs from height {} to {}:\n\
+                                   \t-> inputs:       {:9}\n\
+                                   \t-> with witness: {:9}",
+             self.start_height, block_height, self.in_count, self.witness_count);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::header::BlockHeader;
+    use crate::blockchain::proto::script::AddressFormat;
+    use crate::blockchain::proto::tx::{RawTx, TxInput, TxOutpoint};
+    use crate::blockchain::proto::varuint::VarUint;
+    use crate::common::utils;
+    use bitcoin::hashes::{sha256d, Hash};
+    use std::fs;
+
+    fn tx(inputs: Vec<TxInput>) -> RawTx {
+        RawTx {
+            version: 2,
+            in_count: VarUint::from(inputs.len() as u8),
+            inputs,
+            out_count: VarUint::from(0u8),
+            outputs: vec![],
+            locktime: 0,
+            address_format: AddressFormat::default(),
+            witness_size: 0,
+        }
+    }
+
+    #[test]
+    fn test_input_csv_dump() {
+        let coinbase = TxInput {
+            outpoint: TxOutpoint::new(sha256d::Hash::all_zeros(), 0xFFFFFFFF),
+            script_len: VarUint::from(3u8),
+            script_sig: vec![0x02, 0x0a, 0x00],
+            seq_no: 0xFFFFFFFF,
+            witness: vec![],
+        };
+        // P2SH-P2WPKH spend followed by a legacy P2PKH spend
+        let prev_txid = sha256d::Hash::hash(&[1]);
+        let nested_segwit = TxInput {
+            outpoint: TxOutpoint::new(prev_txid, 1),
+            script_len: VarUint::from(23u8),
+            script_sig: utils::hex_to_vec("1600141d7cd6c75c2e86f4cbf98eaed221b30bd9a0b928"),
+            seq_no: 0xFFFFFFFE,
+            witness: vec![vec![0x30; 71], vec![0x02; 33]],
+        };
+        let legacy = TxInput {
+            outpoint: TxOutpoint::new(prev_txid, 0),
+            script_len: VarUint::from(106u8),
+            script_sig: vec![0x01; 106],
+            seq_no: 0xFFFFFFFD,
+            witness: vec![],
+        };
+        let header = BlockHeader {
+            version: 1,
+            prev_hash: sha256d::Hash::all_zeros(),
+            merkle_root: sha256d::Hash::all_zeros(),
+            timestamp: 0,
+            bits: 0,
+            nonce: 0,
+        };
+        let block = Block::new(
+            0,
+            header,
+            None,
+            VarUint::from(2u8),
+            vec![tx(vec![coinbase]), tx(vec![nested_segwit, legacy])],
+        );
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let matches = InputCsvDump::build_subcommand()
+            .get_matches_from(["inputcsvdump", tmp_dir.path().to_str().unwrap()]);
+        let mut callback = InputCsvDump::new(&matches).unwrap();
+        callback.on_start(10).unwrap();
+        callback.on_block(&block, 10).unwrap();
+        callback.on_complete(10).unwrap();
+        assert_eq!(callback.witness_count, 1);
+
+        let coinbase_txid = HashByteOrder::Rpc.hex(&block.txs[0].hash);
+        let txid = HashByteOrder::Rpc.hex(&block.txs[1].hash);
+        let prev_txid = HashByteOrder::Rpc.hex(&prev_txid);
+        let zeros = "0".repeat(64);
+        let csv = fs::read_to_string(tmp_dir.path().join("inputs-10-10.csv")).unwrap();
+        assert_eq!(
+            csv,
+            format!(
+                "spending_txid;input_index;prev_txid;prev_vout;script_sig_size;sequence;has_witness\n\
+                 {coinbase_txid};0;{zeros};4294967295;3;4294967295;0\n\
+                 {txid};0;{prev_txid};1;23;4294967294;1\n\
+                 {txid};1;{prev_txid};0;106;4294967293;0\n"
+            )
+        );
+
+        // The schema describes the header and the fields of each row
+        INPUTS.assert_csv(&csv);
+    }
+}
@@ -0,0 +1,265 @@
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::script::disasm::Script;
+use crate::callbacks::compress::{Compression, OutputWriter};
+use crate::callbacks::rotate::{ColumnType, Table};
+use crate::callbacks::{BlockFlow, Callback};
+use crate::common::utils::HashByteOrder;
+use crate::errors::OpResult;
+
+const INSCRIPTIONS: Table = Table::csv(
+    "inscriptions",
+    &[
+        ("txid", ColumnType::String),
+        ("content_type", ColumnType::String),
+        ("content_bytes", ColumnType::Integer),
+        ("height", ColumnType::Integer),
+    ],
+);
+
+/// Quotes the field if it contains the separator or quotes
+fn csv_field(value: &str) -> String {
+    if value.contains([';', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Dumps ordinals inscriptions revealed by taproot script path spends.
+/// The envelopes are found in the tapscript of each input, inscriptions of a transaction
+/// are numbered in input order like the inscription ids of `ord` (`<txid>i<n>`).
+pub struct Inscriptions {
+    dump_folder: PathBuf,
+    writer: OutputWriter,
+    hash_byte_order: HashByteOrder,
+    // Folder to store the inscription bodies, if enabled
+    content_folder: Option<PathBuf>,
+
+    start_height: u64,
+    inscription_count: u64,
+    content_bytes: u64,
+}
+
+impl Callback for Inscriptions {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        let cmd = Command::new("inscriptions")
+            .about("Dumps ordinals inscriptions found in taproot witness scripts to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            )
+            .arg(
+                Arg::new("save-content")
+                    .long("save-content")
+                    .action(clap::ArgAction::SetTrue)
+                    .help("Stores the body of each inscription in the `inscriptions` subfolder"),
+            );
+        Compression::add_args(cmd)
+    }
+
+    fn schema(_: &ArgMatches) -> Vec<Table>
+    where
+        Self: Sized,
+    {
+        vec![INSCRIPTIONS]
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let dump_folder = &PathBuf::from(matches.get_one::<String>("dump-folder").unwrap());
+        let content_folder = if matches.get_flag("save-content") {
+            let folder = dump_folder.join("inscriptions");
+            fs::create_dir_all(&folder)?;
+            Some(folder)
+        } else {
+            None
+        };
+        let cb = Inscriptions {
+            dump_folder: PathBuf::from(dump_folder),
+            writer: Compression::from_matches(matches)?
+                .create(&dump_folder.join("inscriptions.csv.tmp"))?,
+            hash_byte_order: HashByteOrder::from_matches(matches)?,
+            content_folder,
+            start_height: 0,
+            inscription_count: 0,
+            content_bytes: 0,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing inscriptions with dump folder: {} ...", &self.dump_folder.display());
+        self.writer.write_all(INSCRIPTIONS.header().as_bytes())?;
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<BlockFlow> {
+        for tx in &block.txs {
+            let txid = self.hash_byte_order.hex(&tx.hash);
+            let inscriptions = tx
+                .value
+                .inputs
+                .iter()
+                .filter_map(|input| input.tapscript())
+                .flat_map(|script| Script::new(script).inscriptions());
+            for (i, inscription) in inscriptions.enumerate() {
+                let content_type = inscription
+                    .content_type
+                    .as_deref()
+                    .map_or(String::new(), |content_type| {
+                        csv_field(&String::from_utf8_lossy(content_type))
+                    });
+                self.writer.write_all(
+                    format!(
+                        "{};{};{};{}\n",
+                        txid,
+                        content_type,
+                        inscription.body.len(),
+                        block_height
+                    )
+                    .as_bytes(),
+                )?;
+                if let Some(folder) = &self.content_folder {
+                    fs::write(folder.join(format!("{}i{}", txid, i)), &inscription.body)?;
+                }
+                self.inscription_count += 1;
+                self.content_bytes += inscription.body.len() as u64;
+            }
+        }
+        Ok(BlockFlow::Continue)
+    }
+
+    fn needs_addresses(&self) -> bool {
+        false
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.writer.finish()?;
+        fs::rename(
+            self.dump_folder.as_path().join("inscriptions.csv.tmp"),
+            self.dump_folder
+                .as_path()
+                .join(self.writer.file_name(format!(
+                    "inscriptions-{}-{}.csv",
+                    self.start_height, block_height
+                ))),
+        )?;
+        info!(target: "callback", "Done.\nDumped inscriptions from height {} to {}:\n\
+                                   \t-> inscriptions: {:9}\n\
+                                   \t-> bytes:        {:9}",
+             self.start_height, block_height, self.inscription_count, self.content_bytes);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::header::BlockHeader;
+    use crate::blockchain::proto::script::AddressFormat;
+    use crate::blockchain::proto::tx::{RawTx, TxInput, TxOutpoint};
+    use crate::blockchain::proto::varuint::VarUint;
+    use bitcoin::hashes::{sha256d, Hash};
+
+    /// Returns a taproot script path witness revealing an inscription
+    fn witness(content_type: &str, body: &[u8]) -> Vec<Vec<u8>> {
+        let mut script = vec![0x20];
+        script.extend([0x02; 32]);
+        script.extend([0xac, 0x00, 0x63, 0x03]);
+        script.extend(b"ord");
+        script.extend([0x01, 0x01, content_type.len() as u8]);
+        script.extend(content_type.as_bytes());
+        script.extend([0x00, body.len() as u8]);
+        script.extend(body);
+        script.push(0x68);
+        let control = [&[0xc0][..], &[0x03; 32]].concat();
+        vec![vec![0x01; 64], script, control]
+    }
+
+    #[test]
+    fn test_inscriptions() {
+        let inputs = [
+            witness("text/plain;charset=utf-8", b"Hello, world!"),
+            vec![vec![0x01; 64]],
+            witness("image/png", &[0x89, 0x50, 0x4e, 0x47]),
+        ]
+        .into_iter()
+        .map(|witness| TxInput {
+            outpoint: TxOutpoint::new(sha256d::Hash::hash(&[1]), 0),
+            script_len: VarUint::from(0u8),
+            script_sig: vec![],
+            seq_no: 0xFFFFFFFD,
+            witness,
+        })
+        .collect::<Vec<TxInput>>();
+        let tx = RawTx {
+            version: 2,
+            in_count: VarUint::from(inputs.len() as u8),
+            inputs,
+            out_count: VarUint::from(0u8),
+            outputs: vec![],
+            locktime: 0,
+            address_format: AddressFormat::default(),
+            witness_size: 0,
+        };
+        let header = BlockHeader {
+            version: 1,
+            prev_hash: sha256d::Hash::all_zeros(),
+            merkle_root: sha256d::Hash::all_zeros(),
+            timestamp: 0,
+            bits: 0,
+            nonce: 0,
+        };
+        let block = Block::new(0, header, None, VarUint::from(1u8), vec![tx]);
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let matches = Inscriptions::build_subcommand().get_matches_from([
+            "inscriptions",
+            tmp_dir.path().to_str().unwrap(),
+            "--save-content",
+        ]);
+        let mut callback = Inscriptions::new(&matches).unwrap();
+        callback.on_start(800000).unwrap();
+        callback.on_block(&block, 800000).unwrap();
+        callback.on_complete(800000).unwrap();
+        assert_eq!(callback.content_bytes, 17);
+
+        let txid = HashByteOrder::Rpc.hex(&block.txs[0].hash);
+        let csv =
+            fs::read_to_string(tmp_dir.path().join("inscriptions-800000-800000.csv")).unwrap();
+        INSCRIPTIONS.assert_csv(&csv);
+        assert_eq!(
+            csv,
+            format!(
+                "txid;content_type;content_bytes;height\n\
+                 {txid};\"text/plain;charset=utf-8\";13;800000\n\
+                 {txid};image/png;4;800000\n"
+            )
+        );
+        let content = tmp_dir.path().join("inscriptions");
+        assert_eq!(
+            fs::read(content.join(format!("{}i0", txid))).unwrap(),
+            b"Hello, world!"
+        );
+        assert_eq!(
+            fs::read(content.join(format!("{}i1", txid))).unwrap(),
+            [0x89, 0x50, 0x4e, 0x47]
+        );
+    }
+}
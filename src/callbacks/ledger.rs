@@ -0,0 +1,357 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::ToRaw;
+use crate::callbacks::compress::{Compression, OutputWriter};
+use crate::callbacks::rotate::{ColumnType, Table};
+use crate::callbacks::{common, BlockFlow, Callback};
+use crate::common::utils::HashByteOrder;
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+const LEDGER: Table = Table::csv(
+    "ledger",
+    &[
+        ("height", ColumnType::Integer),
+        ("txid", ColumnType::String),
+        ("address", ColumnType::String),
+        ("delta", ColumnType::Integer),
+        ("running_balance", ColumnType::Integer),
+    ],
+);
+
+/// Dumps every balance change of the watchlisted addresses in chain order.
+/// Only outputs paying to watchlisted addresses are tracked, so spends can be resolved with little memory.
+/// If inverted, all addresses except the watchlisted ones are tracked instead.
+pub struct Ledger {
+    dump_folder: PathBuf,
+    writer: OutputWriter,
+    hash_byte_order: HashByteOrder,
+    watchlist: HashSet<String>,
+    invert: bool,
+
+    // key: txid + index, value: (address, value) of watchlisted outputs
+    unspents: common::Unspents<(String, u64)>,
+    balances: HashMap<String, u64>,
+
+    start_height: u64,
+    event_count: u64,
+}
+
+impl Ledger {
+    fn write_event(
+        &mut self,
+        block_height: u64,
+        txid: &str,
+        address: &str,
+        delta: Option<i64>,
+        balance: Option<u64>,
+    ) -> OpResult<()> {
+        let delta = delta.map(|delta| delta.to_string()).unwrap_or_default();
+        let balance = balance
+            .map(|balance| balance.to_string())
+            .unwrap_or_default();
+        self.writer.write_all(
+            format!(
+                "{};{};{};{};{}\n",
+                block_height, txid, address, delta, balance
+            )
+            .as_bytes(),
+        )?;
+        self.event_count += 1;
+        Ok(())
+    }
+}
+
+impl Callback for Ledger {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        let cmd = Command::new("ledger")
+            .about("Dumps every balance change of the watchlisted addresses to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            )
+            .arg(
+                Arg::new("watchlist")
+                    .long("watchlist")
+                    .value_name("PATH")
+                    .required(true)
+                    .help("File with one address per line"),
+            )
+            .arg(
+                Arg::new("invert")
+                    .long("invert")
+                    .action(clap::ArgAction::SetTrue)
+                    .help("Dumps all addresses except the watchlisted ones"),
+            );
+        Compression::add_args(cmd)
+    }
+
+    fn schema(_: &ArgMatches) -> Vec<Table>
+    where
+        Self: Sized,
+    {
+        vec![LEDGER]
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let dump_folder = &PathBuf::from(matches.get_one::<String>("dump-folder").unwrap());
+        let watchlist = common::parse_watchlist(&fs::read_to_string(
+            matches.get_one::<String>("watchlist").unwrap(),
+        )?);
+        if watchlist.is_empty() {
+            return Err(OpError::new(OpErrorKind::InvalidArgsError)
+                .join_msg("The watchlist doesn't contain any addresses"));
+        }
+        let cb = Ledger {
+            dump_folder: PathBuf::from(dump_folder),
+            writer: Compression::from_matches(matches)?
+                .create(&dump_folder.join("ledger.csv.tmp"))?,
+            hash_byte_order: HashByteOrder::from_matches(matches)?,
+            watchlist,
+            invert: matches.get_flag("invert"),
+            unspents: HashMap::new(),
+            balances: HashMap::new(),
+            start_height: 0,
+            event_count: 0,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing ledger for {} addresses{} with dump folder: {} ...",
+              self.watchlist.len(), if self.invert { " (inverted)" } else { "" }, &self.dump_folder.display());
+        self.writer.write_all(LEDGER.header().as_bytes())?;
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<BlockFlow> {
+        for tx in &block.txs {
+            let txid = self.hash_byte_order.hex(&tx.hash);
+            for input in &tx.value.inputs {
+                match self.unspents.remove(&input.outpoint.to_bytes()) {
+                    Some((address, value)) => {
+                        let balance = self.balances.get_mut(&address).unwrap();
+                        *balance -= value;
+                        let balance = *balance;
+                        let delta = -(value as i64);
+                        self.write_event(
+                            block_height,
+                            &txid,
+                            &address,
+                            Some(delta),
+                            Some(balance),
+                        )?;
+                    }
+                    // Created before the start height, only nested SegWit spends reveal the address.
+                    // The spent value is unknown, so the balance can't be updated.
+                    None => {
+                        let (watchlist, invert) = (&self.watchlist, self.invert);
+                        let address = tx.value.input_address(input);
+                        if let Some(address) =
+                            address.filter(|address| watchlist.contains(address) != invert)
+                        {
+                            self.write_event(block_height, &txid, &address, None, None)?;
+                        }
+                    }
+                }
+            }
+
+            let (watchlist, invert) = (&self.watchlist, self.invert);
+            let received = common::eval_unspents_with(tx, block.genesis, |output| {
+                output
+                    .address
+                    .filter(|address| watchlist.contains(*address) != invert)
+                    .map(|address| (address.to_string(), output.value))
+            });
+            for (outpoint, (address, value)) in received {
+                let balance = self.balances.entry(address.clone()).or_insert(0);
+                *balance += value;
+                let balance = *balance;
+                self.write_event(
+                    block_height,
+                    &txid,
+                    &address,
+                    Some(value as i64),
+                    Some(balance),
+                )?;
+                self.unspents.insert(outpoint.to_bytes(), (address, value));
+            }
+        }
+        Ok(BlockFlow::Continue)
+    }
+
+    fn tracks_utxos(&self) -> bool {
+        true
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.writer.finish()?;
+        fs::rename(
+            self.dump_folder.as_path().join("ledger.csv.tmp"),
+            self.dump_folder.as_path().join(
+                self.writer
+                    .file_name(format!("ledger-{}-{}.csv", self.start_height, block_height)),
+            ),
+        )?;
+        info!(target: "callback", "Done.\nDumped ledger from height {} to {}:\n\
+                                   \t-> addresses: {:9}\n\
+                                   \t-> events:    {:9}",
+             self.start_height, block_height, self.balances.len(), self.event_count);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::tx::TxOutpoint;
+    use crate::callbacks::common::test_block;
+    use crate::common::utils;
+    use bitcoin::hashes::{sha256d, Hash};
+
+    // P2PKH to 1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa
+    const WATCHED: &str = "76a91462e907b15cbf27d5425399ebf6f0fb50ebb88f1888ac";
+    // P2PKH to 1KFHE7w8BhaENAswwryaoccDb6qcT6DbYY
+    const OTHER: &str = "76a914c825a1ecf2a6830c4401620c3a16f1995057c2ab88ac";
+
+    #[test]
+    fn test_ledger() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let watchlist = tmp_dir.path().join("watchlist.txt");
+        fs::write(&watchlist, "1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa\n").unwrap();
+        let matches = Ledger::build_subcommand().get_matches_from([
+            "ledger",
+            tmp_dir.path().to_str().unwrap(),
+            "--watchlist",
+            watchlist.to_str().unwrap(),
+        ]);
+        let mut callback = Ledger::new(&matches).unwrap();
+        callback.on_start(1).unwrap();
+
+        let receive = test_block(vec![], &[(WATCHED, 100), (OTHER, 50), (WATCHED, 20)]);
+        callback.on_block(&receive, 1).unwrap();
+        // Spends the first watched output with change back to the watched address
+        let spend = test_block(
+            vec![
+                TxOutpoint::new(receive.txs[0].hash, 0),
+                TxOutpoint::new(receive.txs[0].hash, 1),
+            ],
+            &[(OTHER, 110), (WATCHED, 30)],
+        );
+        callback.on_block(&spend, 2).unwrap();
+        callback.on_complete(2).unwrap();
+
+        let receive_txid = HashByteOrder::Rpc.hex(&receive.txs[0].hash);
+        let spend_txid = HashByteOrder::Rpc.hex(&spend.txs[0].hash);
+        let address = "1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa";
+        let csv = fs::read_to_string(tmp_dir.path().join("ledger-1-2.csv")).unwrap();
+        LEDGER.assert_csv(&csv);
+        assert_eq!(
+            csv,
+            format!(
+                "height;txid;address;delta;running_balance\n\
+                 1;{receive_txid};{address};100;100\n\
+                 1;{receive_txid};{address};20;120\n\
+                 2;{spend_txid};{address};-100;20\n\
+                 2;{spend_txid};{address};30;50\n"
+            )
+        );
+        assert!(callback
+            .unspents
+            .contains_key(&TxOutpoint::new(spend.txs[0].hash, 1).to_bytes()));
+    }
+
+    #[test]
+    fn test_ledger_nested_segwit() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let watchlist = tmp_dir.path().join("watchlist.txt");
+        fs::write(&watchlist, "3Eq1QAi9gU2eJJNdPg2CpZ1wqZpPFSFpzv\n").unwrap();
+        let matches = Ledger::build_subcommand().get_matches_from([
+            "ledger",
+            tmp_dir.path().to_str().unwrap(),
+            "--watchlist",
+            watchlist.to_str().unwrap(),
+        ]);
+        let mut callback = Ledger::new(&matches).unwrap();
+        callback.on_start(1).unwrap();
+
+        // Spends an output created before the start height
+        let outpoint = TxOutpoint::new(sha256d::Hash::hash(b"unknown"), 0);
+        let mut spend = test_block(vec![outpoint], &[(OTHER, 50)]);
+        spend.txs[0].value.inputs[0].script_sig = utils::hex_to_vec(common::P2SH_P2WPKH_SCRIPT_SIG);
+        callback.on_block(&spend, 1).unwrap();
+        callback.on_complete(1).unwrap();
+
+        // The spent value and therefore the balance are unknown
+        let csv = fs::read_to_string(tmp_dir.path().join("ledger-1-1.csv")).unwrap();
+        LEDGER.assert_csv(&csv);
+        assert_eq!(
+            csv,
+            format!(
+                "height;txid;address;delta;running_balance\n\
+                 1;{};3Eq1QAi9gU2eJJNdPg2CpZ1wqZpPFSFpzv;;\n",
+                HashByteOrder::Rpc.hex(&spend.txs[0].hash)
+            )
+        );
+    }
+
+    #[test]
+    fn test_ledger_invert() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let watchlist = tmp_dir.path().join("watchlist.txt");
+        fs::write(&watchlist, "1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa\n").unwrap();
+        let matches = Ledger::build_subcommand().get_matches_from([
+            "ledger",
+            tmp_dir.path().to_str().unwrap(),
+            "--watchlist",
+            watchlist.to_str().unwrap(),
+            "--invert",
+        ]);
+        let mut callback = Ledger::new(&matches).unwrap();
+        callback.on_start(1).unwrap();
+
+        let receive = test_block(vec![], &[(WATCHED, 100), (OTHER, 50)]);
+        callback.on_block(&receive, 1).unwrap();
+        let spend = test_block(
+            vec![
+                TxOutpoint::new(receive.txs[0].hash, 0),
+                TxOutpoint::new(receive.txs[0].hash, 1),
+            ],
+            &[(WATCHED, 110), (OTHER, 30)],
+        );
+        callback.on_block(&spend, 2).unwrap();
+        callback.on_complete(2).unwrap();
+
+        // Only the address which is not on the watchlist
+        let receive_txid = HashByteOrder::Rpc.hex(&receive.txs[0].hash);
+        let spend_txid = HashByteOrder::Rpc.hex(&spend.txs[0].hash);
+        let address = "1KFHE7w8BhaENAswwryaoccDb6qcT6DbYY";
+        let csv = fs::read_to_string(tmp_dir.path().join("ledger-1-2.csv")).unwrap();
+        LEDGER.assert_csv(&csv);
+        assert_eq!(
+            csv,
+            format!(
+                "height;txid;address;delta;running_balance\n\
+                 1;{receive_txid};{address};50;50\n\
+                 2;{spend_txid};{address};-50;0\n\
+                 2;{spend_txid};{address};30;30\n"
+            )
+        );
+    }
+}
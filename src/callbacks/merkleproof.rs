@@ -0,0 +1,145 @@
+use bitcoin::hashes::sha256d;
+use std::str::FromStr;
+
+use clap::{Arg, ArgMatches, Command};
+use serde_json::{json, Value};
+
+use crate::blockchain::proto::block::Block;
+use crate::callbacks::{BlockFlow, Callback};
+use crate::common::utils::{self, HashByteOrder};
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+/// Prints the merkle branch which proves the inclusion of a transaction in its block
+pub struct MerkleProof {
+    txid: sha256d::Hash,
+    found: bool,
+    hash_byte_order: HashByteOrder,
+}
+
+impl MerkleProof {
+    /// Returns the proof as JSON if the block contains the transaction
+    fn proof(&self, block: &Block, block_height: u64) -> Option<Value> {
+        let index = block.txs.iter().position(|tx| tx.hash == self.txid)?;
+        let hashes = block.txs.iter().map(|tx| tx.hash).collect();
+        let branch = utils::merkle_branch(hashes, index)
+            .iter()
+            .map(|(hash, is_right)| {
+                json!({
+                    "hash": self.hash_byte_order.hex(hash),
+                    "side": if *is_right { "right" } else { "left" },
+                })
+            })
+            .collect::<Vec<Value>>();
+        Some(json!({
+            "txid": self.hash_byte_order.hex(&self.txid),
+            "block_hash": self.hash_byte_order.hex(&block.header.hash),
+            "height": block_height,
+            "index": index,
+            "merkle_root": self.hash_byte_order.hex(&block.header.value.merkle_root),
+            "branch": branch,
+        }))
+    }
+}
+
+impl Callback for MerkleProof {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        Command::new("merkleproof")
+            .about("Shows the merkle proof for the given transaction as JSON")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("txid")
+                    .help("Transaction id to prove")
+                    .index(1)
+                    .required(true),
+            )
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let txid = matches.get_one::<String>("txid").unwrap();
+        let txid = sha256d::Hash::from_str(txid).map_err(|e| {
+            OpError::new(OpErrorKind::InvalidArgsError)
+                .join_msg(&format!("Invalid txid `{}`: {}", txid, e))
+        })?;
+        Ok(MerkleProof {
+            txid,
+            found: false,
+            hash_byte_order: HashByteOrder::from_matches(matches)?,
+        })
+    }
+
+    fn on_start(&mut self, _: u64) -> OpResult<()> {
+        info!(target: "callback", "Executing merkleproof for txid: {} ...", &self.txid);
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<BlockFlow> {
+        if let Some(proof) = self.proof(block, block_height) {
+            println!("{}", serde_json::to_string_pretty(&proof).unwrap());
+            // The proof for the first block containing the transaction is enough
+            self.found = true;
+            return Ok(BlockFlow::Stop);
+        }
+        Ok(BlockFlow::Continue)
+    }
+
+    fn needs_addresses(&self) -> bool {
+        false
+    }
+
+    fn on_complete(&mut self, _: u64) -> OpResult<()> {
+        if !self.found {
+            warn!(target: "callback", "Transaction {} not found.", &self.txid);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::parser::reader::BlockchainRead;
+    use crate::blockchain::parser::types::CoinType;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_merkle_proof() {
+        // Bitcoin genesis block, the coinbase is the only transaction
+        let raw_block = utils::hex_to_vec(
+            "0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c0101000000010000000000000000000000000000000000000000000000000000000000000000ffffffff4d04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72206f6e206272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73ffffffff0100f2052a01000000434104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac00000000",
+        );
+        let block = Cursor::new(&raw_block)
+            .read_block(raw_block.len() as u32, &CoinType::default())
+            .unwrap();
+
+        let matches = MerkleProof::build_subcommand().get_matches_from([
+            "merkleproof",
+            "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b",
+        ]);
+        let mut callback = MerkleProof::new(&matches).unwrap();
+        let proof = callback.proof(&block, 0).unwrap();
+        assert_eq!(
+            proof["merkle_root"],
+            "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b"
+        );
+        assert_eq!(proof["index"], 0);
+        assert_eq!(proof["branch"], json!([]));
+        // Parsing stops once the transaction is found
+        assert_eq!(callback.on_block(&block, 0).unwrap(), BlockFlow::Stop);
+        assert!(callback.found);
+
+        let matches = MerkleProof::build_subcommand().get_matches_from([
+            "merkleproof",
+            "0000000000000000000000000000000000000000000000000000000000000000",
+        ]);
+        let mut callback = MerkleProof::new(&matches).unwrap();
+        assert!(callback.proof(&block, 0).is_none());
+        assert_eq!(callback.on_block(&block, 0).unwrap(), BlockFlow::Continue);
+    }
+}
@@ -1,17 +1,71 @@
+use std::io::{Read, Write};
+
 use clap::{ArgMatches, Command};
 
+use crate::blockchain::parser::chainstate::SnapshotUtxo;
 use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::tx::EvaluatedTx;
+use crate::blockchain::proto::Hashed;
+use crate::callbacks::rotate::Table;
 use crate::errors::OpResult;
 
+pub mod addressactivity;
+pub mod addressfilter;
+pub mod addressindex;
+pub mod addressreuse;
+pub mod awakened;
 pub mod balances;
+pub mod blockfees;
+pub mod blockintervals;
+pub mod bloomfilter;
+pub mod clusterflows;
+pub mod clusters;
+pub mod coinbaseaddresses;
 mod common;
+mod compress;
 pub mod csvdump;
+pub mod difficulty;
+pub mod distinctaddresses;
+pub mod dustaddresses;
+pub mod dustbreakdown;
+pub mod feepercentiles;
+pub mod feerates;
+pub mod feesubsidy;
+pub mod headers;
+pub mod inputcsvdump;
+pub mod inscriptions;
+pub mod ledger;
+pub mod merkleproof;
+pub mod netissuance;
 pub mod opreturn;
+pub mod opreturnbytes;
+pub mod opreturnprotocols;
+pub mod pools;
+pub mod pubkeyreuse;
+mod rotate;
+pub mod segwit;
 pub mod simplestats;
+pub mod spendlatency;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+pub mod timelocks;
+pub mod txversions;
+pub mod typeflows;
 pub mod unspentcsvdump;
+pub mod utxoages;
+
+/// Returned by `Callback::on_block()` to tell the parser whether to continue with the next block
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockFlow {
+    Continue,
+    /// Ends the run early: `on_txs()` still gets called for this block, then the parser
+    /// calls `on_complete()` with its height. With `--disk-order` the heights aren't ascending,
+    /// so blocks below that height may not have been processed yet.
+    Stop,
+}
 
 /// Implement this trait for a custom Callback.
-/// The parser ensures that the blocks arrive in the correct order.
+/// The parser ensures that the blocks arrive in the correct order, unless `--disk-order` is used.
 /// At this stage the main chain is already determined and orphans/stales are removed.
 pub trait Callback {
     /// Builds Command to specify callback name and required args,
@@ -20,6 +74,15 @@ pub trait Callback {
     where
         Self: Sized;
 
+    /// Describes the files written by the callback with the given arguments for `--print-schema`.
+    /// Empty if the callback doesn't write any files.
+    fn schema(_matches: &ArgMatches) -> Vec<Table>
+    where
+        Self: Sized,
+    {
+        Vec::new()
+    }
+
     /// Instantiates callback
     fn new(matches: &ArgMatches) -> OpResult<Self>
     where
@@ -29,7 +92,71 @@ pub trait Callback {
     fn on_start(&mut self, block_height: u64) -> OpResult<()>;
 
     /// Gets called if a new block is available.
-    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()>;
+    /// Returning `BlockFlow::Stop` ends the run after this block, see `BlockFlow`.
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<BlockFlow>;
+
+    /// Gets called for each transaction of a block if the default `on_txs()` is used.
+    fn on_tx(&mut self, _tx: &Hashed<EvaluatedTx>, _block_height: u64) -> OpResult<()> {
+        Ok(())
+    }
+
+    /// Gets called once per block with all transactions of that block.
+    /// The transactions are passed in block order (coinbase first) and this is always
+    /// invoked right after `on_block()` for the same height and before the next block.
+    /// Sinks that benefit from batching (e.g. one write or commit per block) can override this,
+    /// the default implementation forwards each transaction to `on_tx()`.
+    fn on_txs(&mut self, txs: &[Hashed<EvaluatedTx>], block_height: u64) -> OpResult<()> {
+        for tx in txs {
+            self.on_tx(tx, block_height)?;
+        }
+        Ok(())
+    }
+
+    /// Hint for callbacks which only look at the coinbase transaction.
+    /// If true, `on_txs()` only receives the coinbase of each block.
+    fn wants_only_coinbase(&self) -> bool {
+        false
+    }
+
+    /// Whether the callback relies on output addresses.
+    /// If false, the callback can run with `--no-address`, which skips the address encoding of scripts.
+    fn needs_addresses(&self) -> bool {
+        true
+    }
+
+    /// Whether the callback can be bootstrapped from a UTXO snapshot (`--chainstate`)
+    fn supports_utxo_snapshot(&self) -> bool {
+        false
+    }
+
+    /// Whether the callback tracks the UTXO set and therefore relies on blocks in chain order.
+    /// If true, the callback can't run with `--disk-order`.
+    fn tracks_utxos(&self) -> bool {
+        self.supports_utxo_snapshot()
+    }
+
+    /// Gets called for each unspent output of the snapshot before `on_start()`.
+    /// Parsing continues with the block after the snapshot's best block.
+    fn on_snapshot_utxo(&mut self, _utxo: &SnapshotUtxo) -> OpResult<()> {
+        Ok(())
+    }
+
+    /// Whether the callback can save its state to a checkpoint (`--checkpoint`) and resume from it
+    fn supports_checkpoint(&self) -> bool {
+        false
+    }
+
+    /// Serializes everything needed to continue after the last processed block.
+    /// Only called if `supports_checkpoint()` is true.
+    fn save_state(&self, _writer: &mut dyn Write) -> OpResult<()> {
+        Ok(())
+    }
+
+    /// Restores the state written by `save_state()`.
+    /// Gets called right after `on_start()` when resuming from a checkpoint.
+    fn load_state(&mut self, _reader: &mut dyn Read) -> OpResult<()> {
+        Ok(())
+    }
 
     /// Gets called if the parser has finished and all blocks are handled
     fn on_complete(&mut self, block_height: u64) -> OpResult<()>;
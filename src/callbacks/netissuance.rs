@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::callbacks::compress::{Compression, OutputWriter};
+use crate::callbacks::rotate::{ColumnType, Table};
+use crate::callbacks::{common, BlockFlow, Callback};
+use crate::errors::OpResult;
+
+const NET_ISSUANCE: Table = Table::csv(
+    "netissuance",
+    &[
+        ("height", ColumnType::Integer),
+        ("value_created", ColumnType::Integer),
+        ("value_spent", ColumnType::Integer),
+        ("net", ColumnType::Integer),
+    ],
+);
+
+/// Dumps the output value created and the input value spent per block.
+/// For a valid block, the difference is the subsidy minus the fees which were not claimed by the miner,
+/// so it can be used to cross-check the block data. Outputs created before the start height are unknown,
+/// so `value_spent` is too low until they have been spent.
+pub struct NetIssuance {
+    dump_folder: PathBuf,
+    writer: OutputWriter,
+
+    // key: txid + index, value: output value in satoshis
+    unspents: common::Unspents<u64>,
+
+    start_height: u64,
+    total_net: i64,
+}
+
+impl NetIssuance {
+    /// Returns (value_created, value_spent) of the block and updates the unspents
+    fn block_values(&mut self, block: &Block) -> (u64, u64) {
+        let (mut created, mut spent) = (0, 0);
+        for tx in &block.txs {
+            spent += common::take_unspents(tx, &mut self.unspents)
+                .map(|(_, value)| value)
+                .sum::<u64>();
+            created += tx.value.outputs.iter().map(|o| o.out.value).sum::<u64>();
+            common::insert_unspents_with(tx, block.genesis, &mut self.unspents, |output| {
+                Some(output.value)
+            });
+        }
+        (created, spent)
+    }
+}
+
+impl Callback for NetIssuance {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        let cmd = Command::new("netissuance")
+            .about("Dumps the value created and spent per block to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            );
+        Compression::add_args(cmd)
+    }
+
+    fn schema(_: &ArgMatches) -> Vec<Table>
+    where
+        Self: Sized,
+    {
+        vec![NET_ISSUANCE]
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let dump_folder = &PathBuf::from(matches.get_one::<String>("dump-folder").unwrap());
+        let cb = NetIssuance {
+            dump_folder: PathBuf::from(dump_folder),
+            writer: Compression::from_matches(matches)?
+                .create(&dump_folder.join("netissuance.csv.tmp"))?,
+            unspents: HashMap::with_capacity(10000000),
+            start_height: 0,
+            total_net: 0,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing netissuance with dump folder: {} ...", &self.dump_folder.display());
+        self.writer.write_all(NET_ISSUANCE.header().as_bytes())?;
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<BlockFlow> {
+        let (created, spent) = self.block_values(block);
+        let net = created as i64 - spent as i64;
+        self.total_net += net;
+        self.writer
+            .write_all(format!("{};{};{};{}\n", block_height, created, spent, net).as_bytes())?;
+        Ok(BlockFlow::Continue)
+    }
+
+    fn needs_addresses(&self) -> bool {
+        false
+    }
+
+    fn tracks_utxos(&self) -> bool {
+        true
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.writer.finish()?;
+        fs::rename(
+            self.dump_folder.as_path().join("netissuance.csv.tmp"),
+            self.dump_folder
+                .as_path()
+                .join(self.writer.file_name(format!(
+                    "netissuance-{}-{}.csv",
+                    self.start_height, block_height
+                ))),
+        )?;
+        info!(target: "callback", "Done.\nDumped net issuance from height {} to {}:\n\
+                                   \t-> net: {:20}",
+             self.start_height, block_height, self.total_net);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::parser::reader::BlockchainRead;
+    use crate::blockchain::parser::types::CoinType;
+    use crate::blockchain::proto::header::BlockHeader;
+    use crate::blockchain::proto::script::AddressFormat;
+    use crate::blockchain::proto::tx::{RawTx, TxInput, TxOutpoint, TxOutput};
+    use crate::blockchain::proto::varuint::VarUint;
+    use crate::common::utils;
+    use bitcoin::hashes::{sha256d, Hash};
+    use std::io::Cursor;
+
+    // Bitcoin blocks 0 and 1 without magic and size
+    const GENESIS: &str = concat!(
+        "0100000000000000000000000000000000000000000000000000000000000000",
+        "000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa",
+        "4b1e5e4a29ab5f49ffff001d1dac2b7c01010000000100000000000000000000",
+        "00000000000000000000000000000000000000000000ffffffff4d04ffff001d",
+        "0104455468652054696d65732030332f4a616e2f32303039204368616e63656c",
+        "6c6f72206f6e206272696e6b206f66207365636f6e64206261696c6f75742066",
+        "6f722062616e6b73ffffffff0100f2052a01000000434104678afdb0fe554827",
+        "1967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4",
+        "f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac00000000",
+    );
+    const BLOCK1: &str = concat!(
+        "010000006fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d61900",
+        "00000000982051fd1e4ba744bbbe680e1fee14677ba1a3c3540bf7b1cdb606e8",
+        "57233e0e61bc6649ffff001d01e3629901010000000100000000000000000000",
+        "00000000000000000000000000000000000000000000ffffffff0704ffff001d",
+        "0104ffffffff0100f2052a0100000043410496b538e853519c726a2c91e61ec1",
+        "1600ae1390813a627c66fb8be7947be63c52da7589379515d4e0a604f8141781",
+        "e62294721166bf621e73a82cbf2342c858eeac00000000",
+    );
+
+    fn read_block(hex: &str) -> Block {
+        let raw_block = utils::hex_to_vec(hex);
+        Cursor::new(&raw_block)
+            .read_block(raw_block.len() as u32, &CoinType::default())
+            .unwrap()
+    }
+
+    fn tx(outpoint: TxOutpoint, value: u64) -> RawTx {
+        RawTx {
+            version: 1,
+            in_count: VarUint::from(1u8),
+            inputs: vec![TxInput {
+                outpoint,
+                script_len: VarUint::from(0u8),
+                script_sig: vec![],
+                seq_no: 0xFFFFFFFF,
+                witness: vec![],
+            }],
+            out_count: VarUint::from(1u8),
+            outputs: vec![TxOutput {
+                value,
+                script_len: VarUint::from(0u8),
+                script_pubkey: vec![],
+            }],
+            locktime: 0,
+            address_format: AddressFormat::default(),
+            witness_size: 0,
+        }
+    }
+
+    #[test]
+    fn test_net_issuance() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let matches = NetIssuance::build_subcommand()
+            .get_matches_from(["netissuance", tmp_dir.path().to_str().unwrap()]);
+        let mut callback = NetIssuance::new(&matches).unwrap();
+        callback.on_start(0).unwrap();
+
+        let block1 = read_block(BLOCK1);
+        assert_eq!(
+            utils::HashByteOrder::Rpc.hex(&block1.header.hash),
+            "00000000839a8e6886ab5951d76f411475428afc90947ee320161bbf18eb6048"
+        );
+        callback.on_block(&read_block(GENESIS), 0).unwrap();
+        callback.on_block(&block1, 1).unwrap();
+
+        // Spends the coinbase of block 1 with a fee of 1000, of which the miner only claims 500
+        let header = BlockHeader {
+            version: 1,
+            prev_hash: block1.header.hash,
+            merkle_root: sha256d::Hash::all_zeros(),
+            timestamp: 0,
+            bits: 0,
+            nonce: 0,
+        };
+        let coinbase = TxOutpoint::new(sha256d::Hash::all_zeros(), 0xFFFFFFFF);
+        let spend = TxOutpoint::new(block1.txs[0].hash, 0);
+        let txs = vec![tx(coinbase, 5000000500), tx(spend, 4999999000)];
+        let block2 = Block::new(0, header, None, VarUint::from(2u8), txs);
+        callback.on_block(&block2, 2).unwrap();
+        callback.on_complete(2).unwrap();
+        assert_eq!(callback.total_net, 14999999500);
+
+        let csv = fs::read_to_string(tmp_dir.path().join("netissuance-0-2.csv")).unwrap();
+        NET_ISSUANCE.assert_csv(&csv);
+        assert_eq!(
+            csv,
+            "height;value_created;value_spent;net\n\
+             0;5000000000;0;5000000000\n\
+             1;5000000000;0;5000000000\n\
+             2;9999999500;5000000000;4999999500\n"
+        );
+    }
+}
@@ -1,12 +1,58 @@
-use clap::{ArgMatches, Command};
+use clap::{Arg, ArgMatches, Command};
 
 use crate::blockchain::proto::block::Block;
-use crate::blockchain::proto::script::ScriptPattern;
-use crate::callbacks::Callback;
+use crate::blockchain::proto::script::{self, ScriptPattern};
+use crate::callbacks::{BlockFlow, Callback};
+use crate::common::utils::{self, HashByteOrder};
 use crate::errors::OpResult;
 
+/// Shows the data pushed after OP_RETURN, one row per push.
+/// With `--join-hex` the pushes of each output are concatenated and shown as hex instead.
 #[derive(Default)]
-pub struct OpReturn;
+pub struct OpReturn {
+    join_hex: bool,
+    hash_byte_order: HashByteOrder,
+}
+
+impl OpReturn {
+    /// Returns the rows to show for the OP_RETURN outputs of the block
+    fn rows(&self, block: &Block, block_height: u64) -> Vec<String> {
+        let mut rows = Vec::new();
+        for tx in &block.txs {
+            for out in tx.value.outputs.iter() {
+                if !matches!(out.script.pattern, ScriptPattern::OpReturn(_)) {
+                    continue;
+                }
+                let prefix = format!(
+                    "height: {: <9} txid: {}",
+                    block_height,
+                    self.hash_byte_order.hex(&tx.hash)
+                );
+                let pushes = script::op_return_pushes(&out.out.script_pubkey);
+                if self.join_hex {
+                    let payload = pushes.concat();
+                    if !payload.is_empty() {
+                        rows.push(format!(
+                            "{}    data: {}",
+                            prefix,
+                            utils::arr_to_hex(&payload)
+                        ));
+                    }
+                    continue;
+                }
+                for (index, push) in pushes.iter().enumerate() {
+                    match std::str::from_utf8(push) {
+                        Ok(data) if !data.is_empty() => {
+                            rows.push(format!("{}    push: {: <3} data: {}", prefix, index, data))
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        rows
+    }
+}
 
 impl Callback for OpReturn {
     fn build_subcommand() -> Command
@@ -17,13 +63,22 @@ impl Callback for OpReturn {
             .about("Shows embedded OP_RETURN data that is representable as UTF8")
             .version("0.1")
             .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("join-hex")
+                    .long("join-hex")
+                    .action(clap::ArgAction::SetTrue)
+                    .help("Shows the concatenated pushes of each output as hex"),
+            )
     }
 
-    fn new(_: &ArgMatches) -> OpResult<Self>
+    fn new(matches: &ArgMatches) -> OpResult<Self>
     where
         Self: Sized,
     {
-        Ok(OpReturn::default())
+        Ok(OpReturn {
+            join_hex: matches.get_flag("join-hex"),
+            hash_byte_order: HashByteOrder::from_matches(matches)?,
+        })
     }
 
     fn on_start(&mut self, _: u64) -> OpResult<()> {
@@ -31,21 +86,15 @@ impl Callback for OpReturn {
         Ok(())
     }
 
-    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
-        for tx in &block.txs {
-            for out in tx.value.outputs.iter() {
-                if let ScriptPattern::OpReturn(data) = &out.script.pattern {
-                    if data.is_empty() {
-                        continue;
-                    }
-                    println!(
-                        "height: {: <9} txid: {}    data: {}",
-                        block_height, &tx.hash, data
-                    );
-                }
-            }
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<BlockFlow> {
+        for row in self.rows(block, block_height) {
+            println!("{}", row);
         }
-        Ok(())
+        Ok(BlockFlow::Continue)
+    }
+
+    fn needs_addresses(&self) -> bool {
+        false
     }
 
     fn on_complete(&mut self, _: u64) -> OpResult<()> {
@@ -56,3 +105,75 @@ impl Callback for OpReturn {
         false
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::header::BlockHeader;
+    use crate::blockchain::proto::script::AddressFormat;
+    use crate::blockchain::proto::tx::{RawTx, TxOutput};
+    use crate::blockchain::proto::varuint::VarUint;
+    use bitcoin::hashes::{sha256d, Hash};
+
+    fn block(scripts: &[&str]) -> Block {
+        let tx = RawTx {
+            version: 1,
+            in_count: VarUint::from(0u8),
+            inputs: vec![],
+            out_count: VarUint::from(scripts.len() as u8),
+            outputs: scripts
+                .iter()
+                .map(|script| {
+                    let script_pubkey = utils::hex_to_vec(script);
+                    TxOutput {
+                        value: 0,
+                        script_len: VarUint::from(script_pubkey.len() as u8),
+                        script_pubkey,
+                    }
+                })
+                .collect(),
+            locktime: 0,
+            address_format: AddressFormat::default(),
+            witness_size: 0,
+        };
+        let header = BlockHeader {
+            version: 1,
+            prev_hash: sha256d::Hash::all_zeros(),
+            merkle_root: sha256d::Hash::all_zeros(),
+            timestamp: 0,
+            bits: 0,
+            nonce: 0,
+        };
+        Block::new(0, header, None, VarUint::from(1u8), vec![tx])
+    }
+
+    #[test]
+    fn test_multiple_pushes() {
+        // OP_RETURN "hello" <ff> "world", followed by a P2PKH output
+        let block = block(&[
+            "6a0568656c6c6f01ff05776f726c64",
+            "76a91412ab8dc588ca9d5787dde7eb29569da63c3a238c88ac",
+        ]);
+        let prefix = format!(
+            "height: 7         txid: {}",
+            HashByteOrder::Rpc.hex(&block.txs[0].hash)
+        );
+
+        let matches = OpReturn::build_subcommand().get_matches_from(["opreturn"]);
+        let callback = OpReturn::new(&matches).unwrap();
+        assert_eq!(
+            callback.rows(&block, 7),
+            vec![
+                format!("{}    push: 0   data: hello", prefix),
+                format!("{}    push: 2   data: world", prefix),
+            ]
+        );
+
+        let matches = OpReturn::build_subcommand().get_matches_from(["opreturn", "--join-hex"]);
+        let callback = OpReturn::new(&matches).unwrap();
+        assert_eq!(
+            callback.rows(&block, 7),
+            vec![format!("{}    data: 68656c6c6fff776f726c64", prefix)]
+        );
+    }
+}
@@ -0,0 +1,197 @@
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::script::ScriptPattern;
+use crate::callbacks::compress::{Compression, OutputWriter};
+use crate::callbacks::opreturnprotocols::op_return_payload;
+use crate::callbacks::rotate::{ColumnType, Table};
+use crate::callbacks::{BlockFlow, Callback};
+use crate::errors::OpResult;
+
+const OP_RETURN_BYTES: Table = Table::csv(
+    "opreturnbytes",
+    &[
+        ("height", ColumnType::Integer),
+        ("timestamp", ColumnType::Integer),
+        ("op_return_bytes", ColumnType::Integer),
+        ("cumulative_bytes", ColumnType::Integer),
+    ],
+);
+
+/// Dumps the number of bytes pushed in OP_RETURN outputs per block and the running total.
+/// Only the pushed data is counted, not OP_RETURN itself or the push opcodes.
+pub struct OpReturnBytes {
+    dump_folder: PathBuf,
+    writer: OutputWriter,
+
+    start_height: u64,
+    output_count: u64,
+    cumulative_bytes: u64,
+}
+
+impl Callback for OpReturnBytes {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        let cmd = Command::new("opreturnbytes")
+            .about("Dumps the number of bytes stored in OP_RETURN outputs per block to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            );
+        Compression::add_args(cmd)
+    }
+
+    fn schema(_: &ArgMatches) -> Vec<Table>
+    where
+        Self: Sized,
+    {
+        vec![OP_RETURN_BYTES]
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let dump_folder = &PathBuf::from(matches.get_one::<String>("dump-folder").unwrap());
+        let cb = OpReturnBytes {
+            dump_folder: PathBuf::from(dump_folder),
+            writer: Compression::from_matches(matches)?
+                .create(&dump_folder.join("opreturnbytes.csv.tmp"))?,
+            start_height: 0,
+            output_count: 0,
+            cumulative_bytes: 0,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing opreturnbytes with dump folder: {} ...", &self.dump_folder.display());
+        self.writer.write_all(OP_RETURN_BYTES.header().as_bytes())?;
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<BlockFlow> {
+        let mut bytes = 0;
+        for output in block.txs.iter().flat_map(|tx| &tx.value.outputs) {
+            if matches!(output.script.pattern, ScriptPattern::OpReturn(_)) {
+                bytes += op_return_payload(&output.out.script_pubkey).len() as u64;
+                self.output_count += 1;
+            }
+        }
+        self.cumulative_bytes += bytes;
+        self.writer.write_all(
+            format!(
+                "{};{};{};{}\n",
+                block_height, block.header.value.timestamp, bytes, self.cumulative_bytes
+            )
+            .as_bytes(),
+        )?;
+        Ok(BlockFlow::Continue)
+    }
+
+    fn needs_addresses(&self) -> bool {
+        false
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.writer.finish()?;
+        fs::rename(
+            self.dump_folder.as_path().join("opreturnbytes.csv.tmp"),
+            self.dump_folder
+                .as_path()
+                .join(self.writer.file_name(format!(
+                    "opreturnbytes-{}-{}.csv",
+                    self.start_height, block_height
+                ))),
+        )?;
+        info!(target: "callback", "Done.\nDumped OP_RETURN bytes from height {} to {}:\n\
+                                   \t-> outputs: {:9}\n\
+                                   \t-> bytes:   {:9}",
+             self.start_height, block_height, self.output_count, self.cumulative_bytes);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::header::BlockHeader;
+    use crate::blockchain::proto::script::AddressFormat;
+    use crate::blockchain::proto::tx::{RawTx, TxOutput};
+    use crate::blockchain::proto::varuint::VarUint;
+    use crate::common::utils;
+    use bitcoin::hashes::{sha256d, Hash};
+
+    fn block(timestamp: u32, scripts: &[&str]) -> Block {
+        let tx = RawTx {
+            version: 1,
+            in_count: VarUint::from(0u8),
+            inputs: vec![],
+            out_count: VarUint::from(scripts.len() as u8),
+            outputs: scripts
+                .iter()
+                .map(|script| {
+                    let script_pubkey = utils::hex_to_vec(script);
+                    TxOutput {
+                        value: 0,
+                        script_len: VarUint::from(script_pubkey.len() as u8),
+                        script_pubkey,
+                    }
+                })
+                .collect(),
+            locktime: 0,
+            address_format: AddressFormat::default(),
+            witness_size: 0,
+        };
+        let header = BlockHeader {
+            version: 1,
+            prev_hash: sha256d::Hash::all_zeros(),
+            merkle_root: sha256d::Hash::all_zeros(),
+            timestamp,
+            bits: 0,
+            nonce: 0,
+        };
+        Block::new(0, header, None, VarUint::from(1u8), vec![tx])
+    }
+
+    #[test]
+    fn test_op_return_bytes() {
+        // 19 bytes in a single push, 5 bytes in two pushes and a bare OP_RETURN
+        let text = "6a13636861726c6579206c6f766573206865696469";
+        let pushes = "6a0268690368656c";
+        let bare = "6a";
+        let p2pkh = "76a91462e907b15cbf27d5425399ebf6f0fb50ebb88f1888ac";
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let matches = OpReturnBytes::build_subcommand()
+            .get_matches_from(["opreturnbytes", tmp_dir.path().to_str().unwrap()]);
+        let mut callback = OpReturnBytes::new(&matches).unwrap();
+        callback.on_start(1).unwrap();
+        callback.on_block(&block(100, &[text, p2pkh]), 1).unwrap();
+        callback.on_block(&block(200, &[p2pkh]), 2).unwrap();
+        callback.on_block(&block(300, &[pushes, bare]), 3).unwrap();
+        callback.on_complete(3).unwrap();
+        assert_eq!(callback.output_count, 3);
+
+        let csv = fs::read_to_string(tmp_dir.path().join("opreturnbytes-1-3.csv")).unwrap();
+        OP_RETURN_BYTES.assert_csv(&csv);
+        assert_eq!(
+            csv,
+            "height;timestamp;op_return_bytes;cumulative_bytes\n\
+             1;100;19;19\n\
+             2;200;0;19\n\
+             3;300;5;24\n"
+        );
+    }
+}
@@ -0,0 +1,326 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+use bitcoin::hashes::hex::FromHex;
+use clap::{Arg, ArgMatches, Command};
+use serde_json::Value;
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::script::{self, ScriptPattern};
+use crate::blockchain::proto::tx::EvaluatedTx;
+use crate::blockchain::proto::Hashed;
+use crate::callbacks::compress::{Compression, OutputWriter};
+use crate::callbacks::rotate::{ColumnType, Table};
+use crate::callbacks::{BlockFlow, Callback};
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+const OP_RETURN_PROTOCOLS: Table = Table::csv(
+    "opreturnprotocols",
+    &[
+        ("period", ColumnType::Integer),
+        ("protocol", ColumnType::String),
+        ("count", ColumnType::Integer),
+        ("total_data_bytes", ColumnType::Integer),
+    ],
+);
+
+/// Prefixes which are always known, `--protocol-map` extends them
+const BUNDLED_PROTOCOL_MAP: &str = include_str!("opreturnprotocols.json");
+
+/// Maps OP_RETURN payload prefixes to protocol names
+pub struct ProtocolMap {
+    // Sorted by descending prefix length, so more specific prefixes win
+    prefixes: Vec<(Vec<u8>, String)>,
+}
+
+impl ProtocolMap {
+    /// Parses a mapping in the following format: `{"<hex prefix>": "<protocol>"}`
+    pub fn from_json(json: &str) -> OpResult<Self> {
+        let mut map = ProtocolMap {
+            prefixes: Vec::new(),
+        };
+        map.extend_from_json(json)?;
+        Ok(map)
+    }
+
+    /// Adds the prefixes of the given mapping, existing prefixes are overwritten
+    pub fn extend_from_json(&mut self, json: &str) -> OpResult<()> {
+        let invalid = |msg: String| {
+            OpError::new(OpErrorKind::InvalidArgsError)
+                .join_msg(&format!("Unable to parse protocol map: {}", msg))
+        };
+        let value: Value = serde_json::from_str(json).map_err(|e| invalid(e.to_string()))?;
+        let entries = value
+            .as_object()
+            .ok_or_else(|| invalid(String::from("expected an object")))?;
+        for (prefix, name) in entries {
+            let bytes = Vec::<u8>::from_hex(prefix)
+                .map_err(|e| invalid(format!("invalid prefix `{}`: {}", prefix, e)))?;
+            if bytes.is_empty() {
+                return Err(invalid(String::from("prefixes must not be empty")));
+            }
+            let name = name
+                .as_str()
+                .ok_or_else(|| invalid(format!("`{}` must map to a protocol name", prefix)))?;
+            self.prefixes.retain(|(p, _)| *p != bytes);
+            self.prefixes.push((bytes, name.to_string()));
+        }
+        self.prefixes
+            .sort_by(|a, b| b.0.len().cmp(&a.0.len()).then(a.0.cmp(&b.0)));
+        Ok(())
+    }
+
+    /// Returns the protocol name for the given payload or `other`
+    pub fn classify(&self, payload: &[u8]) -> &str {
+        self.prefixes
+            .iter()
+            .find(|(prefix, _)| payload.starts_with(prefix))
+            .map_or("other", |(_, name)| name.as_str())
+    }
+}
+
+/// Returns the concatenated pushes following OP_RETURN
+pub fn op_return_payload(script_pubkey: &[u8]) -> Vec<u8> {
+    script::op_return_pushes(script_pubkey).concat()
+}
+
+/// Classifies OP_RETURN outputs by protocol prefix and counts them per period of blocks
+pub struct OpReturnProtocols {
+    dump_folder: PathBuf,
+    writer: OutputWriter,
+    period: u64,
+    protocol_map: ProtocolMap,
+
+    // First height of the current period
+    cur_period: u64,
+    // protocol -> (count, total_data_bytes) of the current period
+    counts: BTreeMap<String, (u64, u64)>,
+
+    start_height: u64,
+    output_count: u64,
+}
+
+impl OpReturnProtocols {
+    /// Writes the counts of the current period and resets them
+    fn flush_period(&mut self) -> OpResult<()> {
+        for (protocol, (count, data_bytes)) in &self.counts {
+            self.writer.write_all(
+                format!(
+                    "{};{};{};{}\n",
+                    self.cur_period, protocol, count, data_bytes
+                )
+                .as_bytes(),
+            )?;
+        }
+        self.counts.clear();
+        Ok(())
+    }
+}
+
+impl Callback for OpReturnProtocols {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        let cmd = Command::new("opreturnprotocols")
+            .about("Dumps OP_RETURN outputs per protocol and period to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            )
+            .arg(
+                Arg::new("period")
+                    .long("period")
+                    .value_name("NUM")
+                    .value_parser(clap::value_parser!(u64).range(1..))
+                    .default_value("2016")
+                    .help("Number of blocks per period"),
+            )
+            .arg(
+                Arg::new("protocol-map")
+                    .long("protocol-map")
+                    .value_name("PATH")
+                    .help("JSON file mapping hex payload prefixes to protocol names, extends the bundled mapping"),
+            );
+        Compression::add_args(cmd)
+    }
+
+    fn schema(_: &ArgMatches) -> Vec<Table>
+    where
+        Self: Sized,
+    {
+        vec![OP_RETURN_PROTOCOLS]
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let dump_folder = &PathBuf::from(matches.get_one::<String>("dump-folder").unwrap());
+        let mut protocol_map = ProtocolMap::from_json(BUNDLED_PROTOCOL_MAP)?;
+        if let Some(path) = matches.get_one::<String>("protocol-map") {
+            protocol_map.extend_from_json(&fs::read_to_string(path)?)?;
+        }
+        let cb = OpReturnProtocols {
+            dump_folder: PathBuf::from(dump_folder),
+            writer: Compression::from_matches(matches)?
+                .create(&dump_folder.join("opreturnprotocols.csv.tmp"))?,
+            period: *matches.get_one::<u64>("period").unwrap(),
+            protocol_map,
+            cur_period: 0,
+            counts: BTreeMap::new(),
+            start_height: 0,
+            output_count: 0,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        self.cur_period = block_height - block_height % self.period;
+        info!(target: "callback", "Executing opreturnprotocols with dump folder: {} ...", &self.dump_folder.display());
+        self.writer
+            .write_all(OP_RETURN_PROTOCOLS.header().as_bytes())?;
+        Ok(())
+    }
+
+    fn on_block(&mut self, _: &Block, _: u64) -> OpResult<BlockFlow> {
+        Ok(BlockFlow::Continue)
+    }
+
+    fn on_tx(&mut self, tx: &Hashed<EvaluatedTx>, block_height: u64) -> OpResult<()> {
+        let period = block_height - block_height % self.period;
+        if period != self.cur_period {
+            self.flush_period()?;
+            self.cur_period = period;
+        }
+        for output in &tx.value.outputs {
+            if !matches!(output.script.pattern, ScriptPattern::OpReturn(_)) {
+                continue;
+            }
+            let payload = op_return_payload(&output.out.script_pubkey);
+            let protocol = self.protocol_map.classify(&payload);
+            let entry = match self.counts.get_mut(protocol) {
+                Some(entry) => entry,
+                None => self.counts.entry(protocol.to_string()).or_default(),
+            };
+            entry.0 += 1;
+            entry.1 += payload.len() as u64;
+            self.output_count += 1;
+        }
+        Ok(())
+    }
+
+    fn needs_addresses(&self) -> bool {
+        false
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.flush_period()?;
+        self.writer.finish()?;
+        fs::rename(
+            self.dump_folder.as_path().join("opreturnprotocols.csv.tmp"),
+            self.dump_folder
+                .as_path()
+                .join(self.writer.file_name(format!(
+                    "opreturnprotocols-{}-{}.csv",
+                    self.start_height, block_height
+                ))),
+        )?;
+        info!(target: "callback", "Done.\nDumped OP_RETURN protocols from height {} to {}:\n\
+                                   \t-> outputs: {:9}",
+             self.start_height, block_height, self.output_count);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::script::AddressFormat;
+    use crate::blockchain::proto::tx::{RawTx, TxOutput};
+    use crate::blockchain::proto::varuint::VarUint;
+    use crate::common::utils;
+
+    fn tx(scripts: &[&str]) -> Hashed<EvaluatedTx> {
+        Hashed::double_sha256(EvaluatedTx::from(RawTx {
+            version: 1,
+            in_count: VarUint::from(0u8),
+            inputs: vec![],
+            out_count: VarUint::from(scripts.len() as u8),
+            outputs: scripts
+                .iter()
+                .map(|script| {
+                    let script_pubkey = utils::hex_to_vec(script);
+                    TxOutput {
+                        value: 0,
+                        script_len: VarUint::from(script_pubkey.len() as u8),
+                        script_pubkey,
+                    }
+                })
+                .collect(),
+            locktime: 0,
+            address_format: AddressFormat::default(),
+            witness_size: 0,
+        }))
+    }
+
+    #[test]
+    fn test_protocol_map() {
+        let mut map = ProtocolMap::from_json(BUNDLED_PROTOCOL_MAP).unwrap();
+        assert_eq!(map.classify(&utils::hex_to_vec("6f6d6e6900000000")), "omni");
+        assert_eq!(map.classify(&utils::hex_to_vec("6f6d")), "other");
+        assert_eq!(map.classify(&[]), "other");
+
+        // User prefixes extend the bundled ones, the longest prefix wins
+        map.extend_from_json(r#"{"6f6d6e6901": "omni-v1", "aabb": "custom"}"#)
+            .unwrap();
+        assert_eq!(map.classify(&utils::hex_to_vec("6f6d6e6901ff")), "omni-v1");
+        assert_eq!(map.classify(&utils::hex_to_vec("6f6d6e6900ff")), "omni");
+        assert_eq!(map.classify(&utils::hex_to_vec("aabbcc")), "custom");
+
+        assert!(ProtocolMap::from_json(r#"{"zz": "invalid"}"#).is_err());
+        assert!(ProtocolMap::from_json(r#"{"": "empty"}"#).is_err());
+        assert!(ProtocolMap::from_json(r#"["6f6d6e69"]"#).is_err());
+    }
+
+    #[test]
+    fn test_op_return_protocols() {
+        let omni = "6a146f6d6e69000000000000001f000000002faf0800";
+        let stacks = "6a0458325b00";
+        let text = "6a13636861726c6579206c6f766573206865696469";
+        let p2pkh = "76a91462e907b15cbf27d5425399ebf6f0fb50ebb88f1888ac";
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let matches = OpReturnProtocols::build_subcommand().get_matches_from([
+            "opreturnprotocols",
+            tmp_dir.path().to_str().unwrap(),
+            "--period",
+            "10",
+        ]);
+        let mut callback = OpReturnProtocols::new(&matches).unwrap();
+        callback.on_start(5).unwrap();
+        callback
+            .on_txs(&[tx(&[omni, p2pkh]), tx(&[text])], 5)
+            .unwrap();
+        callback.on_txs(&[tx(&[omni])], 9).unwrap();
+        callback.on_txs(&[tx(&[stacks, p2pkh])], 12).unwrap();
+        callback.on_complete(12).unwrap();
+
+        let csv = fs::read_to_string(tmp_dir.path().join("opreturnprotocols-5-12.csv")).unwrap();
+        OP_RETURN_PROTOCOLS.assert_csv(&csv);
+        assert_eq!(
+            csv,
+            "period;protocol;count;total_data_bytes\n\
+             0;omni;2;40\n\
+             0;other;1;19\n\
+             10;stacks;1;4\n"
+        );
+    }
+}
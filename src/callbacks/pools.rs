@@ -0,0 +1,261 @@
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+use clap::{Arg, ArgMatches, Command};
+use serde_json::Value;
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::tx::EvaluatedTx;
+use crate::callbacks::compress::{Compression, OutputWriter};
+use crate::callbacks::rotate::{ColumnType, Table};
+use crate::callbacks::{BlockFlow, Callback};
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+const POOLS: Table = Table::csv(
+    "pools",
+    &[
+        ("height", ColumnType::Integer),
+        ("pool_name", ColumnType::String),
+        ("method", ColumnType::String),
+    ],
+);
+
+/// Mapping which is used if no `--pool-map` is given
+const BUNDLED_POOL_MAP: &str = include_str!("pools.json");
+
+/// Maps coinbase tags and payout addresses to pool names
+pub struct PoolMap {
+    // Sorted by descending tag length, so more specific tags win
+    coinbase_tags: Vec<(String, String)>,
+    payout_addresses: Vec<(String, String)>,
+}
+
+impl PoolMap {
+    /// Parses a mapping in the following format:
+    /// `{"coinbase_tags": {"<tag>": "<pool>"}, "payout_addresses": {"<address>": "<pool>"}}`
+    pub fn from_json(json: &str) -> OpResult<Self> {
+        let value: Value = serde_json::from_str(json).map_err(|e| {
+            OpError::new(OpErrorKind::InvalidArgsError)
+                .join_msg(&format!("Unable to parse pool map: {}", e))
+        })?;
+        let mut coinbase_tags = PoolMap::parse_section(&value, "coinbase_tags")?;
+        coinbase_tags.sort_by(|a, b| b.0.len().cmp(&a.0.len()).then(a.0.cmp(&b.0)));
+        Ok(Self {
+            coinbase_tags,
+            payout_addresses: PoolMap::parse_section(&value, "payout_addresses")?,
+        })
+    }
+
+    fn parse_section(value: &Value, key: &str) -> OpResult<Vec<(String, String)>> {
+        let section = match value.get(key) {
+            Some(section) => section,
+            None => return Ok(Vec::new()),
+        };
+        let invalid = || {
+            OpError::new(OpErrorKind::InvalidArgsError).join_msg(&format!(
+                "Pool map: `{}` must map strings to pool names",
+                key
+            ))
+        };
+        let mut entries = Vec::new();
+        for (k, v) in section.as_object().ok_or_else(invalid)? {
+            entries.push((k.clone(), v.as_str().ok_or_else(invalid)?.to_string()));
+        }
+        Ok(entries)
+    }
+
+    /// Returns pool name and identification method for the given coinbase transaction
+    pub fn identify(&self, coinbase: &EvaluatedTx) -> (&str, &'static str) {
+        if let Some(input) = coinbase.inputs.first() {
+            let tag = String::from_utf8_lossy(&input.script_sig);
+            for (needle, pool) in &self.coinbase_tags {
+                if tag.contains(needle.as_str()) {
+                    return (pool, "tag");
+                }
+            }
+        }
+        for output in &coinbase.outputs {
+            if let Some(address) = &output.script.address {
+                for (payout, pool) in &self.payout_addresses {
+                    if address == payout {
+                        return (pool, "address");
+                    }
+                }
+            }
+        }
+        ("unknown", "unknown")
+    }
+}
+
+/// Labels each block with its likely mining pool
+pub struct Pools {
+    dump_folder: PathBuf,
+    writer: OutputWriter,
+    pool_map: PoolMap,
+
+    start_height: u64,
+    n_identified: u64,
+    n_unknown: u64,
+}
+
+impl Callback for Pools {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        let cmd = Command::new("pools")
+            .about("Labels each block with its likely mining pool")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            )
+            .arg(
+                Arg::new("pool-map")
+                    .long("pool-map")
+                    .value_name("PATH")
+                    .help("JSON file mapping coinbase tags and payout addresses to pool names"),
+            );
+        Compression::add_args(cmd)
+    }
+
+    fn schema(_: &ArgMatches) -> Vec<Table>
+    where
+        Self: Sized,
+    {
+        vec![POOLS]
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let dump_folder = &PathBuf::from(matches.get_one::<String>("dump-folder").unwrap());
+        let pool_map = match matches.get_one::<String>("pool-map") {
+            Some(path) => PoolMap::from_json(&fs::read_to_string(path)?)?,
+            None => PoolMap::from_json(BUNDLED_POOL_MAP)?,
+        };
+        let cb = Pools {
+            dump_folder: PathBuf::from(dump_folder),
+            writer: Compression::from_matches(matches)?
+                .create(&dump_folder.join("pools.csv.tmp"))?,
+            pool_map,
+            start_height: 0,
+            n_identified: 0,
+            n_unknown: 0,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing pools with dump folder: {} ...", &self.dump_folder.display());
+        self.writer.write_all(POOLS.header().as_bytes())?;
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<BlockFlow> {
+        let (pool, method) = match block.txs.first() {
+            Some(coinbase) => self.pool_map.identify(&coinbase.value),
+            None => ("unknown", "unknown"),
+        };
+        if method == "unknown" {
+            self.n_unknown += 1;
+        } else {
+            self.n_identified += 1;
+        }
+        self.writer
+            .write_all(format!("{};{};{}\n", block_height, pool, method).as_bytes())?;
+        Ok(BlockFlow::Continue)
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.writer.finish()?;
+        fs::rename(
+            self.dump_folder.as_path().join("pools.csv.tmp"),
+            self.dump_folder.as_path().join(
+                self.writer
+                    .file_name(format!("pools-{}-{}.csv", self.start_height, block_height)),
+            ),
+        )?;
+        info!(target: "callback", "Done.\nLabeled blocks from height {} to {}:\n\
+                                   \t-> identified: {:9}\n\
+                                   \t-> unknown:    {:9}",
+             self.start_height, block_height, self.n_identified, self.n_unknown);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::script::AddressFormat;
+    use crate::blockchain::proto::tx::{RawTx, TxInput, TxOutpoint, TxOutput};
+    use crate::blockchain::proto::varuint::VarUint;
+    use bitcoin::hashes::{sha256d, Hash};
+
+    fn coinbase(script_sig: &[u8], script_pubkey: Vec<u8>) -> EvaluatedTx {
+        let input = TxInput {
+            outpoint: TxOutpoint::new(sha256d::Hash::all_zeros(), 0xFFFFFFFF),
+            script_len: VarUint::from(script_sig.len() as u8),
+            script_sig: script_sig.to_vec(),
+            seq_no: 0xFFFFFFFF,
+            witness: vec![],
+        };
+        let output = TxOutput {
+            value: 625000000,
+            script_len: VarUint::from(script_pubkey.len() as u8),
+            script_pubkey,
+        };
+        EvaluatedTx::from(RawTx {
+            version: 1,
+            in_count: VarUint::from(1u8),
+            inputs: vec![input],
+            out_count: VarUint::from(1u8),
+            outputs: vec![output],
+            locktime: 0,
+            address_format: AddressFormat::default(),
+            witness_size: 0,
+        })
+    }
+
+    #[test]
+    fn test_identify_pool() {
+        let pool_map = PoolMap::from_json(
+            r#"{
+                "coinbase_tags": {"/Pool/": "Pool", "/Pool/Special/": "Special Pool"},
+                "payout_addresses": {"1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa": "Genesis Pool"}
+            }"#,
+        )
+        .unwrap();
+
+        let tx = coinbase(b"\x03\xa0\x86\x01/Pool/Special/", vec![]);
+        assert_eq!(pool_map.identify(&tx), ("Special Pool", "tag"));
+        let tx = coinbase(b"\x03\xa0\x86\x01/Pool/", vec![]);
+        assert_eq!(pool_map.identify(&tx), ("Pool", "tag"));
+
+        // P2PKH to 1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa
+        let mut script_pubkey = vec![0x76, 0xa9, 0x14];
+        script_pubkey.extend(crate::common::utils::hex_to_vec(
+            "62e907b15cbf27d5425399ebf6f0fb50ebb88f18",
+        ));
+        script_pubkey.extend([0x88, 0xac]);
+        let tx = coinbase(b"\x03\xa0\x86\x01", script_pubkey);
+        assert_eq!(pool_map.identify(&tx), ("Genesis Pool", "address"));
+
+        let tx = coinbase(b"\x03\xa0\x86\x01", vec![]);
+        assert_eq!(pool_map.identify(&tx), ("unknown", "unknown"));
+    }
+
+    #[test]
+    fn test_bundled_pool_map() {
+        let pool_map = PoolMap::from_json(BUNDLED_POOL_MAP).unwrap();
+        let tx = coinbase(b"\x03\xa0\x86\x01/ViaBTC/", vec![]);
+        assert_eq!(pool_map.identify(&tx), ("ViaBTC", "tag"));
+        assert!(PoolMap::from_json(r#"{"coinbase_tags": []}"#).is_err());
+    }
+}
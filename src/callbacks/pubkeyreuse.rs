@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::PathBuf;
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::script::{self, ScriptPattern};
+use crate::blockchain::proto::tx::EvaluatedTx;
+use crate::blockchain::proto::Hashed;
+use crate::callbacks::compress::Compression;
+use crate::callbacks::rotate::{ColumnType, Table};
+use crate::callbacks::{BlockFlow, Callback};
+use crate::common::utils;
+use crate::errors::OpResult;
+
+const PUBKEY_REUSE: Table = Table::csv(
+    "pubkeyreuse",
+    &[
+        ("keyhash", ColumnType::String),
+        ("p2pk_received", ColumnType::Integer),
+        ("p2pkh_received", ColumnType::Integer),
+    ],
+);
+
+/// Links P2PK outputs to P2PKH outputs paying to the same public key hash.
+/// P2PK keys are hashed as they appear in the script, so a P2PKH output only matches
+/// P2PK outputs using the same (compressed or uncompressed) key encoding.
+/// Only key hashes which received funds in both forms are dumped.
+pub struct PubkeyReuse {
+    dump_folder: PathBuf,
+    compression: Compression,
+    // Value received via (P2PK, P2PKH) per public key hash
+    received: HashMap<[u8; 20], (u64, u64)>,
+
+    start_height: u64,
+}
+
+impl PubkeyReuse {
+    /// Returns the key hashes which received funds in both forms, sorted by key hash
+    fn linked(&self) -> Vec<(&[u8; 20], u64, u64)> {
+        let mut linked: Vec<(&[u8; 20], u64, u64)> = self
+            .received
+            .iter()
+            .filter(|(_, (p2pk, p2pkh))| *p2pk > 0 && *p2pkh > 0)
+            .map(|(key_hash, (p2pk, p2pkh))| (key_hash, *p2pk, *p2pkh))
+            .collect();
+        linked.sort_unstable();
+        linked
+    }
+}
+
+impl Callback for PubkeyReuse {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        let cmd = Command::new("pubkeyreuse")
+            .about(
+                "Dumps public key hashes which received funds via both P2PK and P2PKH to CSV file",
+            )
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            );
+        Compression::add_args(cmd)
+    }
+
+    fn schema(_: &ArgMatches) -> Vec<Table>
+    where
+        Self: Sized,
+    {
+        vec![PUBKEY_REUSE]
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let dump_folder = &PathBuf::from(matches.get_one::<String>("dump-folder").unwrap());
+        let cb = PubkeyReuse {
+            dump_folder: PathBuf::from(dump_folder),
+            compression: Compression::from_matches(matches)?,
+            received: HashMap::with_capacity(10000000),
+            start_height: 0,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing pubkeyreuse with dump folder: {} ...", &self.dump_folder.display());
+        Ok(())
+    }
+
+    fn on_block(&mut self, _: &Block, _: u64) -> OpResult<BlockFlow> {
+        Ok(BlockFlow::Continue)
+    }
+
+    fn on_tx(&mut self, tx: &Hashed<EvaluatedTx>, _: u64) -> OpResult<()> {
+        for output in &tx.value.outputs {
+            let is_p2pk = match output.script.pattern {
+                ScriptPattern::Pay2PublicKey => true,
+                ScriptPattern::Pay2PublicKeyHash => false,
+                _ => continue,
+            };
+            if let Some(key_hash) = script::pubkey_hash(&output.out.script_pubkey) {
+                let entry = self.received.entry(key_hash).or_default();
+                if is_p2pk {
+                    entry.0 += output.out.value;
+                } else {
+                    entry.1 += output.out.value;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn needs_addresses(&self) -> bool {
+        false
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        let path = self.dump_folder.join(format!(
+            "pubkeyreuse-{}-{}.csv{}",
+            self.start_height,
+            block_height,
+            self.compression.suffix()
+        ));
+        let linked = self.linked();
+        let mut writer = self.compression.create(&path)?;
+        writer.write_all(PUBKEY_REUSE.header().as_bytes())?;
+        for (key_hash, p2pk, p2pkh) in &linked {
+            writer.write_all(
+                format!("{};{};{}\n", utils::arr_to_hex(*key_hash), p2pk, p2pkh).as_bytes(),
+            )?;
+        }
+        writer.finish()?;
+        info!(target: "callback", "Done.\nDumped pubkey reuse from height {} to {}:\n\
+                                   \t-> key hashes: {:9}\n\
+                                   \t-> linked:     {:9}",
+             self.start_height, block_height, self.received.len(), linked.len());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::script::AddressFormat;
+    use crate::blockchain::proto::tx::{RawTx, TxOutput};
+    use crate::blockchain::proto::varuint::VarUint;
+    use std::fs;
+
+    fn tx(outputs: &[(&str, u64)]) -> Hashed<EvaluatedTx> {
+        Hashed::double_sha256(EvaluatedTx::from(RawTx {
+            version: 1,
+            in_count: VarUint::from(0u8),
+            inputs: vec![],
+            out_count: VarUint::from(outputs.len() as u8),
+            outputs: outputs
+                .iter()
+                .map(|(script, value)| {
+                    let script_pubkey = utils::hex_to_vec(script);
+                    TxOutput {
+                        value: *value,
+                        script_len: VarUint::from(script_pubkey.len() as u8),
+                        script_pubkey,
+                    }
+                })
+                .collect(),
+            locktime: 0,
+            address_format: AddressFormat::default(),
+            witness_size: 0,
+        }))
+    }
+
+    #[test]
+    fn test_pubkey_reuse() {
+        // Compressed generator point and the P2PKH script paying to its hash
+        let p2pk = "210279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798ac";
+        let p2pkh = "76a914751e76e8199196d454941c45d1b3a323f1433bd688ac";
+        // Only received via P2PKH and P2WPKH
+        let other_p2pkh = "76a91462e907b15cbf27d5425399ebf6f0fb50ebb88f1888ac";
+        let p2wpkh = "0014751e76e8199196d454941c45d1b3a323f1433bd6";
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let matches = PubkeyReuse::build_subcommand()
+            .get_matches_from(["pubkeyreuse", tmp_dir.path().to_str().unwrap()]);
+        let mut callback = PubkeyReuse::new(&matches).unwrap();
+        callback.on_start(1).unwrap();
+        callback
+            .on_tx(&tx(&[(p2pk, 5000000000), (other_p2pkh, 1)]), 1)
+            .unwrap();
+        callback
+            .on_tx(&tx(&[(p2pkh, 100), (p2wpkh, 7), (p2pkh, 20)]), 2)
+            .unwrap();
+        callback.on_complete(2).unwrap();
+
+        let csv = fs::read_to_string(tmp_dir.path().join("pubkeyreuse-1-2.csv")).unwrap();
+        PUBKEY_REUSE.assert_csv(&csv);
+        assert_eq!(
+            csv,
+            "keyhash;p2pk_received;p2pkh_received\n\
+             751e76e8199196d454941c45d1b3a323f1433bd6;5000000000;120\n"
+        );
+    }
+}
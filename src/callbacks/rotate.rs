@@ -0,0 +1,447 @@
+use std::borrow::Cow;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use clap::{Arg, ArgMatches, Command};
+use serde_json::{json, Value};
+
+use crate::callbacks::compress::{Compression, OutputWriter};
+use crate::errors::OpResult;
+
+/// Type of a column as printed by `--print-schema`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColumnType {
+    String,
+    Integer,
+    Float,
+    /// Only written by JSON Lines files
+    Boolean,
+    /// Only written by JSON Lines files. Arrays of objects are described by the table `<table>.<column>`
+    Array,
+}
+
+impl ColumnType {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ColumnType::String => "string",
+            ColumnType::Integer => "integer",
+            ColumnType::Float => "float",
+            ColumnType::Boolean => "boolean",
+            ColumnType::Array => "array",
+        }
+    }
+}
+
+/// Format of the files described by a Table
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileFormat {
+    /// Separated by semicolons with a header line
+    Csv,
+    /// One JSON object per line
+    Jsonl,
+}
+
+impl FileFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FileFormat::Csv => "csv",
+            FileFormat::Jsonl => "jsonl",
+        }
+    }
+}
+
+/// Column names and types of a file written by a callback. The header of csv files is derived from it,
+/// so the printed schema always matches the written rows.
+pub struct Table {
+    pub name: &'static str,
+    pub format: FileFormat,
+    pub columns: Cow<'static, [(&'static str, ColumnType)]>,
+}
+
+impl Table {
+    pub const fn csv(name: &'static str, columns: &'static [(&'static str, ColumnType)]) -> Self {
+        Table {
+            name,
+            format: FileFormat::Csv,
+            columns: Cow::Borrowed(columns),
+        }
+    }
+
+    pub const fn jsonl(name: &'static str, columns: &'static [(&'static str, ColumnType)]) -> Self {
+        Table {
+            name,
+            format: FileFormat::Jsonl,
+            columns: Cow::Borrowed(columns),
+        }
+    }
+
+    /// Appends optional columns
+    pub fn with_columns(mut self, columns: &[(&'static str, ColumnType)]) -> Self {
+        self.columns.to_mut().extend_from_slice(columns);
+        self
+    }
+
+    /// Returns the header line of the csv file
+    pub fn header(&self) -> String {
+        let names: Vec<&str> = self.columns.iter().map(|(name, _)| *name).collect();
+        format!("{}\n", names.join(";"))
+    }
+
+    pub fn to_json(&self) -> Value {
+        let columns: Vec<Value> = self
+            .columns
+            .iter()
+            .map(|(name, kind)| json!({ "name": name, "type": kind.as_str() }))
+            .collect();
+        json!({ "name": self.name, "columns": columns })
+    }
+
+    /// Asserts that the csv content starts with the header and that each row has a
+    /// parseable field for each column. Empty fields are accepted for missing values.
+    #[cfg(test)]
+    pub fn assert_csv(&self, csv: &str) {
+        let (header, rows) = csv.split_once('\n').unwrap();
+        assert_eq!(format!("{}\n", header), self.header());
+        self.assert_rows(rows);
+    }
+
+    /// Asserts that each row of a csv file without header has a parseable field for each column
+    #[cfg(test)]
+    pub fn assert_rows(&self, rows: &str) {
+        for line in rows.lines() {
+            // Semicolons within quoted fields don't separate
+            let mut quoted = false;
+            let fields: Vec<&str> = line
+                .split(|c| {
+                    quoted ^= c == '"';
+                    c == ';' && !quoted
+                })
+                .collect();
+            assert_eq!(fields.len(), self.columns.len(), "{}: {}", self.name, line);
+            for (field, (name, kind)) in fields.iter().zip(self.columns.iter()) {
+                let valid = field.is_empty()
+                    || match kind {
+                        ColumnType::Integer => field.parse::<i128>().is_ok(),
+                        ColumnType::Float => field.parse::<f64>().is_ok(),
+                        _ => true,
+                    };
+                assert!(valid, "{}.{}: {}", self.name, name, field);
+            }
+        }
+    }
+
+    /// Asserts that each field of the JSON object is a column with a matching type or null.
+    /// The objects of arrays are checked against the table `<name>.<column>` in `tables`, if any.
+    #[cfg(test)]
+    pub fn assert_json(&self, object: &Value, tables: &[Table]) {
+        for (key, value) in object.as_object().unwrap() {
+            let (_, kind) = self
+                .columns
+                .iter()
+                .find(|(name, _)| name == key)
+                .unwrap_or_else(|| panic!("{}.{} isn't described", self.name, key));
+            let valid = value.is_null()
+                || match kind {
+                    ColumnType::String => value.is_string(),
+                    ColumnType::Integer => value.is_i64() || value.is_u64(),
+                    ColumnType::Float => value.is_number(),
+                    ColumnType::Boolean => value.is_boolean(),
+                    ColumnType::Array => value.is_array(),
+                };
+            assert!(valid, "{}.{}: {}", self.name, key, value);
+            let name = format!("{}.{}", self.name, key);
+            if let Some(table) = tables.iter().find(|table| table.name == name) {
+                for item in value.as_array().unwrap() {
+                    table.assert_json(item, tables);
+                }
+            }
+        }
+    }
+}
+
+/// Controls when a RotatingWriter starts a new part
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rotation {
+    Never,
+    /// Starts a new part every N blocks
+    EveryBlocks(u64),
+    /// Starts a new part before a part would exceed N bytes (excluding the header)
+    Size(u64),
+}
+
+impl Rotation {
+    /// Adds `--rotate-every` and `--rotate-size` to the given subcommand
+    pub fn add_args(cmd: Command) -> Command {
+        cmd.arg(
+            Arg::new("rotate-every")
+                .long("rotate-every")
+                .value_name("NUM")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .conflicts_with("rotate-size")
+                .help("Splits csv files into numbered parts every NUM blocks"),
+        )
+        .arg(
+            Arg::new("rotate-size")
+                .long("rotate-size")
+                .value_name("BYTES")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .help("Splits csv files into numbered parts of at most BYTES (uncompressed)"),
+        )
+    }
+
+    pub fn from_matches(matches: &ArgMatches) -> Self {
+        if let Some(n) = matches.get_one::<u64>("rotate-every") {
+            Rotation::EveryBlocks(*n)
+        } else if let Some(n) = matches.get_one::<u64>("rotate-size") {
+            Rotation::Size(*n)
+        } else {
+            Rotation::Never
+        }
+    }
+}
+
+/// Writes csv rows to `<name>.csv.tmp`, which is renamed to `<name>-<start>-<end>.csv` on finish.
+/// With rotation enabled, rows are written to `<name>.00001.csv`, `<name>.00002.csv`, ... instead,
+/// each part starting with the header (if any). Other extensions than `csv` can be set with `with_extension`.
+/// With compression, its suffix is appended to the extension (e.g. `<name>.00001.csv.gz`).
+pub struct RotatingWriter {
+    folder: PathBuf,
+    name: String,
+    extension: String,
+    header: Option<String>,
+    rotation: Rotation,
+    compression: Compression,
+
+    writer: OutputWriter,
+    part: u32,
+    // Height of the first block in the current part
+    part_start: Option<u64>,
+    // Bytes written to the current part, excluding the header
+    part_size: u64,
+}
+
+impl RotatingWriter {
+    pub fn new(
+        folder: &Path,
+        name: &str,
+        header: Option<&str>,
+        rotation: Rotation,
+        compression: Compression,
+    ) -> OpResult<Self> {
+        RotatingWriter::with_extension(folder, name, "csv", header, rotation, compression)
+    }
+
+    pub fn with_extension(
+        folder: &Path,
+        name: &str,
+        extension: &str,
+        header: Option<&str>,
+        rotation: Rotation,
+        compression: Compression,
+    ) -> OpResult<Self> {
+        let extension = format!("{}{}", extension, compression.suffix());
+        let mut writer = Self {
+            folder: PathBuf::from(folder),
+            name: String::from(name),
+            header: header.map(String::from),
+            rotation,
+            compression,
+            writer: compression.create(&part_path(folder, name, &extension, rotation, 1))?,
+            extension,
+            part: 1,
+            part_start: None,
+            part_size: 0,
+        };
+        writer.write_header()?;
+        Ok(writer)
+    }
+
+    fn write_header(&mut self) -> OpResult<()> {
+        if let Some(header) = &self.header {
+            self.writer.write_all(header.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Finishes the current part and opens the next one
+    fn next_part(&mut self) -> OpResult<()> {
+        self.writer.finish()?;
+        self.part += 1;
+        self.writer = self.compression.create(&part_path(
+            &self.folder,
+            &self.name,
+            &self.extension,
+            self.rotation,
+            self.part,
+        ))?;
+        self.part_size = 0;
+        self.write_header()
+    }
+
+    /// Has to be called before the rows of the block at the given height are written
+    pub fn start_block(&mut self, block_height: u64) -> OpResult<()> {
+        let part_start = *self.part_start.get_or_insert(block_height);
+        if let Rotation::EveryBlocks(n) = self.rotation {
+            if block_height >= part_start + n {
+                self.part_start = Some(block_height);
+                self.next_part()?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write_row(&mut self, row: &str) -> OpResult<()> {
+        if let Rotation::Size(max) = self.rotation {
+            if self.part_size > 0 && self.part_size + row.len() as u64 > max {
+                self.next_part()?;
+            }
+        }
+        self.writer.write_all(row.as_bytes())?;
+        self.part_size += row.len() as u64;
+        Ok(())
+    }
+
+    /// Finishes the last part. Without rotation the temp file gets its final name.
+    /// Returns the paths of all parts.
+    pub fn finish(&mut self, start_height: u64, end_height: u64) -> OpResult<Vec<PathBuf>> {
+        self.writer.finish()?;
+        if self.rotation == Rotation::Never {
+            let path = self.folder.join(format!(
+                "{}-{}-{}.{}",
+                self.name, start_height, end_height, self.extension
+            ));
+            fs::rename(
+                part_path(
+                    &self.folder,
+                    &self.name,
+                    &self.extension,
+                    self.rotation,
+                    self.part,
+                ),
+                &path,
+            )?;
+            return Ok(vec![path]);
+        }
+        Ok((1..=self.part)
+            .map(|part| {
+                part_path(
+                    &self.folder,
+                    &self.name,
+                    &self.extension,
+                    self.rotation,
+                    part,
+                )
+            })
+            .collect())
+    }
+}
+
+fn part_path(folder: &Path, name: &str, extension: &str, rotation: Rotation, part: u32) -> PathBuf {
+    match rotation {
+        Rotation::Never => folder.join(format!("{}.{}.tmp", name, extension)),
+        _ => folder.join(format!("{}.{:05}.{}", name, part, extension)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotate_every() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut writer = RotatingWriter::new(
+            tmp_dir.path(),
+            "out",
+            Some("height\n"),
+            Rotation::EveryBlocks(2),
+            Compression::None,
+        )
+        .unwrap();
+        for height in 10..15 {
+            writer.start_block(height).unwrap();
+            writer.write_row(&format!("{}\n", height)).unwrap();
+        }
+        let paths = writer.finish(10, 14).unwrap();
+        assert_eq!(paths.len(), 3);
+        assert_eq!(paths[2], tmp_dir.path().join("out.00003.csv"));
+
+        let read = |name: &str| fs::read_to_string(tmp_dir.path().join(name)).unwrap();
+        assert_eq!(read("out.00001.csv"), "height\n10\n11\n");
+        assert_eq!(read("out.00002.csv"), "height\n12\n13\n");
+        assert_eq!(read("out.00003.csv"), "height\n14\n");
+        assert_eq!(fs::read_dir(tmp_dir.path()).unwrap().count(), 3);
+    }
+
+    #[test]
+    fn test_rotate_size() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut writer = RotatingWriter::new(
+            tmp_dir.path(),
+            "out",
+            None,
+            Rotation::Size(6),
+            Compression::None,
+        )
+        .unwrap();
+        writer.start_block(0).unwrap();
+        for row in ["aa\n", "bb\n", "cc\n", "ddddddd\n", "ee\n"] {
+            writer.write_row(row).unwrap();
+        }
+        writer.finish(0, 0).unwrap();
+
+        let read = |name: &str| fs::read_to_string(tmp_dir.path().join(name)).unwrap();
+        assert_eq!(read("out.00001.csv"), "aa\nbb\n");
+        assert_eq!(read("out.00002.csv"), "cc\n");
+        // Rows bigger than the limit get a part on their own
+        assert_eq!(read("out.00003.csv"), "ddddddd\n");
+        assert_eq!(read("out.00004.csv"), "ee\n");
+    }
+
+    #[test]
+    fn test_no_rotation() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut writer = RotatingWriter::new(
+            tmp_dir.path(),
+            "out",
+            Some("h\n"),
+            Rotation::Never,
+            Compression::None,
+        )
+        .unwrap();
+        writer.start_block(3).unwrap();
+        writer.write_row("3\n").unwrap();
+        writer.finish(3, 3).unwrap();
+        assert_eq!(
+            fs::read_to_string(tmp_dir.path().join("out-3-3.csv")).unwrap(),
+            "h\n3\n"
+        );
+        assert_eq!(fs::read_dir(tmp_dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    #[cfg(feature = "gzip")]
+    fn test_rotate_compressed() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut writer = RotatingWriter::new(
+            tmp_dir.path(),
+            "out",
+            Some("height\n"),
+            Rotation::EveryBlocks(1),
+            Compression::Gzip,
+        )
+        .unwrap();
+        for height in 1..3 {
+            writer.start_block(height).unwrap();
+            writer.write_row(&format!("{}\n", height)).unwrap();
+        }
+        let paths = writer.finish(1, 2).unwrap();
+        assert_eq!(paths[1], tmp_dir.path().join("out.00002.csv.gz"));
+
+        assert_eq!(
+            crate::callbacks::compress::read_to_string(&paths[1]),
+            "height\n2\n"
+        );
+    }
+}
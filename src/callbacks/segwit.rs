@@ -0,0 +1,268 @@
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::script::ScriptPattern;
+use crate::blockchain::proto::tx::EvaluatedTx;
+use crate::blockchain::proto::Hashed;
+use crate::callbacks::compress::{Compression, OutputWriter};
+use crate::callbacks::rotate::{ColumnType, Table};
+use crate::callbacks::{BlockFlow, Callback};
+use crate::errors::OpResult;
+
+const SEGWIT: Table = Table::csv(
+    "segwit",
+    &[
+        ("period", ColumnType::Integer),
+        ("segwit_input_pct", ColumnType::Float),
+        ("segwit_output_pct", ColumnType::Float),
+    ],
+);
+
+/// Tracks SegWit adoption per period of blocks, from the spending side (inputs with witness data)
+/// and from the receiving side (native SegWit outputs).
+pub struct SegWit {
+    dump_folder: PathBuf,
+    writer: OutputWriter,
+    period: u64,
+
+    // First height of the current period
+    cur_period: u64,
+    // Counts of the current period, coinbase inputs are not counted
+    inputs: u64,
+    segwit_inputs: u64,
+    outputs: u64,
+    segwit_outputs: u64,
+
+    start_height: u64,
+}
+
+impl SegWit {
+    /// Writes the fractions of the current period and resets the counts
+    fn flush_period(&mut self) -> OpResult<()> {
+        if self.inputs > 0 || self.outputs > 0 {
+            self.writer.write_all(
+                format!(
+                    "{};{};{}\n",
+                    self.cur_period,
+                    percentage(self.segwit_inputs, self.inputs),
+                    percentage(self.segwit_outputs, self.outputs)
+                )
+                .as_bytes(),
+            )?;
+        }
+        self.inputs = 0;
+        self.segwit_inputs = 0;
+        self.outputs = 0;
+        self.segwit_outputs = 0;
+        Ok(())
+    }
+}
+
+/// Returns true for outputs paying to a native SegWit script (any witness version)
+fn is_native_segwit(pattern: &ScriptPattern) -> bool {
+    matches!(
+        pattern,
+        ScriptPattern::Pay2WitnessPublicKeyHash
+            | ScriptPattern::Pay2WitnessScriptHash
+            | ScriptPattern::Pay2Taproot
+            | ScriptPattern::WitnessProgram
+    )
+}
+
+/// Formats count / total as percentage with two decimals
+fn percentage(count: u64, total: u64) -> String {
+    if total == 0 {
+        return String::from("0.00");
+    }
+    format!("{:.2}", count as f64 * 100.0 / total as f64)
+}
+
+impl Callback for SegWit {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        let cmd = Command::new("segwit")
+            .about("Dumps the fraction of SegWit inputs and outputs per period to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            )
+            .arg(
+                Arg::new("period")
+                    .long("period")
+                    .value_name("NUM")
+                    .value_parser(clap::value_parser!(u64).range(1..))
+                    .default_value("2016")
+                    .help("Number of blocks per period"),
+            );
+        Compression::add_args(cmd)
+    }
+
+    fn schema(_: &ArgMatches) -> Vec<Table>
+    where
+        Self: Sized,
+    {
+        vec![SEGWIT]
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let dump_folder = &PathBuf::from(matches.get_one::<String>("dump-folder").unwrap());
+        let cb = SegWit {
+            dump_folder: PathBuf::from(dump_folder),
+            writer: Compression::from_matches(matches)?
+                .create(&dump_folder.join("segwit.csv.tmp"))?,
+            period: *matches.get_one::<u64>("period").unwrap(),
+            cur_period: 0,
+            inputs: 0,
+            segwit_inputs: 0,
+            outputs: 0,
+            segwit_outputs: 0,
+            start_height: 0,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        self.cur_period = block_height - block_height % self.period;
+        info!(target: "callback", "Executing segwit with dump folder: {} ...", &self.dump_folder.display());
+        self.writer.write_all(SEGWIT.header().as_bytes())?;
+        Ok(())
+    }
+
+    fn on_block(&mut self, _: &Block, _: u64) -> OpResult<BlockFlow> {
+        Ok(BlockFlow::Continue)
+    }
+
+    fn on_tx(&mut self, tx: &Hashed<EvaluatedTx>, block_height: u64) -> OpResult<()> {
+        let period = block_height - block_height % self.period;
+        if period != self.cur_period {
+            self.flush_period()?;
+            self.cur_period = period;
+        }
+        if !tx.value.is_coinbase() {
+            self.inputs += tx.value.inputs.len() as u64;
+            self.segwit_inputs += tx.value.inputs.iter().filter(|i| i.has_witness()).count() as u64;
+        }
+        self.outputs += tx.value.outputs.len() as u64;
+        self.segwit_outputs += tx
+            .value
+            .outputs
+            .iter()
+            .filter(|o| is_native_segwit(&o.script.pattern))
+            .count() as u64;
+        Ok(())
+    }
+
+    fn needs_addresses(&self) -> bool {
+        false
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.flush_period()?;
+        self.writer.finish()?;
+        fs::rename(
+            self.dump_folder.as_path().join("segwit.csv.tmp"),
+            self.dump_folder.as_path().join(
+                self.writer
+                    .file_name(format!("segwit-{}-{}.csv", self.start_height, block_height)),
+            ),
+        )?;
+        info!(target: "callback", "Done.\nDumped SegWit adoption from height {} to {}.",
+             self.start_height, block_height);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::script::AddressFormat;
+    use crate::blockchain::proto::tx::{RawTx, TxInput, TxOutpoint, TxOutput};
+    use crate::blockchain::proto::varuint::VarUint;
+    use crate::common::utils;
+    use bitcoin::hashes::{sha256d, Hash};
+
+    fn tx(witnesses: &[bool], scripts: &[&str]) -> Hashed<EvaluatedTx> {
+        Hashed::double_sha256(EvaluatedTx::from(RawTx {
+            version: 1,
+            in_count: VarUint::from(witnesses.len() as u8),
+            inputs: witnesses
+                .iter()
+                .enumerate()
+                .map(|(i, has_witness)| TxInput {
+                    outpoint: TxOutpoint::new(sha256d::Hash::all_zeros(), i as u32),
+                    script_len: VarUint::from(0u8),
+                    script_sig: vec![],
+                    seq_no: 0xFFFFFFFF,
+                    witness: if *has_witness {
+                        vec![vec![0x01]]
+                    } else {
+                        vec![]
+                    },
+                })
+                .collect(),
+            out_count: VarUint::from(scripts.len() as u8),
+            outputs: scripts
+                .iter()
+                .map(|script| {
+                    let script_pubkey = utils::hex_to_vec(script);
+                    TxOutput {
+                        value: 1,
+                        script_len: VarUint::from(script_pubkey.len() as u8),
+                        script_pubkey,
+                    }
+                })
+                .collect(),
+            locktime: 0,
+            address_format: AddressFormat::default(),
+            witness_size: 0,
+        }))
+    }
+
+    #[test]
+    fn test_segwit_adoption() {
+        let p2pkh = "76a91462e907b15cbf27d5425399ebf6f0fb50ebb88f1888ac";
+        let p2wpkh = "00141d7cd6c75c2e86f4cbf98eaed221b30bd9a0b928";
+        let p2tr = "51200f0c8db753acbd17343a39c2f3f4e35e4be6da749f9e35137ab220e7b238a667";
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let matches = SegWit::build_subcommand().get_matches_from([
+            "segwit",
+            tmp_dir.path().to_str().unwrap(),
+            "--period",
+            "2",
+        ]);
+        let mut callback = SegWit::new(&matches).unwrap();
+        callback.on_start(0).unwrap();
+        callback
+            .on_txs(&[tx(&[false, false], &[p2pkh, p2pkh])], 0)
+            .unwrap();
+        callback
+            .on_txs(&[tx(&[true, false], &[p2wpkh, p2pkh])], 1)
+            .unwrap();
+        callback
+            .on_txs(&[tx(&[true, true, true], &[p2tr, p2wpkh, p2pkh])], 2)
+            .unwrap();
+        callback.on_complete(2).unwrap();
+
+        let csv = fs::read_to_string(tmp_dir.path().join("segwit-0-2.csv")).unwrap();
+        SEGWIT.assert_csv(&csv);
+        assert_eq!(
+            csv,
+            "period;segwit_input_pct;segwit_output_pct\n0;25.00;25.00\n2;100.00;66.67\n"
+        );
+    }
+}
@@ -2,18 +2,24 @@ use bitcoin::hashes::{sha256d, Hash};
 use std::collections::HashMap;
 use std::io::{self, Write};
 
-use clap::{ArgMatches, Command};
+use clap::{Arg, ArgMatches, Command};
 
 use crate::blockchain::proto::block::{self, Block};
 use crate::blockchain::proto::script::ScriptPattern;
-use crate::blockchain::proto::ToRaw;
-use crate::callbacks::Callback;
-use crate::common::utils;
+use crate::blockchain::proto::tx::SizeBasis;
+use crate::callbacks::{BlockFlow, Callback};
+use crate::common::utils::{self, HashByteOrder};
 use crate::errors::OpResult;
 
 pub struct SimpleStats {
+    size_basis: SizeBasis,
+    hash_byte_order: HashByteOrder,
+
     n_valid_blocks: u64,
     block_sizes: Vec<u32>,
+    /// Weight (BIP141) and vsize of each block
+    block_weights: Vec<u32>,
+    block_vsizes: Vec<u32>,
 
     n_tx: u64,
     n_tx_inputs: u64,
@@ -24,7 +30,7 @@ pub struct SimpleStats {
     /// Biggest value transaction (value, height, txid)
     tx_biggest_value: (u64, u64, sha256d::Hash),
     /// Biggest size transaction (size, height, txid)
-    tx_biggest_size: (usize, u64, sha256d::Hash),
+    tx_biggest_size: (u64, u64, sha256d::Hash),
     /// Contains transaction type count
     n_tx_types: HashMap<ScriptPattern, u64>,
     /// First occurence of transaction type
@@ -39,8 +45,12 @@ pub struct SimpleStats {
 impl Default for SimpleStats {
     fn default() -> Self {
         SimpleStats {
+            size_basis: SizeBasis::Base,
+            hash_byte_order: HashByteOrder::default(),
             n_valid_blocks: 0,
             block_sizes: vec![],
+            block_weights: vec![],
+            block_vsizes: vec![],
             n_tx: 0,
             n_tx_inputs: 0,
             n_tx_outputs: 0,
@@ -65,9 +75,16 @@ impl SimpleStats {
         txid: sha256d::Hash,
         index: u32,
     ) {
-        // Strip exact OP_RETURN bytes
+        // Strip exact OP_RETURN bytes and multisig keys
         let pattern = match script_pattern {
             ScriptPattern::OpReturn(_) => ScriptPattern::OpReturn(String::new()),
+            ScriptPattern::Pay2MultiSig {
+                required, total, ..
+            } => ScriptPattern::Pay2MultiSig {
+                required,
+                total,
+                pubkeys: Vec::new(),
+            },
             p => p,
         };
         if !self.n_tx_types.contains_key(&pattern) {
@@ -108,6 +125,20 @@ impl SimpleStats {
             "   -> avg block size:\t\t{:.2} KiB",
             utils::get_mean(&self.block_sizes) / 1024.00
         )?;
+        writeln!(
+            buffer,
+            "   -> avg block weight:\t{:.2} WU (min: {}, max: {})",
+            utils::get_mean(&self.block_weights),
+            self.block_weights.iter().min().unwrap_or(&0),
+            self.block_weights.iter().max().unwrap_or(&0)
+        )?;
+        writeln!(
+            buffer,
+            "   -> avg block vsize:\t\t{:.2} vB (min: {}, max: {})",
+            utils::get_mean(&self.block_vsizes),
+            self.block_vsizes.iter().min().unwrap_or(&0),
+            self.block_vsizes.iter().max().unwrap_or(&0)
+        )?;
         writeln!(
             buffer,
             "   -> avg time between blocks:\t{:.2} (minutes)",
@@ -147,14 +178,21 @@ impl SimpleStats {
         writeln!(
             buffer,
             "        seen in block #{}, txid: {}\n",
-            height, &txid
+            height,
+            self.hash_byte_order.hex(&txid)
         )?;
         let (value, height, txid) = self.tx_biggest_size;
-        writeln!(buffer, "   -> biggest size tx:\t\t{} bytes", value,)?;
+        writeln!(
+            buffer,
+            "   -> biggest size tx:\t\t{} {}",
+            value,
+            self.size_basis.unit()
+        )?;
         writeln!(
             buffer,
             "        seen in block #{}, txid: {}\n",
-            height, &txid
+            height,
+            self.hash_byte_order.hex(&txid)
         )?;
         Ok(())
     }
@@ -174,7 +212,8 @@ impl SimpleStats {
             writeln!(
                 buffer,
                 "        first seen in block #{}, txid: {}\n",
-                pos.0, &pos.1
+                pos.0,
+                self.hash_byte_order.hex(&pos.1)
             )?;
         }
         Ok(())
@@ -190,13 +229,27 @@ impl Callback for SimpleStats {
             .about("Shows various Blockchain stats")
             .version("0.1")
             .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("size-basis")
+                    .long("size-basis")
+                    .value_name("BASIS")
+                    .value_parser(clap::builder::PossibleValuesParser::new([
+                        "base", "total", "weight", "vsize",
+                    ]))
+                    .default_value("base")
+                    .help("Transaction size metric: stripped bytes, bytes including witness data, weight units or vbytes"),
+            )
     }
 
-    fn new(_: &ArgMatches) -> OpResult<Self>
+    fn new(matches: &ArgMatches) -> OpResult<Self>
     where
         Self: Sized,
     {
-        Ok(SimpleStats::default())
+        Ok(SimpleStats {
+            size_basis: matches.get_one::<String>("size-basis").unwrap().parse()?,
+            hash_byte_order: HashByteOrder::from_matches(matches)?,
+            ..Default::default()
+        })
     }
 
     fn on_start(&mut self, _: u64) -> OpResult<()> {
@@ -204,10 +257,12 @@ impl Callback for SimpleStats {
         Ok(())
     }
 
-    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<BlockFlow> {
         self.n_valid_blocks += 1;
         self.n_tx += block.tx_count.value;
         self.block_sizes.push(block.size);
+        self.block_weights.push(block.weight() as u32);
+        self.block_vsizes.push(block.vsize() as u32);
 
         for tx in &block.txs {
             // Collect fee rewards
@@ -222,20 +277,20 @@ impl Callback for SimpleStats {
             self.n_tx_inputs += tx.value.in_count.value;
             self.n_tx_outputs += tx.value.out_count.value;
 
-            let mut tx_value = 0;
+            let mut tx_value: u64 = 0;
             for (i, o) in tx.value.outputs.iter().enumerate() {
                 self.process_tx_pattern(o.script.pattern.clone(), block_height, tx.hash, i as u32);
-                tx_value += o.out.value;
+                tx_value = tx_value.saturating_add(o.out.value);
             }
             // Calculate and save biggest value transaction
             if tx_value > self.tx_biggest_value.0 {
                 self.tx_biggest_value = (tx_value, block_height, tx.hash);
             }
 
-            self.n_tx_total_volume += tx_value;
+            self.n_tx_total_volume = self.n_tx_total_volume.saturating_add(tx_value);
 
             // Calculate and save biggest size transaction
-            let tx_size = tx.value.to_bytes().len();
+            let tx_size = tx.value.size(self.size_basis);
             if tx_size > self.tx_biggest_size.0 {
                 self.tx_biggest_size = (tx_size, block_height, tx.hash);
             }
@@ -252,7 +307,11 @@ impl Callback for SimpleStats {
             self.t_between_blocks.push(diff);
         }
         self.last_timestamp = block.header.value.timestamp;
-        Ok(())
+        Ok(BlockFlow::Continue)
+    }
+
+    fn needs_addresses(&self) -> bool {
+        false
     }
 
     fn on_complete(&mut self, _: u64) -> OpResult<()> {
@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::callbacks::compress::{Compression, OutputWriter};
+use crate::callbacks::rotate::{ColumnType, Table};
+use crate::callbacks::{common, BlockFlow, Callback};
+use crate::common::utils::HashByteOrder;
+use crate::errors::OpResult;
+
+const SPEND_LATENCY: Table = Table::csv(
+    "spendlatency",
+    &[
+        ("spent_txid", ColumnType::String),
+        ("source_outpoint", ColumnType::String),
+        ("created_height", ColumnType::Integer),
+        ("spent_height", ColumnType::Integer),
+        ("blocks_held", ColumnType::Integer),
+        ("value", ColumnType::Integer),
+    ],
+);
+
+/// Only the creation height and value are needed per output, so addresses are not stored
+struct Created {
+    block_height: u64,
+    value: u64,
+}
+
+/// Dumps how many blocks each output was held before it got spent.
+/// Outputs which are still unspent at the end are not dumped.
+pub struct SpendLatency {
+    dump_folder: PathBuf,
+    writer: OutputWriter,
+    hash_byte_order: HashByteOrder,
+
+    // key: txid + index
+    unspents: common::Unspents<Created>,
+
+    start_height: u64,
+    spent_count: u64,
+}
+
+impl Callback for SpendLatency {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        let cmd = Command::new("spendlatency")
+            .about(
+                "Dumps the number of blocks between creation and spend of each output to CSV file",
+            )
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            );
+        Compression::add_args(cmd)
+    }
+
+    fn schema(_: &ArgMatches) -> Vec<Table>
+    where
+        Self: Sized,
+    {
+        vec![SPEND_LATENCY]
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let dump_folder = &PathBuf::from(matches.get_one::<String>("dump-folder").unwrap());
+        let cb = SpendLatency {
+            dump_folder: PathBuf::from(dump_folder),
+            writer: Compression::from_matches(matches)?
+                .create(&dump_folder.join("spendlatency.csv.tmp"))?,
+            hash_byte_order: HashByteOrder::from_matches(matches)?,
+            unspents: HashMap::with_capacity(10000000),
+            start_height: 0,
+            spent_count: 0,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing spendlatency with dump folder: {} ...", &self.dump_folder.display());
+        self.writer.write_all(SPEND_LATENCY.header().as_bytes())?;
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<BlockFlow> {
+        for tx in &block.txs {
+            let txid = self.hash_byte_order.hex(&tx.hash);
+            for (outpoint, created) in common::take_unspents(tx, &mut self.unspents) {
+                self.writer.write_all(
+                    format!(
+                        "{};{}:{};{};{};{};{}\n",
+                        txid,
+                        self.hash_byte_order.hex(&outpoint.txid),
+                        outpoint.index,
+                        created.block_height,
+                        block_height,
+                        block_height.saturating_sub(created.block_height),
+                        created.value
+                    )
+                    .as_bytes(),
+                )?;
+                self.spent_count += 1;
+            }
+            common::insert_unspents_with(tx, block.genesis, &mut self.unspents, |output| {
+                output.address.map(|_| Created {
+                    block_height,
+                    value: output.value,
+                })
+            });
+        }
+        Ok(BlockFlow::Continue)
+    }
+
+    fn tracks_utxos(&self) -> bool {
+        true
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.writer.finish()?;
+        fs::rename(
+            self.dump_folder.as_path().join("spendlatency.csv.tmp"),
+            self.dump_folder
+                .as_path()
+                .join(self.writer.file_name(format!(
+                    "spendlatency-{}-{}.csv",
+                    self.start_height, block_height
+                ))),
+        )?;
+        info!(target: "callback", "Done.\nDumped spend latencies from height {} to {}:\n\
+                                   \t-> spent outputs:   {:9}\n\
+                                   \t-> unspent outputs: {:9}",
+             self.start_height, block_height, self.spent_count, self.unspents.len());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::tx::TxOutpoint;
+    use crate::callbacks::common::test_block;
+
+    // P2PKH to 1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa
+    const P2PKH: &str = "76a91462e907b15cbf27d5425399ebf6f0fb50ebb88f1888ac";
+
+    #[test]
+    fn test_spend_latency() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let matches = SpendLatency::build_subcommand()
+            .get_matches_from(["spendlatency", tmp_dir.path().to_str().unwrap()]);
+        let mut callback = SpendLatency::new(&matches).unwrap();
+        callback.on_start(1).unwrap();
+
+        let funding = test_block(vec![], &[(P2PKH, 100), (P2PKH, 200)]);
+        callback.on_block(&funding, 1).unwrap();
+        let funding_txid = funding.txs[0].hash;
+
+        // Spends the second output, the first one stays unspent
+        let spend = test_block(vec![TxOutpoint::new(funding_txid, 1)], &[(P2PKH, 150)]);
+        callback.on_block(&spend, 7).unwrap();
+        callback.on_complete(7).unwrap();
+        assert_eq!(callback.spent_count, 1);
+        assert_eq!(callback.unspents.len(), 2);
+
+        let csv = fs::read_to_string(tmp_dir.path().join("spendlatency-1-7.csv")).unwrap();
+        SPEND_LATENCY.assert_csv(&csv);
+        assert_eq!(
+            csv,
+            format!(
+                "spent_txid;source_outpoint;created_height;spent_height;blocks_held;value\n\
+                 {};{}:1;1;7;6;200\n",
+                HashByteOrder::Rpc.hex(&spend.txs[0].hash),
+                HashByteOrder::Rpc.hex(&funding_txid),
+            )
+        );
+    }
+}
@@ -0,0 +1,66 @@
+use std::path::Path;
+
+use rusqlite::{params, Connection};
+
+use crate::errors::OpResult;
+
+/// Number of upserts per transaction
+const BATCH_SIZE: u64 = 5000;
+
+const UPSERT: &str =
+    "INSERT INTO balances (address, balance, last_seen_height) VALUES (?1, ?2, ?3) \
+                      ON CONFLICT (address) DO UPDATE SET balance = balance + excluded.balance, \
+                      last_seen_height = max(last_seen_height, excluded.last_seen_height)";
+
+/// Keeps the balance of each address in the table
+/// `balances(address TEXT PRIMARY KEY, balance INTEGER, last_seen_height INTEGER)` of a SQLite database.
+/// Changes are committed every `BATCH_SIZE` upserts.
+pub struct SqliteBalances {
+    conn: Connection,
+    // Upserts since the last commit
+    pending: u64,
+}
+
+impl SqliteBalances {
+    /// Opens or creates the database, fails if it already contains a balances table
+    pub fn open(path: &Path) -> OpResult<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(
+            "CREATE TABLE balances (
+               address          TEXT PRIMARY KEY,
+               balance          INTEGER NOT NULL,
+               last_seen_height INTEGER NOT NULL
+             );
+             BEGIN;",
+        )?;
+        Ok(Self { conn, pending: 0 })
+    }
+
+    /// Adds `delta` to the balance of the address and sets its last seen height
+    pub fn upsert(&mut self, address: &str, delta: i64, block_height: u64) -> OpResult<()> {
+        self.conn
+            .prepare_cached(UPSERT)?
+            .execute(params![address, delta, block_height as i64])?;
+        self.pending += 1;
+        if self.pending >= BATCH_SIZE {
+            self.conn.execute_batch("COMMIT; BEGIN;")?;
+            self.pending = 0;
+        }
+        Ok(())
+    }
+
+    /// Removes the emptied addresses, commits the last batch and indexes the balances for range queries.
+    /// Returns the number of addresses with non-zero balance.
+    pub fn finish(&mut self) -> OpResult<u64> {
+        self.conn.execute_batch(
+            "DELETE FROM balances WHERE balance = 0;
+             COMMIT;
+             CREATE INDEX balances_balance ON balances (balance);",
+        )?;
+        self.pending = 0;
+        let count: i64 = self
+            .conn
+            .query_row("SELECT COUNT(*) FROM balances", [], |row| row.get(0))?;
+        Ok(count as u64)
+    }
+}
@@ -0,0 +1,322 @@
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+use bitcoin::opcodes::{Class, ClassifyContext};
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::script::disasm::{LockType, Script, ScriptOp};
+use crate::blockchain::proto::tx::TxInput;
+use crate::callbacks::compress::{Compression, OutputWriter};
+use crate::callbacks::rotate::{ColumnType, Table};
+use crate::callbacks::{BlockFlow, Callback};
+use crate::common::utils::HashByteOrder;
+use crate::errors::OpResult;
+
+const TIMELOCKS: Table = Table::csv(
+    "timelocks",
+    &[
+        ("txid", ColumnType::String),
+        ("outpoint", ColumnType::String),
+        ("source", ColumnType::String),
+        ("lock_type", ColumnType::String),
+        ("lock_value", ColumnType::Integer),
+        ("height", ColumnType::Integer),
+    ],
+);
+
+/// Returns true if the data looks like a compressed or uncompressed public key
+fn is_pubkey(data: &[u8]) -> bool {
+    matches!(
+        (data.len(), data.first()),
+        (33, Some(0x02 | 0x03)) | (65, Some(0x04))
+    )
+}
+
+/// Returns the script revealed by the input along with its kind.
+/// The spent output isn't known, so the script is derived from the shape of the input:
+///  - tapscript: the witness item before a taproot control block
+///  - witness: the last witness item, unless the witness looks like P2WPKH or a taproot key path spend
+///  - p2sh: the last push of a push-only scriptSig, unless it's a public key (P2PKH)
+fn revealed_script(input: &TxInput) -> Option<(&'static str, &[u8])> {
+    if let Some(script) = input.tapscript() {
+        return Some(("tapscript", script));
+    }
+    match input.witness_without_annex() {
+        [] => {}
+        [signature] if signature.len() == 64 || signature.len() == 65 => return None,
+        [_, pubkey] if is_pubkey(pubkey) => return None,
+        [.., script] => return Some(("witness", script)),
+    }
+
+    let ops = Script::new(&input.script_sig).disassemble();
+    let push_only = ops.iter().all(|op| match op {
+        ScriptOp::Push(_) => true,
+        ScriptOp::Op(op) => matches!(
+            op.classify(ClassifyContext::Legacy),
+            Class::PushNum(_) | Class::PushBytes(_)
+        ),
+        ScriptOp::Truncated(_) => false,
+    });
+    match ops.last() {
+        Some(ScriptOp::Push(script)) if push_only && !is_pubkey(script) => Some((
+            "p2sh",
+            &input.script_sig[input.script_sig.len() - script.len()..],
+        )),
+        _ => None,
+    }
+}
+
+/// Dumps all scripts containing OP_CHECKLOCKTIMEVERIFY or OP_CHECKSEQUENCEVERIFY.
+/// Output scripts are checked directly, P2SH redeemscripts, witness scripts and tapscripts
+/// only when they are revealed by the spending input.
+pub struct Timelocks {
+    dump_folder: PathBuf,
+    writer: OutputWriter,
+    hash_byte_order: HashByteOrder,
+
+    start_height: u64,
+    cltv_count: u64,
+    csv_count: u64,
+}
+
+impl Timelocks {
+    fn write_timelocks(
+        &mut self,
+        txid: &str,
+        outpoint: &str,
+        source: &str,
+        script: &[u8],
+        block_height: u64,
+    ) -> OpResult<()> {
+        for (lock_type, lock_value) in Script::new(script).timelocks() {
+            match lock_type {
+                LockType::Cltv => self.cltv_count += 1,
+                LockType::Csv => self.csv_count += 1,
+            }
+            self.writer.write_all(
+                format!(
+                    "{};{};{};{};{};{}\n",
+                    txid,
+                    outpoint,
+                    source,
+                    lock_type,
+                    lock_value.map_or(String::new(), |value| value.to_string()),
+                    block_height
+                )
+                .as_bytes(),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Callback for Timelocks {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        let cmd = Command::new("timelocks")
+            .about(
+                "Dumps scripts with OP_CHECKLOCKTIMEVERIFY or OP_CHECKSEQUENCEVERIFY to CSV file",
+            )
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            );
+        Compression::add_args(cmd)
+    }
+
+    fn schema(_: &ArgMatches) -> Vec<Table>
+    where
+        Self: Sized,
+    {
+        vec![TIMELOCKS]
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let dump_folder = &PathBuf::from(matches.get_one::<String>("dump-folder").unwrap());
+        let cb = Timelocks {
+            dump_folder: PathBuf::from(dump_folder),
+            writer: Compression::from_matches(matches)?
+                .create(&dump_folder.join("timelocks.csv.tmp"))?,
+            hash_byte_order: HashByteOrder::from_matches(matches)?,
+            start_height: 0,
+            cltv_count: 0,
+            csv_count: 0,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing timelocks with dump folder: {} ...", &self.dump_folder.display());
+        self.writer.write_all(TIMELOCKS.header().as_bytes())?;
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<BlockFlow> {
+        for tx in &block.txs {
+            let txid = self.hash_byte_order.hex(&tx.hash);
+            if !tx.value.is_coinbase() {
+                for input in &tx.value.inputs {
+                    if let Some((source, script)) = revealed_script(input) {
+                        let outpoint = format!(
+                            "{}:{}",
+                            self.hash_byte_order.hex(&input.outpoint.txid),
+                            input.outpoint.index
+                        );
+                        self.write_timelocks(&txid, &outpoint, source, script, block_height)?;
+                    }
+                }
+            }
+            for (i, output) in tx.value.outputs.iter().enumerate() {
+                let outpoint = format!("{}:{}", txid, i);
+                let script = &output.out.script_pubkey;
+                self.write_timelocks(&txid, &outpoint, "output", script, block_height)?;
+            }
+        }
+        Ok(BlockFlow::Continue)
+    }
+
+    fn needs_addresses(&self) -> bool {
+        false
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.writer.finish()?;
+        fs::rename(
+            self.dump_folder.as_path().join("timelocks.csv.tmp"),
+            self.dump_folder
+                .as_path()
+                .join(self.writer.file_name(format!(
+                    "timelocks-{}-{}.csv",
+                    self.start_height, block_height
+                ))),
+        )?;
+        info!(target: "callback", "Done.\nDumped timelocks from height {} to {}:\n\
+                                   \t-> cltv: {:9}\n\
+                                   \t-> csv:  {:9}",
+             self.start_height, block_height, self.cltv_count, self.csv_count);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::header::BlockHeader;
+    use crate::blockchain::proto::script::AddressFormat;
+    use crate::blockchain::proto::tx::{RawTx, TxOutpoint, TxOutput};
+    use crate::blockchain::proto::varuint::VarUint;
+    use crate::common::utils;
+    use bitcoin::hashes::{sha256d, Hash};
+
+    // <1577836800> OP_CHECKLOCKTIMEVERIFY OP_DROP <pubkey> OP_CHECKSIG
+    const CLTV_SCRIPT: &str = concat!(
+        "0400e10b5eb17521",
+        "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798ac"
+    );
+
+    fn input(script_sig: Vec<u8>, witness: Vec<Vec<u8>>) -> TxInput {
+        TxInput {
+            outpoint: TxOutpoint::new(sha256d::Hash::hash(&[1]), 3),
+            script_len: VarUint::from(script_sig.len() as u8),
+            script_sig,
+            seq_no: 0xFFFFFFFE,
+            witness,
+        }
+    }
+
+    #[test]
+    fn test_revealed_script() {
+        let script = utils::hex_to_vec(CLTV_SCRIPT);
+        let signature = vec![0x30; 71];
+        let pubkey = utils::hex_to_vec(&CLTV_SCRIPT[16..82]);
+
+        // P2SH: <sig> <redeemscript>
+        let script_sig = [&[0x47][..], &signature, &[0x2a], &script].concat();
+        assert_eq!(
+            revealed_script(&input(script_sig, vec![])),
+            Some(("p2sh", script.as_slice()))
+        );
+        // P2PKH: <sig> <pubkey>
+        let script_sig = [&[0x47][..], &signature, &[0x21], &pubkey].concat();
+        assert_eq!(revealed_script(&input(script_sig, vec![])), None);
+        // P2WSH
+        let witness = vec![signature.clone(), script.clone()];
+        assert_eq!(
+            revealed_script(&input(vec![], witness)),
+            Some(("witness", script.as_slice()))
+        );
+        // P2WPKH and taproot key path
+        let witness = vec![signature.clone(), pubkey.clone()];
+        assert_eq!(revealed_script(&input(vec![], witness)), None);
+        assert_eq!(revealed_script(&input(vec![], vec![vec![0x01; 64]])), None);
+        // Taproot script path with annex
+        let control = [&[0xc1][..], &[0x02; 64]].concat();
+        let witness = vec![vec![0x01; 64], script.clone(), control, vec![0x50, 0x00]];
+        assert_eq!(
+            revealed_script(&input(vec![], witness)),
+            Some(("tapscript", script.as_slice()))
+        );
+    }
+
+    #[test]
+    fn test_timelocks() {
+        let script = utils::hex_to_vec(CLTV_SCRIPT);
+        let tx = RawTx {
+            version: 2,
+            in_count: VarUint::from(1u8),
+            inputs: vec![input(vec![], vec![vec![0x30; 71], script.clone()])],
+            out_count: VarUint::from(1u8),
+            outputs: vec![TxOutput {
+                value: 1,
+                script_len: VarUint::from(script.len() as u8),
+                script_pubkey: script,
+            }],
+            locktime: 0,
+            address_format: AddressFormat::default(),
+            witness_size: 0,
+        };
+        let header = BlockHeader {
+            version: 1,
+            prev_hash: sha256d::Hash::all_zeros(),
+            merkle_root: sha256d::Hash::all_zeros(),
+            timestamp: 0,
+            bits: 0,
+            nonce: 0,
+        };
+        let block = Block::new(0, header, None, VarUint::from(1u8), vec![tx]);
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let matches = Timelocks::build_subcommand()
+            .get_matches_from(["timelocks", tmp_dir.path().to_str().unwrap()]);
+        let mut callback = Timelocks::new(&matches).unwrap();
+        callback.on_start(700000).unwrap();
+        callback.on_block(&block, 700000).unwrap();
+        callback.on_complete(700000).unwrap();
+        assert_eq!(callback.cltv_count, 2);
+
+        let txid = HashByteOrder::Rpc.hex(&block.txs[0].hash);
+        let prev_txid = HashByteOrder::Rpc.hex(&sha256d::Hash::hash(&[1]));
+        let csv = fs::read_to_string(tmp_dir.path().join("timelocks-700000-700000.csv")).unwrap();
+        TIMELOCKS.assert_csv(&csv);
+        assert_eq!(
+            csv,
+            format!(
+                "txid;outpoint;source;lock_type;lock_value;height\n\
+                 {txid};{prev_txid}:3;witness;cltv;1577836800;700000\n\
+                 {txid};{txid}:0;output;cltv;1577836800;700000\n"
+            )
+        );
+    }
+}
@@ -0,0 +1,210 @@
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::tx::EvaluatedTx;
+use crate::blockchain::proto::Hashed;
+use crate::callbacks::compress::{Compression, OutputWriter};
+use crate::callbacks::rotate::{ColumnType, Table};
+use crate::callbacks::{BlockFlow, Callback};
+use crate::errors::OpResult;
+
+const TX_VERSIONS: Table = Table::csv(
+    "txversions",
+    &[
+        ("period", ColumnType::Integer),
+        ("version", ColumnType::String),
+        ("count", ColumnType::Integer),
+    ],
+);
+
+/// Versions which get their own row, everything else is counted as `other`
+const KNOWN_VERSIONS: [u32; 2] = [1, 2];
+
+/// Counts transactions by version per period of blocks
+pub struct TxVersions {
+    dump_folder: PathBuf,
+    writer: OutputWriter,
+    period: u64,
+
+    // First height of the current period
+    cur_period: u64,
+    // Counts for KNOWN_VERSIONS followed by other
+    counts: [u64; KNOWN_VERSIONS.len() + 1],
+
+    start_height: u64,
+    tx_count: u64,
+}
+
+impl TxVersions {
+    /// Writes the counts of the current period and resets them
+    fn flush_period(&mut self) -> OpResult<()> {
+        for (i, count) in self.counts.iter().enumerate() {
+            if *count == 0 {
+                continue;
+            }
+            let version = match KNOWN_VERSIONS.get(i) {
+                Some(v) => v.to_string(),
+                None => String::from("other"),
+            };
+            self.writer
+                .write_all(format!("{};{};{}\n", self.cur_period, version, count).as_bytes())?;
+        }
+        self.counts = [0; KNOWN_VERSIONS.len() + 1];
+        Ok(())
+    }
+}
+
+impl Callback for TxVersions {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        let cmd = Command::new("txversions")
+            .about("Dumps the transaction version distribution per period to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            )
+            .arg(
+                Arg::new("period")
+                    .long("period")
+                    .value_name("NUM")
+                    .value_parser(clap::value_parser!(u64).range(1..))
+                    .default_value("2016")
+                    .help("Number of blocks per period"),
+            );
+        Compression::add_args(cmd)
+    }
+
+    fn schema(_: &ArgMatches) -> Vec<Table>
+    where
+        Self: Sized,
+    {
+        vec![TX_VERSIONS]
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let dump_folder = &PathBuf::from(matches.get_one::<String>("dump-folder").unwrap());
+        let cb = TxVersions {
+            dump_folder: PathBuf::from(dump_folder),
+            writer: Compression::from_matches(matches)?
+                .create(&dump_folder.join("txversions.csv.tmp"))?,
+            period: *matches.get_one::<u64>("period").unwrap(),
+            cur_period: 0,
+            counts: [0; KNOWN_VERSIONS.len() + 1],
+            start_height: 0,
+            tx_count: 0,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        self.cur_period = block_height - block_height % self.period;
+        info!(target: "callback", "Executing txversions with dump folder: {} ...", &self.dump_folder.display());
+        self.writer.write_all(TX_VERSIONS.header().as_bytes())?;
+        Ok(())
+    }
+
+    fn on_block(&mut self, _: &Block, _: u64) -> OpResult<BlockFlow> {
+        Ok(BlockFlow::Continue)
+    }
+
+    fn on_tx(&mut self, tx: &Hashed<EvaluatedTx>, block_height: u64) -> OpResult<()> {
+        let period = block_height - block_height % self.period;
+        if period != self.cur_period {
+            self.flush_period()?;
+            self.cur_period = period;
+        }
+        let idx = KNOWN_VERSIONS
+            .iter()
+            .position(|v| *v == tx.value.version)
+            .unwrap_or(KNOWN_VERSIONS.len());
+        self.counts[idx] += 1;
+        self.tx_count += 1;
+        Ok(())
+    }
+
+    fn needs_addresses(&self) -> bool {
+        false
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.flush_period()?;
+        self.writer.finish()?;
+        fs::rename(
+            self.dump_folder.as_path().join("txversions.csv.tmp"),
+            self.dump_folder
+                .as_path()
+                .join(self.writer.file_name(format!(
+                    "txversions-{}-{}.csv",
+                    self.start_height, block_height
+                ))),
+        )?;
+        info!(target: "callback", "Done.\nDumped tx versions from height {} to {}:\n\
+                                   \t-> transactions: {:9}",
+             self.start_height, block_height, self.tx_count);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::script::AddressFormat;
+    use crate::blockchain::proto::tx::RawTx;
+    use crate::blockchain::proto::varuint::VarUint;
+
+    fn tx_with_version(version: u32) -> Hashed<EvaluatedTx> {
+        Hashed::double_sha256(EvaluatedTx::from(RawTx {
+            version,
+            in_count: VarUint::from(0u8),
+            inputs: vec![],
+            out_count: VarUint::from(0u8),
+            outputs: vec![],
+            locktime: 0,
+            address_format: AddressFormat::default(),
+            witness_size: 0,
+        }))
+    }
+
+    #[test]
+    fn test_tx_versions() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let matches = TxVersions::build_subcommand().get_matches_from([
+            "txversions",
+            tmp_dir.path().to_str().unwrap(),
+            "--period",
+            "2",
+        ]);
+        let mut callback = TxVersions::new(&matches).unwrap();
+        callback.on_start(0).unwrap();
+        let blocks = [vec![1, 1], vec![2, 0], vec![1, 2, 2], vec![3]];
+        for (height, versions) in blocks.iter().enumerate() {
+            let txs = versions
+                .iter()
+                .map(|v| tx_with_version(*v))
+                .collect::<Vec<_>>();
+            callback.on_txs(&txs, height as u64).unwrap();
+        }
+        callback.on_complete(3).unwrap();
+
+        let csv = fs::read_to_string(tmp_dir.path().join("txversions-0-3.csv")).unwrap();
+        TX_VERSIONS.assert_csv(&csv);
+        assert_eq!(
+            csv,
+            "period;version;count\n0;1;2\n0;2;1\n0;other;1\n2;1;1\n2;2;2\n2;other;1\n"
+        );
+    }
+}
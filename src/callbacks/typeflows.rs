@@ -0,0 +1,227 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::script::ScriptType;
+use crate::blockchain::proto::ToRaw;
+use crate::callbacks::compress::{Compression, OutputWriter};
+use crate::callbacks::rotate::{ColumnType, Table};
+use crate::callbacks::{common, BlockFlow, Callback};
+use crate::errors::OpResult;
+
+const TYPE_FLOWS: Table = Table::csv(
+    "typeflows",
+    &[
+        ("period", ColumnType::Integer),
+        ("src_type", ColumnType::String),
+        ("dst_type", ColumnType::String),
+        ("value", ColumnType::Integer),
+    ],
+);
+
+/// Sums the value moving between script types per period of blocks, e.g. from P2PKH to P2WPKH.
+/// Like in `clusterflows`, the output value of each spending transaction is split between the
+/// input types in proportion to their input value (truncated). Fees are not attributed and inputs
+/// spending outputs created before the start height are unknown.
+pub struct TypeFlows {
+    dump_folder: PathBuf,
+    writer: OutputWriter,
+    period: u64,
+
+    // key: txid + index, value: (script type, value)
+    unspents: common::Unspents<(ScriptType, u64)>,
+    // First height of the current period
+    cur_period: u64,
+    // key: (src type, dst type)
+    flows: BTreeMap<(ScriptType, ScriptType), u64>,
+
+    start_height: u64,
+    row_count: u64,
+}
+
+impl TypeFlows {
+    /// Writes the flows of the current period and resets them
+    fn flush_period(&mut self) -> OpResult<()> {
+        for ((src, dst), value) in &self.flows {
+            self.writer.write_all(
+                format!("{};{:?};{:?};{}\n", self.cur_period, src, dst, value).as_bytes(),
+            )?;
+        }
+        self.row_count += self.flows.len() as u64;
+        self.flows.clear();
+        Ok(())
+    }
+}
+
+impl Callback for TypeFlows {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        let cmd = Command::new("typeflows")
+            .about("Dumps the value moving between script types per period to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            )
+            .arg(
+                Arg::new("period")
+                    .long("period")
+                    .value_name("NUM")
+                    .value_parser(clap::value_parser!(u64).range(1..))
+                    .default_value("2016")
+                    .help("Number of blocks per period"),
+            );
+        Compression::add_args(cmd)
+    }
+
+    fn schema(_: &ArgMatches) -> Vec<Table>
+    where
+        Self: Sized,
+    {
+        vec![TYPE_FLOWS]
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let dump_folder = &PathBuf::from(matches.get_one::<String>("dump-folder").unwrap());
+        let cb = TypeFlows {
+            dump_folder: PathBuf::from(dump_folder),
+            writer: Compression::from_matches(matches)?
+                .create(&dump_folder.join("typeflows.csv.tmp"))?,
+            period: *matches.get_one::<u64>("period").unwrap(),
+            unspents: HashMap::with_capacity(10000000),
+            cur_period: 0,
+            flows: BTreeMap::new(),
+            start_height: 0,
+            row_count: 0,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        self.cur_period = block_height - block_height % self.period;
+        info!(target: "callback", "Executing typeflows with dump folder: {} ...", &self.dump_folder.display());
+        self.writer.write_all(TYPE_FLOWS.header().as_bytes())?;
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<BlockFlow> {
+        let period = block_height - block_height % self.period;
+        if period != self.cur_period {
+            self.flush_period()?;
+            self.cur_period = period;
+        }
+        for tx in &block.txs {
+            // Input value per source type
+            let mut sources: Vec<(ScriptType, u64)> = Vec::new();
+            for (_, (script_type, value)) in common::take_unspents(tx, &mut self.unspents) {
+                match sources.iter_mut().find(|(t, _)| *t == script_type) {
+                    Some((_, sum)) => *sum += value,
+                    None => sources.push((script_type, value)),
+                }
+            }
+
+            let outputs = common::eval_unspents_with(tx, block.genesis, |output| {
+                Some((ScriptType::from(output.script_type), output.value))
+            });
+
+            let input_value: u64 = sources.iter().map(|(_, value)| value).sum();
+            if input_value > 0 {
+                for (_, (dst, value)) in &outputs {
+                    for (src, src_value) in &sources {
+                        let share =
+                            (*value as u128 * *src_value as u128 / input_value as u128) as u64;
+                        *self.flows.entry((*src, *dst)).or_insert(0) += share;
+                    }
+                }
+            }
+
+            for (outpoint, unspent) in outputs {
+                self.unspents.insert(outpoint.to_bytes(), unspent);
+            }
+        }
+        Ok(BlockFlow::Continue)
+    }
+
+    fn needs_addresses(&self) -> bool {
+        false
+    }
+
+    fn tracks_utxos(&self) -> bool {
+        true
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.flush_period()?;
+        self.writer.finish()?;
+        fs::rename(
+            self.dump_folder.as_path().join("typeflows.csv.tmp"),
+            self.dump_folder
+                .as_path()
+                .join(self.writer.file_name(format!(
+                    "typeflows-{}-{}.csv",
+                    self.start_height, block_height
+                ))),
+        )?;
+        info!(target: "callback", "Done.\nDumped script type flows from height {} to {}:\n\
+                                   \t-> rows: {:9}",
+             self.start_height, block_height, self.row_count);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::tx::TxOutpoint;
+    use crate::callbacks::common::test_block;
+    use bitcoin::hashes::{sha256d, Hash};
+
+    #[test]
+    fn test_type_flows() {
+        let p2pkh = "76a91462e907b15cbf27d5425399ebf6f0fb50ebb88f1888ac";
+        let p2wpkh = "00141d7cd6c75c2e86f4cbf98eaed221b30bd9a0b928";
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let matches = TypeFlows::build_subcommand().get_matches_from([
+            "typeflows",
+            tmp_dir.path().to_str().unwrap(),
+            "--period",
+            "2",
+        ]);
+        let mut callback = TypeFlows::new(&matches).unwrap();
+        callback.on_start(1).unwrap();
+
+        let coinbase = TxOutpoint::new(sha256d::Hash::all_zeros(), 0xFFFFFFFF);
+        let block1 = test_block(vec![coinbase], &[(p2pkh, 10000)]);
+        callback.on_block(&block1, 1).unwrap();
+        // Migrates 6000 to P2WPKH, keeps 3000 as change and pays 1000 fees
+        let block2 = test_block(
+            vec![TxOutpoint::new(block1.txs[0].hash, 0)],
+            &[(p2wpkh, 6000), (p2pkh, 3000)],
+        );
+        callback.on_block(&block2, 2).unwrap();
+        callback.on_complete(2).unwrap();
+
+        let csv = fs::read_to_string(tmp_dir.path().join("typeflows-1-2.csv")).unwrap();
+        TYPE_FLOWS.assert_csv(&csv);
+        assert_eq!(
+            csv,
+            "period;src_type;dst_type;value\n\
+             2;Pay2PublicKeyHash;Pay2PublicKeyHash;3000\n\
+             2;Pay2PublicKeyHash;Pay2WitnessPublicKeyHash;6000\n"
+        );
+    }
+}
@@ -1,23 +1,50 @@
-use bitcoin::hashes::{sha256d, Hash};
-use std::collections::HashMap;
-use std::fs::{self, File};
-use std::io::{BufWriter, Write};
 use std::path::PathBuf;
 
-use byteorder::{LittleEndian, ReadBytesExt};
 use clap::{Arg, ArgMatches, Command};
 
+use crate::blockchain::parser::chainstate::SnapshotUtxo;
 use crate::blockchain::proto::block::Block;
-use crate::callbacks::{common, Callback};
+use crate::blockchain::proto::tx::TxOutpoint;
+use crate::callbacks::compress::Compression;
+use crate::callbacks::rotate::{ColumnType, RotatingWriter, Rotation, Table};
+use crate::callbacks::{common, BlockFlow, Callback};
+use crate::common::utils::HashByteOrder;
 use crate::errors::OpResult;
 
+const UNSPENT: Table = Table::csv(
+    "unspent",
+    &[
+        ("txid", ColumnType::String),
+        ("indexOut", ColumnType::Integer),
+        ("height", ColumnType::Integer),
+        ("value", ColumnType::Integer),
+        ("address", ColumnType::String),
+    ],
+);
+
+/// Returns the table of the written file, named `outputs` with `--outputs-only`
+fn table(outputs_only: bool) -> Table {
+    if outputs_only {
+        Table {
+            name: "outputs",
+            ..UNSPENT
+        }
+    } else {
+        UNSPENT
+    }
+}
+
 /// Dumps the UTXOs along with address in a csv file
 pub struct UnspentCsvDump {
     dump_folder: PathBuf,
-    writer: BufWriter<File>,
+    writer: RotatingWriter,
+    hash_byte_order: HashByteOrder,
 
     // key: txid + index
-    unspents: HashMap<Vec<u8>, common::UnspentValue>,
+    unspents: common::ShardedUnspents,
+    interner: common::AddressInterner,
+    // Dumps every output as soon as it is seen and ignores inputs
+    outputs_only: bool,
 
     start_height: u64,
     tx_count: u64,
@@ -26,8 +53,19 @@ pub struct UnspentCsvDump {
 }
 
 impl UnspentCsvDump {
-    fn create_writer(cap: usize, path: PathBuf) -> OpResult<BufWriter<File>> {
-        Ok(BufWriter::with_capacity(cap, File::create(path)?))
+    fn write_unspent(
+        &mut self,
+        outpoint: &TxOutpoint,
+        unspent: &common::UnspentValue,
+    ) -> OpResult<()> {
+        self.writer.write_row(&format!(
+            "{};{};{};{};{}\n",
+            self.hash_byte_order.hex(&outpoint.txid),
+            outpoint.index,
+            unspent.block_height,
+            unspent.value,
+            unspent.address()
+        ))
     }
 }
 
@@ -36,7 +74,7 @@ impl Callback for UnspentCsvDump {
     where
         Self: Sized,
     {
-        Command::new("unspentcsvdump")
+        let cmd = Command::new("unspentcsvdump")
             .about("Dumps the unspent outputs to CSV file")
             .version("0.1")
             .author("fsvm88 <fsvm88@gmail.com>")
@@ -46,6 +84,26 @@ impl Callback for UnspentCsvDump {
                     .index(1)
                     .required(true),
             )
+            .arg(
+                Arg::new("dedup-addresses")
+                    .long("dedup-addresses")
+                    .action(clap::ArgAction::SetTrue)
+                    .help("Shares memory between unspents paying to the same address"),
+            )
+            .arg(
+                Arg::new("outputs-only")
+                    .long("outputs-only")
+                    .action(clap::ArgAction::SetTrue)
+                    .help("Dumps every output without tracking spends (receive-only ledger)"),
+            );
+        common::add_utxo_shards_arg(Compression::add_args(Rotation::add_args(cmd)))
+    }
+
+    fn schema(matches: &ArgMatches) -> Vec<Table>
+    where
+        Self: Sized,
+    {
+        vec![table(matches.get_flag("outputs-only"))]
     }
 
     fn new(matches: &ArgMatches) -> OpResult<Self>
@@ -53,10 +111,21 @@ impl Callback for UnspentCsvDump {
         Self: Sized,
     {
         let dump_folder = &PathBuf::from(matches.get_one::<String>("dump-folder").unwrap());
+        let outputs_only = matches.get_flag("outputs-only");
+        let table = table(outputs_only);
         let cb = UnspentCsvDump {
             dump_folder: PathBuf::from(dump_folder),
-            writer: UnspentCsvDump::create_writer(4000000, dump_folder.join("unspent.csv.tmp"))?,
-            unspents: HashMap::with_capacity(10000000),
+            writer: RotatingWriter::new(
+                dump_folder,
+                table.name,
+                Some(&table.header()),
+                Rotation::from_matches(matches),
+                Compression::from_matches(matches)?,
+            )?,
+            hash_byte_order: HashByteOrder::from_matches(matches)?,
+            unspents: common::ShardedUnspents::from_matches(matches, 10000000),
+            interner: common::AddressInterner::new(matches.get_flag("dedup-addresses")),
+            outputs_only,
             start_height: 0,
             tx_count: 0,
             in_count: 0,
@@ -78,46 +147,59 @@ impl Callback for UnspentCsvDump {
     ///   * block height as "last modified"
     ///   * output_val
     ///   * address
-    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
-        for tx in &block.txs {
-            self.in_count += common::remove_unspents(tx, &mut self.unspents);
-            self.out_count += common::insert_unspents(tx, block_height, &mut self.unspents);
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<BlockFlow> {
+        if self.outputs_only {
+            self.writer.start_block(block_height)?;
+            for tx in &block.txs {
+                for (outpoint, unspent) in
+                    common::eval_unspents(tx, block_height, block.genesis, &mut self.interner)
+                {
+                    self.write_unspent(&outpoint, &unspent)?;
+                    self.out_count += 1;
+                }
+            }
+            self.tx_count += block.tx_count.value;
+            return Ok(BlockFlow::Continue);
         }
+
+        self.in_count += block
+            .txs
+            .iter()
+            .map(|tx| tx.value.in_count.value)
+            .sum::<u64>();
+        self.out_count += common::apply_unspents(
+            &block.txs,
+            block_height,
+            block.genesis,
+            &mut self.unspents,
+            &mut self.interner,
+        );
         self.tx_count += block.tx_count.value;
+        if block_height % 10000 == 0 {
+            self.interner.prune();
+        }
+        Ok(BlockFlow::Continue)
+    }
+
+    /// Snapshots only contain unspents, so they can't be combined with `--outputs-only`
+    fn supports_utxo_snapshot(&self) -> bool {
+        !self.outputs_only
+    }
+
+    fn on_snapshot_utxo(&mut self, utxo: &SnapshotUtxo) -> OpResult<()> {
+        if common::insert_snapshot_utxo(utxo, &mut self.unspents, &mut self.interner) {
+            self.out_count += 1;
+        }
         Ok(())
     }
 
     fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
-        self.writer.write_all(
-            format!(
-                "{};{};{};{};{}\n",
-                "txid", "indexOut", "height", "value", "address"
-            )
-            .as_bytes(),
-        )?;
-        for (key, value) in self.unspents.iter() {
-            let txid = sha256d::Hash::from_slice(&key[0..32]).unwrap();
-            let mut index = &key[32..];
-            self.writer.write_all(
-                format!(
-                    "{};{};{};{};{}\n",
-                    txid,
-                    index.read_u32::<LittleEndian>()?,
-                    value.block_height,
-                    value.value,
-                    value.address
-                )
-                .as_bytes(),
-            )?;
+        self.writer.start_block(block_height)?;
+        let unspents = std::mem::replace(&mut self.unspents, common::ShardedUnspents::new(1, 0));
+        for (key, value) in unspents.iter() {
+            self.write_unspent(&TxOutpoint::from_bytes(key), value)?;
         }
-
-        fs::rename(
-            self.dump_folder.as_path().join("unspent.csv.tmp"),
-            self.dump_folder.as_path().join(format!(
-                "unspent-{}-{}.csv",
-                self.start_height, block_height
-            )),
-        )?;
+        self.writer.finish(self.start_height, block_height)?;
 
         info!(target: "callback", "Done.\nDumped blocks from height {} to {}:\n\
                                    \t-> transactions: {:9}\n\
@@ -127,3 +209,88 @@ impl Callback for UnspentCsvDump {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::tx::TxOutpoint;
+    use crate::callbacks::common::test_block;
+    use std::fs;
+
+    // P2PKH to 1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa
+    const P2PKH: &str = "76a91462e907b15cbf27d5425399ebf6f0fb50ebb88f1888ac";
+
+    fn run(args: &[&str]) -> (tempfile::TempDir, Block, Block) {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut argv = vec!["unspentcsvdump", tmp_dir.path().to_str().unwrap()];
+        argv.extend_from_slice(args);
+        let matches = UnspentCsvDump::build_subcommand().get_matches_from(argv);
+        let mut callback = UnspentCsvDump::new(&matches).unwrap();
+        callback.on_start(1).unwrap();
+
+        let funding = test_block(vec![], &[(P2PKH, 100), (P2PKH, 200)]);
+        callback.on_block(&funding, 1).unwrap();
+        // Spends the first output
+        let spend = test_block(
+            vec![TxOutpoint::new(funding.txs[0].hash, 0)],
+            &[(P2PKH, 90)],
+        );
+        callback.on_block(&spend, 2).unwrap();
+        callback.on_complete(2).unwrap();
+        (tmp_dir, funding, spend)
+    }
+
+    /// Returns the sorted rows of the file described by the schema
+    fn rows(tmp_dir: &tempfile::TempDir, args: &[&str]) -> Vec<String> {
+        let mut argv = vec!["unspentcsvdump", tmp_dir.path().to_str().unwrap()];
+        argv.extend_from_slice(args);
+        let matches = UnspentCsvDump::build_subcommand().get_matches_from(argv);
+        let table = &UnspentCsvDump::schema(&matches)[0];
+        let path = tmp_dir.path().join(format!("{}-1-2.csv", table.name));
+        let csv = fs::read_to_string(path).unwrap();
+        table.assert_csv(&csv);
+        let mut rows: Vec<String> = csv.lines().skip(1).map(String::from).collect();
+        rows.sort();
+        rows
+    }
+
+    #[test]
+    fn test_unspent() {
+        let (tmp_dir, funding, spend) = run(&[]);
+        let mut expected = vec![
+            format!(
+                "{};1;1;200;1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa",
+                HashByteOrder::Rpc.hex(&funding.txs[0].hash)
+            ),
+            format!(
+                "{};0;2;90;1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa",
+                HashByteOrder::Rpc.hex(&spend.txs[0].hash)
+            ),
+        ];
+        expected.sort();
+        assert_eq!(rows(&tmp_dir, &[]), expected);
+    }
+
+    #[test]
+    fn test_outputs_only() {
+        let (tmp_dir, funding, spend) = run(&["--outputs-only"]);
+        assert!(!tmp_dir.path().join("unspent-1-2.csv").exists());
+        // The spent output is still dumped
+        let mut expected = vec![
+            format!(
+                "{};0;1;100;1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa",
+                HashByteOrder::Rpc.hex(&funding.txs[0].hash)
+            ),
+            format!(
+                "{};1;1;200;1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa",
+                HashByteOrder::Rpc.hex(&funding.txs[0].hash)
+            ),
+            format!(
+                "{};0;2;90;1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa",
+                HashByteOrder::Rpc.hex(&spend.txs[0].hash)
+            ),
+        ];
+        expected.sort();
+        assert_eq!(rows(&tmp_dir, &["--outputs-only"]), expected);
+    }
+}
@@ -0,0 +1,266 @@
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::parser::chainstate::SnapshotUtxo;
+use crate::blockchain::proto::block::Block;
+use crate::callbacks::compress::Compression;
+use crate::callbacks::rotate::{ColumnType, Table};
+use crate::callbacks::{common, BlockFlow, Callback};
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+const UTXO_AGES: Table = Table::csv(
+    "utxoages",
+    &[
+        ("age_bucket", ColumnType::String),
+        ("utxo_count", ColumnType::Integer),
+        ("total_value", ColumnType::Integer),
+    ],
+);
+
+/// Bucket upper bounds in blocks: 1 day, 1 week, 1, 3 and 6 months, 1, 2, 3 and 5 years
+const DEFAULT_BUCKETS: &str = "144,1008,4320,12960,25920,52560,105120,157680,262800";
+
+/// Bins all UTXOs at the last parsed height by age
+pub struct UtxoAges {
+    dump_folder: PathBuf,
+    compression: Compression,
+    // Ascending, exclusive upper bounds of each bucket. The last bucket is open ended.
+    buckets: Vec<u64>,
+
+    // key: txid + index
+    unspents: common::ShardedUnspents,
+    interner: common::AddressInterner,
+
+    start_height: u64,
+}
+
+impl UtxoAges {
+    /// Parses a comma separated list of strictly ascending bucket bounds
+    fn parse_buckets(buckets: &str) -> OpResult<Vec<u64>> {
+        let bounds = buckets
+            .split(',')
+            .map(|b| b.trim().parse::<u64>())
+            .collect::<Result<Vec<u64>, _>>()
+            .map_err(|e| {
+                OpError::new(OpErrorKind::InvalidArgsError)
+                    .join_msg(&format!("Invalid --buckets: {}", e))
+            })?;
+        if bounds.first() == Some(&0) || bounds.windows(2).any(|w| w[0] >= w[1]) {
+            return Err(OpError::new(OpErrorKind::InvalidArgsError)
+                .join_msg("--buckets must be strictly ascending and greater than 0"));
+        }
+        Ok(bounds)
+    }
+
+    /// Returns (label, utxo_count, total_value) for each bucket
+    fn distribution(&self, tip_height: u64) -> Vec<(String, u64, u64)> {
+        let mut counts = vec![(0u64, 0u64); self.buckets.len() + 1];
+        for unspent in self.unspents.values() {
+            let age = tip_height.saturating_sub(unspent.block_height);
+            let idx = self.buckets.partition_point(|bound| *bound <= age);
+            counts[idx].0 += 1;
+            counts[idx].1 += unspent.value;
+        }
+        let mut lower = 0;
+        let mut distribution = Vec::with_capacity(counts.len());
+        for (i, (count, value)) in counts.into_iter().enumerate() {
+            let label = match self.buckets.get(i) {
+                Some(upper) => format!("{}-{}", lower, upper - 1),
+                None => format!("{}+", lower),
+            };
+            distribution.push((label, count, value));
+            lower = self.buckets.get(i).copied().unwrap_or(lower);
+        }
+        distribution
+    }
+}
+
+impl Callback for UtxoAges {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        let cmd = Command::new("utxoages")
+            .about("Dumps the UTXO age distribution at the last parsed height to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            )
+            .arg(
+                Arg::new("buckets")
+                    .long("buckets")
+                    .value_name("BOUNDS")
+                    .default_value(DEFAULT_BUCKETS)
+                    .help("Comma separated, ascending bucket upper bounds in blocks"),
+            )
+            .arg(
+                Arg::new("dedup-addresses")
+                    .long("dedup-addresses")
+                    .action(clap::ArgAction::SetTrue)
+                    .help("Shares memory between unspents paying to the same address"),
+            );
+        Compression::add_args(common::add_utxo_shards_arg(cmd))
+    }
+
+    fn schema(_: &ArgMatches) -> Vec<Table>
+    where
+        Self: Sized,
+    {
+        vec![UTXO_AGES]
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let dump_folder = &PathBuf::from(matches.get_one::<String>("dump-folder").unwrap());
+        let cb = UtxoAges {
+            dump_folder: PathBuf::from(dump_folder),
+            compression: Compression::from_matches(matches)?,
+            buckets: UtxoAges::parse_buckets(matches.get_one::<String>("buckets").unwrap())?,
+            unspents: common::ShardedUnspents::from_matches(matches, 10000000),
+            interner: common::AddressInterner::new(matches.get_flag("dedup-addresses")),
+            start_height: 0,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing utxoages with dump folder: {} ...", &self.dump_folder.display());
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<BlockFlow> {
+        common::apply_unspents(
+            &block.txs,
+            block_height,
+            block.genesis,
+            &mut self.unspents,
+            &mut self.interner,
+        );
+        if block_height % 10000 == 0 {
+            self.interner.prune();
+        }
+        Ok(BlockFlow::Continue)
+    }
+
+    fn supports_utxo_snapshot(&self) -> bool {
+        true
+    }
+
+    fn supports_checkpoint(&self) -> bool {
+        true
+    }
+
+    fn save_state(&self, writer: &mut dyn Write) -> OpResult<()> {
+        common::write_utxo_state(writer, self.start_height, &self.unspents)
+    }
+
+    fn load_state(&mut self, reader: &mut dyn Read) -> OpResult<()> {
+        self.start_height =
+            common::read_utxo_state(reader, &mut self.unspents, &mut self.interner)?;
+        Ok(())
+    }
+
+    fn on_snapshot_utxo(&mut self, utxo: &SnapshotUtxo) -> OpResult<()> {
+        common::insert_snapshot_utxo(utxo, &mut self.unspents, &mut self.interner);
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        let path = self.dump_folder.join(format!(
+            "utxoages-{}-{}.csv{}",
+            self.start_height,
+            block_height,
+            self.compression.suffix()
+        ));
+        let mut writer = self.compression.create(&path)?;
+        writer.write_all(UTXO_AGES.header().as_bytes())?;
+        for (label, count, value) in self.distribution(block_height) {
+            writer.write_all(format!("{};{};{}\n", label, count, value).as_bytes())?;
+        }
+        writer.finish()?;
+        info!(target: "callback", "Done.\nDumped age distribution of {} UTXOs at height {}.",
+             self.unspents.len(), block_height);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::script::AddressFormat;
+    use crate::blockchain::proto::tx::{EvaluatedTx, RawTx, TxOutput};
+    use crate::blockchain::proto::varuint::VarUint;
+    use crate::blockchain::proto::Hashed;
+
+    #[test]
+    fn test_parse_buckets() {
+        assert_eq!(
+            UtxoAges::parse_buckets("1, 10,100").unwrap(),
+            vec![1, 10, 100]
+        );
+        assert!(UtxoAges::parse_buckets("10,1").is_err());
+        assert!(UtxoAges::parse_buckets("0,1").is_err());
+        assert!(UtxoAges::parse_buckets("1,x").is_err());
+    }
+
+    #[test]
+    fn test_distribution() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let matches = UtxoAges::build_subcommand().get_matches_from([
+            "utxoages",
+            tmp_dir.path().to_str().unwrap(),
+            "--buckets",
+            "10,100",
+        ]);
+        let mut callback = UtxoAges::new(&matches).unwrap();
+
+        // P2PKH output to 1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa
+        let mut script_pubkey = vec![0x76, 0xa9, 0x14];
+        script_pubkey.extend(crate::common::utils::hex_to_vec(
+            "62e907b15cbf27d5425399ebf6f0fb50ebb88f18",
+        ));
+        script_pubkey.extend([0x88, 0xac]);
+        for (height, value) in [(0, 1), (150, 2), (195, 4), (200, 8)] {
+            let tx = Hashed::double_sha256(EvaluatedTx::from(RawTx {
+                version: 1,
+                in_count: VarUint::from(0u8),
+                inputs: vec![],
+                out_count: VarUint::from(1u8),
+                outputs: vec![TxOutput {
+                    value,
+                    script_len: VarUint::from(script_pubkey.len() as u8),
+                    script_pubkey: script_pubkey.clone(),
+                }],
+                locktime: height as u32,
+                address_format: AddressFormat::default(),
+                witness_size: 0,
+            }));
+            common::apply_unspents(
+                std::slice::from_ref(&tx),
+                height,
+                false,
+                &mut callback.unspents,
+                &mut callback.interner,
+            );
+        }
+
+        let distribution = callback.distribution(200);
+        assert_eq!(
+            distribution,
+            vec![
+                (String::from("0-9"), 2, 12),
+                (String::from("10-99"), 1, 2),
+                (String::from("100+"), 1, 1),
+            ]
+        );
+    }
+}
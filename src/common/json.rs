@@ -0,0 +1,66 @@
+use serde_json::{json, Value};
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::tx::EvaluatedTx;
+use crate::blockchain::proto::Hashed;
+use crate::common::utils::{self, HashByteOrder};
+
+/// Returns the block with all transactions as JSON
+pub fn block_json(block: &Block, height: Option<u64>, order: HashByteOrder) -> Value {
+    let header = &block.header.value;
+    json!({
+        "hash": order.hex(&block.header.hash),
+        "height": height,
+        "size": block.size,
+        "header": {
+            "version": header.version,
+            "prev_hash": order.hex(&header.prev_hash),
+            "merkle_root": order.hex(&header.merkle_root),
+            "timestamp": header.timestamp,
+            "bits": header.bits,
+            "nonce": header.nonce,
+        },
+        "txs": block.txs.iter().map(|tx| tx_json(tx, height, order)).collect::<Vec<Value>>(),
+    })
+}
+
+/// Returns the transaction with its inputs and outputs as JSON.
+/// `height` is null for transactions which were parsed on their own.
+pub fn tx_json(tx: &Hashed<EvaluatedTx>, height: Option<u64>, order: HashByteOrder) -> Value {
+    let inputs: Vec<Value> = tx
+        .value
+        .inputs
+        .iter()
+        .map(|input| {
+            json!({
+                "txid": order.hex(&input.outpoint.txid),
+                "index": input.outpoint.index,
+                "script_sig": utils::arr_to_hex(&input.script_sig),
+                "seq_no": input.seq_no,
+                "has_witness": input.has_witness(),
+            })
+        })
+        .collect();
+    let outputs: Vec<Value> = tx
+        .value
+        .outputs
+        .iter()
+        .map(|output| {
+            json!({
+                "value": output.out.value,
+                "script_pubkey": utils::arr_to_hex(&output.out.script_pubkey),
+                "address": output.script.address,
+                "pattern": output.script.pattern.to_string(),
+            })
+        })
+        .collect();
+    json!({
+        "txid": order.hex(&tx.hash),
+        "height": height,
+        "version": tx.value.version,
+        "inputs": inputs,
+        "outputs": outputs,
+        "locktime": tx.value.locktime,
+        "weight": tx.value.weight(),
+    })
+}
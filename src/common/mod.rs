@@ -1,2 +1,4 @@
+pub mod json;
 pub mod logger;
+pub mod profile;
 pub mod utils;
@@ -0,0 +1,209 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Phases of the main loop which are timed with `--profile`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Reading and deserializing blocks, including script resolution
+    Read,
+    /// Hashing transactions and resolving their output scripts
+    Scripts,
+    /// Updating the UTXO set of the callback
+    Utxo,
+    /// Invoking the callback, including UTXO updates
+    Callback,
+}
+
+/// Wall time of a phase. Measurements which overlap, e.g. of blocks deserialized in parallel with `--threads`,
+/// are only counted once, so nested phases can be subtracted from the wall time of their outer phase.
+struct Timer {
+    // Number of running measurements
+    active: usize,
+    since: Option<Instant>,
+    elapsed: Duration,
+}
+
+impl Timer {
+    const fn new() -> Self {
+        Timer {
+            active: 0,
+            since: None,
+            elapsed: Duration::ZERO,
+        }
+    }
+
+    fn start(&mut self, now: Instant) {
+        if self.active == 0 {
+            self.since = Some(now);
+        }
+        self.active += 1;
+    }
+
+    fn stop(&mut self, now: Instant) {
+        self.active -= 1;
+        if self.active == 0 {
+            if let Some(since) = self.since.take() {
+                self.elapsed += now.saturating_duration_since(since);
+            }
+        }
+    }
+}
+
+/// One timer per phase
+struct Timers([Mutex<Timer>; 4]);
+
+impl Timers {
+    const fn new() -> Self {
+        Timers([
+            Mutex::new(Timer::new()),
+            Mutex::new(Timer::new()),
+            Mutex::new(Timer::new()),
+            Mutex::new(Timer::new()),
+        ])
+    }
+
+    fn measure<T>(&self, phase: Phase, f: impl FnOnce() -> T) -> T {
+        let timer = &self.0[phase as usize];
+        timer.lock().unwrap().start(Instant::now());
+        let result = f();
+        timer.lock().unwrap().stop(Instant::now());
+        result
+    }
+
+    /// Returns the time per phase and resets the timers
+    fn take(&self) -> Breakdown {
+        let [read, scripts, utxo, callback] =
+            [Phase::Read, Phase::Scripts, Phase::Utxo, Phase::Callback]
+                .map(|phase| std::mem::take(&mut self.0[phase as usize].lock().unwrap().elapsed));
+        Breakdown {
+            read: read.saturating_sub(scripts),
+            scripts,
+            utxo,
+            callback: callback.saturating_sub(utxo),
+        }
+    }
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static TIMERS: Timers = Timers::new();
+
+/// Enables or disables the timers. Should be called once before parsing.
+/// If disabled, `measure()` only calls the closure.
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Runs the closure and adds its wall time to the phase if profiling is enabled
+#[inline]
+pub fn measure<T>(phase: Phase, f: impl FnOnce() -> T) -> T {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return f();
+    }
+    TIMERS.measure(phase, f)
+}
+
+/// Time spent per phase since the last call to `Breakdown::take()`.
+/// Nested phases are subtracted, so the phases add up to the measured total.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Breakdown {
+    pub read: Duration,
+    pub scripts: Duration,
+    pub utxo: Duration,
+    pub callback: Duration,
+}
+
+impl Breakdown {
+    /// Returns the time per phase and resets the timers
+    pub fn take() -> Self {
+        TIMERS.take()
+    }
+
+    pub fn total(&self) -> Duration {
+        self.read + self.scripts + self.utxo + self.callback
+    }
+}
+
+impl std::fmt::Display for Breakdown {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let total = self.total().as_secs_f64().max(f64::MIN_POSITIVE);
+        let parts = [
+            ("read", self.read),
+            ("scripts", self.scripts),
+            ("utxo", self.utxo),
+            ("callback", self.callback),
+        ]
+        .map(|(name, duration)| {
+            format!(
+                "{} {:.2}s ({:.0}%)",
+                name,
+                duration.as_secs_f64(),
+                duration.as_secs_f64() * 100.0 / total
+            )
+        });
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn test_timer() {
+        let t0 = Instant::now();
+        let mut timer = Timer::new();
+        timer.start(t0);
+        timer.stop(t0 + ms(10));
+        assert_eq!(timer.elapsed, ms(10));
+
+        // Overlapping measurements, e.g. of blocks deserialized in parallel, count as wall time
+        timer.start(t0 + ms(20));
+        timer.start(t0 + ms(25));
+        timer.stop(t0 + ms(40));
+        assert_eq!(timer.elapsed, ms(10));
+        timer.stop(t0 + ms(50));
+        assert_eq!(timer.elapsed, ms(40));
+        assert_eq!(timer.active, 0);
+    }
+
+    #[test]
+    fn test_breakdown() {
+        let timers = Timers::new();
+        assert_eq!(timers.measure(Phase::Read, || 1), 1);
+        timers.take();
+
+        let t0 = Instant::now();
+        let record = |phase: Phase, from: u64, to: u64| {
+            let mut timer = timers.0[phase as usize].lock().unwrap();
+            timer.start(t0 + ms(from));
+            timer.stop(t0 + ms(to));
+        };
+        record(Phase::Read, 0, 30);
+        record(Phase::Scripts, 10, 20);
+        record(Phase::Callback, 30, 60);
+        record(Phase::Utxo, 40, 50);
+        let breakdown = timers.take();
+        // Nested phases are subtracted from their outer phase
+        assert_eq!(
+            breakdown,
+            Breakdown {
+                read: ms(20),
+                scripts: ms(10),
+                utxo: ms(10),
+                callback: ms(20),
+            }
+        );
+        assert_eq!(breakdown.total(), ms(60));
+        assert_eq!(
+            breakdown.to_string(),
+            "read 0.02s (33%), scripts 0.01s (17%), utxo 0.01s (17%), callback 0.02s (33%)"
+        );
+        // The timers are reset
+        assert_eq!(timers.take(), Breakdown::default());
+    }
+}
@@ -1,39 +1,126 @@
 use bitcoin::hashes::{sha256d, Hash};
+use clap::ArgMatches;
 use std::path::PathBuf;
+use std::str::FromStr;
 
 use crate::blockchain::parser::types::CoinType;
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+/// Byte order used to display hashes in callback output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HashByteOrder {
+    /// Byte order as stored in the blk files and used for hashing
+    Internal,
+    /// Reversed byte order as shown by Bitcoin Core RPC and block explorers
+    #[default]
+    Rpc,
+}
+
+impl FromStr for HashByteOrder {
+    type Err = OpError;
+    fn from_str(s: &str) -> OpResult<Self> {
+        match s {
+            "internal" => Ok(HashByteOrder::Internal),
+            "rpc" => Ok(HashByteOrder::Rpc),
+            n => Err(OpError::new(OpErrorKind::InvalidArgsError)
+                .join_msg(&format!("Unknown hash byte order `{}`!", n))),
+        }
+    }
+}
+
+impl HashByteOrder {
+    /// Reads the global `--hash-byte-order` argument from the matches of a callback.
+    /// Defaults to rpc if the argument is not set or the subcommand is used on its own (e.g. in tests).
+    pub fn from_matches(matches: &ArgMatches) -> OpResult<Self> {
+        match matches.try_get_one::<String>("hash-byte-order") {
+            Ok(Some(order)) => order.parse(),
+            _ => Ok(HashByteOrder::default()),
+        }
+    }
+
+    /// Formats the hash in this byte order
+    pub fn hex(self, hash: &sha256d::Hash) -> String {
+        match self {
+            HashByteOrder::Internal => arr_to_hex(hash.as_byte_array()),
+            HashByteOrder::Rpc => arr_to_hex_swapped(hash.as_byte_array()),
+        }
+    }
+}
 
 /// Calculates merkle root for the whole block
 /// See: https://en.bitcoin.it/wiki/Protocol_documentation#Merkle_Trees
+/// Returns an all-zero hash if there are no hashes (e.g. a block without transactions).
 pub fn merkle_root(hashes: Vec<sha256d::Hash>) -> sha256d::Hash {
     let mut hashes = hashes;
+    if hashes.is_empty() {
+        return sha256d::Hash::all_zeros();
+    }
+    while hashes.len() > 1 {
+        hashes = merkle_level(&hashes);
+    }
+    hashes[0]
+}
+
+/// Computes the merkle branch which proves the inclusion of the hash at `index`.
+/// Each entry holds the sibling hash and whether the sibling is on the right side.
+pub fn merkle_branch(hashes: Vec<sha256d::Hash>, index: usize) -> Vec<(sha256d::Hash, bool)> {
+    let mut hashes = hashes;
+    let mut index = index;
+    let mut branch = Vec::new();
+    while hashes.len() > 1 {
+        let sibling = index ^ 1;
+        // If the length is odd, the last hash is its own sibling
+        let hash = *hashes.get(sibling).unwrap_or(&hashes[index]);
+        branch.push((hash, sibling > index));
+        hashes = merkle_level(&hashes);
+        index /= 2;
+    }
+    branch
+}
 
+/// Returns true if two identical hashes are paired on any level of the merkle tree (CVE-2012-2459).
+/// Duplicating the last transactions of a block this way doesn't change its merkle root.
+pub fn is_merkle_mutated(hashes: &[sha256d::Hash]) -> bool {
+    let mut hashes = hashes.to_vec();
     while hashes.len() > 1 {
-        // Calculates double sha hash for each pair. If len is odd, last value is ignored.
-        let mut new_hashes = hashes
-            .chunks(2)
-            .filter(|c| c.len() == 2)
-            .map(|c| sha256d::Hash::hash(&[c[0], c[1]].concat()))
-            .collect::<Vec<sha256d::Hash>>();
-
-        // If the length is odd, take the last hash twice
-        if hashes.len() % 2 == 1 {
-            let last_hash = hashes.last().unwrap();
-            new_hashes.push(sha256d::Hash::hash(
-                &[&last_hash[..], &last_hash[..]].concat(),
-            ));
+        if hashes.chunks_exact(2).any(|c| c[0] == c[1]) {
+            return true;
         }
-        hashes = new_hashes;
+        hashes = merkle_level(&hashes);
     }
-    *hashes
-        .first()
-        .expect("unable to calculate merkle root on empty hashes")
+    false
+}
+
+/// Calculates double sha hash for each pair. If the length is odd, the last hash is taken twice.
+fn merkle_level(hashes: &[sha256d::Hash]) -> Vec<sha256d::Hash> {
+    hashes
+        .chunks(2)
+        .map(|c| {
+            let right = c.get(1).unwrap_or(&c[0]);
+            sha256d::Hash::hash(&[&c[0][..], &right[..]].concat())
+        })
+        .collect()
 }
 
 pub fn arr_to_hex(data: &[u8]) -> String {
     data.iter().map(|b| format!("{:02x?}", b)).collect()
 }
 
+pub fn arr_to_hex_swapped(data: &[u8]) -> String {
+    data.iter().rev().map(|b| format!("{:02x?}", b)).collect()
+}
+
+/// Decodes a hex string, returns None if it isn't valid hex
+pub fn try_hex_to_vec(hex_str: &str) -> Option<Vec<u8>> {
+    if hex_str.len() % 2 != 0 || !hex_str.is_ascii() {
+        return None;
+    }
+    (0..hex_str.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex_str[i..i + 2], 16).ok())
+        .collect()
+}
+
 pub fn hex_to_vec(hex_str: &str) -> Vec<u8> {
     if hex_str.len() % 2 != 0 {
         panic!("string length is not even");
@@ -45,6 +132,79 @@ pub fn hex_to_vec(hex_str: &str) -> Vec<u8> {
         .collect()
 }
 
+/// Script types which can be encoded as CashAddr
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CashAddrType {
+    P2PKH,
+    P2SH,
+}
+
+const CASHADDR_CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+/// BCH checksum over 5-bit values, see https://github.com/bitcoincashorg/bitcoincash.org/blob/master/spec/cashaddr.md
+fn cashaddr_polymod(values: impl Iterator<Item = u8>) -> u64 {
+    const GENERATORS: [u64; 5] = [
+        0x98f2bc8e61,
+        0x79b76d99e2,
+        0xf33e5fb3c4,
+        0xae2eabe2a8,
+        0x1e4f43e470,
+    ];
+    let mut c: u64 = 1;
+    for value in values {
+        let c0 = c >> 35;
+        c = ((c & 0x07ffffffff) << 5) ^ value as u64;
+        for (i, generator) in GENERATORS.iter().enumerate() {
+            if (c0 >> i) & 1 == 1 {
+                c ^= generator;
+            }
+        }
+    }
+    c ^ 1
+}
+
+/// Encodes a hash160 as CashAddr, e.g. `bitcoincash:qpm2qsznhks23z7629mms6s4cwef74vcwvy22gdx6a`
+pub fn cashaddr_encode(prefix: &str, addr_type: CashAddrType, hash: &[u8; 20]) -> String {
+    // Type bits followed by the size bits, which are 0 for 160 bit hashes
+    let version = match addr_type {
+        CashAddrType::P2PKH => 0u8,
+        CashAddrType::P2SH => 1u8 << 3,
+    };
+    // Regroup the 8-bit payload into 5-bit values, padded with zeros
+    let mut payload = Vec::with_capacity(34);
+    let (mut acc, mut bits) = (0u32, 0u32);
+    for byte in std::iter::once(version).chain(hash.iter().copied()) {
+        acc = (acc << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            payload.push(((acc >> bits) & 0x1f) as u8);
+        }
+    }
+    if bits > 0 {
+        payload.push(((acc << (5 - bits)) & 0x1f) as u8);
+    }
+
+    let checksum = cashaddr_polymod(
+        prefix
+            .bytes()
+            .map(|b| b & 0x1f)
+            .chain(std::iter::once(0))
+            .chain(payload.iter().copied())
+            .chain([0u8; 8]),
+    );
+    let mut address = String::with_capacity(prefix.len() + 1 + payload.len() + 8);
+    address.push_str(prefix);
+    address.push(':');
+    for value in payload
+        .into_iter()
+        .chain((0..8).rev().map(|i| ((checksum >> (5 * i)) & 0x1f) as u8))
+    {
+        address.push(CASHADDR_CHARSET[value as usize] as char);
+    }
+    address
+}
+
 /// Returns default directory. TODO: test on windows
 pub fn get_absolute_blockchain_dir(coin: &CoinType) -> PathBuf {
     dirs::home_dir()
@@ -57,7 +217,7 @@ pub fn get_mean(slice: &[u32]) -> f64 {
     if slice.is_empty() {
         return 0.00;
     }
-    let sum = slice.iter().sum::<u32>();
+    let sum = slice.iter().map(|v| *v as u64).sum::<u64>();
     sum as f64 / slice.len() as f64
 }
 
@@ -76,6 +236,38 @@ mod tests {
         assert_eq!(arr_to_hex(&test), expected);
     }
 
+    #[test]
+    fn test_arr_to_hex_swapped() {
+        let hash = sha256d::Hash::from_str(
+            "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f",
+        )
+        .unwrap();
+        assert_eq!(
+            arr_to_hex_swapped(hash.as_byte_array()),
+            "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f"
+        );
+        assert_eq!(
+            arr_to_hex(hash.as_byte_array()),
+            "6fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d6190000000000"
+        );
+    }
+
+    #[test]
+    fn test_cashaddr_encode() {
+        // Test vectors from the CashAddr specification
+        let hash: [u8; 20] = hex_to_vec("76a04053bda0a88bda5177b86a15c3b29f559873")
+            .try_into()
+            .unwrap();
+        assert_eq!(
+            cashaddr_encode("bitcoincash", CashAddrType::P2PKH, &hash),
+            "bitcoincash:qpm2qsznhks23z7629mms6s4cwef74vcwvy22gdx6a"
+        );
+        assert_eq!(
+            cashaddr_encode("bitcoincash", CashAddrType::P2SH, &hash),
+            "bitcoincash:ppm2qsznhks23z7629mms6s4cwef74vcwvn0h829pq"
+        );
+    }
+
     #[test]
     fn test_merkle_root() {
         let hashes = Vec::from([
@@ -120,4 +312,59 @@ mod tests {
         let merkle_hash = merkle_root(hashes);
         assert_eq!(merkle_hash, expected);
     }
+
+    #[test]
+    fn test_merkle_root_block_100000() {
+        let txids: Vec<sha256d::Hash> = [
+            "8c14f0db3df150123e6f3dbbf30f8b955a8249b62ac1d1ff16284aefa3d06d87",
+            "fff2525b8931402dd09222c50775608f75787bd2b87e56995a7bdd30f79702c4",
+            "6359f0868171b1d194cbee1af2f16ea598ae8fad666d9b012c8ed2b79a236ec4",
+            "e9a66845e05d5abc0ad04ec80f774a7e585c6e8db975962d069a522137b80c1d",
+        ]
+        .iter()
+        .map(|txid| sha256d::Hash::from_str(txid).unwrap())
+        .collect();
+        assert_eq!(
+            merkle_root(txids.clone()).to_string(),
+            "f3e94742aca4b5ef85488dc37c06c3282295ffec960994b2c0d5ac2a25a95766"
+        );
+        assert!(!is_merkle_mutated(&txids));
+
+        // Duplicating the last of an odd number of txids keeps the root
+        let mut mutated = txids[..3].to_vec();
+        assert!(!is_merkle_mutated(&mutated));
+        mutated.push(txids[2]);
+        assert_eq!(
+            merkle_root(mutated.clone()),
+            merkle_root(txids[..3].to_vec())
+        );
+        assert!(is_merkle_mutated(&mutated));
+    }
+
+    #[test]
+    fn test_merkle_branch() {
+        let a = sha256d::Hash::from_byte_array([0x01; 32]);
+        let b = sha256d::Hash::from_byte_array([0x02; 32]);
+        let c = sha256d::Hash::from_byte_array([0x03; 32]);
+        //        root
+        //      /      \
+        //    ab        cc
+        //   /  \     /  \
+        //  a    b   c   (c)
+        let ab = sha256d::Hash::hash(&[&a[..], &b[..]].concat());
+        let cc = sha256d::Hash::hash(&[&c[..], &c[..]].concat());
+        let root = sha256d::Hash::hash(&[&ab[..], &cc[..]].concat());
+        assert_eq!(merkle_root(vec![a, b, c]), root);
+
+        assert_eq!(merkle_branch(vec![a, b, c], 0), vec![(b, true), (cc, true)]);
+        assert_eq!(
+            merkle_branch(vec![a, b, c], 1),
+            vec![(a, false), (cc, true)]
+        );
+        assert_eq!(
+            merkle_branch(vec![a, b, c], 2),
+            vec![(c, true), (ab, false)]
+        );
+        assert!(merkle_branch(vec![a], 0).is_empty());
+    }
 }
@@ -2,6 +2,7 @@ use std::convert::From;
 use std::error;
 use std::fmt;
 use std::io;
+use std::path::{Path, PathBuf};
 use std::string;
 use std::sync;
 
@@ -25,6 +26,7 @@ macro_rules! transform {
 }
 
 pub type OpResult<T> = Result<T, OpError>;
+pub type ParseResult<T> = Result<T, ParseError>;
 
 #[derive(Debug)]
 /// Custom error type
@@ -84,6 +86,9 @@ pub enum OpErrorKind {
     PoisonError,
     SendError,
     LevelDBError(String),
+    ParseError(ParseError),
+    #[cfg(feature = "sqlite")]
+    SqliteError(rusqlite::Error),
 }
 
 impl fmt::Display for OpErrorKind {
@@ -94,12 +99,15 @@ impl fmt::Display for OpErrorKind {
             OpErrorKind::Utf8Error(ref err) => write!(f, "Utf8 Conversion: {}", err),
             OpErrorKind::ScriptError(ref err) => write!(f, "Script: {}", err),
             OpErrorKind::LevelDBError(ref err) => write!(f, "LevelDB: {}", err),
-            ref err @ OpErrorKind::PoisonError => write!(f, "Threading Error: {}", err),
-            ref err @ OpErrorKind::SendError => write!(f, "Sync: {}", err),
-            ref err @ OpErrorKind::InvalidArgsError => write!(f, "InvalidArgs: {}", err),
-            ref err @ OpErrorKind::CallbackError => write!(f, "Callback: {}", err),
-            ref err @ OpErrorKind::ValidationError => write!(f, "Validation: {}", err),
-            ref err @ OpErrorKind::RuntimeError => write!(f, "RuntimeError: {}", err),
+            OpErrorKind::ParseError(ref err) => write!(f, "Parse: {}", err),
+            #[cfg(feature = "sqlite")]
+            OpErrorKind::SqliteError(ref err) => write!(f, "SQLite: {}", err),
+            OpErrorKind::PoisonError => write!(f, "Threading Error"),
+            OpErrorKind::SendError => write!(f, "Sync"),
+            OpErrorKind::InvalidArgsError => write!(f, "InvalidArgs"),
+            OpErrorKind::CallbackError => write!(f, "Callback"),
+            OpErrorKind::ValidationError => write!(f, "Validation"),
+            OpErrorKind::RuntimeError => write!(f, "RuntimeError"),
             OpErrorKind::None => write!(f, ""),
         }
     }
@@ -112,6 +120,9 @@ impl error::Error for OpErrorKind {
             OpErrorKind::ByteOrderError(ref err) => Some(err),
             OpErrorKind::Utf8Error(ref err) => Some(err),
             OpErrorKind::ScriptError(ref err) => Some(err),
+            OpErrorKind::ParseError(ref err) => Some(err),
+            #[cfg(feature = "sqlite")]
+            OpErrorKind::SqliteError(ref err) => Some(err),
             ref err @ OpErrorKind::PoisonError => Some(err),
             ref err @ OpErrorKind::SendError => Some(err),
             _ => None,
@@ -119,6 +130,112 @@ impl error::Error for OpErrorKind {
     }
 }
 
+#[derive(Debug)]
+/// Error while deserializing blocks, optionally with the file and offset of the block
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub file: Option<PathBuf>,
+    pub offset: Option<u64>,
+}
+
+impl ParseError {
+    pub fn new(kind: ParseErrorKind) -> Self {
+        ParseError {
+            kind,
+            file: None,
+            offset: None,
+        }
+    }
+
+    /// Attaches the file and data offset of the block which failed to parse
+    pub fn at(mut self, file: &Path, offset: u64) -> Self {
+        self.file = Some(PathBuf::from(file));
+        self.offset = Some(offset);
+        self
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", &self.kind)?;
+        if let (Some(file), Some(offset)) = (&self.file, self.offset) {
+            write!(f, " (in {} at offset {})", file.display(), offset)?;
+        }
+        Ok(())
+    }
+}
+
+impl error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self.kind {
+            ParseErrorKind::IoError(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ParseErrorKind {
+    /// The data ends in the middle of a block
+    UnexpectedEof,
+    /// The block isn't preceded by the magic value of the coin
+    InvalidMagic {
+        expected: u32,
+        found: u32,
+    },
+    /// CompactSize which isn't minimally encoded or exceeds the maximum size
+    BadVarInt(u64),
+    /// Script larger than `--max-script-size` or witness item larger than the maximum block size
+    ScriptTooLarge(u64),
+    /// Output value which doesn't fit into a signed 64 bit amount
+    ValueOverflow(u64),
+    /// Bytes left over after a standalone block or transaction
+    TrailingBytes(u64),
+    /// Transaction with MWEB data, which is only expected in the skipped extension block
+    MwebTransaction,
+    IoError(io::Error),
+}
+
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseErrorKind::UnexpectedEof => write!(f, "Unexpected EOF"),
+            ParseErrorKind::InvalidMagic { expected, found } => write!(
+                f,
+                "Invalid magic: expected {:#010x}, found {:#010x}",
+                expected, found
+            ),
+            ParseErrorKind::BadVarInt(value) => write!(f, "Bad VarInt: {}", value),
+            ParseErrorKind::ScriptTooLarge(len) => write!(f, "Script too large: {} bytes", len),
+            ParseErrorKind::ValueOverflow(value) => write!(f, "Value overflow: {}", value),
+            ParseErrorKind::TrailingBytes(count) => write!(f, "{} trailing bytes", count),
+            ParseErrorKind::MwebTransaction => write!(f, "Unsupported MWEB transaction"),
+            ParseErrorKind::IoError(ref err) => write!(f, "I/O Error: {}", err),
+        }
+    }
+}
+
+impl From<io::Error> for ParseError {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::UnexpectedEof => Self::new(ParseErrorKind::UnexpectedEof),
+            _ => Self::new(ParseErrorKind::IoError(err)),
+        }
+    }
+}
+
+impl From<ParseErrorKind> for ParseError {
+    fn from(kind: ParseErrorKind) -> Self {
+        Self::new(kind)
+    }
+}
+
+impl From<ParseError> for OpError {
+    fn from(err: ParseError) -> Self {
+        Self::new(OpErrorKind::ParseError(err))
+    }
+}
+
 impl From<io::Error> for OpError {
     fn from(err: io::Error) -> Self {
         Self::new(OpErrorKind::IoError(err))
@@ -161,6 +278,13 @@ impl From<rusty_leveldb::Status> for OpError {
     }
 }
 
+#[cfg(feature = "sqlite")]
+impl From<rusqlite::Error> for OpError {
+    fn from(err: rusqlite::Error) -> Self {
+        Self::new(OpErrorKind::SqliteError(err))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -174,5 +298,21 @@ mod tests {
 
         let err = err.join_msg("Cannot proceed.");
         assert_eq!(format!("{}", err), "Cannot proceed. I/O Error: oh no!");
+
+        // Kinds without inner error only print their name
+        let err = OpError::new(OpErrorKind::ValidationError).join_msg("Invalid merkle_root!");
+        assert_eq!(format!("{}", err), "Invalid merkle_root! Validation");
+    }
+
+    #[test]
+    fn test_parse_error() {
+        let err = ParseError::from(io::Error::from(io::ErrorKind::UnexpectedEof));
+        assert!(matches!(err.kind, ParseErrorKind::UnexpectedEof));
+
+        let err = ParseError::new(ParseErrorKind::BadVarInt(1)).at(Path::new("blk00000.dat"), 8);
+        assert_eq!(
+            format!("{}", OpError::from(err)),
+            "Parse: Bad VarInt: 1 (in blk00000.dat at offset 8)"
+        );
     }
 }
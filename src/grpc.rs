@@ -0,0 +1,312 @@
+// tonic::Status is large, but it is the error type mandated by the generated service trait
+#![allow(clippy::result_large_err)]
+
+use bitcoin::hashes::sha256d;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use clap::{Arg, Command};
+use tokio_stream::{Stream, StreamExt};
+use tonic::{Request, Response, Status};
+
+use crate::blockchain::parser::chain::{ChainStorage, TxidIndex};
+use crate::blockchain::parser::stream;
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::tx::EvaluatedTx;
+use crate::blockchain::proto::Hashed;
+use crate::common::utils::HashByteOrder;
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+/// Generated from proto/blockparser.proto
+pub mod proto {
+    tonic::include_proto!("blockparser");
+}
+
+use proto::block_parser_server::{BlockParser, BlockParserServer};
+
+/// Adds `--grpc-listen`
+pub fn add_args(cmd: Command) -> Command {
+    cmd.arg(
+        Arg::new("grpc-listen")
+            .long("grpc-listen")
+            .value_name("ADDR")
+            .value_parser(clap::value_parser!(SocketAddr))
+            .help("Serves blocks and transactions via gRPC on the given address instead of running a callback"),
+    )
+}
+
+/// Serves the chain until the process gets terminated.
+/// All blocks in the configured range are read once on startup to build the txid index.
+pub fn serve(addr: SocketAddr, chain_storage: ChainStorage, start_height: u64) -> OpResult<()> {
+    let service = Service::new(chain_storage, start_height)?;
+    let runtime = tokio::runtime::Runtime::new()?;
+    info!(target: "grpc", "Listening on {} ...", addr);
+    runtime
+        .block_on(
+            tonic::transport::Server::builder()
+                .add_service(BlockParserServer::new(service))
+                .serve(addr),
+        )
+        .map_err(|e| OpError::new(OpErrorKind::RuntimeError).join_msg(&e.to_string()))
+}
+
+/// Implements the gRPC service on top of the chain storage
+pub struct Service {
+    chain_storage: Arc<Mutex<ChainStorage>>,
+    txids: TxidIndex,
+    hash_byte_order: HashByteOrder,
+}
+
+impl Service {
+    fn new(mut chain_storage: ChainStorage, start_height: u64) -> OpResult<Self> {
+        Ok(Self {
+            txids: TxidIndex::build(&mut chain_storage, start_height)?,
+            hash_byte_order: chain_storage.hash_byte_order(),
+            chain_storage: Arc::new(Mutex::new(chain_storage)),
+        })
+    }
+
+    /// Reads the block at the given height on the blocking threadpool
+    async fn read_block(&self, height: u64) -> Result<Block, Status> {
+        let chain_storage = Arc::clone(&self.chain_storage);
+        tokio::task::spawn_blocking(move || chain_storage.lock().unwrap().get_block(height))
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?
+            .map_err(to_status)?
+            .ok_or_else(|| Status::not_found(format!("No block at height {}", height)))
+    }
+}
+
+#[tonic::async_trait]
+impl BlockParser for Service {
+    async fn get_block(
+        &self,
+        request: Request<proto::GetBlockRequest>,
+    ) -> Result<Response<proto::Block>, Status> {
+        let height = request.into_inner().height;
+        let block = self.read_block(height).await?;
+        Ok(Response::new(proto::Block::from_block(
+            &block,
+            height,
+            self.hash_byte_order,
+        )))
+    }
+
+    async fn get_tx(
+        &self,
+        request: Request<proto::GetTxRequest>,
+    ) -> Result<Response<proto::Tx>, Status> {
+        let txid = request.into_inner().txid;
+        let txid = sha256d::Hash::from_str(&txid)
+            .map_err(|e| Status::invalid_argument(format!("Invalid txid `{}`: {}", txid, e)))?;
+        let height = self
+            .txids
+            .height(&txid)
+            .ok_or_else(|| Status::not_found(format!("Unknown txid {}", txid)))?;
+        let block = self.read_block(height).await?;
+        let tx =
+            block.txs.iter().find(|tx| tx.hash == txid).ok_or_else(|| {
+                Status::internal(format!("{} not found in block {}", txid, height))
+            })?;
+        Ok(Response::new(proto::Tx::from_tx(
+            tx,
+            height,
+            self.hash_byte_order,
+        )))
+    }
+
+    type StreamBlocksStream = Pin<Box<dyn Stream<Item = Result<proto::Block, Status>> + Send>>;
+
+    async fn stream_blocks(
+        &self,
+        request: Request<proto::StreamBlocksRequest>,
+    ) -> Result<Response<Self::StreamBlocksStream>, Status> {
+        let proto::StreamBlocksRequest { from, to } = request.into_inner();
+        if from > to {
+            return Err(Status::invalid_argument(
+                "`from` must not be greater than `to`",
+            ));
+        }
+        let chain_storage = Arc::clone(&self.chain_storage);
+        let mut next = from;
+        let blocks = stream::spawn_reader(
+            move || {
+                if next > to {
+                    return Ok(None);
+                }
+                let block = chain_storage.lock().unwrap().get_block(next)?;
+                next += 1;
+                Ok(block)
+            },
+            16,
+        );
+        let mut height = from;
+        let order = self.hash_byte_order;
+        let blocks = blocks.map(move |block| {
+            let block = proto::Block::from_block(&block.map_err(to_status)?, height, order);
+            height += 1;
+            Ok(block)
+        });
+        Ok(Response::new(Box::pin(blocks)))
+    }
+}
+
+/// Uses the error message only, since the Display impl of some error kinds recurses
+fn to_status(err: OpError) -> Status {
+    Status::internal(err.message)
+}
+
+impl proto::Block {
+    fn from_block(block: &Block, height: u64, order: HashByteOrder) -> Self {
+        let header = &block.header.value;
+        Self {
+            hash: order.hex(&block.header.hash),
+            height,
+            size: block.size,
+            header: Some(proto::BlockHeader {
+                version: header.version,
+                prev_hash: order.hex(&header.prev_hash),
+                merkle_root: order.hex(&header.merkle_root),
+                timestamp: header.timestamp,
+                bits: header.bits,
+                nonce: header.nonce,
+            }),
+            txs: block
+                .txs
+                .iter()
+                .map(|tx| proto::Tx::from_tx(tx, height, order))
+                .collect(),
+        }
+    }
+}
+
+impl proto::Tx {
+    fn from_tx(tx: &Hashed<EvaluatedTx>, height: u64, order: HashByteOrder) -> Self {
+        Self {
+            txid: order.hex(&tx.hash),
+            height,
+            version: tx.value.version,
+            inputs: tx
+                .value
+                .inputs
+                .iter()
+                .map(|input| proto::TxInput {
+                    outpoint: Some(proto::TxOutpoint {
+                        txid: order.hex(&input.outpoint.txid),
+                        index: input.outpoint.index,
+                    }),
+                    script_sig: input.script_sig.clone(),
+                    seq_no: input.seq_no,
+                    has_witness: input.has_witness(),
+                })
+                .collect(),
+            outputs: tx
+                .value
+                .outputs
+                .iter()
+                .map(|output| proto::TxOutput {
+                    value: output.out.value,
+                    script_pubkey: output.out.script_pubkey.clone(),
+                    address: output.script.address.clone().unwrap_or_default(),
+                    pattern: output.script.pattern.to_string(),
+                })
+                .collect(),
+            locktime: tx.value.locktime,
+            weight: tx.value.weight(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::utils;
+    use std::io::Write;
+
+    const GENESIS: &str = concat!(
+        "f9beb4d91d010000010000000000000000000000000000000000000000000000",
+        "0000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3",
+        "888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c0101000000010000",
+        "000000000000000000000000000000000000000000000000000000000000ffff",
+        "ffff4d04ffff001d0104455468652054696d65732030332f4a616e2f32303039",
+        "204368616e63656c6c6f72206f6e206272696e6b206f66207365636f6e642062",
+        "61696c6f757420666f722062616e6b73ffffffff0100f2052a01000000434104",
+        "678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb6",
+        "49f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5f",
+        "ac00000000",
+    );
+    const BLOCK1: &str = concat!(
+        "f9beb4d9d7000000010000006fe28c0ab6f1b372c1a6a246ae63f74f931e8365",
+        "e15a089c68d6190000000000982051fd1e4ba744bbbe680e1fee14677ba1a3c3",
+        "540bf7b1cdb606e857233e0e61bc6649ffff001d01e362990101000000010000",
+        "000000000000000000000000000000000000000000000000000000000000ffff",
+        "ffff0704ffff001d0104ffffffff0100f2052a0100000043410496b538e85351",
+        "9c726a2c91e61ec11600ae1390813a627c66fb8be7947be63c52da7589379515",
+        "d4e0a604f8141781e62294721166bf621e73a82cbf2342c858eeac00000000",
+    );
+
+    #[test]
+    fn test_service() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&utils::hex_to_vec(GENESIS)).unwrap();
+        file.write_all(&utils::hex_to_vec(BLOCK1)).unwrap();
+        file.flush().unwrap();
+        let options = crate::parse_args(crate::command().get_matches_from([
+            "rusty-blockparser",
+            "--block-file",
+            file.path().to_str().unwrap(),
+            "--grpc-listen",
+            "127.0.0.1:0",
+        ]))
+        .unwrap();
+        let service = Service::new(ChainStorage::new(&options).unwrap(), 0).unwrap();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let block = service
+                .get_block(Request::new(proto::GetBlockRequest { height: 1 }))
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(
+                block.hash,
+                "00000000839a8e6886ab5951d76f411475428afc90947ee320161bbf18eb6048"
+            );
+            assert_eq!(
+                block.header.unwrap().prev_hash,
+                "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f"
+            );
+            assert_eq!(block.txs.len(), 1);
+
+            let txid = "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b";
+            let tx = service
+                .get_tx(Request::new(proto::GetTxRequest {
+                    txid: String::from(txid),
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(tx.txid, txid);
+            assert_eq!(tx.height, 0);
+            assert_eq!(tx.outputs[0].value, 5000000000);
+
+            let err = service
+                .get_block(Request::new(proto::GetBlockRequest { height: 2 }))
+                .await
+                .unwrap_err();
+            assert_eq!(err.code(), tonic::Code::NotFound);
+
+            let heights = service
+                .stream_blocks(Request::new(proto::StreamBlocksRequest { from: 0, to: 5 }))
+                .await
+                .unwrap()
+                .into_inner()
+                .map(|block| block.unwrap().height)
+                .collect::<Vec<u64>>()
+                .await;
+            assert_eq!(heights, vec![0, 1]);
+        });
+    }
+}
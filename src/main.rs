@@ -1,21 +1,65 @@
+use bitcoin::hashes::sha256d;
 use clap::{Arg, Command};
 use std::boxed::Box;
+use std::collections::BTreeMap;
 use std::fmt;
+use std::fs;
+use std::io;
+#[cfg(any(feature = "grpc", feature = "rest"))]
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::process;
 
-use crate::blockchain::parser::chain::ChainStorage;
+use crate::blockchain::parser::chain::{self, ChainStorage};
 use crate::blockchain::parser::types::{Bitcoin, CoinType};
 use crate::blockchain::parser::BlockchainParser;
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::tx::EvaluatedTx;
+use crate::callbacks::addressactivity::AddressActivity;
+use crate::callbacks::addressfilter::AddressFilter;
+use crate::callbacks::addressindex::AddressIndex;
+use crate::callbacks::addressreuse::AddressReuse;
+use crate::callbacks::awakened::AwakenedCoins;
 use crate::callbacks::balances::Balances;
+use crate::callbacks::blockfees::BlockFees;
+use crate::callbacks::blockintervals::BlockIntervals;
+use crate::callbacks::bloomfilter::{self, BloomFilterDump};
+use crate::callbacks::clusterflows::ClusterFlows;
+use crate::callbacks::clusters::Clusters;
+use crate::callbacks::coinbaseaddresses::CoinbaseAddresses;
 use crate::callbacks::csvdump::CsvDump;
+use crate::callbacks::difficulty::Difficulty;
+use crate::callbacks::distinctaddresses::DistinctAddresses;
+use crate::callbacks::dustaddresses::DustAddresses;
+use crate::callbacks::dustbreakdown::DustBreakdown;
+use crate::callbacks::feepercentiles::FeePercentiles;
+use crate::callbacks::feerates::FeeRates;
+use crate::callbacks::feesubsidy::FeeSubsidy;
+use crate::callbacks::headers::Headers;
+use crate::callbacks::inputcsvdump::InputCsvDump;
+use crate::callbacks::inscriptions::Inscriptions;
+use crate::callbacks::ledger::Ledger;
+use crate::callbacks::merkleproof::MerkleProof;
+use crate::callbacks::netissuance::NetIssuance;
 use crate::callbacks::opreturn::OpReturn;
+use crate::callbacks::opreturnbytes::OpReturnBytes;
+use crate::callbacks::opreturnprotocols::OpReturnProtocols;
+use crate::callbacks::pools::Pools;
+use crate::callbacks::pubkeyreuse::PubkeyReuse;
+use crate::callbacks::segwit::SegWit;
 use crate::callbacks::simplestats::SimpleStats;
+use crate::callbacks::spendlatency::SpendLatency;
+use crate::callbacks::timelocks::Timelocks;
+use crate::callbacks::txversions::TxVersions;
+use crate::callbacks::typeflows::TypeFlows;
 use crate::callbacks::unspentcsvdump::UnspentCsvDump;
+use crate::callbacks::utxoages::UtxoAges;
 use crate::callbacks::Callback;
+use crate::common::json;
 use crate::common::logger::SimpleLogger;
+use crate::common::profile;
 use crate::common::utils;
-use crate::errors::{OpError, OpResult};
+use crate::errors::{OpError, OpErrorKind, OpResult};
 
 #[macro_use]
 extern crate log;
@@ -33,6 +77,10 @@ pub mod errors;
 pub mod blockchain;
 pub mod common;
 pub mod callbacks;
+#[cfg(feature = "grpc")]
+mod grpc;
+#[cfg(feature = "rest")]
+mod rest;
 
 #[derive(Copy, Clone)]
 #[cfg_attr(test, derive(PartialEq, Debug))]
@@ -43,9 +91,9 @@ pub struct BlockHeightRange {
 
 impl BlockHeightRange {
     pub fn new(start: u64, end: Option<u64>) -> OpResult<Self> {
-        if end.is_some() && start >= end.unwrap() {
+        if end.is_some_and(|end| start > end) {
             return Err(OpError::from(String::from(
-                "--start value must be lower than --end value",
+                "--start value must not be greater than --end value",
             )));
         }
         Ok(Self { start, end })
@@ -69,23 +117,75 @@ impl fmt::Display for BlockHeightRange {
 /// Holds all available user arguments
 pub struct ParserOptions {
     // Name of the callback which gets executed for each block. (See callbacks/mod.rs)
-    callback: Box<dyn Callback>,
+    // Only None for dry runs.
+    callback: Option<Box<dyn Callback>>,
     // Holds the relevant coin parameters we need for parsing
     coin: CoinType,
     // Enable this if you want to check the chain index integrity and merkle root for each block.
     verify: bool,
+    // Verify the proof of work of each block against its difficulty target.
+    verify_pow: bool,
+    // Recompute the merkle root and the witness commitment of each block.
+    verify_merkle: bool,
+    // Blocks up to the highest checkpoint (height -> hash) skip the merkle root and proof of work checks
+    checkpoints: BTreeMap<u64, sha256d::Hash>,
+    // Sanity check output values against the maximum money supply of the coin.
+    max_money: bool,
+    // Abort on failed sanity checks instead of logging them.
+    strict: bool,
     // Path to directory where blk.dat files are stored
     blockchain_dir: PathBuf,
+    // Single file with concatenated blocks, replaces blk.dat files and index if set
+    block_file: Option<PathBuf>,
+    // File with concatenated blocks which continue the chain after the tip
+    extra_blocks: Option<PathBuf>,
     // Verbosity level, 0 = Error, 1 = Info, 2 = Debug, 3+ = Trace
     log_level_filter: log::LevelFilter,
     // Range which is considered for parsing
     range: BlockHeightRange,
+    // Number of blocks below the tip which are not parsed, as they might still get reorged
+    keep_confirmations: u64,
+    // Stop after processing N blocks
+    max_blocks: Option<u64>,
+    // Byte order of hashes in callback output
+    hash_byte_order: utils::HashByteOrder,
+    // Maintain a rolling digest over all processed txids and log it every N blocks
+    state_digest: Option<u64>,
+    // Only check the chain for gaps and broken links without invoking the callback
+    dry_run: bool,
+    // CSV file to store the stale blocks which are not part of the main chain
+    orphans: Option<PathBuf>,
+    // CSV file to store the forks off the main chain and the length of their stale branches
+    reorgs: Option<PathBuf>,
+    // CSV file to store the number of blocks, transactions and bytes per blk file
+    blk_stats: Option<PathBuf>,
+    // Compute a SHA256 over the serialized bytes of all parsed blocks
+    chain_fingerprint: bool,
+    // Bitcoin Core chainstate to load the UTXO set from, parsing continues after its best block
+    chainstate: Option<PathBuf>,
+    // Pass blocks to the callback in the order they are stored in the blk files instead of chain order
+    disk_order: bool,
+    // Print the time spent reading, resolving scripts, updating the UTXO set and in the callback every N blocks
+    profile: Option<u64>,
+    // Deserialize batches of N blocks in parallel on a pool of N threads
+    threads: Option<usize>,
+    // File to save the last processed block and the callback state to, parsing resumes from it if it exists
+    checkpoint_file: Option<PathBuf>,
+    // Save the checkpoint every N blocks
+    checkpoint_every: u64,
+    // Serve the chain via gRPC instead of invoking the callback
+    #[cfg(feature = "grpc")]
+    grpc_listen: Option<SocketAddr>,
+    // Serve the chain as JSON via HTTP instead of invoking the callback
+    #[cfg(feature = "rest")]
+    rest_listen: Option<SocketAddr>,
 }
 
 fn command() -> Command {
     let coins = [
         "bitcoin",
         "testnet3",
+        "bitcoincash",
         "namecoin",
         "litecoin",
         "dogecoin",
@@ -93,7 +193,7 @@ fn command() -> Command {
         "unobtanium",
         "noteblockchain",
     ];
-    Command::new("rusty-blockparser")
+    let command = Command::new("rusty-blockparser")
     .version(crate_version!())
     // Add flags
     .arg(Arg::new("verify")
@@ -101,6 +201,36 @@ fn command() -> Command {
         .action(clap::ArgAction::SetTrue)
         .value_parser(clap::value_parser!(bool))
         .help("Verifies merkle roots and block hashes"))
+    .arg(Arg::new("verify-pow")
+        .long("verify-pow")
+        .action(clap::ArgAction::SetTrue)
+        .value_parser(clap::value_parser!(bool))
+        .help("Verifies the proof of work of each block against its difficulty target"))
+    .arg(Arg::new("verify-merkle")
+        .long("verify-merkle")
+        .action(clap::ArgAction::SetTrue)
+        .value_parser(clap::value_parser!(bool))
+        .help("Recomputes the merkle root and witness commitment of each block and logs mismatches (aborts with --strict)"))
+    .arg(Arg::new("checkpoints")
+        .long("checkpoints")
+        .value_name("FILE")
+        .help("Trusts blocks up to the highest of the given checkpoints (height;hash per line) and skips their verification"))
+    .arg(Arg::new("max-money")
+        .long("max-money")
+        .action(clap::ArgAction::SetTrue)
+        .value_parser(clap::value_parser!(bool))
+        .help("Checks output values against the maximum money supply of the coin"))
+    .arg(Arg::new("strict")
+        .long("strict")
+        .action(clap::ArgAction::SetTrue)
+        .value_parser(clap::value_parser!(bool))
+        .help("Aborts on failed sanity checks and unreadable blocks instead of logging them and rejects non-canonical CompactSize encodings"))
+    .arg(Arg::new("max-script-size")
+        .long("max-script-size")
+        .value_name("BYTES")
+        .value_parser(clap::value_parser!(u64))
+        .default_value("10000")
+        .help("Fails to parse blocks with a scriptSig or scriptPubKey larger than BYTES instead of allocating it"))
     .arg(Arg::new("verbosity")
         .short('v')
         .action(clap::ArgAction::Count)
@@ -116,28 +246,314 @@ fn command() -> Command {
         .short('d')
         .long("blockchain-dir")
         .help("Sets blockchain directory which contains blk.dat files (default: ~/.bitcoin/blocks)"))
+    .arg(Arg::new("block-file")
+        .long("block-file")
+        .value_name("PATH")
+        .conflicts_with("blockchain-dir")
+        .help("Reads blocks from a single file with concatenated blocks instead of blk.dat files and block index"))
+    .arg(Arg::new("extra-blocks")
+        .long("extra-blocks")
+        .value_name("PATH")
+        .help("Appends blocks from a file with concatenated blocks to the tip of the chain"))
     .arg(Arg::new("start")
         .short('s')
         .long("start")
+        .visible_alias("start-height")
         .value_name("HEIGHT")
         .value_parser(clap::value_parser!(u64))
         .help("Specify starting block for parsing (inclusive)"))
     .arg(Arg::new("end")
         .short('e')
         .long("end")
+        .visible_alias("end-height")
         .value_name("HEIGHT")
         .value_parser(clap::value_parser!(u64))
         .help("Specify last block for parsing (inclusive) (default: all known blocks)"))
+    .arg(Arg::new("keep-confirmations")
+        .long("keep-confirmations")
+        .value_name("N")
+        .value_parser(clap::value_parser!(u64))
+        .help("Stops parsing N blocks below the tip to skip blocks which might still get reorged"))
+    .arg(Arg::new("max-blocks")
+        .long("max-blocks")
+        .value_name("N")
+        .value_parser(clap::value_parser!(u64).range(1..))
+        .help("Stops after processing N blocks, e.g. to quickly test a callback"))
+    .arg(Arg::new("hash-byte-order")
+        .long("hash-byte-order")
+        .value_name("ORDER")
+        .value_parser(clap::builder::PossibleValuesParser::new(["internal", "rpc"]))
+        .global(true)
+        .help("Byte order of hashes in callback output (default: rpc)"))
+    .arg(Arg::new("state-digest")
+        .long("state-digest")
+        .value_name("N")
+        .value_parser(clap::value_parser!(u64).range(1..))
+        .help("Computes a rolling digest over all processed txids and prints it every N blocks and at the end"))
+    .arg(Arg::new("chain-fingerprint")
+        .long("chain-fingerprint")
+        .action(clap::ArgAction::SetTrue)
+        .help("Computes a SHA256 over the serialized bytes of all parsed blocks and prints it at the end"))
+    .arg(Arg::new("dry-run")
+        .long("dry-run")
+        .action(clap::ArgAction::SetTrue)
+        .help("Checks the chain for missing blocks and broken links and exits without running a callback"))
+    .arg(Arg::new("orphans")
+        .long("orphans")
+        .value_name("PATH")
+        .help("Writes the stale blocks which were discarded during main chain selection to a CSV file"))
+    .arg(Arg::new("reorgs")
+        .long("reorgs")
+        .value_name("PATH")
+        .help("Writes the forks off the main chain with the length of their stale branches to a CSV file"))
+    .arg(Arg::new("blk-stats")
+        .long("blk-stats")
+        .value_name("PATH")
+        .help("Writes the number of blocks, transactions and bytes per blk file to a CSV file and exits without running a callback"))
+    .arg(Arg::new("no-address")
+        .long("no-address")
+        .action(clap::ArgAction::SetTrue)
+        .help("Skips the address encoding of scripts for callbacks which only need values and script types"))
+    .arg(Arg::new("chainstate")
+        .long("chainstate")
+        .value_name("PATH")
+        .conflicts_with("start")
+        .help("Loads the UTXO set from a Bitcoin Core chainstate directory and continues parsing after its best block"))
+    .arg(Arg::new("disk-order")
+        .long("disk-order")
+        .action(clap::ArgAction::SetTrue)
+        .help("Processes the blocks in the order they are stored in the blk files instead of chain order"))
+    .arg(Arg::new("profile")
+        .long("profile")
+        .value_name("N")
+        .value_parser(clap::value_parser!(u64).range(1..))
+        .help("Logs the time spent per phase (read, scripts, utxo, callback) every N blocks"))
+    .arg(Arg::new("threads")
+        .long("threads")
+        .value_name("N")
+        .value_parser(clap::value_parser!(u64).range(1..))
+        .help("Deserializes N blocks at a time on N threads, the callback still gets them in height order (default: one block at a time, thread pool left unconfigured)"))
+    .arg(Arg::new("checkpoint")
+        .long("checkpoint")
+        .value_name("PATH")
+        .conflicts_with_all(["start", "chainstate", "disk-order"])
+        .help("Saves the last processed block and the callback state to PATH and resumes from it if PATH exists"))
+    .arg(Arg::new("checkpoint-every")
+        .long("checkpoint-every")
+        .value_name("N")
+        .requires("checkpoint")
+        .value_parser(clap::value_parser!(u64).range(1..))
+        .default_value("10000")
+        .help("Saves the checkpoint every N blocks and once parsing is done"))
+    .arg(Arg::new("print-schema")
+        .long("print-schema")
+        .value_name("FORMAT")
+        .value_parser(clap::builder::PossibleValuesParser::new(["csv", "jsonl"]))
+        .help("Prints the columns of the files written by the callback in the given format as JSON and exits"))
+    .arg(Arg::new("parse-block")
+        .long("parse-block")
+        .value_name("HEX")
+        .conflicts_with("parse-tx")
+        .help("Prints the given serialized block as JSON and exits, - reads the hex from stdin"))
+    .arg(Arg::new("parse-tx")
+        .long("parse-tx")
+        .value_name("HEX")
+        .help("Prints the given serialized transaction as JSON and exits, - reads the hex from stdin"))
+    .arg(Arg::new("bloom-query")
+        .long("bloom-query")
+        .value_names(["FILTER", "ADDRESS"])
+        .num_args(2..)
+        .conflicts_with_all(["parse-block", "parse-tx"])
+        .help("Checks the addresses against a filter written by bloomfilter, prints the result as JSON and exits"))
     // Add callbacks
     .subcommand(UnspentCsvDump::build_subcommand())
     .subcommand(CsvDump::build_subcommand())
     .subcommand(SimpleStats::build_subcommand())
     .subcommand(Balances::build_subcommand())
     .subcommand(OpReturn::build_subcommand())
+    .subcommand(BloomFilterDump::build_subcommand())
+    .subcommand(Pools::build_subcommand())
+    .subcommand(TxVersions::build_subcommand())
+    .subcommand(UtxoAges::build_subcommand())
+    .subcommand(MerkleProof::build_subcommand())
+    .subcommand(FeeRates::build_subcommand())
+    .subcommand(SegWit::build_subcommand())
+    .subcommand(AddressReuse::build_subcommand())
+    .subcommand(AwakenedCoins::build_subcommand())
+    .subcommand(OpReturnProtocols::build_subcommand())
+    .subcommand(Headers::build_subcommand())
+    .subcommand(Difficulty::build_subcommand())
+    .subcommand(SpendLatency::build_subcommand())
+    .subcommand(CoinbaseAddresses::build_subcommand())
+    .subcommand(Ledger::build_subcommand())
+    .subcommand(FeePercentiles::build_subcommand())
+    .subcommand(Clusters::build_subcommand())
+    .subcommand(ClusterFlows::build_subcommand())
+    .subcommand(BlockIntervals::build_subcommand())
+    .subcommand(DistinctAddresses::build_subcommand())
+    .subcommand(Timelocks::build_subcommand())
+    .subcommand(FeeSubsidy::build_subcommand())
+    .subcommand(DustBreakdown::build_subcommand())
+    .subcommand(OpReturnBytes::build_subcommand())
+    .subcommand(Inscriptions::build_subcommand())
+    .subcommand(PubkeyReuse::build_subcommand())
+    .subcommand(InputCsvDump::build_subcommand())
+    .subcommand(NetIssuance::build_subcommand())
+    .subcommand(TypeFlows::build_subcommand())
+    .subcommand(AddressActivity::build_subcommand())
+    .subcommand(DustAddresses::build_subcommand())
+    .subcommand(BlockFees::build_subcommand())
+    .subcommand(AddressFilter::build_subcommand())
+    .subcommand(AddressIndex::build_subcommand());
+    add_server_args(command)
+}
+
+fn add_server_args(command: Command) -> Command {
+    #[cfg(feature = "grpc")]
+    let command = grpc::add_args(command);
+    #[cfg(feature = "rest")]
+    let command = rest::add_args(command);
+    command
+}
+
+fn coin(matches: &clap::ArgMatches) -> CoinType {
+    let mut coin = matches
+        .get_one::<String>("coin")
+        .map_or_else(|| CoinType::from(Bitcoin), |v| v.parse().unwrap());
+    coin.resolve_addresses = !matches.get_flag("no-address");
+    coin.canonical_compact_size = matches.get_flag("strict");
+    coin.max_script_size = *matches.get_one::<u64>("max-script-size").unwrap();
+    coin
+}
+
+/// Decodes the hex argument, `-` reads the hex from stdin
+fn read_hex_arg(arg: &str) -> OpResult<Vec<u8>> {
+    let hex = match arg {
+        "-" => io::read_to_string(io::stdin())?,
+        hex => String::from(hex),
+    };
+    utils::try_hex_to_vec(hex.trim()).ok_or_else(|| {
+        OpError::new(OpErrorKind::InvalidArgsError).join_msg("Input is not a valid hex string")
+    })
+}
+
+/// Parses the block given with `--parse-block` or the transaction given with `--parse-tx`.
+/// Returns None if neither is set.
+fn parse_standalone(matches: &clap::ArgMatches) -> Option<OpResult<serde_json::Value>> {
+    let coin = coin(matches);
+    if let Some(arg) = matches.get_one::<String>("parse-block") {
+        let parse = || -> OpResult<serde_json::Value> {
+            let block = Block::parse(&read_hex_arg(arg)?, &coin)?;
+            Ok(json::block_json(
+                &block,
+                None,
+                utils::HashByteOrder::from_matches(matches)?,
+            ))
+        };
+        return Some(parse());
+    }
+    if let Some(mut args) = matches.get_many::<String>("bloom-query") {
+        let path = PathBuf::from(args.next().unwrap());
+        let addresses: Vec<String> = args.cloned().collect();
+        return Some(bloomfilter::query(&path, &addresses));
+    }
+    let arg = matches.get_one::<String>("parse-tx")?;
+    let parse = || -> OpResult<serde_json::Value> {
+        let tx = EvaluatedTx::parse(&read_hex_arg(arg)?, &coin)?;
+        Ok(json::tx_json(
+            &tx,
+            None,
+            utils::HashByteOrder::from_matches(matches)?,
+        ))
+    };
+    Some(parse())
+}
+
+/// Describes the files which the selected callback writes in the given format
+fn schema(matches: &clap::ArgMatches, format: &str) -> OpResult<serde_json::Value> {
+    let (name, matches) = matches
+        .subcommand()
+        .ok_or_else(|| OpError::from(String::from("--print-schema requires a callback")))?;
+    let tables = match name {
+        "csvdump" => CsvDump::schema(matches),
+        "unspentcsvdump" => UnspentCsvDump::schema(matches),
+        "balances" => Balances::schema(matches),
+        "pools" => Pools::schema(matches),
+        "txversions" => TxVersions::schema(matches),
+        "utxoages" => UtxoAges::schema(matches),
+        "feerates" => FeeRates::schema(matches),
+        "segwit" => SegWit::schema(matches),
+        "addressreuse" => AddressReuse::schema(matches),
+        "awakened" => AwakenedCoins::schema(matches),
+        "opreturnprotocols" => OpReturnProtocols::schema(matches),
+        "headers" => Headers::schema(matches),
+        "difficulty" => Difficulty::schema(matches),
+        "spendlatency" => SpendLatency::schema(matches),
+        "coinbaseaddresses" => CoinbaseAddresses::schema(matches),
+        "ledger" => Ledger::schema(matches),
+        "feepercentiles" => FeePercentiles::schema(matches),
+        "clusters" => Clusters::schema(matches),
+        "clusterflows" => ClusterFlows::schema(matches),
+        "blockintervals" => BlockIntervals::schema(matches),
+        "distinctaddresses" => DistinctAddresses::schema(matches),
+        "timelocks" => Timelocks::schema(matches),
+        "feesubsidy" => FeeSubsidy::schema(matches),
+        "dustbreakdown" => DustBreakdown::schema(matches),
+        "opreturnbytes" => OpReturnBytes::schema(matches),
+        "inscriptions" => Inscriptions::schema(matches),
+        "pubkeyreuse" => PubkeyReuse::schema(matches),
+        "inputcsvdump" => InputCsvDump::schema(matches),
+        "netissuance" => NetIssuance::schema(matches),
+        "typeflows" => TypeFlows::schema(matches),
+        "addressactivity" => AddressActivity::schema(matches),
+        "dustaddresses" => DustAddresses::schema(matches),
+        "blockfees" => BlockFees::schema(matches),
+        "addressfilter" => AddressFilter::schema(matches),
+        "addressindex" => AddressIndex::schema(matches),
+        _ => Vec::new(),
+    };
+    let tables: Vec<serde_json::Value> = tables
+        .iter()
+        .filter(|table| table.format.as_str() == format)
+        .map(|table| table.to_json())
+        .collect();
+    if tables.is_empty() {
+        return Err(OpError::from(format!(
+            "--print-schema {} is not supported by this callback",
+            format
+        )));
+    }
+    let mut schema = serde_json::json!({
+        "callback": name,
+        "format": format,
+        "tables": tables,
+    });
+    if format == "csv" {
+        schema["separator"] = ";".into();
+    }
+    Ok(schema)
 }
 
 fn main() {
-    let options = match parse_args(command().get_matches()) {
+    let matches = command().get_matches();
+    let output = match matches.get_one::<String>("print-schema") {
+        Some(format) => Some(schema(&matches, format)),
+        None => parse_standalone(&matches),
+    };
+    if let Some(output) = output {
+        match output {
+            Ok(output) => {
+                println!("{}", serde_json::to_string_pretty(&output).unwrap());
+                process::exit(0);
+            }
+            Err(desc) => {
+                SimpleLogger::init(log::LevelFilter::Debug).unwrap();
+                error!(target: "main", "{}", desc);
+                process::exit(1);
+            }
+        }
+    }
+    let options = match parse_args(matches) {
         Ok(o) => o,
         Err(desc) => {
             // Init logger to print outstanding error message
@@ -152,23 +568,111 @@ fn main() {
     SimpleLogger::init(log_level).expect("Unable to initialize logger!");
     info!(target: "main", "Starting rusty-blockparser v{} ...", env!("CARGO_PKG_VERSION"));
     debug!(target: "main", "Using log level {}", log_level);
+    profile::set_enabled(options.profile.is_some());
+    if let Some(threads) = options.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .expect("Unable to initialize thread pool!");
+        info!(target: "main", "Configured to deserialize {} blocks in parallel", threads);
+    }
     if options.verify {
         info!(target: "main", "Configured to verify merkle roots and block hashes");
     }
+    if options.verify_pow {
+        info!(target: "main", "Configured to verify proof of work");
+    }
+    if options.verify_merkle {
+        info!(target: "main", "Configured to verify merkle roots and witness commitments (strict: {})", options.strict);
+    }
+    if options.max_money {
+        info!(target: "main", "Configured to check output values against max money (strict: {})", options.strict);
+    }
 
-    let chain_storage = match ChainStorage::new(&options) {
+    if let Some(path) = &options.blk_stats {
+        match blockchain::parser::write_blk_stats(&options, path) {
+            Ok(count) => {
+                info!(target: "main", "Wrote stats of {} blk files to {}", count, path.display())
+            }
+            Err(e) => {
+                error!(target: "main", "Cannot write blk stats to '{}'. {}", path.display(), e);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let mut chain_storage = match ChainStorage::new(&options) {
         Ok(storage) => storage,
         Err(e) => {
+            let source = options
+                .block_file
+                .as_ref()
+                .unwrap_or(&options.blockchain_dir);
             error!(
                 target: "main",
                 "Cannot load blockchain data from: '{}'. {}",
-                options.blockchain_dir.display(),
+                source.display(),
                 e
             );
             process::exit(1);
         }
     };
 
+    if let Some(path) = &options.orphans {
+        match chain_storage.write_orphans(path) {
+            Ok(count) => {
+                info!(target: "main", "Wrote {} orphan blocks to {}", count, path.display())
+            }
+            Err(e) => {
+                error!(target: "main", "Cannot write orphans to '{}'. {}", path.display(), e);
+                process::exit(1);
+            }
+        }
+    }
+
+    if let Some(path) = &options.reorgs {
+        match chain_storage.write_reorgs(path) {
+            Ok(count) => {
+                info!(target: "main", "Wrote {} forks to {}", count, path.display())
+            }
+            Err(e) => {
+                error!(target: "main", "Cannot write reorgs to '{}'. {}", path.display(), e);
+                process::exit(1);
+            }
+        }
+    }
+
+    if options.dry_run {
+        info!(target: "main", "Checking blocks starting from height {} ...", options.range.start);
+        let report = chain_storage.dry_run(options.range.start);
+        if report.is_ok() {
+            info!(target: "main", "Chain is complete:{}", report);
+        } else {
+            error!(target: "main", "Chain is broken:{}", report);
+            process::exit(1);
+        }
+        return;
+    }
+
+    #[cfg(feature = "grpc")]
+    if let Some(addr) = options.grpc_listen {
+        if let Err(e) = grpc::serve(addr, chain_storage, options.range.start) {
+            error!(target: "main", "{}", e.message);
+            process::exit(1);
+        }
+        return;
+    }
+
+    #[cfg(feature = "rest")]
+    if let Some(addr) = options.rest_listen {
+        if let Err(e) = rest::serve(addr, chain_storage, options.range.start) {
+            error!(target: "main", "{}", e.message);
+            process::exit(1);
+        }
+        return;
+    }
+
     let mut parser = BlockchainParser::new(options, chain_storage);
     match parser.start() {
         Ok(_) => info!(target: "main", "Fin."),
@@ -182,35 +686,151 @@ fn main() {
 /// Parses args or panics if some requirements are not met.
 fn parse_args(matches: clap::ArgMatches) -> OpResult<ParserOptions> {
     let verify = matches.get_flag("verify");
+    let verify_pow = matches.get_flag("verify-pow");
+    let verify_merkle = matches.get_flag("verify-merkle");
+    let checkpoints = match matches.get_one::<String>("checkpoints") {
+        Some(path) => chain::parse_checkpoints(&fs::read_to_string(path)?)?,
+        None => BTreeMap::new(),
+    };
+    let max_money = matches.get_flag("max-money");
+    let strict = matches.get_flag("strict");
     let log_level_filter = match matches.get_count("verbosity") {
         0 => log::LevelFilter::Info,
         1 => log::LevelFilter::Debug,
         _ => log::LevelFilter::Trace,
     };
 
-    let coin = matches
-        .get_one::<String>("coin")
-        .map_or_else(|| CoinType::from(Bitcoin), |v| v.parse().unwrap());
+    let coin = coin(&matches);
+    if verify_pow && coin.pow_algorithm.is_none() {
+        return Err(OpError::from(format!(
+            "--verify-pow is not supported for {}",
+            coin.name
+        )));
+    }
     let blockchain_dir = match matches.get_one::<String>("blockchain-dir") {
         Some(p) => PathBuf::from(p),
         None => utils::get_absolute_blockchain_dir(&coin),
     };
+    let block_file = matches.get_one::<String>("block-file").map(PathBuf::from);
+    let extra_blocks = matches.get_one::<String>("extra-blocks").map(PathBuf::from);
     let start = matches.get_one::<u64>("start").copied().unwrap_or(0);
     let end = matches.get_one::<u64>("end").copied();
     let range = BlockHeightRange::new(start, end)?;
+    let keep_confirmations = matches
+        .get_one::<u64>("keep-confirmations")
+        .copied()
+        .unwrap_or(0);
+    let max_blocks = matches.get_one::<u64>("max-blocks").copied();
+    let hash_byte_order = matches
+        .get_one::<String>("hash-byte-order")
+        .map_or(Ok(utils::HashByteOrder::Rpc), |v| v.parse())?;
+    let state_digest = matches.get_one::<u64>("state-digest").copied();
+    let chain_fingerprint = matches.get_flag("chain-fingerprint");
+    let dry_run = matches.get_flag("dry-run");
+    let orphans = matches.get_one::<String>("orphans").map(PathBuf::from);
+    let reorgs = matches.get_one::<String>("reorgs").map(PathBuf::from);
+    let blk_stats = matches.get_one::<String>("blk-stats").map(PathBuf::from);
+    let no_address = matches.get_flag("no-address");
+    let chainstate = matches.get_one::<String>("chainstate").map(PathBuf::from);
+    let disk_order = matches.get_flag("disk-order");
+    let profile = matches.get_one::<u64>("profile").copied();
+    let threads = matches
+        .get_one::<u64>("threads")
+        .map(|threads| *threads as usize);
+    let checkpoint_file = matches.get_one::<String>("checkpoint").map(PathBuf::from);
+    let checkpoint_every = *matches.get_one::<u64>("checkpoint-every").unwrap();
+    #[cfg(feature = "grpc")]
+    let grpc_listen = matches.get_one::<SocketAddr>("grpc-listen").copied();
+    #[cfg(feature = "rest")]
+    let rest_listen = matches.get_one::<SocketAddr>("listen").copied();
+    let serve = false;
+    #[cfg(feature = "grpc")]
+    let serve = serve || grpc_listen.is_some();
+    #[cfg(feature = "rest")]
+    let serve = serve || rest_listen.is_some();
 
     // Set callback
-    let callback: Box<dyn Callback>;
-    if let Some(matches) = matches.subcommand_matches("simplestats") {
-        callback = Box::new(SimpleStats::new(matches)?);
+    let callback: Option<Box<dyn Callback>>;
+    if dry_run || serve || blk_stats.is_some() {
+        callback = None;
+    } else if let Some(matches) = matches.subcommand_matches("simplestats") {
+        callback = Some(Box::new(SimpleStats::new(matches)?));
     } else if let Some(matches) = matches.subcommand_matches("csvdump") {
-        callback = Box::new(CsvDump::new(matches)?);
+        callback = Some(Box::new(CsvDump::new(matches)?));
     } else if let Some(matches) = matches.subcommand_matches("unspentcsvdump") {
-        callback = Box::new(UnspentCsvDump::new(matches)?);
+        callback = Some(Box::new(UnspentCsvDump::new(matches)?));
     } else if let Some(matches) = matches.subcommand_matches("balances") {
-        callback = Box::new(Balances::new(matches)?);
+        callback = Some(Box::new(Balances::new(matches)?));
     } else if let Some(matches) = matches.subcommand_matches("opreturn") {
-        callback = Box::new(OpReturn::new(matches)?);
+        callback = Some(Box::new(OpReturn::new(matches)?));
+    } else if let Some(matches) = matches.subcommand_matches("bloomfilter") {
+        callback = Some(Box::new(BloomFilterDump::new(matches)?));
+    } else if let Some(matches) = matches.subcommand_matches("pools") {
+        callback = Some(Box::new(Pools::new(matches)?));
+    } else if let Some(matches) = matches.subcommand_matches("txversions") {
+        callback = Some(Box::new(TxVersions::new(matches)?));
+    } else if let Some(matches) = matches.subcommand_matches("utxoages") {
+        callback = Some(Box::new(UtxoAges::new(matches)?));
+    } else if let Some(matches) = matches.subcommand_matches("merkleproof") {
+        callback = Some(Box::new(MerkleProof::new(matches)?));
+    } else if let Some(matches) = matches.subcommand_matches("feerates") {
+        callback = Some(Box::new(FeeRates::new(matches)?));
+    } else if let Some(matches) = matches.subcommand_matches("segwit") {
+        callback = Some(Box::new(SegWit::new(matches)?));
+    } else if let Some(matches) = matches.subcommand_matches("addressreuse") {
+        callback = Some(Box::new(AddressReuse::new(matches)?));
+    } else if let Some(matches) = matches.subcommand_matches("awakened") {
+        callback = Some(Box::new(AwakenedCoins::new(matches)?));
+    } else if let Some(matches) = matches.subcommand_matches("opreturnprotocols") {
+        callback = Some(Box::new(OpReturnProtocols::new(matches)?));
+    } else if let Some(matches) = matches.subcommand_matches("headers") {
+        callback = Some(Box::new(Headers::new(matches)?));
+    } else if let Some(matches) = matches.subcommand_matches("difficulty") {
+        callback = Some(Box::new(Difficulty::new(matches)?));
+    } else if let Some(matches) = matches.subcommand_matches("spendlatency") {
+        callback = Some(Box::new(SpendLatency::new(matches)?));
+    } else if let Some(matches) = matches.subcommand_matches("coinbaseaddresses") {
+        callback = Some(Box::new(CoinbaseAddresses::new(matches)?));
+    } else if let Some(matches) = matches.subcommand_matches("ledger") {
+        callback = Some(Box::new(Ledger::new(matches)?));
+    } else if let Some(matches) = matches.subcommand_matches("feepercentiles") {
+        callback = Some(Box::new(FeePercentiles::new(matches)?));
+    } else if let Some(matches) = matches.subcommand_matches("clusters") {
+        callback = Some(Box::new(Clusters::new(matches)?));
+    } else if let Some(matches) = matches.subcommand_matches("clusterflows") {
+        callback = Some(Box::new(ClusterFlows::new(matches)?));
+    } else if let Some(matches) = matches.subcommand_matches("blockintervals") {
+        callback = Some(Box::new(BlockIntervals::new(matches)?));
+    } else if let Some(matches) = matches.subcommand_matches("distinctaddresses") {
+        callback = Some(Box::new(DistinctAddresses::new(matches)?));
+    } else if let Some(matches) = matches.subcommand_matches("timelocks") {
+        callback = Some(Box::new(Timelocks::new(matches)?));
+    } else if let Some(matches) = matches.subcommand_matches("feesubsidy") {
+        callback = Some(Box::new(FeeSubsidy::new(matches)?));
+    } else if let Some(matches) = matches.subcommand_matches("dustbreakdown") {
+        callback = Some(Box::new(DustBreakdown::new(matches)?));
+    } else if let Some(matches) = matches.subcommand_matches("opreturnbytes") {
+        callback = Some(Box::new(OpReturnBytes::new(matches)?));
+    } else if let Some(matches) = matches.subcommand_matches("inscriptions") {
+        callback = Some(Box::new(Inscriptions::new(matches)?));
+    } else if let Some(matches) = matches.subcommand_matches("pubkeyreuse") {
+        callback = Some(Box::new(PubkeyReuse::new(matches)?));
+    } else if let Some(matches) = matches.subcommand_matches("inputcsvdump") {
+        callback = Some(Box::new(InputCsvDump::new(matches)?));
+    } else if let Some(matches) = matches.subcommand_matches("netissuance") {
+        callback = Some(Box::new(NetIssuance::new(matches)?));
+    } else if let Some(matches) = matches.subcommand_matches("typeflows") {
+        callback = Some(Box::new(TypeFlows::new(matches)?));
+    } else if let Some(matches) = matches.subcommand_matches("addressactivity") {
+        callback = Some(Box::new(AddressActivity::new(matches)?));
+    } else if let Some(matches) = matches.subcommand_matches("dustaddresses") {
+        callback = Some(Box::new(DustAddresses::new(matches)?));
+    } else if let Some(matches) = matches.subcommand_matches("blockfees") {
+        callback = Some(Box::new(BlockFees::new(matches)?));
+    } else if let Some(matches) = matches.subcommand_matches("addressfilter") {
+        callback = Some(Box::new(AddressFilter::new(matches)?));
+    } else if let Some(matches) = matches.subcommand_matches("addressindex") {
+        callback = Some(Box::new(AddressIndex::new(matches)?));
     } else {
         clap::error::Error::<clap::error::DefaultFormatter>::raw(
             clap::error::ErrorKind::MissingSubcommand,
@@ -218,14 +838,68 @@ fn parse_args(matches: clap::ArgMatches) -> OpResult<ParserOptions> {
         )
         .exit();
     }
+    if no_address && callback.as_ref().is_some_and(|cb| cb.needs_addresses()) {
+        return Err(OpError::from(String::from(
+            "--no-address is not supported by this callback",
+        )));
+    }
+    if disk_order && callback.as_ref().is_some_and(|cb| cb.tracks_utxos()) {
+        return Err(OpError::from(String::from(
+            "--disk-order is not supported by callbacks which track the UTXO set",
+        )));
+    }
+    if chainstate.is_some()
+        && callback
+            .as_ref()
+            .is_some_and(|cb| !cb.supports_utxo_snapshot())
+    {
+        return Err(OpError::from(String::from(
+            "--chainstate is not supported by this callback",
+        )));
+    }
+    if checkpoint_file.is_some()
+        && callback
+            .as_ref()
+            .is_some_and(|cb| !cb.supports_checkpoint())
+    {
+        return Err(OpError::from(String::from(
+            "--checkpoint is not supported by this callback",
+        )));
+    }
 
     let options = ParserOptions {
         coin,
         callback,
         verify,
+        verify_pow,
+        verify_merkle,
+        checkpoints,
+        max_money,
+        strict,
         blockchain_dir,
+        block_file,
+        extra_blocks,
         log_level_filter,
         range,
+        keep_confirmations,
+        max_blocks,
+        hash_byte_order,
+        state_digest,
+        dry_run,
+        orphans,
+        reorgs,
+        blk_stats,
+        chain_fingerprint,
+        chainstate,
+        disk_order,
+        profile,
+        threads,
+        checkpoint_file,
+        checkpoint_every,
+        #[cfg(feature = "grpc")]
+        grpc_listen,
+        #[cfg(feature = "rest")]
+        rest_listen,
     };
     Ok(options)
 }
@@ -233,6 +907,8 @@ fn parse_args(matches: clap::ArgMatches) -> OpResult<ParserOptions> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::errors::ParseErrorKind;
+    use std::io::Write;
 
     #[test]
     fn test_args_subcommand() {
@@ -257,6 +933,223 @@ mod tests {
         ]))
         .unwrap();
         parse_args(command().get_matches_from(["rusty-blockparser", "opreturn"])).unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "bloomfilter",
+            "--bloom-out",
+            tmp_dir.path().join("addresses.bloom").to_str().unwrap(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "pools",
+            tmp_dir.path().to_str().unwrap(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "txversions",
+            tmp_dir.path().to_str().unwrap(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "utxoages",
+            tmp_dir.path().to_str().unwrap(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "merkleproof",
+            "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b",
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "feerates",
+            tmp_dir.path().to_str().unwrap(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "segwit",
+            tmp_dir.path().to_str().unwrap(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "addressreuse",
+            tmp_dir.path().to_str().unwrap(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "awakened",
+            tmp_dir.path().to_str().unwrap(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "opreturnprotocols",
+            tmp_dir.path().to_str().unwrap(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "headers",
+            tmp_dir.path().to_str().unwrap(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "difficulty",
+            tmp_dir.path().to_str().unwrap(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "spendlatency",
+            tmp_dir.path().to_str().unwrap(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "coinbaseaddresses",
+            tmp_dir.path().to_str().unwrap(),
+        ]))
+        .unwrap();
+        let watchlist = tmp_dir.path().join("watchlist.txt");
+        std::fs::write(&watchlist, "1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa\n").unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "ledger",
+            tmp_dir.path().to_str().unwrap(),
+            "--watchlist",
+            watchlist.to_str().unwrap(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "feepercentiles",
+            tmp_dir.path().to_str().unwrap(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "clusters",
+            tmp_dir.path().to_str().unwrap(),
+        ]))
+        .unwrap();
+        let clusters = tmp_dir.path().join("clusters.csv");
+        std::fs::write(&clusters, "address;cluster_id\n").unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "clusterflows",
+            tmp_dir.path().to_str().unwrap(),
+            "--clusters",
+            clusters.to_str().unwrap(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "blockintervals",
+            tmp_dir.path().to_str().unwrap(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "blockfees",
+            tmp_dir.path().to_str().unwrap(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "addressfilter",
+            tmp_dir.path().to_str().unwrap(),
+            "--address",
+            "1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa",
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "addressindex",
+            tmp_dir.path().to_str().unwrap(),
+            "--min-received",
+            "100000",
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "distinctaddresses",
+            tmp_dir.path().to_str().unwrap(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "timelocks",
+            tmp_dir.path().to_str().unwrap(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "feesubsidy",
+            tmp_dir.path().to_str().unwrap(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "dustbreakdown",
+            tmp_dir.path().to_str().unwrap(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "opreturnbytes",
+            tmp_dir.path().to_str().unwrap(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "inscriptions",
+            tmp_dir.path().to_str().unwrap(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "pubkeyreuse",
+            tmp_dir.path().to_str().unwrap(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "inputcsvdump",
+            tmp_dir.path().to_str().unwrap(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "netissuance",
+            tmp_dir.path().to_str().unwrap(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "typeflows",
+            tmp_dir.path().to_str().unwrap(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "addressactivity",
+            tmp_dir.path().to_str().unwrap(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "dustaddresses",
+            tmp_dir.path().to_str().unwrap(),
+        ]))
+        .unwrap();
     }
 
     #[test]
@@ -285,6 +1178,425 @@ mod tests {
         assert!(options.verify);
     }
 
+    #[test]
+    fn test_args_verify_pow() {
+        let args = ["rusty-blockparser", "--verify-pow", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert!(options.verify_pow);
+
+        let args = [
+            "rusty-blockparser",
+            "--verify-pow",
+            "--coin",
+            "myriadcoin",
+            "simplestats",
+        ];
+        assert!(parse_args(command().get_matches_from(args)).is_err());
+    }
+
+    #[test]
+    fn test_args_verify_merkle() {
+        let args = ["rusty-blockparser", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert!(!options.verify_merkle);
+
+        let args = ["rusty-blockparser", "--verify-merkle", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert!(options.verify_merkle);
+    }
+
+    #[test]
+    fn test_args_checkpoints() {
+        let args = ["rusty-blockparser", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert!(options.checkpoints.is_empty());
+
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(
+            b"# height;hash\n\
+              11111;0000000069e244f73d78e8fd29ba2fd2ed618bd6fa2ee92559f542fdb26e7c1d\n",
+        )
+        .unwrap();
+        let args = [
+            "rusty-blockparser",
+            "--checkpoints",
+            file.path().to_str().unwrap(),
+            "simplestats",
+        ];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(
+            options.checkpoints.get(&11111).unwrap().to_string(),
+            "0000000069e244f73d78e8fd29ba2fd2ed618bd6fa2ee92559f542fdb26e7c1d"
+        );
+
+        let args = [
+            "rusty-blockparser",
+            "--checkpoints",
+            "/nonexistent",
+            "simplestats",
+        ];
+        assert!(parse_args(command().get_matches_from(args)).is_err());
+    }
+
+    #[test]
+    fn test_args_max_money() {
+        let args = ["rusty-blockparser", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert!(!options.max_money);
+        assert!(!options.strict);
+
+        let args = [
+            "rusty-blockparser",
+            "--max-money",
+            "--strict",
+            "simplestats",
+        ];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert!(options.max_money);
+        assert!(options.strict);
+    }
+
+    #[test]
+    fn test_args_hash_byte_order() {
+        let args = ["rusty-blockparser", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.hash_byte_order, utils::HashByteOrder::Rpc);
+
+        let args = [
+            "rusty-blockparser",
+            "--hash-byte-order",
+            "internal",
+            "simplestats",
+        ];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.hash_byte_order, utils::HashByteOrder::Internal);
+    }
+
+    #[test]
+    fn test_args_state_digest() {
+        let args = ["rusty-blockparser", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.state_digest, None);
+
+        let args = ["rusty-blockparser", "--state-digest", "1000", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.state_digest, Some(1000));
+    }
+
+    #[test]
+    fn test_args_profile() {
+        let args = ["rusty-blockparser", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.profile, None);
+
+        let args = ["rusty-blockparser", "--profile", "500", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.profile, Some(500));
+    }
+
+    #[test]
+    fn test_print_schema() {
+        let matches = command().get_matches_from([
+            "rusty-blockparser",
+            "--print-schema",
+            "csv",
+            "unspentcsvdump",
+            "/tmp",
+        ]);
+        let printed = schema(&matches, "csv").unwrap();
+        assert_eq!(printed["callback"], "unspentcsvdump");
+        assert_eq!(printed["tables"][0]["name"], "unspent");
+        assert_eq!(printed["tables"][0]["columns"][1]["name"], "indexOut");
+        assert_eq!(printed["tables"][0]["columns"][1]["type"], "integer");
+
+        assert_eq!(printed["separator"], ";");
+
+        let matches = command().get_matches_from([
+            "rusty-blockparser",
+            "--print-schema",
+            "jsonl",
+            "csvdump",
+            "--with-rbf",
+            "/tmp",
+        ]);
+        let printed = schema(&matches, "jsonl").unwrap();
+        assert_eq!(printed["tables"][0]["name"], "transactions");
+        assert_eq!(printed["tables"][0]["columns"][7]["name"], "rbf");
+        assert_eq!(printed["tables"][0]["columns"][7]["type"], "boolean");
+        assert_eq!(printed["tables"][1]["name"], "transactions.inputs");
+        assert!(printed.get("separator").is_none());
+        // Only csvdump writes JSON Lines files
+        assert!(schema(&matches, "csv").is_ok());
+        let matches = command().get_matches_from([
+            "rusty-blockparser",
+            "--print-schema",
+            "jsonl",
+            "unspentcsvdump",
+            "/tmp",
+        ]);
+        assert!(schema(&matches, "jsonl").is_err());
+
+        let matches = command().get_matches_from([
+            "rusty-blockparser",
+            "--print-schema",
+            "csv",
+            "simplestats",
+        ]);
+        assert!(schema(&matches, "csv").is_err());
+        // Every callback writing to a dump folder describes its files
+        for cmd in command().get_subcommands() {
+            let name = cmd.get_name();
+            if cmd.get_arguments().all(|arg| arg.get_id() != "dump-folder") {
+                continue;
+            }
+            // Other required arguments don't affect the schema
+            let matches = command().ignore_errors(true).get_matches_from([
+                "rusty-blockparser",
+                "--print-schema",
+                "csv",
+                name,
+                "/tmp",
+            ]);
+            assert!(schema(&matches, "csv").is_ok(), "{}", name);
+        }
+        let args = [
+            "rusty-blockparser",
+            "--print-schema",
+            "parquet",
+            "simplestats",
+        ];
+        assert!(command().try_get_matches_from(args).is_err());
+    }
+
+    #[test]
+    fn test_parse_standalone() {
+        // Bitcoin genesis block and its coinbase transaction
+        let genesis = concat!(
+            "0100000000000000000000000000000000000000000000000000000000000000",
+            "000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa",
+            "4b1e5e4a29ab5f49ffff001d1dac2b7c01",
+        );
+        let coinbase = concat!(
+            "01000000010000000000000000000000000000000000000000000000000000000000000000",
+            "ffffffff4d04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368",
+            "616e63656c6c6f72206f6e206272696e6b206f66207365636f6e64206261696c6f757420",
+            "666f722062616e6b73ffffffff0100f2052a01000000434104678afdb0fe5548271967f1",
+            "a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112",
+            "de5c384df7ba0b8d578a4c702b6bf11d5fac00000000",
+        );
+        let parse = |args: &[&str]| {
+            let mut argv = vec!["rusty-blockparser"];
+            argv.extend_from_slice(args);
+            parse_standalone(&command().get_matches_from(argv))
+        };
+        assert!(parse(&["simplestats"]).is_none());
+
+        let block = parse(&["--parse-block", &format!("{}{}", genesis, coinbase)])
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            block["hash"],
+            "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f"
+        );
+        assert!(block["height"].is_null());
+        let tx = parse(&["--parse-tx", coinbase]).unwrap().unwrap();
+        assert_eq!(tx, block["txs"][0]);
+        assert_eq!(
+            tx["txid"],
+            "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b"
+        );
+        assert_eq!(tx["outputs"][0]["value"], 5000000000u64);
+
+        // Invalid hex, truncated and trailing data
+        let err = parse(&["--parse-tx", "01zz"]).unwrap().unwrap_err();
+        assert!(matches!(err.kind, OpErrorKind::InvalidArgsError));
+        let err = parse(&["--parse-tx", &coinbase[..100]])
+            .unwrap()
+            .unwrap_err();
+        assert!(
+            matches!(err.kind, OpErrorKind::ParseError(ref e) if matches!(e.kind, ParseErrorKind::UnexpectedEof))
+        );
+        let err = parse(&["--parse-tx", &format!("{}00", coinbase)])
+            .unwrap()
+            .unwrap_err();
+        assert!(
+            matches!(err.kind, OpErrorKind::ParseError(ref e) if matches!(e.kind, ParseErrorKind::TrailingBytes(1)))
+        );
+    }
+
+    #[test]
+    fn test_args_max_script_size() {
+        let args = ["rusty-blockparser", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.coin.max_script_size, 10000);
+
+        let args = [
+            "rusty-blockparser",
+            "--max-script-size",
+            "520",
+            "simplestats",
+        ];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.coin.max_script_size, 520);
+    }
+
+    #[test]
+    fn test_args_max_blocks() {
+        let args = ["rusty-blockparser", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.max_blocks, None);
+
+        let args = ["rusty-blockparser", "--max-blocks", "2", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.max_blocks, Some(2));
+
+        let args = ["rusty-blockparser", "--max-blocks", "0", "simplestats"];
+        assert!(command().try_get_matches_from(args).is_err());
+    }
+
+    #[test]
+    fn test_args_threads() {
+        let args = ["rusty-blockparser", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.threads, None);
+
+        let args = ["rusty-blockparser", "--threads", "4", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.threads, Some(4));
+
+        let args = ["rusty-blockparser", "--threads", "0", "simplestats"];
+        assert!(command().try_get_matches_from(args).is_err());
+    }
+
+    #[test]
+    fn test_args_no_address() {
+        let args = ["rusty-blockparser", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert!(options.coin.resolve_addresses);
+
+        let args = ["rusty-blockparser", "--no-address", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert!(!options.coin.resolve_addresses);
+
+        let args = ["rusty-blockparser", "--no-address", "balances", "/tmp"];
+        assert!(parse_args(command().get_matches_from(args)).is_err());
+    }
+
+    #[test]
+    fn test_args_disk_order() {
+        let args = ["rusty-blockparser", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert!(!options.disk_order);
+
+        let args = ["rusty-blockparser", "--disk-order", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert!(options.disk_order);
+
+        let args = ["rusty-blockparser", "--disk-order", "balances", "/tmp"];
+        assert!(parse_args(command().get_matches_from(args)).is_err());
+        let args = ["rusty-blockparser", "--disk-order", "feerates", "/tmp"];
+        assert!(parse_args(command().get_matches_from(args)).is_err());
+    }
+
+    #[test]
+    fn test_args_chainstate() {
+        let args = [
+            "rusty-blockparser",
+            "--chainstate",
+            "/tmp/chainstate",
+            "balances",
+            "/tmp",
+        ];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.chainstate, Some(PathBuf::from("/tmp/chainstate")));
+
+        let args = [
+            "rusty-blockparser",
+            "--chainstate",
+            "/tmp/chainstate",
+            "simplestats",
+        ];
+        assert!(parse_args(command().get_matches_from(args)).is_err());
+
+        let args = [
+            "rusty-blockparser",
+            "--chainstate",
+            "/tmp/chainstate",
+            "--start",
+            "10",
+            "balances",
+            "/tmp",
+        ];
+        assert!(command().try_get_matches_from(args).is_err());
+    }
+
+    #[test]
+    fn test_args_checkpoint() {
+        let args = ["rusty-blockparser", "balances", "/tmp"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.checkpoint_file, None);
+        assert_eq!(options.checkpoint_every, 10000);
+
+        let args = [
+            "rusty-blockparser",
+            "--checkpoint",
+            "/tmp/checkpoint",
+            "--checkpoint-every",
+            "500",
+            "balances",
+            "/tmp",
+        ];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(
+            options.checkpoint_file,
+            Some(PathBuf::from("/tmp/checkpoint"))
+        );
+        assert_eq!(options.checkpoint_every, 500);
+
+        // The callback has to support checkpoints
+        let args = [
+            "rusty-blockparser",
+            "--checkpoint",
+            "/tmp/checkpoint",
+            "simplestats",
+        ];
+        assert!(parse_args(command().get_matches_from(args)).is_err());
+
+        // The checkpoint determines the start height
+        let args = [
+            "rusty-blockparser",
+            "--checkpoint",
+            "/tmp/checkpoint",
+            "--start",
+            "10",
+            "balances",
+            "/tmp",
+        ];
+        assert!(command().try_get_matches_from(args).is_err());
+        let args = [
+            "rusty-blockparser",
+            "--checkpoint-every",
+            "5",
+            "balances",
+            "/tmp",
+        ];
+        assert!(command().try_get_matches_from(args).is_err());
+    }
+
+    #[test]
+    fn test_args_dry_run() {
+        let args = ["rusty-blockparser", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert!(!options.dry_run);
+        assert!(options.callback.is_some());
+
+        // No callback is required for dry runs
+        let args = ["rusty-blockparser", "--dry-run"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert!(options.dry_run);
+        assert!(options.callback.is_none());
+    }
+
     #[test]
     fn test_args_blockchain_dir() {
         let args = ["rusty-blockparser", "simplestats"];
@@ -396,6 +1708,24 @@ mod tests {
             }
         );
 
+        // A single block
+        let args = [
+            "rusty-blockparser",
+            "--start-height",
+            "2",
+            "--end-height",
+            "2",
+            "simplestats",
+        ];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(
+            options.range,
+            BlockHeightRange {
+                start: 2,
+                end: Some(2)
+            }
+        );
+
         let args = ["rusty-blockparser", "-s", "2", "-e", "1", "simplestats"];
         assert!(parse_args(command().get_matches_from(args)).is_err());
     }
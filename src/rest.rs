@@ -0,0 +1,240 @@
+use bitcoin::hashes::sha256d;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::str::FromStr;
+use std::time::Duration;
+
+use clap::{Arg, Command};
+use serde_json::{json, Value};
+
+use crate::blockchain::parser::chain::{ChainStorage, TxidIndex};
+use crate::blockchain::proto::block::Block;
+use crate::common::json;
+use crate::common::utils::HashByteOrder;
+use crate::errors::OpResult;
+
+/// Time a client may take to send its request or to receive the response
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Adds `--listen`
+pub fn add_args(cmd: Command) -> Command {
+    cmd.arg(
+        Arg::new("listen")
+            .long("listen")
+            .value_name("ADDR")
+            .value_parser(clap::value_parser!(SocketAddr))
+            .help("Serves blocks and transactions as JSON via HTTP on the given address instead of running a callback"),
+    )
+}
+
+/// Serves the chain until the process gets terminated. Requests are handled one at a time.
+/// All blocks in the configured range are read once on startup to build the txid index.
+pub fn serve(addr: SocketAddr, chain_storage: ChainStorage, start_height: u64) -> OpResult<()> {
+    let mut server = Server::new(chain_storage, start_height)?;
+    let listener = TcpListener::bind(addr)?;
+    info!(target: "rest", "Listening on {} ...", listener.local_addr()?);
+    for stream in listener.incoming() {
+        let result = stream.and_then(|stream| server.handle_connection(stream));
+        if let Err(e) = result {
+            warn!(target: "rest", "Unable to handle request: {}", e);
+        }
+    }
+    Ok(())
+}
+
+/// Answers `GET /block/{height}` and `GET /tx/{txid}` on top of the chain storage
+pub struct Server {
+    chain_storage: ChainStorage,
+    txids: TxidIndex,
+    hash_byte_order: HashByteOrder,
+    // Read and write timeout of the connections, see `REQUEST_TIMEOUT`
+    timeout: Duration,
+}
+
+impl Server {
+    fn new(mut chain_storage: ChainStorage, start_height: u64) -> OpResult<Self> {
+        Ok(Self {
+            txids: TxidIndex::build(&mut chain_storage, start_height)?,
+            hash_byte_order: chain_storage.hash_byte_order(),
+            chain_storage,
+            timeout: REQUEST_TIMEOUT,
+        })
+    }
+
+    /// Reads a single request from the stream and writes the response.
+    /// Clients which stall longer than the timeout are disconnected, so they can't block the server.
+    fn handle_connection(&mut self, mut stream: TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        let mut reader = BufReader::new(&stream);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        // Skip the headers, requests don't have a body
+        let mut header = String::new();
+        while reader.read_line(&mut header)? > 2 {
+            header.clear();
+        }
+
+        let (status, body) = match request_line.split_whitespace().collect::<Vec<&str>>()[..] {
+            ["GET", path, _] => self.handle(path),
+            _ => (405, json!({ "error": "Only GET requests are supported" })),
+        };
+        debug!(target: "rest", "{} -> {}", request_line.trim_end(), status);
+        let body = body.to_string();
+        write!(
+            stream,
+            "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            reason(status),
+            body.len(),
+            body
+        )?;
+        stream.flush()
+    }
+
+    /// Returns the status code and JSON body for the given path
+    fn handle(&mut self, path: &str) -> (u16, Value) {
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+        let result = match segments[..] {
+            ["block", height] => match height.parse::<u64>() {
+                Ok(height) => self.block(height),
+                Err(_) => Err((400, format!("Invalid height `{}`", height))),
+            },
+            ["tx", txid] => match sha256d::Hash::from_str(txid) {
+                Ok(txid) => self.tx(&txid),
+                Err(e) => Err((400, format!("Invalid txid `{}`: {}", txid, e))),
+            },
+            _ => Err((404, format!("Unknown path {}", path))),
+        };
+        match result {
+            Ok(body) => (200, body),
+            Err((status, msg)) => (status, json!({ "error": msg })),
+        }
+    }
+
+    /// Reads the block at the given height
+    fn read_block(&mut self, height: u64) -> Result<Block, (u16, String)> {
+        self.chain_storage
+            .get_block(height)
+            .map_err(|e| (500, e.message))?
+            .ok_or_else(|| (404, format!("No block at height {}", height)))
+    }
+
+    fn block(&mut self, height: u64) -> Result<Value, (u16, String)> {
+        let block = self.read_block(height)?;
+        Ok(json::block_json(&block, Some(height), self.hash_byte_order))
+    }
+
+    fn tx(&mut self, txid: &sha256d::Hash) -> Result<Value, (u16, String)> {
+        let height = self
+            .txids
+            .height(txid)
+            .ok_or_else(|| (404, format!("Unknown txid {}", txid)))?;
+        let block = self.read_block(height)?;
+        let tx = block
+            .txs
+            .iter()
+            .find(|tx| tx.hash == *txid)
+            .ok_or_else(|| (500, format!("{} not found in block {}", txid, height)))?;
+        Ok(json::tx_json(tx, Some(height), self.hash_byte_order))
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Internal Server Error",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::utils;
+    use std::io::Read;
+    use std::thread;
+
+    const GENESIS: &str = concat!(
+        "f9beb4d91d010000010000000000000000000000000000000000000000000000",
+        "0000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3",
+        "888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c0101000000010000",
+        "000000000000000000000000000000000000000000000000000000000000ffff",
+        "ffff4d04ffff001d0104455468652054696d65732030332f4a616e2f32303039",
+        "204368616e63656c6c6f72206f6e206272696e6b206f66207365636f6e642062",
+        "61696c6f757420666f722062616e6b73ffffffff0100f2052a01000000434104",
+        "678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb6",
+        "49f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5f",
+        "ac00000000",
+    );
+
+    #[test]
+    fn test_server() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&utils::hex_to_vec(GENESIS)).unwrap();
+        file.flush().unwrap();
+        let options = crate::parse_args(crate::command().get_matches_from([
+            "rusty-blockparser",
+            "--block-file",
+            file.path().to_str().unwrap(),
+            "--listen",
+            "127.0.0.1:0",
+        ]))
+        .unwrap();
+        assert!(options.callback.is_none());
+        let mut server = Server::new(ChainStorage::new(&options).unwrap(), 0).unwrap();
+
+        let (status, block) = server.handle("/block/0");
+        assert_eq!(status, 200);
+        assert_eq!(
+            block["hash"],
+            "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f"
+        );
+        assert_eq!(block["txs"][0]["outputs"][0]["value"], 5000000000u64);
+
+        let txid = "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b";
+        let (status, tx) = server.handle(&format!("/tx/{}", txid));
+        assert_eq!(status, 200);
+        assert_eq!(tx["txid"], txid);
+        assert_eq!(tx["height"], 0);
+
+        assert_eq!(server.handle("/block/1").0, 404);
+        assert_eq!(server.handle(&format!("/tx/{}", "00".repeat(32))).0, 404);
+        assert_eq!(server.handle("/tx/xyz").0, 400);
+        assert_eq!(server.handle("/blocks").0, 404);
+
+        // Full round trip over HTTP
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream
+                .write_all(b"GET /block/1 HTTP/1.1\r\nHost: localhost\r\n\r\n")
+                .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        });
+        let (stream, _) = listener.accept().unwrap();
+        server.handle_connection(stream).unwrap();
+        let response = client.join().unwrap();
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(response.ends_with(r#"{"error":"No block at height 1"}"#));
+
+        // A client which doesn't send its request is disconnected after the timeout
+        server.timeout = Duration::from_millis(50);
+        let _idle = TcpStream::connect(addr).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let err = server.handle_connection(stream).unwrap_err();
+        assert!(
+            matches!(
+                err.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ),
+            "{}",
+            err
+        );
+    }
+}